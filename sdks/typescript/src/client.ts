@@ -20,6 +20,9 @@ import {
   type AgentInstallRequest,
   type AgentInstallResponse,
   type AgentListResponse,
+  type AgentUninstallQuery,
+  type AgentUninstallResponse,
+  type CustomAgentRequest,
   type FsActionResponse,
   type FsDeleteQuery,
   type FsEntriesQuery,
@@ -640,6 +643,22 @@ export class SandboxAgent {
     });
   }
 
+  async uninstallAgent(agent: string, query: AgentUninstallQuery = {}): Promise<AgentUninstallResponse> {
+    return this.requestJson("DELETE", `${API_PREFIX}/agents/${encodeURIComponent(agent)}`, {
+      query,
+    });
+  }
+
+  async registerCustomAgent(agent: string, definition: CustomAgentRequest): Promise<void> {
+    await this.requestRaw("PUT", `${API_PREFIX}/agents/${encodeURIComponent(agent)}/definition`, {
+      body: definition,
+    });
+  }
+
+  async unregisterCustomAgent(agent: string): Promise<void> {
+    await this.requestRaw("DELETE", `${API_PREFIX}/agents/${encodeURIComponent(agent)}/definition`);
+  }
+
   async listAcpServers(): Promise<AcpServerListResponse> {
     return this.requestJson("GET", `${API_PREFIX}/acp`);
   }
@@ -12,12 +12,24 @@ export interface paths {
     get: operations["get_v1_acp"];
     post: operations["post_v1_acp"];
     delete: operations["delete_v1_acp"];
+    patch: operations["patch_v1_acp"];
+  };
+  "/v1/acp/{server_id}/logs": {
+    get: operations["get_v1_acp_logs"];
+  };
+  "/v1/acp/{server_id}/ws": {
+    get: operations["get_v1_acp_ws"];
   };
   "/v1/agents": {
     get: operations["get_v1_agents"];
   };
   "/v1/agents/{agent}": {
     get: operations["get_v1_agent"];
+    delete: operations["delete_v1_agent"];
+  };
+  "/v1/agents/{agent}/definition": {
+    put: operations["put_v1_agent_definition"];
+    delete: operations["delete_v1_agent_definition"];
   };
   "/v1/agents/{agent}/install": {
     post: operations["post_v1_agent_install"];
@@ -71,18 +83,80 @@ export interface components {
       params?: unknown;
       result?: unknown;
     };
-    AcpPostQuery: {
-      agent?: string | null;
-    };
+    AcpLogLine: {
+      line: string;
+      /** Format: int64 */
+      timestampMs: number;
+    };
+    AcpLogsQuery: {
+      /** @description Stream new lines as SSE after the matching backlog. */
+      follow?: boolean | null;
+      /**
+       * Format: int64
+       * @description Only return lines captured at or after this time, in milliseconds
+       * since the Unix epoch.
+       */
+      since?: number | null;
+      /** @description Only return the last N lines. */
+      tail?: number | null;
+    };
+    AcpLogsResponse: {
+      lines: components["schemas"]["AcpLogLine"][];
+    };
+    /** @description A JSON-RPC envelope, or a batch of them. */
+    AcpPayload: components["schemas"]["AcpEnvelope"] | components["schemas"]["AcpEnvelope"][];
     AcpServerInfo: {
       agent: string;
+      args?: string[];
       /** Format: int64 */
       createdAtMs: number;
+      cwd?: string | null;
+      env?: {
+        [key: string]: string;
+      };
+      labels?: {
+        [key: string]: string;
+      };
+      /**
+       * Format: int64
+       * @description Last request or stream activity, in milliseconds since the Unix epoch.
+       */
+      lastActivityMs: number;
+      /**
+       * Format: int64
+       * @description Requests sent to the agent that have not been answered yet.
+       */
+      pendingRequests: number;
+      /**
+       * Format: int32
+       * @description Agent process id; absent while the process is restarting or after it
+       * exited.
+       */
+      pid?: number | null;
+      /** Format: int32 */
+      restartCount: number;
       serverId: string;
+      /** @description `running`, `restarting` or `exited`. */
+      state: string;
+      /** Format: int64 */
+      uptimeMs: number;
+    };
+    AcpServerListQuery: {
+      /**
+       * @description Comma-separated label requirements: `key=value`, `key!=value`, `key`
+       * or `!key`.
+       */
+      labelSelector?: string | null;
     };
     AcpServerListResponse: {
       servers: components["schemas"]["AcpServerInfo"][];
     };
+    AcpServerPatchRequest: {
+      /** @description Labels to set; a `null` value removes the label. */
+      labels?: {
+        [key: string]: string | null;
+      };
+    };
     AgentCapabilities: {
       commandExecution: boolean;
       errorEvents: boolean;
@@ -108,6 +182,7 @@ export interface components {
       configError?: string | null;
       configOptions?: unknown[] | null;
       credentialsAvailable: boolean;
+      displayName: string;
       id: string;
       installed: boolean;
       path?: string | null;
@@ -123,6 +198,8 @@ export interface components {
     AgentInstallRequest: {
       agentProcessVersion?: string | null;
       agentVersion?: string | null;
+      /** @description Fail unless the install matches the agent lockfile. */
+      frozen?: boolean | null;
       reinstall?: boolean | null;
     };
     AgentInstallResponse: {
@@ -132,6 +209,43 @@ export interface components {
     AgentListResponse: {
       agents: components["schemas"]["AgentInfo"][];
     };
+    AgentUninstallQuery: {
+      force?: boolean | null;
+    };
+    AgentUninstallResponse: {
+      /** @description Files and directories removed from the install dir. */
+      removed: string[];
+    };
+    /** @description Where a custom agent process comes from. */
+    CustomAgentInstallSpec: {
+      /** @description An ACP registry entry id. */
+      registry: string;
+    } | {
+      /** @description An npm package run through `npx`. */
+      npx: string;
+    } | {
+      /** @description An absolute executable path or a name on `PATH`. */
+      command: string;
+    };
+    /** @description A user-defined agent registered under the id in the path. */
+    CustomAgentRequest: {
+      /** @description Appended to the agent process command line. */
+      args?: string[];
+      /** @description Capability flags keyed like `AgentCapabilities`; unset flags are false. */
+      capabilities?: {
+        [key: string]: boolean;
+      };
+      displayName?: string | null;
+      env?: {
+        [key: string]: string;
+      };
+      /**
+       * @description Stdio framing (`content-length` or `auto`); newline-delimited JSON
+       * when unset.
+       */
+      framing?: string | null;
+      install: components["schemas"]["CustomAgentInstallSpec"];
+    };
     /** @enum {string} */
     ErrorType: "invalid_request" | "conflict" | "unsupported_agent" | "agent_not_installed" | "install_failed" | "agent_process_exited" | "token_invalid" | "permission_denied" | "not_acceptable" | "unsupported_media_type" | "session_not_found" | "session_already_exists" | "mode_not_supported" | "stream_error" | "timeout";
     FsActionResponse: {
@@ -266,6 +380,12 @@ export type external = Record<string, never>;
 export interface operations {
 
   get_v1_acp_servers: {
+    parameters: {
+      query?: {
+        /** @description Comma-separated label requirements: key=value, key!=value, key or !key */
+        labelSelector?: string | null;
+      };
+    };
     responses: {
       /** @description Active ACP server instances */
       200: {
@@ -273,6 +393,12 @@ export interface operations {
           "application/json": components["schemas"]["AcpServerListResponse"];
         };
       };
+      /** @description Invalid label selector */
+      400: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
     };
   };
   get_v1_acp: {
@@ -312,6 +438,44 @@ export interface operations {
       query?: {
         /** @description Agent id required for first POST */
         agent?: string | null;
+        /** @description Working directory for the agent process; first POST only */
+        cwd?: string | null;
+        /** @description Extra agent environment as KEY=VALUE, repeatable; first POST only */
+        env?: string[] | null;
+        /** @description Extra agent argument, repeatable; first POST only */
+        arg?: string[] | null;
+        /** @description JSON permission policy for this server; first POST only */
+        permissionPolicy?: string | null;
+        /** @description Label as KEY=VALUE, repeatable; first POST only */
+        label?: string[] | null;
+        /**
+         * Format: int64
+         * @description Milliseconds to wait for the agent's response; overrides the server defaults
+         */
+        timeoutMs?: number | null;
+        /**
+         * Format: int64
+         * @description Unix time in milliseconds after which the response is no longer awaited
+         */
+        deadline?: number | null;
+        /** @description Send the agent a cancellation when the request times out */
+        cancelOnTimeout?: boolean | null;
+        /** @description Names the caller when it answers an agent request */
+        clientId?: string | null;
+      };
+      header?: {
+        /**
+         * Format: int64
+         * @description Same as timeoutMs
+         */
+        "X-Request-Timeout-Ms"?: number | null;
+        /**
+         * Format: int64
+         * @description Same as deadline
+         */
+        "X-Request-Deadline"?: number | null;
+        /** @description Same as clientId */
+        "X-Client-Id"?: string | null;
       };
       path: {
         /** @description Client-defined ACP server id */
@@ -320,26 +484,32 @@ export interface operations {
     };
     requestBody: {
       content: {
-        "application/json": components["schemas"]["AcpEnvelope"];
+        "application/json": components["schemas"]["AcpPayload"];
       };
     };
     responses: {
-      /** @description JSON-RPC response envelope */
+      /** @description JSON-RPC response envelope, or an array of responses for a batch */
       200: {
         content: {
-          "application/json": components["schemas"]["AcpEnvelope"];
+          "application/json": components["schemas"]["AcpPayload"];
         };
       };
       /** @description JSON-RPC notification accepted */
       202: {
         content: never;
       };
-      /** @description Invalid ACP envelope */
+      /** @description Invalid ACP envelope or launch override */
       400: {
         content: {
           "application/json": components["schemas"]["ProblemDetails"];
         };
       };
+      /** @description Launch override not allowed */
+      403: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
       /** @description Unknown ACP server */
       404: {
         content: {
@@ -352,7 +522,7 @@ export interface operations {
           "application/json": components["schemas"]["ProblemDetails"];
         };
       };
-      /** @description ACP server bound to different agent */
+      /** @description ACP server bound to different agent or launch settings, or agent request already answered */
       409: {
         content: {
           "application/json": components["schemas"]["ProblemDetails"];
@@ -386,6 +556,131 @@ export interface operations {
       };
     };
   };
+  patch_v1_acp: {
+    parameters: {
+      path: {
+        /** @description Client-defined ACP server id */
+        server_id: string;
+      };
+    };
+    requestBody: {
+      content: {
+        "application/json": components["schemas"]["AcpServerPatchRequest"];
+      };
+    };
+    responses: {
+      /** @description Updated ACP server */
+      200: {
+        content: {
+          "application/json": components["schemas"]["AcpServerInfo"];
+        };
+      };
+      /** @description Invalid label key */
+      400: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+      /** @description Unknown ACP server */
+      404: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+    };
+  };
+  get_v1_acp_logs: {
+    parameters: {
+      query?: {
+        /** @description Only return the last N lines */
+        tail?: number | null;
+        /**
+         * Format: int64
+         * @description Only return lines captured at or after this Unix time in milliseconds
+         */
+        since?: number | null;
+        /** @description Stream new lines as SSE after the matching backlog */
+        follow?: boolean | null;
+      };
+      path: {
+        /** @description Client-defined ACP server id */
+        server_id: string;
+      };
+    };
+    responses: {
+      /** @description Captured agent stderr, or an SSE stream of lines when following */
+      200: {
+        content: {
+          "application/json": components["schemas"]["AcpLogsResponse"];
+        };
+      };
+      /** @description Unknown ACP server */
+      404: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+    };
+  };
+  get_v1_acp_ws: {
+    parameters: {
+      query?: {
+        /** @description Agent id; creates the server if it does not exist */
+        agent?: string | null;
+        /** @description Working directory for the agent process; first connection only */
+        cwd?: string | null;
+        /** @description Extra agent environment as KEY=VALUE, repeatable; first connection only */
+        env?: string[] | null;
+        /** @description Extra agent argument, repeatable; first connection only */
+        arg?: string[] | null;
+        /** @description JSON permission policy for this server; first connection only */
+        permissionPolicy?: string | null;
+        /** @description Label as KEY=VALUE, repeatable; first connection only */
+        label?: string[] | null;
+        /**
+         * Format: int64
+         * @description Replay stream messages after this event id
+         */
+        lastEventId?: number | null;
+        /** @description Names this connection when it answers agent requests */
+        clientId?: string | null;
+      };
+      path: {
+        /** @description Client-defined ACP server id */
+        server_id: string;
+      };
+    };
+    responses: {
+      /** @description WebSocket carrying JSON-RPC envelopes in both directions */
+      101: {
+        content: never;
+      };
+      /** @description Invalid request or launch override */
+      400: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+      /** @description Launch override not allowed */
+      403: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+      /** @description Unknown ACP server */
+      404: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+      /** @description ACP server bound to different agent or launch settings */
+      409: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+    };
+  };
   get_v1_agents: {
     parameters: {
       query?: {
@@ -444,6 +739,83 @@ export interface operations {
       };
     };
   };
+  delete_v1_agent: {
+    parameters: {
+      query?: {
+        /** @description Shut down ACP servers running the agent and remove it */
+        force?: boolean | null;
+      };
+      path: {
+        /** @description Agent id */
+        agent: string;
+      };
+    };
+    responses: {
+      /** @description Removed agent files */
+      200: {
+        content: {
+          "application/json": components["schemas"]["AgentUninstallResponse"];
+        };
+      };
+      /** @description Unknown agent */
+      400: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+      /** @description ACP servers are running the agent */
+      409: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+    };
+  };
+  put_v1_agent_definition: {
+    parameters: {
+      path: {
+        /** @description Custom agent id */
+        agent: string;
+      };
+    };
+    requestBody: {
+      content: {
+        "application/json": components["schemas"]["CustomAgentRequest"];
+      };
+    };
+    responses: {
+      /** @description Custom agent registered */
+      204: {
+        content: never;
+      };
+      /** @description Invalid definition or built-in agent id */
+      400: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+    };
+  };
+  delete_v1_agent_definition: {
+    parameters: {
+      path: {
+        /** @description Custom agent id */
+        agent: string;
+      };
+    };
+    responses: {
+      /** @description Custom agent unregistered */
+      204: {
+        content: never;
+      };
+      /** @description Unknown or built-in agent */
+      400: {
+        content: {
+          "application/json": components["schemas"]["ProblemDetails"];
+        };
+      };
+    };
+  };
   post_v1_agent_install: {
     parameters: {
       path: {
@@ -32,6 +32,9 @@ export type {
   AgentInstallRequest,
   AgentInstallResponse,
   AgentListResponse,
+  AgentUninstallQuery,
+  AgentUninstallResponse,
+  CustomAgentRequest,
   FsActionResponse,
   FsDeleteQuery,
   FsEntriesQuery,
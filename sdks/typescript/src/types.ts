@@ -8,6 +8,9 @@ export type AgentListResponse = JsonResponse<operations["get_v1_agents"], 200>;
 export type AgentInfo = components["schemas"]["AgentInfo"];
 export type AgentInstallRequest = JsonRequestBody<operations["post_v1_agent_install"]>;
 export type AgentInstallResponse = JsonResponse<operations["post_v1_agent_install"], 200>;
+export type AgentUninstallQuery = QueryParams<operations["delete_v1_agent"]>;
+export type AgentUninstallResponse = JsonResponse<operations["delete_v1_agent"], 200>;
+export type CustomAgentRequest = components["schemas"]["CustomAgentRequest"];
 
 export type AcpEnvelope = components["schemas"]["AcpEnvelope"];
 export type AcpServerInfo = components["schemas"]["AcpServerInfo"];
@@ -86,12 +86,21 @@ fn map_error(err: AdapterError) -> Response {
         AdapterError::InvalidEnvelope => problem(
             StatusCode::BAD_REQUEST,
             "invalid_envelope",
-            "request body must be a JSON-RPC object",
+            "request body must be a JSON-RPC object or batch array",
         ),
-        AdapterError::Timeout => problem(
-            StatusCode::GATEWAY_TIMEOUT,
-            "timeout",
-            "timed out waiting for agent response",
+        err @ AdapterError::Timeout { .. } => {
+            problem(StatusCode::GATEWAY_TIMEOUT, "timeout", &err.to_string())
+        }
+        err @ AdapterError::AlreadyResolved { .. } => {
+            problem(StatusCode::CONFLICT, "already_resolved", &err.to_string())
+        }
+        AdapterError::AgentExited { code } => problem(
+            StatusCode::BAD_GATEWAY,
+            "agent_exited",
+            &match code {
+                Some(code) => format!("agent process exited with code {code} before responding"),
+                None => "agent process exited before responding".to_string(),
+            },
         ),
         AdapterError::Write(write) => problem(
             StatusCode::BAD_GATEWAY,
@@ -0,0 +1,104 @@
+//! Agent-to-client requests broadcast to subscribers, so that only the first
+//! client to answer one reaches the agent.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Answered requests remembered for rejecting late answers.
+const RESOLVED_RETAINED: usize = 1024;
+
+#[derive(Debug, Default)]
+pub(crate) struct ClientRequestTracker {
+    state: Mutex<TrackerState>,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    /// Method of each unanswered request, by id key.
+    outstanding: HashMap<String, String>,
+    resolved: HashMap<String, Resolution>,
+    /// Insertion order of `resolved`, for eviction.
+    resolved_order: VecDeque<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Resolution {
+    pub(crate) method: String,
+    /// The answering client's id, when it gave one.
+    pub(crate) resolved_by: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Claim {
+    /// First answer: forward it.
+    Won { method: String },
+    /// Someone answered first: reject.
+    AlreadyResolved(Resolution),
+    /// Not a request this runtime broadcast; forward it as-is.
+    Untracked,
+}
+
+impl ClientRequestTracker {
+    /// Record a request the agent sent to subscribers. A reused id starts
+    /// over, as after an agent restart.
+    pub(crate) fn track(&self, key: String, method: &str) {
+        let mut state = self.lock();
+        state.resolved.remove(&key);
+        state.outstanding.insert(key, method.to_string());
+    }
+
+    pub(crate) fn claim(&self, key: &str, client_id: Option<&str>) -> Claim {
+        let mut state = self.lock();
+        if let Some(method) = state.outstanding.remove(key) {
+            let resolution = Resolution {
+                method: method.clone(),
+                resolved_by: client_id.map(str::to_string),
+            };
+            if state.resolved_order.len() == RESOLVED_RETAINED {
+                if let Some(oldest) = state.resolved_order.pop_front() {
+                    state.resolved.remove(&oldest);
+                }
+            }
+            state.resolved_order.push_back(key.to_string());
+            state.resolved.insert(key.to_string(), resolution);
+            return Claim::Won { method };
+        }
+        match state.resolved.get(key) {
+            Some(resolution) => Claim::AlreadyResolved(resolution.clone()),
+            None => Claim::Untracked,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TrackerState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_answer_wins() {
+        let tracker = ClientRequestTracker::default();
+        tracker.track("7".to_string(), "session/request_permission");
+
+        assert_eq!(
+            tracker.claim("7", Some("ui-a")),
+            Claim::Won {
+                method: "session/request_permission".to_string()
+            }
+        );
+        assert_eq!(
+            tracker.claim("7", Some("ui-b")),
+            Claim::AlreadyResolved(Resolution {
+                method: "session/request_permission".to_string(),
+                resolved_by: Some("ui-a".to_string()),
+            })
+        );
+        assert_eq!(tracker.claim("8", None), Claim::Untracked);
+
+        tracker.track("7".to_string(), "fs/read_text_file");
+        assert!(matches!(tracker.claim("7", None), Claim::Won { .. }));
+    }
+}
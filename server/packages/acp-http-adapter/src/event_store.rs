@@ -0,0 +1,454 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const SEGMENT_MAX_EVENTS: u64 = 1024;
+const SEGMENT_MAX_BYTES: u64 = 4 * 1024 * 1024;
+const SEGMENT_EXTENSION: &str = "jsonl";
+/// How often the writer thread applies retention while the stream is idle.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Durable log of sequenced stream messages. The in-memory ring serves
+/// recent replays; the store answers `Last-Event-ID` requests that fall
+/// behind the ring and keeps history across restarts.
+pub trait EventStore: Send + Sync + std::fmt::Debug {
+    fn append(&self, sequence: u64, payload: &Value) -> io::Result<()>;
+
+    /// Events with `after < sequence < before`, in sequence order.
+    fn read_range(&self, after: u64, before: u64) -> io::Result<Vec<(u64, Value)>>;
+
+    fn last_sequence(&self) -> Option<u64>;
+
+    /// Remove every stored event.
+    fn clear(&self) -> io::Result<()>;
+
+    /// Apply retention limits that depend on time rather than appends.
+    fn expire(&self) {}
+}
+
+/// Retention limits. Unset limits are unbounded.
+#[derive(Debug, Clone, Default)]
+pub struct EventRetention {
+    pub max_events: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+/// Append-only JSONL segment files named after their first sequence.
+/// Retention drops whole segments, oldest first. Event and byte limits never
+/// drop the active segment; `max_age` does once it has been idle that long.
+#[derive(Debug)]
+pub struct SegmentEventStore {
+    dir: PathBuf,
+    retention: EventRetention,
+    segment_max_events: u64,
+    segment_max_bytes: u64,
+    state: Mutex<SegmentState>,
+}
+
+#[derive(Debug, Default)]
+struct SegmentState {
+    segments: Vec<Segment>,
+    writer: Option<File>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    first_sequence: u64,
+    last_sequence: u64,
+    events: u64,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    sequence: u64,
+    payload: &'a Value,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    sequence: u64,
+    payload: Value,
+}
+
+impl SegmentEventStore {
+    pub fn open(dir: impl Into<PathBuf>, retention: EventRetention) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let Some(first_sequence) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if let Some(segment) = scan_segment(&path, first_sequence)? {
+                segments.push(segment);
+            }
+        }
+        segments.sort_by_key(|segment| segment.first_sequence);
+
+        // Keep segments small relative to the limits so whole-segment
+        // retention stays reasonably close to what was asked for.
+        let segment_max_events = retention
+            .max_events
+            .map(|max| (max / 4).max(1))
+            .unwrap_or(SEGMENT_MAX_EVENTS)
+            .min(SEGMENT_MAX_EVENTS);
+        let segment_max_bytes = retention
+            .max_bytes
+            .map(|max| (max / 4).max(1))
+            .unwrap_or(SEGMENT_MAX_BYTES)
+            .min(SEGMENT_MAX_BYTES);
+
+        let store = Self {
+            dir,
+            retention,
+            segment_max_events,
+            segment_max_bytes,
+            state: Mutex::new(SegmentState {
+                segments,
+                writer: None,
+            }),
+        };
+        {
+            let mut state = store.lock();
+            store.enforce_retention(&mut state);
+        }
+        Ok(store)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SegmentState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn enforce_retention(&self, state: &mut SegmentState) {
+        let now = SystemTime::now();
+        while let Some(oldest) = state.segments.first() {
+            let total_events: u64 = state.segments.iter().map(|segment| segment.events).sum();
+            let total_bytes: u64 = state.segments.iter().map(|segment| segment.bytes).sum();
+
+            let over_events = self
+                .retention
+                .max_events
+                .is_some_and(|max| total_events > max);
+            let over_bytes = self
+                .retention
+                .max_bytes
+                .is_some_and(|max| total_bytes > max);
+            let expired = self.retention.max_age.is_some_and(|max_age| {
+                now.duration_since(oldest.modified)
+                    .is_ok_and(|age| age > max_age)
+            });
+            let active = state.segments.len() == 1;
+            if !(expired || (!active && (over_events || over_bytes))) {
+                break;
+            }
+
+            let removed = state.segments.remove(0);
+            if active {
+                state.writer = None;
+            }
+            if let Err(err) = fs::remove_file(&removed.path) {
+                tracing::warn!(
+                    path = %removed.path.display(),
+                    error = %err,
+                    "event store: failed to remove expired segment"
+                );
+            }
+        }
+    }
+}
+
+impl EventStore for SegmentEventStore {
+    fn append(&self, sequence: u64, payload: &Value) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&RecordRef { sequence, payload })?;
+        line.push(b'\n');
+
+        let mut state = self.lock();
+        // Segments left over from a previous run are never appended to, so a
+        // record torn by a crash cannot corrupt the next one.
+        let roll = state.writer.is_none()
+            || state.segments.last().is_none_or(|segment| {
+                segment.events >= self.segment_max_events || segment.bytes >= self.segment_max_bytes
+            });
+
+        if roll {
+            let path = self.dir.join(format!("{sequence:020}.{SEGMENT_EXTENSION}"));
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            file.write_all(&line)?;
+            state.writer = Some(file);
+            state.segments.push(Segment {
+                path,
+                first_sequence: sequence,
+                last_sequence: sequence,
+                events: 1,
+                bytes: line.len() as u64,
+                modified: SystemTime::now(),
+            });
+            self.enforce_retention(&mut state);
+            return Ok(());
+        }
+
+        state.writer.as_mut().expect("checked").write_all(&line)?;
+
+        let segment = state.segments.last_mut().expect("checked");
+        segment.last_sequence = sequence;
+        segment.events += 1;
+        segment.bytes += line.len() as u64;
+        segment.modified = SystemTime::now();
+        Ok(())
+    }
+
+    fn read_range(&self, after: u64, before: u64) -> io::Result<Vec<(u64, Value)>> {
+        let mut state = self.lock();
+        self.enforce_retention(&mut state);
+        let mut events = Vec::new();
+        for segment in state
+            .segments
+            .iter()
+            .filter(|segment| segment.last_sequence > after && segment.first_sequence < before)
+        {
+            let reader = BufReader::new(File::open(&segment.path)?);
+            for line in reader.lines() {
+                let Ok(record) = serde_json::from_str::<Record>(&line?) else {
+                    continue;
+                };
+                if record.sequence > after && record.sequence < before {
+                    events.push((record.sequence, record.payload));
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    fn last_sequence(&self) -> Option<u64> {
+        self.lock()
+            .segments
+            .last()
+            .map(|segment| segment.last_sequence)
+    }
+
+    fn clear(&self) -> io::Result<()> {
+        let mut state = self.lock();
+        state.writer = None;
+        for segment in state.segments.drain(..) {
+            match fs::remove_file(&segment.path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let _ = fs::remove_dir(&self.dir);
+        Ok(())
+    }
+
+    fn expire(&self) {
+        let mut state = self.lock();
+        self.enforce_retention(&mut state);
+    }
+}
+
+enum WriterCommand {
+    Append(u64, Value),
+    Flush(tokio::sync::oneshot::Sender<()>),
+}
+
+/// Appends to an [`EventStore`] from a dedicated thread so slow disks never
+/// hold up the stream. Holds at most a ring buffer's worth of events; when
+/// the thread falls further behind, events are kept only in memory.
+#[derive(Debug, Clone)]
+pub(crate) struct EventStoreWriter {
+    sender: SyncSender<WriterCommand>,
+}
+
+impl EventStoreWriter {
+    /// The thread exits once every clone of the writer is dropped.
+    pub(crate) fn spawn(store: Arc<dyn EventStore>, capacity: usize) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        std::thread::Builder::new()
+            .name("acp-event-store".to_string())
+            .spawn(move || {
+                let mut last_expired = Instant::now();
+                loop {
+                    match receiver.recv_timeout(RETENTION_INTERVAL) {
+                        Ok(WriterCommand::Append(sequence, payload)) => {
+                            if let Err(err) = store.append(sequence, &payload) {
+                                tracing::warn!(
+                                    sequence = sequence,
+                                    error = %err,
+                                    "event store: append failed"
+                                );
+                            }
+                        }
+                        Ok(WriterCommand::Flush(done)) => {
+                            let _ = done.send(());
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    if last_expired.elapsed() >= RETENTION_INTERVAL {
+                        store.expire();
+                        last_expired = Instant::now();
+                    }
+                }
+            })?;
+        Ok(Self { sender })
+    }
+
+    pub(crate) fn append(&self, sequence: u64, payload: &Value) {
+        match self
+            .sender
+            .try_send(WriterCommand::Append(sequence, payload.clone()))
+        {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!(
+                    sequence = sequence,
+                    "event store: writer is behind, event not persisted"
+                );
+            }
+        }
+    }
+
+    /// Wait until everything appended so far has been written.
+    pub(crate) async fn flush(&self) {
+        let (done, written) = tokio::sync::oneshot::channel();
+        let sender = self.sender.clone();
+        let queued =
+            tokio::task::spawn_blocking(move || sender.send(WriterCommand::Flush(done)).is_ok())
+                .await
+                .unwrap_or(false);
+        if queued {
+            let _ = written.await;
+        }
+    }
+}
+
+/// Read a segment's bounds. Lines that fail to parse (for example a write
+/// torn by a crash) are skipped.
+fn scan_segment(path: &Path, first_sequence: u64) -> io::Result<Option<Segment>> {
+    let metadata = fs::metadata(path)?;
+    let reader = BufReader::new(File::open(path)?);
+    let mut last_sequence = None;
+    let mut events = 0;
+    for line in reader.lines() {
+        if let Ok(record) = serde_json::from_str::<Record>(&line?) {
+            last_sequence = Some(record.sequence);
+            events += 1;
+        }
+    }
+
+    Ok(last_sequence.map(|last_sequence| Segment {
+        path: path.to_path_buf(),
+        first_sequence,
+        last_sequence,
+        events,
+        bytes: metadata.len(),
+        modified: metadata.modified().unwrap_or_else(|_| SystemTime::now()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn append_range(store: &SegmentEventStore, sequences: std::ops::RangeInclusive<u64>) {
+        for sequence in sequences {
+            store
+                .append(sequence, &json!({ "n": sequence }))
+                .expect("append");
+        }
+    }
+
+    #[test]
+    fn reads_ranges_and_reopens() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        {
+            let store =
+                SegmentEventStore::open(dir.path(), EventRetention::default()).expect("open");
+            append_range(&store, 1..=10);
+            let events = store.read_range(3, 7).expect("read");
+            let sequences = events.iter().map(|(seq, _)| *seq).collect::<Vec<_>>();
+            assert_eq!(sequences, vec![4, 5, 6]);
+            assert_eq!(events[0].1["n"], 4);
+        }
+
+        let store = SegmentEventStore::open(dir.path(), EventRetention::default()).expect("open");
+        assert_eq!(store.last_sequence(), Some(10));
+        append_range(&store, 11..=12);
+        assert_eq!(store.read_range(9, u64::MAX).expect("read").len(), 3);
+    }
+
+    #[test]
+    fn retention_drops_oldest_segments() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = SegmentEventStore::open(
+            dir.path(),
+            EventRetention {
+                max_events: Some(8),
+                ..EventRetention::default()
+            },
+        )
+        .expect("open");
+        append_range(&store, 1..=20);
+
+        let events = store.read_range(0, u64::MAX).expect("read");
+        assert!(events.len() <= 8);
+        assert_eq!(events.last().map(|(seq, _)| *seq), Some(20));
+        assert!(events.first().is_some_and(|(seq, _)| *seq > 1));
+
+        store.clear().expect("clear");
+        assert_eq!(store.last_sequence(), None);
+        assert!(!dir.path().exists());
+    }
+
+    #[test]
+    fn max_age_expires_idle_segments_on_read() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let store = SegmentEventStore::open(
+            dir.path(),
+            EventRetention {
+                max_age: Some(Duration::from_millis(50)),
+                ..EventRetention::default()
+            },
+        )
+        .expect("open");
+        append_range(&store, 1..=3);
+        assert_eq!(store.read_range(0, u64::MAX).expect("read").len(), 3);
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(store.read_range(0, u64::MAX).expect("read").is_empty());
+        assert_eq!(store.last_sequence(), None);
+
+        append_range(&store, 4..=4);
+        let events = store.read_range(0, u64::MAX).expect("read");
+        assert_eq!(
+            events.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(),
+            vec![4]
+        );
+    }
+}
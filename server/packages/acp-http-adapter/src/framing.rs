@@ -0,0 +1,203 @@
+//! Message framing on the agent's stdio: newline-delimited JSON or LSP-style
+//! `Content-Length:` headers.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Largest `Content-Length` body accepted from an agent.
+const MAX_FRAME_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Framing {
+    /// One JSON message per line.
+    #[default]
+    Ndjson,
+    /// `Content-Length: N` header block, a blank line, then N bytes of JSON.
+    ContentLength,
+    /// Accept both from the agent. Writes are newline-delimited, and switch
+    /// to Content-Length for good once the agent sends a framed message.
+    Auto,
+}
+
+impl Framing {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "ndjson" => Some(Self::Ndjson),
+            "content-length" | "content_length" => Some(Self::ContentLength),
+            "auto" => Some(Self::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ndjson => "ndjson",
+            Self::ContentLength => "content-length",
+            Self::Auto => "auto",
+        }
+    }
+}
+
+/// Framing state shared by the stdout reader and stdin writers of one
+/// runtime.
+#[derive(Debug)]
+pub(crate) struct StdioFraming {
+    mode: Framing,
+    /// Set once the agent has sent a `Content-Length` framed message.
+    saw_content_length: AtomicBool,
+}
+
+impl StdioFraming {
+    pub(crate) fn new(mode: Framing) -> Self {
+        Self {
+            mode,
+            saw_content_length: AtomicBool::new(false),
+        }
+    }
+
+    /// Serialize `payload` as one framed message.
+    pub(crate) fn encode(&self, payload: &Value) -> Result<Vec<u8>, serde_json::Error> {
+        let body = serde_json::to_vec(payload)?;
+        let content_length = match self.mode {
+            Framing::Ndjson => false,
+            Framing::ContentLength => true,
+            Framing::Auto => self.saw_content_length.load(Ordering::Relaxed),
+        };
+        if !content_length {
+            let mut bytes = body;
+            bytes.push(b'\n');
+            return Ok(bytes);
+        }
+        let mut bytes = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    /// Read the next non-empty message, or `None` at end of stream. Lines
+    /// outside a header block are passed through as-is in every mode, so
+    /// stray output still surfaces as invalid stdout.
+    pub(crate) async fn read_message<R>(&self, reader: &mut R) -> io::Result<Option<String>>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        loop {
+            let Some(line) = read_line(reader).await? else {
+                return Ok(None);
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if self.mode != Framing::Ndjson {
+                if let Some(length) = content_length(line) {
+                    return self.read_body(reader, length?).await;
+                }
+            }
+            return Ok(Some(line.to_string()));
+        }
+    }
+
+    /// Skip the rest of the header block and read the body.
+    async fn read_body<R>(&self, reader: &mut R, length: usize) -> io::Result<Option<String>>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        loop {
+            match read_line(reader).await? {
+                None => return Ok(None),
+                Some(header) if header.trim().is_empty() => break,
+                Some(_) => {}
+            }
+        }
+        let mut body = vec![0; length];
+        match reader.read_exact(&mut body).await {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        self.saw_content_length.store(true, Ordering::Relaxed);
+        Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+    }
+}
+
+async fn read_line<R>(reader: &mut R) -> io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// The length from a `Content-Length` header line, if `line` is one.
+fn content_length(line: &str) -> Option<io::Result<usize>> {
+    let (name, value) = line.split_once(':')?;
+    if !name.trim().eq_ignore_ascii_case("content-length") {
+        return None;
+    }
+    Some(match value.trim().parse::<usize>() {
+        Ok(length) if length <= MAX_FRAME_BYTES => Ok(length),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid Content-Length header: {line}"),
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    async fn read_all(framing: &StdioFraming, input: &str) -> Vec<String> {
+        let mut reader = input.as_bytes();
+        let mut messages = Vec::new();
+        while let Some(message) = framing.read_message(&mut reader).await.expect("read") {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn reads_content_length_frames() {
+        let framing = StdioFraming::new(Framing::ContentLength);
+        let input = "Content-Length: 8\r\nContent-Type: application/json\r\n\r\n{\"a\":1}\nContent-Length: 7\r\n\r\n{\"b\":2}";
+        assert_eq!(
+            read_all(&framing, input).await,
+            vec!["{\"a\":1}\n", "{\"b\":2}"]
+        );
+    }
+
+    #[tokio::test]
+    async fn auto_accepts_both_and_follows_the_agent() {
+        let framing = StdioFraming::new(Framing::Auto);
+        let payload = json!({"x": 1});
+        assert_eq!(framing.encode(&payload).expect("encode"), b"{\"x\":1}\n");
+
+        let input = "{\"a\":1}\n\ncontent-length: 7\r\n\r\n{\"b\":2}";
+        assert_eq!(
+            read_all(&framing, input).await,
+            vec!["{\"a\":1}", "{\"b\":2}"]
+        );
+        assert_eq!(
+            framing.encode(&payload).expect("encode"),
+            b"Content-Length: 7\r\n\r\n{\"x\":1}"
+        );
+    }
+
+    #[tokio::test]
+    async fn ndjson_passes_headers_through() {
+        let framing = StdioFraming::new(Framing::Ndjson);
+        assert_eq!(
+            read_all(&framing, "Content-Length: 2\r\n\r\n{}").await,
+            vec!["Content-Length: 2", "{}"]
+        );
+    }
+}
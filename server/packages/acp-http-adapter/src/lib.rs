@@ -7,8 +7,14 @@ use process::AdapterRuntime;
 use registry::LaunchSpec;
 
 pub mod app;
+mod client_requests;
+pub mod event_store;
+pub mod framing;
+pub mod limits;
 pub mod process;
 pub mod registry;
+pub mod stderr_log;
+pub mod transcript;
 
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
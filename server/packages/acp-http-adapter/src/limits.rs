@@ -0,0 +1,351 @@
+//! Resource limits for agent processes. Limits are enforced with a cgroup v2
+//! child group when one can be created, and with rlimits otherwise.
+
+use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::process::Command;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// Memory ceiling in bytes: `memory.max`, or `RLIMIT_DATA` without
+    /// cgroups.
+    pub memory_bytes: Option<u64>,
+    /// CPU bandwidth in cores (`cpu.max`). There is no rlimit equivalent, so
+    /// this is only enforced with cgroups.
+    pub cpu_cores: Option<f64>,
+    /// Processes and threads: `pids.max`, or `RLIMIT_NPROC` (which counts
+    /// every process of the user) without cgroups.
+    pub max_pids: Option<u64>,
+    /// Open file descriptors (`RLIMIT_NOFILE`).
+    pub open_files: Option<u64>,
+    /// Delegated cgroup v2 directory to create agent groups under. It must not
+    /// hold processes itself. Defaults to the adapter's own cgroup when the
+    /// adapter is the only process in it, after moving the adapter into an
+    /// `adapter` leaf below it.
+    pub cgroup_parent: Option<PathBuf>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory_bytes.is_none()
+            && self.cpu_cores.is_none()
+            && self.max_pids.is_none()
+            && self.open_files.is_none()
+    }
+
+    fn needs_cgroup(&self) -> bool {
+        self.memory_bytes.is_some() || self.cpu_cores.is_some() || self.max_pids.is_some()
+    }
+}
+
+/// Applies one runtime's limits to every process it spawns, including
+/// restarts. Dropping it removes the cgroup.
+#[derive(Debug)]
+pub(crate) struct LimitEnforcer {
+    limits: ResourceLimits,
+    #[cfg(target_os = "linux")]
+    cgroup: Option<cgroup::Cgroup>,
+}
+
+impl LimitEnforcer {
+    pub(crate) fn new(limits: ResourceLimits) -> Self {
+        #[cfg(target_os = "linux")]
+        let cgroup = if limits.needs_cgroup() {
+            match cgroup::Cgroup::create(&limits) {
+                Ok(cgroup) => Some(cgroup),
+                Err(err) => {
+                    tracing::warn!(
+                        error = %err,
+                        "agent limits: cgroup v2 unavailable, falling back to rlimits; \
+                         set a delegated cgroup parent to enforce them"
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let enforcer = Self {
+            limits,
+            #[cfg(target_os = "linux")]
+            cgroup,
+        };
+        if enforcer.limits.cpu_cores.is_some() && !enforcer.has_cgroup() {
+            tracing::warn!("agent limits: CPU limit needs cgroup v2 and is not enforced");
+        }
+        enforcer
+    }
+
+    fn has_cgroup(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            self.cgroup.is_some()
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            false
+        }
+    }
+
+    /// Arrange for the spawned process to start inside the limits.
+    pub(crate) fn apply(&self, command: &mut Command) {
+        if self.limits.is_empty() {
+            return;
+        }
+
+        #[cfg(unix)]
+        {
+            let mut rlimits = Vec::new();
+            if let Some(open_files) = self.limits.open_files {
+                rlimits.push((libc::RLIMIT_NOFILE, open_files));
+            }
+            if !self.has_cgroup() {
+                if let Some(memory) = self.limits.memory_bytes {
+                    rlimits.push((libc::RLIMIT_DATA, memory));
+                }
+                if let Some(pids) = self.limits.max_pids {
+                    rlimits.push((libc::RLIMIT_NPROC, pids));
+                }
+            }
+            #[cfg(target_os = "linux")]
+            let procs_fd = self.cgroup.as_ref().map(|cgroup| cgroup.procs_fd());
+            #[cfg(not(target_os = "linux"))]
+            let procs_fd: Option<i32> = None;
+
+            // Only async-signal-safe calls are allowed between fork and exec.
+            unsafe {
+                command.pre_exec(move || {
+                    if let Some(fd) = procs_fd {
+                        if libc::write(fd, b"0".as_ptr().cast(), 1) != 1 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    for &(resource, value) in &rlimits {
+                        let limit = libc::rlimit {
+                            rlim_cur: value as libc::rlim_t,
+                            rlim_max: value as libc::rlim_t,
+                        };
+                        if libc::setrlimit(resource, &limit) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    /// Why the last process exited, when a limit was breached: `"oom"` or
+    /// `"pids_limit"`. Only cgroups report breaches.
+    pub(crate) fn exit_reason(&self) -> Option<&'static str> {
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = &self.cgroup {
+            return cgroup.breach();
+        }
+        None
+    }
+
+    /// Kill everything left in the cgroup, including detached descendants.
+    pub(crate) fn kill_all(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.kill_all();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod cgroup {
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use super::{AtomicU64, Ordering, ResourceLimits};
+
+    const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+    const CPU_PERIOD_US: u64 = 100_000;
+
+    static NEXT_GROUP: AtomicU64 = AtomicU64::new(1);
+    /// The adapter's original cgroup once its processes have been moved out.
+    static DEFAULT_PARENT: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+    #[derive(Debug)]
+    pub(super) struct Cgroup {
+        dir: PathBuf,
+        procs: File,
+        oom_kills: AtomicU64,
+        pids_max_hits: AtomicU64,
+    }
+
+    impl Cgroup {
+        pub(super) fn create(limits: &ResourceLimits) -> std::io::Result<Self> {
+            let parent = match &limits.cgroup_parent {
+                Some(parent) => {
+                    check_cgroup_dir(parent)?;
+                    parent.clone()
+                }
+                None => default_parent()?,
+            };
+
+            let mut controllers = Vec::new();
+            if limits.memory_bytes.is_some() {
+                controllers.push("+memory");
+            }
+            if limits.cpu_cores.is_some() {
+                controllers.push("+cpu");
+            }
+            if limits.max_pids.is_some() {
+                controllers.push("+pids");
+            }
+            std::fs::write(parent.join("cgroup.subtree_control"), controllers.join(" "))?;
+
+            let dir = parent.join(format!(
+                "acp-{}-{}",
+                std::process::id(),
+                NEXT_GROUP.fetch_add(1, Ordering::SeqCst)
+            ));
+            std::fs::create_dir(&dir)?;
+            let cgroup = Self {
+                procs: File::options()
+                    .write(true)
+                    .open(dir.join("cgroup.procs"))
+                    .inspect_err(|_| {
+                        let _ = std::fs::remove_dir(&dir);
+                    })?,
+                dir,
+                oom_kills: AtomicU64::new(0),
+                pids_max_hits: AtomicU64::new(0),
+            };
+
+            if let Some(memory) = limits.memory_bytes {
+                cgroup.write("memory.max", &memory.to_string())?;
+                // Without this, the kernel swaps the agent instead of killing
+                // it and the breach is never reported.
+                let _ = cgroup.write("memory.swap.max", "0");
+            }
+            if let Some(cores) = limits.cpu_cores {
+                let quota = ((cores * CPU_PERIOD_US as f64) as u64).max(1_000);
+                cgroup.write("cpu.max", &format!("{quota} {CPU_PERIOD_US}"))?;
+            }
+            if let Some(pids) = limits.max_pids {
+                cgroup.write("pids.max", &pids.to_string())?;
+            }
+
+            tracing::info!(cgroup = %cgroup.dir.display(), "agent limits: created cgroup");
+            Ok(cgroup)
+        }
+
+        pub(super) fn procs_fd(&self) -> i32 {
+            self.procs.as_raw_fd()
+        }
+
+        /// Compare breach counters against the last check.
+        pub(super) fn breach(&self) -> Option<&'static str> {
+            let oom_kills = self.event_count("memory.events", "oom_kill");
+            if oom_kills > self.oom_kills.swap(oom_kills, Ordering::SeqCst) {
+                return Some("oom");
+            }
+            let pids_max_hits = self.event_count("pids.events", "max");
+            if pids_max_hits > self.pids_max_hits.swap(pids_max_hits, Ordering::SeqCst) {
+                return Some("pids_limit");
+            }
+            None
+        }
+
+        pub(super) fn kill_all(&self) {
+            let _ = self.write("cgroup.kill", "1");
+        }
+
+        fn write(&self, file: &str, value: &str) -> std::io::Result<()> {
+            std::fs::write(self.dir.join(file), value)
+        }
+
+        fn event_count(&self, file: &str, key: &str) -> u64 {
+            std::fs::read_to_string(self.dir.join(file))
+                .ok()
+                .and_then(|events| {
+                    events.lines().find_map(|line| {
+                        let (name, count) = line.split_once(' ')?;
+                        (name == key).then(|| count.trim().parse().ok())?
+                    })
+                })
+                .unwrap_or(0)
+        }
+    }
+
+    impl Drop for Cgroup {
+        fn drop(&mut self) {
+            self.kill_all();
+            if let Err(err) = std::fs::remove_dir(&self.dir) {
+                tracing::debug!(
+                    cgroup = %self.dir.display(),
+                    error = %err,
+                    "agent limits: failed to remove cgroup"
+                );
+            }
+        }
+    }
+
+    /// The adapter's own cgroup, emptied so controllers can be enabled for its
+    /// children: cgroup v2 refuses `subtree_control` on a group that holds
+    /// processes, so the adapter moves itself into an `adapter` leaf first.
+    /// Only a group the adapter has to itself is used; if it shares it with
+    /// other processes, such as a container's init, they are left alone and
+    /// this fails.
+    fn default_parent() -> std::io::Result<PathBuf> {
+        let mut cached = DEFAULT_PARENT.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(parent) = cached.as_ref() {
+            return Ok(parent.clone());
+        }
+
+        let parent = own_cgroup()?;
+        check_cgroup_dir(&parent)?;
+        let own_pid = std::process::id().to_string();
+        let procs = std::fs::read_to_string(parent.join("cgroup.procs"))?;
+        if procs.lines().any(|pid| !pid.is_empty() && pid != own_pid) {
+            return Err(std::io::Error::other(format!(
+                "{} holds processes besides the adapter",
+                parent.display()
+            )));
+        }
+        let leaf = parent.join("adapter");
+        match std::fs::create_dir(&leaf) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+        // Writing the process id moves all of its threads.
+        std::fs::write(leaf.join("cgroup.procs"), &own_pid)?;
+        tracing::info!(cgroup = %leaf.display(), "agent limits: moved adapter into leaf cgroup");
+
+        *cached = Some(parent.clone());
+        Ok(parent)
+    }
+
+    fn check_cgroup_dir(dir: &Path) -> std::io::Result<()> {
+        if dir.join("cgroup.controllers").is_file() {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is not a cgroup v2 directory", dir.display()),
+            ))
+        }
+    }
+
+    /// The adapter's cgroup v2 directory, from `/proc/self/cgroup`.
+    fn own_cgroup() -> std::io::Result<PathBuf> {
+        let membership = std::fs::read_to_string("/proc/self/cgroup")?;
+        let relative = membership
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "no cgroup v2 membership")
+            })?;
+        Ok(Path::new(CGROUP_ROOT).join(relative.trim_start_matches('/')))
+    }
+}
@@ -1,21 +1,36 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::Infallible;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
 use std::time::{Duration, Instant};
 
 use axum::response::sse::Event;
+use futures::future::BoxFuture;
 use futures::{stream, Stream, StreamExt};
 use serde_json::{json, Value};
 use thiserror::Error;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{Child, ChildStdin, Command};
-use tokio::sync::{broadcast, oneshot, Mutex};
-use tokio_stream::wrappers::BroadcastStream;
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::sync::{broadcast, oneshot, watch, Mutex};
 
+use crate::client_requests::{Claim, ClientRequestTracker};
+use crate::event_store::{EventStore, EventStoreWriter};
+use crate::framing::StdioFraming;
+use crate::limits::{LimitEnforcer, ResourceLimits};
 use crate::registry::LaunchSpec;
+use crate::stderr_log::{StderrFileConfig, StderrLine, StderrLog};
+use crate::transcript::{TranscriptDirection, TranscriptWriter};
 
 const RING_BUFFER_SIZE: usize = 1024;
+const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+/// How long to wait for a SIGKILLed process group to disappear.
+const SIGKILL_SETTLE: Duration = Duration::from_secs(1);
+/// How long a failed request waits for the dead agent's remaining stderr.
+const STDERR_DRAIN_TIMEOUT: Duration = Duration::from_millis(250);
+
+const INVALID_REQUEST: i64 = -32600;
+const INTERNAL_ERROR: i64 = -32603;
 
 #[derive(Debug, Error)]
 pub enum AdapterError {
@@ -33,8 +48,35 @@ pub enum AdapterError {
     Serialize(serde_json::Error),
     #[error("failed to write subprocess stdin: {0}")]
     Write(std::io::Error),
-    #[error("timeout waiting for response")]
-    Timeout,
+    #[error("timed out after {} ms waiting for response to '{method}'", elapsed.as_millis())]
+    Timeout {
+        method: String,
+        elapsed: Duration,
+        /// Whether a cancellation was sent to the agent.
+        cancelled: bool,
+    },
+    #[error("agent process exited before responding")]
+    AgentExited { code: Option<i32> },
+    #[error(
+        "'{method}' request {id} was already answered by {}",
+        resolved_by.as_deref().map_or_else(|| "another client".to_string(), |by| format!("'{by}'"))
+    )]
+    AlreadyResolved {
+        id: Value,
+        method: String,
+        resolved_by: Option<String>,
+    },
+}
+
+/// Per-request settings for [`AdapterRuntime::post_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct PostOptions {
+    /// Overrides the per-method and runtime-wide request timeouts.
+    pub timeout: Option<Duration>,
+    /// Overrides [`RuntimeOptions::cancel_on_timeout`].
+    pub cancel_on_timeout: Option<bool>,
+    /// Identifies the caller when it answers an agent-to-client request.
+    pub client_id: Option<String>,
 }
 
 #[derive(Debug)]
@@ -43,24 +85,265 @@ pub enum PostOutcome {
     Accepted,
 }
 
+/// When a supervised agent process should be respawned after it exits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestartMode {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl RestartMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "never" => Some(Self::Never),
+            "on-failure" | "on_failure" => Some(Self::OnFailure),
+            "always" => Some(Self::Always),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Never => "never",
+            Self::OnFailure => "on-failure",
+            Self::Always => "always",
+        }
+    }
+}
+
+/// Lifecycle of the runtime's agent process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    /// Exited and waiting for the restart policy to respawn it.
+    Restarting,
+    /// Exited for good, or shut down.
+    Exited,
+}
+
+impl ProcessState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Restarting => "restarting",
+            Self::Exited => "exited",
+        }
+    }
+}
+
+/// Restart policy for the agent process. Backoff doubles from
+/// `initial_backoff` up to `max_backoff`; the attempt counter resets once a
+/// process has stayed up for at least `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            mode: RestartMode::Never,
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RestartPolicy {
+    fn allows(&self, success: bool) -> bool {
+        match self.mode {
+            RestartMode::Never => false,
+            RestartMode::OnFailure => !success,
+            RestartMode::Always => true,
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeOptions {
+    pub request_timeout: Duration,
+    /// Request timeouts for specific methods, in place of `request_timeout`.
+    pub method_timeouts: HashMap<String, Duration>,
+    /// Cancel requests that time out, as if the caller had disconnected.
+    pub cancel_on_timeout: bool,
+    pub restart: RestartPolicy,
+    /// Durable log behind the in-memory ring. When set, replays can reach
+    /// past the ring and sequence numbers continue from the stored history.
+    pub event_store: Option<Arc<dyn EventStore>>,
+    /// Answer agent-to-client requests in-process instead of broadcasting
+    /// them to subscribers. The first handler serving a method wins.
+    pub client_handlers: Vec<Arc<dyn ClientRequestHandler>>,
+    /// Limits applied to the agent process and everything it starts.
+    pub limits: ResourceLimits,
+    /// How long the agent's process group gets between SIGTERM and SIGKILL
+    /// on shutdown.
+    pub shutdown_grace: Duration,
+    /// Mirror agent stderr to a file in addition to the in-memory ring.
+    pub stderr_file: Option<StderrFileConfig>,
+    /// Append every JSON-RPC message on the agent's stdio to this JSONL
+    /// file; see [`TranscriptEntry`](crate::transcript::TranscriptEntry).
+    pub transcript: Option<PathBuf>,
+}
+
+impl RuntimeOptions {
+    pub fn new(request_timeout: Duration) -> Self {
+        Self {
+            request_timeout,
+            method_timeouts: HashMap::new(),
+            cancel_on_timeout: false,
+            restart: RestartPolicy::default(),
+            event_store: None,
+            client_handlers: Vec::new(),
+            limits: ResourceLimits::default(),
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            stderr_file: None,
+            transcript: None,
+        }
+    }
+}
+
+/// Serves agent-to-client requests (for example `fs/read_text_file`) on
+/// behalf of connected clients. The result is written back to the agent
+/// and the request is never broadcast.
+pub trait ClientRequestHandler: Send + Sync + std::fmt::Debug {
+    /// Returns `None` for requests this handler does not serve. `stream`
+    /// lets the handler record what it did for subscribers.
+    fn handle(
+        &self,
+        method: &str,
+        params: &Value,
+        stream: &StreamPublisher,
+    ) -> Option<BoxFuture<'static, Result<Value, ClientRequestError>>>;
+}
+
+/// Injects adapter-generated messages into a runtime's stream; see
+/// [`AdapterRuntime::publish`].
+#[derive(Debug, Clone)]
+pub struct StreamPublisher {
+    sink: OutputSink,
+}
+
+impl StreamPublisher {
+    pub async fn publish(&self, payload: Value) -> u64 {
+        self.sink.push(payload).await
+    }
+}
+
+/// JSON-RPC error returned to the agent by a [`ClientRequestHandler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientRequestError {
+    pub code: i64,
+    pub message: String,
+}
+
 #[derive(Debug, Clone)]
 struct StreamMessage {
     sequence: u64,
     payload: Value,
 }
 
+/// Messages to send a new subscriber before live output. `gap` is set when
+/// part of the requested history is no longer retained.
+struct Replay {
+    gap: Option<Value>,
+    messages: Vec<(u64, Value)>,
+    /// Last sequence published when the subscription started. Live output
+    /// starts after it.
+    position: u64,
+}
+
+/// Slow-subscriber totals across every stream of a runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LagStats {
+    /// Times a subscriber fell behind the live broadcast.
+    pub lag_events: u64,
+    /// Messages those subscribers missed live (most are re-fed from the
+    /// replay buffer or event store).
+    pub missed_messages: u64,
+}
+
+/// One consumer of [`AdapterRuntime::event_stream`]. Holds the runtime
+/// weakly so the stream ends when the runtime is dropped.
+struct Subscription {
+    id: u64,
+    runtime: Weak<AdapterRuntime>,
+    queue: VecDeque<(Option<u64>, Value)>,
+    receiver: broadcast::Receiver<StreamMessage>,
+    last_sequence: u64,
+}
+
 #[derive(Debug)]
 pub struct AdapterRuntime {
     stdin: Arc<Mutex<ChildStdin>>,
-    child: Arc<Mutex<Child>>,
+    framing: Arc<StdioFraming>,
+    transcript: Option<Arc<TranscriptWriter>>,
+    kill: Mutex<Option<oneshot::Sender<()>>>,
+    exited: watch::Receiver<bool>,
     pending: Arc<Mutex<HashMap<String, oneshot::Sender<Value>>>>,
+    /// Agent-to-client requests broadcast to subscribers.
+    client_requests: Arc<ClientRequestTracker>,
     sender: broadcast::Sender<StreamMessage>,
     ring: Arc<Mutex<VecDeque<StreamMessage>>>,
     sequence: Arc<AtomicU64>,
+    store: Option<Arc<dyn EventStore>>,
+    store_writer: Option<EventStoreWriter>,
+    client_handlers: Vec<Arc<dyn ClientRequestHandler>>,
     request_timeout: Duration,
+    method_timeouts: HashMap<String, Duration>,
+    cancel_on_timeout: bool,
+    shutdown_grace: Duration,
     shutting_down: AtomicBool,
     spawned_at: Instant,
-    first_stdout: Arc<AtomicBool>,
+    /// How long spawning the first agent process took.
+    spawn_duration: Duration,
+    /// Time from spawn to the first stdout message.
+    first_stdout: Arc<OnceLock<Duration>>,
+    restarts: Arc<AtomicU32>,
+    /// Pid of the running agent process, or 0 between processes.
+    pid: Arc<AtomicU32>,
+    stderr: Arc<StderrLog>,
+    /// Exit code of the most recent agent process, once it has exited.
+    last_exit_code: Arc<Mutex<Option<i32>>>,
+    next_subscriber: AtomicU64,
+    lag_events: AtomicU64,
+    missed_messages: AtomicU64,
+}
+
+/// Handles shared by the tasks that publish agent output.
+#[derive(Debug, Clone)]
+struct OutputSink {
+    stdin: Arc<Mutex<ChildStdin>>,
+    framing: Arc<StdioFraming>,
+    transcript: Option<Arc<TranscriptWriter>>,
+    client_handlers: Vec<Arc<dyn ClientRequestHandler>>,
+    pending: Arc<Mutex<HashMap<String, oneshot::Sender<Value>>>>,
+    client_requests: Arc<ClientRequestTracker>,
+    sender: broadcast::Sender<StreamMessage>,
+    ring: Arc<Mutex<VecDeque<StreamMessage>>>,
+    sequence: Arc<AtomicU64>,
+    store_writer: Option<EventStoreWriter>,
+    spawned_at: Instant,
+    first_stdout: Arc<OnceLock<Duration>>,
+}
+
+struct SpawnedProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    stderr: ChildStderr,
 }
 
 impl AdapterRuntime {
@@ -68,72 +351,247 @@ impl AdapterRuntime {
         launch: LaunchSpec,
         request_timeout: Duration,
     ) -> Result<Self, AdapterError> {
-        let spawn_start = Instant::now();
-
-        let mut command = Command::new(&launch.program);
-        command
-            .args(&launch.args)
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped());
-
-        for (key, value) in &launch.env {
-            command.env(key, value);
-        }
-
-        tracing::info!(
-            program = ?launch.program,
-            args = ?launch.args,
-            "spawning agent process"
-        );
-
-        let mut child = command.spawn().map_err(|err| {
-            tracing::error!(
-                program = ?launch.program,
-                error = %err,
-                "failed to spawn agent process"
-            );
-            AdapterError::Spawn(err)
-        })?;
-
-        let pid = child.id().unwrap_or(0);
-        let spawn_elapsed = spawn_start.elapsed();
-        tracing::info!(
-            pid = pid,
-            elapsed_ms = spawn_elapsed.as_millis() as u64,
-            "agent process spawned"
-        );
+        Self::start_with_options(launch, RuntimeOptions::new(request_timeout)).await
+    }
 
-        let stdin = child.stdin.take().ok_or(AdapterError::MissingStdin)?;
-        let stdout = child.stdout.take().ok_or(AdapterError::MissingStdout)?;
-        let stderr = child.stderr.take().ok_or(AdapterError::MissingStderr)?;
+    pub async fn start_with_options(
+        launch: LaunchSpec,
+        options: RuntimeOptions,
+    ) -> Result<Self, AdapterError> {
+        let store_writer = match &options.event_store {
+            Some(store) => Some(
+                EventStoreWriter::spawn(store.clone(), RING_BUFFER_SIZE)
+                    .map_err(AdapterError::Spawn)?,
+            ),
+            None => None,
+        };
+        let spawn_start = Instant::now();
+        let limits = LimitEnforcer::new(options.limits);
+        let process = spawn_process(&launch, &limits)?;
+        let spawn_duration = spawn_start.elapsed();
+        let last_sequence = options
+            .event_store
+            .as_ref()
+            .and_then(|store| store.last_sequence())
+            .unwrap_or(0);
 
         let (sender, _rx) = broadcast::channel(512);
+        let (kill_tx, kill_rx) = oneshot::channel();
+        let (exited_tx, exited_rx) = watch::channel(false);
         let runtime = Self {
-            stdin: Arc::new(Mutex::new(stdin)),
-            child: Arc::new(Mutex::new(child)),
+            stdin: Arc::new(Mutex::new(process.stdin)),
+            framing: Arc::new(StdioFraming::new(launch.framing)),
+            transcript: options
+                .transcript
+                .map(|path| Arc::new(TranscriptWriter::new(path))),
+            kill: Mutex::new(Some(kill_tx)),
+            exited: exited_rx,
             pending: Arc::new(Mutex::new(HashMap::new())),
+            client_requests: Arc::new(ClientRequestTracker::default()),
             sender,
             ring: Arc::new(Mutex::new(VecDeque::with_capacity(RING_BUFFER_SIZE))),
-            sequence: Arc::new(AtomicU64::new(0)),
-            request_timeout,
+            sequence: Arc::new(AtomicU64::new(last_sequence)),
+            store: options.event_store,
+            store_writer,
+            client_handlers: options.client_handlers,
+            request_timeout: options.request_timeout,
+            method_timeouts: options.method_timeouts,
+            cancel_on_timeout: options.cancel_on_timeout,
+            shutdown_grace: options.shutdown_grace,
             shutting_down: AtomicBool::new(false),
             spawned_at: spawn_start,
-            first_stdout: Arc::new(AtomicBool::new(false)),
+            spawn_duration,
+            first_stdout: Arc::new(OnceLock::new()),
+            restarts: Arc::new(AtomicU32::new(0)),
+            pid: Arc::new(AtomicU32::new(process.child.id().unwrap_or(0))),
+            stderr: Arc::new(StderrLog::new(options.stderr_file)),
+            last_exit_code: Arc::new(Mutex::new(None)),
+            next_subscriber: AtomicU64::new(1),
+            lag_events: AtomicU64::new(0),
+            missed_messages: AtomicU64::new(0),
         };
 
-        runtime.spawn_stdout_loop(stdout);
-        runtime.spawn_stderr_loop(stderr);
-        runtime.spawn_exit_watcher();
+        spawn_stdout_loop(runtime.sink(), process.stdout);
+        spawn_stderr_loop(runtime.stderr.clone(), process.stderr);
+        runtime.spawn_supervisor(
+            launch,
+            options.restart,
+            limits,
+            process.child,
+            kill_rx,
+            exited_tx,
+        );
 
         Ok(runtime)
     }
 
+    /// Number of times the agent process has been respawned.
+    pub fn restart_count(&self) -> u32 {
+        self.restarts.load(Ordering::SeqCst)
+    }
+
+    /// Pid of the agent process, while one is running.
+    pub fn pid(&self) -> Option<u32> {
+        Some(self.pid.load(Ordering::SeqCst)).filter(|pid| *pid != 0)
+    }
+
+    pub fn process_state(&self) -> ProcessState {
+        if *self.exited.borrow() {
+            ProcessState::Exited
+        } else if self.pid().is_some() {
+            ProcessState::Running
+        } else {
+            ProcessState::Restarting
+        }
+    }
+
+    /// Requests waiting for the agent's response.
+    pub async fn pending_count(&self) -> usize {
+        self.pending.lock().await.len()
+    }
+
+    /// Live [`Self::event_stream`] subscribers.
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// How long spawning the first agent process took.
+    pub fn spawn_duration(&self) -> Duration {
+        self.spawn_duration
+    }
+
+    /// Time from spawn to the agent's first stdout message, once it arrived.
+    pub fn first_stdout_latency(&self) -> Option<Duration> {
+        self.first_stdout.get().copied()
+    }
+
+    pub fn lag_stats(&self) -> LagStats {
+        LagStats {
+            lag_events: self.lag_events.load(Ordering::SeqCst),
+            missed_messages: self.missed_messages.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Send a JSON-RPC message or batch to the agent. Requests wait for the
+    /// agent's response; notifications and client responses are accepted
+    /// without one.
     pub async fn post(&self, payload: Value) -> Result<PostOutcome, AdapterError> {
-        if !payload.is_object() {
-            return Err(AdapterError::InvalidEnvelope);
+        self.post_with_options(payload, &PostOptions::default())
+            .await
+    }
+
+    /// [`Self::post`] with a per-request timeout. In a batch the options
+    /// apply to every element.
+    pub async fn post_with_options(
+        &self,
+        payload: Value,
+        options: &PostOptions,
+    ) -> Result<PostOutcome, AdapterError> {
+        match payload {
+            Value::Array(batch) => Ok(self.post_batch(batch, options).await),
+            payload if payload.is_object() => self.post_message(payload, options).await,
+            _ => Err(AdapterError::InvalidEnvelope),
+        }
+    }
+
+    /// The timeout for one request: the caller's, else the method's, else
+    /// the runtime-wide default.
+    fn request_timeout_for(&self, method: &str, options: &PostOptions) -> Duration {
+        options
+            .timeout
+            .or_else(|| self.method_timeouts.get(method).copied())
+            .unwrap_or(self.request_timeout)
+    }
+
+    /// Dispatch a batch. Elements are written to the agent one at a time in
+    /// batch order, and the requests' responses are then awaited
+    /// concurrently. Responses are returned as an array in batch order; a
+    /// batch without requests is accepted with no response, as JSON-RPC 2.0
+    /// specifies. Failures become per-element error responses.
+    async fn post_batch(&self, batch: Vec<Value>, options: &PostOptions) -> PostOutcome {
+        if batch.is_empty() {
+            return PostOutcome::Response(error_response(
+                Value::Null,
+                INVALID_REQUEST,
+                "empty batch",
+            ));
+        }
+
+        let mut ids = HashSet::new();
+        let mut slots = Vec::with_capacity(batch.len());
+        for element in batch {
+            let id = element
+                .get("id")
+                .filter(|_| element.get("method").is_some())
+                .cloned();
+            // Responses are correlated by id, so two requests in flight
+            // with the same id cannot both be answered.
+            let duplicate = id.as_ref().is_some_and(|id| !ids.insert(id_key(id)));
+            let slot = if !element.is_object() {
+                BatchSlot::Done(Some(error_response(
+                    Value::Null,
+                    INVALID_REQUEST,
+                    "batch element must be a JSON-RPC object",
+                )))
+            } else if let Some(id) = id {
+                if duplicate {
+                    BatchSlot::Done(Some(error_response(
+                        id,
+                        INVALID_REQUEST,
+                        "duplicate request id in batch",
+                    )))
+                } else {
+                    match self.write_request(&element).await {
+                        Ok(request) => BatchSlot::Awaiting(id, Box::new(request)),
+                        Err(err) => BatchSlot::Done(Some(error_response(
+                            id,
+                            INTERNAL_ERROR,
+                            &err.to_string(),
+                        ))),
+                    }
+                }
+            } else {
+                BatchSlot::Done(match self.post_message(element, options).await {
+                    Ok(PostOutcome::Response(response)) => Some(response),
+                    Ok(PostOutcome::Accepted) => None,
+                    Err(err) => {
+                        tracing::warn!(error = %err, "post: batch notification failed");
+                        None
+                    }
+                })
+            };
+            slots.push(slot);
+        }
+
+        let replies = slots.into_iter().map(|slot| async move {
+            match slot {
+                BatchSlot::Done(reply) => reply,
+                BatchSlot::Awaiting(id, request) => {
+                    match self.await_response(*request, options).await {
+                        Ok(PostOutcome::Response(response)) => Some(response),
+                        Ok(PostOutcome::Accepted) => None,
+                        Err(err) => Some(error_response(id, INTERNAL_ERROR, &err.to_string())),
+                    }
+                }
+            }
+        });
+        let responses = futures::future::join_all(replies)
+            .await
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        if responses.is_empty() {
+            PostOutcome::Accepted
+        } else {
+            PostOutcome::Response(Value::Array(responses))
         }
+    }
 
+    async fn post_message(
+        &self,
+        payload: Value,
+        options: &PostOptions,
+    ) -> Result<PostOutcome, AdapterError> {
         let method: String = payload
             .get("method")
             .and_then(|v| v.as_str())
@@ -143,95 +601,267 @@ impl AdapterRuntime {
         let id = payload.get("id");
 
         if has_method && id.is_some() {
-            let id_value = id.expect("checked");
-            let key = id_key(id_value);
-            let (tx, rx) = oneshot::channel();
+            let request = self.write_request(&payload).await?;
+            self.await_response(request, options).await
+        } else if let (false, Some(id)) = (has_method, id) {
+            self.post_client_response(id.clone(), &payload, options)
+                .await
+        } else {
+            tracing::debug!(
+                method = %method,
+                "post: notification → agent (fire-and-forget)"
+            );
+            self.send_to_subprocess(&payload).await?;
+            Ok(PostOutcome::Accepted)
+        }
+    }
+
+    /// Register a request as pending and write it to the agent.
+    async fn write_request(&self, payload: &Value) -> Result<InFlightRequest, AdapterError> {
+        let method = payload
+            .get("method")
+            .and_then(|v| v.as_str())
+            .unwrap_or("<none>")
+            .to_string();
+        let id_value = payload.get("id").cloned().unwrap_or(Value::Null);
+        let key = id_key(&id_value);
+        let (tx, rx) = oneshot::channel();
+
+        let pending_count = self.pending.lock().await.len();
+        tracing::info!(
+            method = %method,
+            id = %key,
+            pending_count = pending_count,
+            "post: request → agent (awaiting response)"
+        );
 
-            let pending_count = self.pending.lock().await.len();
-            tracing::info!(
+        self.pending.lock().await.insert(key.clone(), tx);
+        let mut cancel = CancelOnDrop {
+            sink: Some(self.sink()),
+            key: key.clone(),
+            id: id_value,
+            method: method.clone(),
+            session_id: payload.pointer("/params/sessionId").cloned(),
+        };
+
+        let write_start = Instant::now();
+        if let Err(err) = self.send_to_subprocess(payload).await {
+            cancel.disarm();
+            tracing::error!(
                 method = %method,
                 id = %key,
-                pending_count = pending_count,
-                "post: request → agent (awaiting response)"
+                error = %err,
+                "post: failed to write to agent stdin"
             );
+            self.pending.lock().await.remove(&key);
+            return Err(err);
+        }
+        let write_ms = write_start.elapsed().as_millis() as u64;
+        tracing::debug!(
+            method = %method,
+            id = %key,
+            write_ms = write_ms,
+            "post: stdin write complete, waiting for response"
+        );
+
+        Ok(InFlightRequest {
+            rx,
+            cancel,
+            write_start,
+            write_ms,
+        })
+    }
 
-            self.pending.lock().await.insert(key.clone(), tx);
+    /// Wait for the agent's response to a request written by
+    /// [`Self::write_request`], up to the request's timeout.
+    async fn await_response(
+        &self,
+        request: InFlightRequest,
+        options: &PostOptions,
+    ) -> Result<PostOutcome, AdapterError> {
+        let InFlightRequest {
+            rx,
+            mut cancel,
+            write_start,
+            write_ms,
+        } = request;
+        let key = cancel.key.clone();
+        let method = cancel.method.clone();
 
-            let write_start = Instant::now();
-            if let Err(err) = self.send_to_subprocess(&payload).await {
+        let timeout = self.request_timeout_for(&method, options);
+        let wait_start = Instant::now();
+        let outcome = tokio::time::timeout(timeout, rx).await;
+        cancel.disarm();
+        match outcome {
+            Ok(Ok(response)) => {
+                let wait_ms = wait_start.elapsed().as_millis() as u64;
+                tracing::info!(
+                    method = %method,
+                    id = %key,
+                    response_ms = wait_ms,
+                    total_ms = write_ms + wait_ms,
+                    "post: got response from agent"
+                );
+                Ok(PostOutcome::Response(response))
+            }
+            Ok(Err(_)) => {
+                let wait_ms = wait_start.elapsed().as_millis() as u64;
                 tracing::error!(
                     method = %method,
                     id = %key,
-                    error = %err,
-                    "post: failed to write to agent stdin"
+                    wait_ms = wait_ms,
+                    "post: response channel dropped (agent process may have exited)"
                 );
                 self.pending.lock().await.remove(&key);
-                return Err(err);
+                self.stderr.drain(STDERR_DRAIN_TIMEOUT).await;
+                Err(AdapterError::AgentExited {
+                    code: *self.last_exit_code.lock().await,
+                })
             }
-            let write_ms = write_start.elapsed().as_millis() as u64;
-            tracing::debug!(
-                method = %method,
-                id = %key,
-                write_ms = write_ms,
-                "post: stdin write complete, waiting for response"
-            );
-
-            let wait_start = Instant::now();
-            match tokio::time::timeout(self.request_timeout, rx).await {
-                Ok(Ok(response)) => {
-                    let wait_ms = wait_start.elapsed().as_millis() as u64;
-                    tracing::info!(
-                        method = %method,
-                        id = %key,
-                        response_ms = wait_ms,
-                        total_ms = write_ms + wait_ms,
-                        "post: got response from agent"
-                    );
-                    Ok(PostOutcome::Response(response))
-                }
-                Ok(Err(_)) => {
-                    let wait_ms = wait_start.elapsed().as_millis() as u64;
-                    tracing::error!(
-                        method = %method,
-                        id = %key,
-                        wait_ms = wait_ms,
-                        "post: response channel dropped (agent process may have exited)"
-                    );
-                    self.pending.lock().await.remove(&key);
-                    Err(AdapterError::Timeout)
-                }
-                Err(_) => {
-                    let pending_keys: Vec<String> =
-                        self.pending.lock().await.keys().cloned().collect();
-                    tracing::error!(
-                        method = %method,
-                        id = %key,
-                        timeout_ms = self.request_timeout.as_millis() as u64,
-                        age_ms = self.spawned_at.elapsed().as_millis() as u64,
-                        pending_keys = ?pending_keys,
-                        first_stdout_seen = self.first_stdout.load(Ordering::Relaxed),
-                        "post: TIMEOUT waiting for agent response"
-                    );
+            Err(_) => {
+                let pending_keys: Vec<String> = self.pending.lock().await.keys().cloned().collect();
+                tracing::error!(
+                    method = %method,
+                    id = %key,
+                    timeout_ms = timeout.as_millis() as u64,
+                    age_ms = self.spawned_at.elapsed().as_millis() as u64,
+                    pending_keys = ?pending_keys,
+                    first_stdout_seen = self.first_stdout.get().is_some(),
+                    "post: TIMEOUT waiting for agent response"
+                );
+                let cancelled = options.cancel_on_timeout.unwrap_or(self.cancel_on_timeout);
+                if cancelled {
+                    self.sink()
+                        .cancel_request(
+                            &key,
+                            cancel.id.take(),
+                            &method,
+                            cancel.session_id.take(),
+                            CancelReason::Timeout,
+                        )
+                        .await;
+                } else {
                     self.pending.lock().await.remove(&key);
-                    Err(AdapterError::Timeout)
                 }
+                Err(AdapterError::Timeout {
+                    method,
+                    elapsed: write_start.elapsed(),
+                    cancelled,
+                })
+            }
+        }
+    }
+
+    /// Forward a client's answer to an agent-to-client request. Only the
+    /// first answer to a broadcast request reaches the agent; subscribers
+    /// are then told, via `_adapter/request_resolved`, to dismiss it.
+    async fn post_client_response(
+        &self,
+        id: Value,
+        payload: &Value,
+        options: &PostOptions,
+    ) -> Result<PostOutcome, AdapterError> {
+        let client_id = options.client_id.as_deref();
+        match self.client_requests.claim(&id_key(&id), client_id) {
+            Claim::Won { method } => {
+                tracing::debug!(
+                    method = %method,
+                    id = %id,
+                    client_id = ?client_id,
+                    "post: client response → agent"
+                );
+                self.send_to_subprocess(payload).await?;
+                self.sink()
+                    .push(json!({
+                        "jsonrpc": "2.0",
+                        "method": "_adapter/request_resolved",
+                        "params": {
+                            "id": id,
+                            "method": method,
+                            "resolvedBy": client_id,
+                            "outcome": if payload.get("error").is_some() { "error" } else { "result" },
+                        }
+                    }))
+                    .await;
+                Ok(PostOutcome::Accepted)
+            }
+            Claim::AlreadyResolved(resolution) => {
+                tracing::info!(
+                    method = %resolution.method,
+                    id = %id,
+                    client_id = ?client_id,
+                    resolved_by = ?resolution.resolved_by,
+                    "post: rejecting duplicate client response"
+                );
+                Err(AdapterError::AlreadyResolved {
+                    id,
+                    method: resolution.method,
+                    resolved_by: resolution.resolved_by,
+                })
+            }
+            Claim::Untracked => {
+                tracing::debug!(id = %id, "post: untracked client response → agent");
+                self.send_to_subprocess(payload).await?;
+                Ok(PostOutcome::Accepted)
             }
-        } else {
-            tracing::debug!(
-                method = %method,
-                "post: notification → agent (fire-and-forget)"
-            );
-            self.send_to_subprocess(&payload).await?;
-            Ok(PostOutcome::Accepted)
         }
     }
 
     async fn subscribe(
         &self,
         last_event_id: Option<u64>,
-    ) -> (Vec<(u64, Value)>, broadcast::Receiver<StreamMessage>) {
-        let replay = {
-            let ring = self.ring.lock().await;
+    ) -> (Replay, broadcast::Receiver<StreamMessage>) {
+        // Subscribing under the ring lock means no message lands between the
+        // replay snapshot and the live receiver.
+        let ring = self.ring.lock().await;
+        let mut replay = Replay {
+            gap: None,
+            messages: Vec::new(),
+            position: self.sequence.load(Ordering::SeqCst),
+        };
+
+        if let Some(last_event_id) = last_event_id {
+            let ring_start = ring
+                .front()
+                .map(|message| message.sequence)
+                .unwrap_or_else(|| self.sequence.load(Ordering::SeqCst) + 1);
+            if last_event_id + 1 < ring_start {
+                // Let the store catch up so its retention reflects the
+                // events already in the ring.
+                if let Some(writer) = &self.store_writer {
+                    writer.flush().await;
+                }
+                let older = match &self.store {
+                    Some(store) => {
+                        store
+                            .read_range(last_event_id, ring_start)
+                            .unwrap_or_else(|err| {
+                                tracing::warn!(
+                                    last_event_id = last_event_id,
+                                    error = %err,
+                                    "event store: failed to read history"
+                                );
+                                Vec::new()
+                            })
+                    }
+                    None => Vec::new(),
+                };
+                let first_available = older.first().map(|(seq, _)| *seq).unwrap_or(ring_start);
+                if first_available > last_event_id + 1 {
+                    replay.gap = Some(json!({
+                        "jsonrpc": "2.0",
+                        "method": "_adapter/gap",
+                        "params": {
+                            "from": last_event_id + 1,
+                            "to": first_available - 1,
+                        }
+                    }));
+                }
+                replay.messages = older;
+            }
+        }
+
+        replay.messages.extend(
             ring.iter()
                 .filter(|message| {
                     if let Some(last_event_id) = last_event_id {
@@ -240,9 +870,8 @@ impl AdapterRuntime {
                         true
                     }
                 })
-                .map(|message| (message.sequence, message.payload.clone()))
-                .collect::<Vec<_>>()
-        };
+                .map(|message| (message.sequence, message.payload.clone())),
+        );
         (replay, self.sender.subscribe())
     }
 
@@ -250,29 +879,18 @@ impl AdapterRuntime {
         self: Arc<Self>,
         last_event_id: Option<u64>,
     ) -> impl Stream<Item = Result<Event, Infallible>> + Send + 'static {
-        let (replay, rx) = self.subscribe(last_event_id).await;
-        let replay_stream = stream::iter(replay.into_iter().map(|(sequence, payload)| {
-            let event = Event::default()
-                .event("message")
-                .id(sequence.to_string())
-                .data(payload.to_string());
-            Ok(event)
-        }));
-
-        let live_stream = BroadcastStream::new(rx).filter_map(|item| async move {
-            match item {
-                Ok(message) => {
-                    let event = Event::default()
-                        .event("message")
-                        .id(message.sequence.to_string())
-                        .data(message.payload.to_string());
-                    Some(Ok(event))
-                }
-                Err(_) => None,
-            }
-        });
-
-        replay_stream.chain(live_stream)
+        self.event_stream(last_event_id)
+            .await
+            .map(|(sequence, payload)| {
+                let event = Event::default().event("message");
+                // The gap notice carries no id so the client's Last-Event-ID
+                // is unaffected by it.
+                let event = match sequence {
+                    Some(sequence) => event.id(sequence.to_string()),
+                    None => event,
+                };
+                Ok(event.data(payload.to_string()))
+            })
     }
 
     /// Stream of raw JSON-RPC `Value` payloads (without SSE framing).
@@ -282,15 +900,57 @@ impl AdapterRuntime {
         self: Arc<Self>,
         last_event_id: Option<u64>,
     ) -> impl Stream<Item = Value> + Send + 'static {
-        let (replay, rx) = self.subscribe(last_event_id).await;
-        let replay_stream = stream::iter(replay.into_iter().map(|(_sequence, payload)| payload));
-        let live_stream = BroadcastStream::new(rx).filter_map(|item| async move {
-            match item {
-                Ok(message) => Some(message.payload),
-                Err(_) => None,
-            }
-        });
-        replay_stream.chain(live_stream)
+        self.event_stream(last_event_id)
+            .await
+            .map(|(_sequence, payload)| payload)
+    }
+
+    /// Stream of payloads paired with their sequence number, starting with
+    /// any replay after `last_event_id`. Gap and lag notices have no
+    /// sequence.
+    ///
+    /// A subscriber that falls behind the live broadcast gets an
+    /// `_adapter/lagged` notice with the missed range and is re-fed from the
+    /// replay buffer or event store; whatever is no longer retained is
+    /// reported with `_adapter/gap`.
+    pub async fn event_stream(
+        self: Arc<Self>,
+        last_event_id: Option<u64>,
+    ) -> impl Stream<Item = (Option<u64>, Value)> + Send + 'static {
+        let (replay, receiver) = self.subscribe(last_event_id).await;
+        let mut subscription = Subscription {
+            id: self.next_subscriber.fetch_add(1, Ordering::SeqCst),
+            runtime: Arc::downgrade(&self),
+            queue: VecDeque::new(),
+            receiver,
+            last_sequence: replay.position,
+        };
+        subscription.enqueue(replay);
+        stream::unfold(subscription, |mut subscription| async move {
+            let item = subscription.next().await?;
+            Some((item, subscription))
+        })
+    }
+
+    /// Captured agent stderr at or after `since_ms` (milliseconds since the
+    /// Unix epoch), limited to the last `tail` lines.
+    pub fn stderr_lines(&self, since_ms: Option<i64>, tail: Option<usize>) -> Vec<StderrLine> {
+        self.stderr.lines(since_ms, tail)
+    }
+
+    /// [`Self::stderr_lines`] followed by live stderr output.
+    pub fn stderr_stream(
+        &self,
+        since_ms: Option<i64>,
+        tail: Option<usize>,
+    ) -> impl Stream<Item = StderrLine> + Send + 'static {
+        self.stderr.follow(since_ms, tail)
+    }
+
+    /// Inject an adapter-generated message into the event stream. It is
+    /// sequenced, buffered for replay, and broadcast like agent output.
+    pub async fn publish(&self, payload: Value) -> u64 {
+        self.sink().push(payload).await
     }
 
     pub async fn shutdown(&self) {
@@ -304,264 +964,843 @@ impl AdapterRuntime {
         );
 
         self.pending.lock().await.clear();
-        let mut child = self.child.lock().await;
-        match child.try_wait() {
-            Ok(Some(_)) => {}
-            Ok(None) => {
-                let _ = child.kill().await;
-                let _ = child.wait().await;
-            }
-            Err(_) => {
-                let _ = child.kill().await;
-            }
+        if let Some(kill) = self.kill.lock().await.take() {
+            let _ = kill.send(());
+        }
+        let mut exited = self.exited.clone();
+        let _ = exited.wait_for(|exited| *exited).await;
+        if let Some(writer) = &self.store_writer {
+            writer.flush().await;
+        }
+    }
+
+    fn sink(&self) -> OutputSink {
+        OutputSink {
+            stdin: self.stdin.clone(),
+            framing: self.framing.clone(),
+            transcript: self.transcript.clone(),
+            client_handlers: self.client_handlers.clone(),
+            pending: self.pending.clone(),
+            client_requests: self.client_requests.clone(),
+            sender: self.sender.clone(),
+            ring: self.ring.clone(),
+            sequence: self.sequence.clone(),
+            store_writer: self.store_writer.clone(),
+            spawned_at: self.spawned_at,
+            first_stdout: self.first_stdout.clone(),
         }
     }
 
-    fn spawn_stdout_loop(&self, stdout: tokio::process::ChildStdout) {
-        let pending = self.pending.clone();
-        let sender = self.sender.clone();
-        let ring = self.ring.clone();
-        let sequence = self.sequence.clone();
-        let spawned_at = self.spawned_at;
-        let first_stdout = self.first_stdout.clone();
+    /// The supervisor owns the child so waiting on it never blocks `shutdown`.
+    /// A kill request (or the runtime being dropped) terminates the process;
+    /// otherwise exits are handled according to the restart policy. Respawned
+    /// processes publish into the same ring, so sequence numbers keep growing.
+    fn spawn_supervisor(
+        &self,
+        launch: LaunchSpec,
+        restart: RestartPolicy,
+        limits: LimitEnforcer,
+        child: Child,
+        kill: oneshot::Receiver<()>,
+        exited: watch::Sender<bool>,
+    ) {
+        let sink = self.sink();
+        let stdin = self.stdin.clone();
+        let restarts = self.restarts.clone();
+        let pid = self.pid.clone();
+        let stderr_log = self.stderr.clone();
+        let last_exit_code = self.last_exit_code.clone();
+        let shutdown_grace = self.shutdown_grace;
 
         tokio::spawn(async move {
-            let mut lines = BufReader::new(stdout).lines();
-            let mut line_count: u64 = 0;
+            let mut child = child;
+            let mut kill = kill;
+            let mut attempt: u32 = 0;
 
-            while let Ok(Some(line)) = lines.next_line().await {
-                let trimmed = line.trim();
-                if trimmed.is_empty() {
-                    continue;
-                }
+            loop {
+                let process_started = Instant::now();
+                let (status, killed, leftovers) = tokio::select! {
+                    status = child.wait() => (status.ok(), false, Vec::new()),
+                    _ = &mut kill => {
+                        let (status, leftovers) =
+                            terminate_process_group(&mut child, shutdown_grace).await;
+                        limits.kill_all();
+                        (status, true, leftovers)
+                    }
+                };
 
-                line_count += 1;
+                pid.store(0, Ordering::SeqCst);
+                let age_ms = sink.spawned_at.elapsed().as_millis() as u64;
+                *last_exit_code.lock().await = status.and_then(|status| status.code());
+                // Requests still waiting on this process will never be answered.
+                let pending_count = {
+                    let mut pending = sink.pending.lock().await;
+                    let count = pending.len();
+                    pending.clear();
+                    count
+                };
 
-                if !first_stdout.swap(true, Ordering::Relaxed) {
-                    tracing::info!(
-                        first_stdout_ms = spawned_at.elapsed().as_millis() as u64,
-                        line_bytes = trimmed.len(),
-                        "agent process: first stdout line received"
-                    );
+                if process_started.elapsed() >= restart.max_backoff {
+                    attempt = 0;
                 }
+                let restarting = !killed
+                    && status.is_some_and(|status| restart.allows(status.success()))
+                    && attempt < restart.max_attempts;
 
-                let payload = match serde_json::from_str::<Value>(trimmed) {
-                    Ok(payload) => payload,
-                    Err(err) => {
-                        tracing::warn!(
-                            error = %err,
-                            line_number = line_count,
-                            raw = %if trimmed.len() > 200 {
-                                format!("{}...", &trimmed[..200])
-                            } else {
-                                trimmed.to_string()
-                            },
-                            "agent stdout: invalid JSON"
-                        );
-                        json!({
-                            "jsonrpc": "2.0",
-                            "method": "_adapter/invalid_stdout",
-                            "params": {
-                                "error": err.to_string(),
-                                "raw": trimmed,
-                            }
-                        })
-                    }
-                };
+                if let Some(status) = status {
+                    let reason = limits.exit_reason();
+                    tracing::warn!(
+                        success = status.success(),
+                        code = status.code(),
+                        reason = reason,
+                        age_ms = age_ms,
+                        pending_requests = pending_count,
+                        restarting = restarting,
+                        "agent process exited"
+                    );
 
-                let is_response = payload.get("id").is_some() && payload.get("method").is_none();
-                if is_response {
-                    let key = id_key(payload.get("id").expect("checked"));
-                    let has_error = payload.get("error").is_some();
-                    if let Some(tx) = pending.lock().await.remove(&key) {
-                        tracing::debug!(
-                            id = %key,
-                            has_error = has_error,
-                            age_ms = spawned_at.elapsed().as_millis() as u64,
-                            "agent stdout: response matched to pending request"
-                        );
-                        let _ = tx.send(payload.clone());
-                        // Also broadcast the response so SSE/notification subscribers
-                        // see it in order after preceding notifications. This lets the
-                        // SSE translation task detect turn completion after all
-                        // session/update events have been processed.
-                        let seq = sequence.fetch_add(1, Ordering::SeqCst) + 1;
-                        let message = StreamMessage {
-                            sequence: seq,
-                            payload,
-                        };
-                        {
-                            let mut guard = ring.lock().await;
-                            guard.push_back(message.clone());
-                            while guard.len() > RING_BUFFER_SIZE {
-                                guard.pop_front();
-                            }
+                    let mut payload = json!({
+                        "jsonrpc": "2.0",
+                        "method": "_adapter/agent_exited",
+                        "params": {
+                            "success": status.success(),
+                            "code": status.code(),
+                            "restarting": restarting,
                         }
-                        let _ = sender.send(message);
-                        continue;
-                    } else {
-                        tracing::warn!(
-                            id = %key,
-                            has_error = has_error,
-                            "agent stdout: response has no matching pending request (orphan)"
-                        );
+                    });
+                    if let Some(reason) = reason {
+                        payload["params"]["reason"] = json!(reason);
                     }
+                    if !leftovers.is_empty() {
+                        payload["params"]["leftoverProcesses"] = json!(leftovers);
+                    }
+
+                    sink.push(payload).await;
+                } else {
+                    tracing::error!(
+                        age_ms = age_ms,
+                        pending_requests = pending_count,
+                        "agent process: failed to get exit status"
+                    );
                 }
 
-                let method = payload
-                    .get("method")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("<none>");
-                tracing::debug!(
-                    method = method,
-                    line_number = line_count,
-                    "agent stdout: notification/event → SSE broadcast"
-                );
+                if !restarting {
+                    break;
+                }
+
+                let respawned = loop {
+                    if attempt >= restart.max_attempts {
+                        break None;
+                    }
+                    attempt += 1;
+                    let delay = restart.backoff(attempt);
+                    tracing::info!(
+                        attempt = attempt,
+                        max_attempts = restart.max_attempts,
+                        delay_ms = delay.as_millis() as u64,
+                        "agent process: scheduling restart"
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = &mut kill => break None,
+                    }
+                    match spawn_process(&launch, &limits) {
+                        Ok(process) => break Some(process),
+                        Err(err) => {
+                            tracing::error!(
+                                attempt = attempt,
+                                error = %err,
+                                "agent process: restart failed"
+                            );
+                        }
+                    }
+                };
 
-                let seq = sequence.fetch_add(1, Ordering::SeqCst) + 1;
-                let message = StreamMessage {
-                    sequence: seq,
-                    payload,
+                let Some(process) = respawned else {
+                    tracing::error!(attempts = attempt, "agent process: giving up on restarts");
+                    break;
                 };
 
-                {
-                    let mut guard = ring.lock().await;
-                    guard.push_back(message.clone());
-                    while guard.len() > RING_BUFFER_SIZE {
-                        guard.pop_front();
-                    }
-                }
+                *stdin.lock().await = process.stdin;
+                spawn_stdout_loop(sink.clone(), process.stdout);
+                spawn_stderr_loop(stderr_log.clone(), process.stderr);
+                child = process.child;
+                pid.store(child.id().unwrap_or(0), Ordering::SeqCst);
+                let restart_count = restarts.fetch_add(1, Ordering::SeqCst) + 1;
 
-                let _ = sender.send(message);
+                let payload = json!({
+                    "jsonrpc": "2.0",
+                    "method": "_adapter/agent_restarted",
+                    "params": {
+                        "attempt": attempt,
+                        "restartCount": restart_count,
+                    }
+                });
+                sink.push(payload).await;
             }
 
-            tracing::info!(
-                total_lines = line_count,
-                age_ms = spawned_at.elapsed().as_millis() as u64,
-                "agent stdout: stream ended"
-            );
+            exited.send_replace(true);
         });
     }
 
-    fn spawn_stderr_loop(&self, stderr: tokio::process::ChildStderr) {
-        let spawned_at = self.spawned_at;
+    async fn send_to_subprocess(&self, payload: &Value) -> Result<(), AdapterError> {
+        let method = payload
+            .get("method")
+            .and_then(|v| v.as_str())
+            .unwrap_or("<none>");
+        let id = payload.get("id").map(|v| v.to_string()).unwrap_or_default();
 
-        tokio::spawn(async move {
-            let mut lines = BufReader::new(stderr).lines();
-            let mut line_count: u64 = 0;
+        tracing::debug!(
+            method = method,
+            id = %id,
+            bytes = serde_json::to_vec(payload).map(|b| b.len()).unwrap_or(0),
+            "stdin: writing message to agent"
+        );
 
-            while let Ok(Some(line)) = lines.next_line().await {
-                line_count += 1;
-                tracing::info!(
-                    line_number = line_count,
-                    age_ms = spawned_at.elapsed().as_millis() as u64,
-                    "agent stderr: {}",
-                    line
+        write_message(
+            &self.stdin,
+            &self.framing,
+            self.transcript.as_deref(),
+            payload,
+        )
+        .await
+        .inspect_err(|err| {
+            tracing::error!(method = method, id = %id, error = %err, "stdin: write failed");
+        })?;
+
+        tracing::debug!(method = method, id = %id, "stdin: write+flush complete");
+        Ok(())
+    }
+}
+
+/// Stop a process spawned as the leader of its own process group, and the
+/// rest of that group: SIGTERM first, then SIGKILL for anything still running
+/// after `grace`. Returns the exit status and the processes that had to be
+/// killed.
+pub async fn terminate_process_group(
+    child: &mut Child,
+    grace: Duration,
+) -> (Option<std::process::ExitStatus>, Vec<Value>) {
+    #[cfg(unix)]
+    if let Some(pgid) = child.id().map(|pid| pid as libc::pid_t) {
+        signal_process_group(pgid, libc::SIGTERM);
+        let deadline = tokio::time::Instant::now() + grace;
+        let mut status = tokio::time::timeout_at(deadline, child.wait())
+            .await
+            .ok()
+            .and_then(Result::ok);
+        while process_group_alive(pgid) && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let mut leftovers = Vec::new();
+        if process_group_alive(pgid) {
+            leftovers = process_group_members(pgid);
+            tracing::warn!(
+                pgid = pgid,
+                grace_ms = grace.as_millis() as u64,
+                leftovers = ?leftovers,
+                "process group survived SIGTERM; sending SIGKILL"
+            );
+            signal_process_group(pgid, libc::SIGKILL);
+            let deadline = tokio::time::Instant::now() + SIGKILL_SETTLE;
+            while process_group_alive(pgid) && tokio::time::Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            let survivors = process_group_members(pgid);
+            if !survivors.is_empty() {
+                tracing::error!(
+                    pgid = pgid,
+                    survivors = ?survivors,
+                    "process group survived SIGKILL"
                 );
             }
+        }
+        if status.is_none() {
+            let _ = child.kill().await;
+            status = child.wait().await.ok();
+        }
+        return (status, leftovers);
+    }
 
-            tracing::debug!(
-                total_lines = line_count,
-                age_ms = spawned_at.elapsed().as_millis() as u64,
-                "agent stderr: stream ended"
-            );
-        });
+    let _ = child.kill().await;
+    (child.wait().await.ok(), Vec::new())
+}
+
+#[cfg(unix)]
+fn signal_process_group(pgid: libc::pid_t, signal: libc::c_int) {
+    // SAFETY: kill(2) has no memory-safety preconditions.
+    unsafe {
+        libc::kill(-pgid, signal);
     }
+}
 
-    fn spawn_exit_watcher(&self) {
-        let child = self.child.clone();
-        let sender = self.sender.clone();
-        let ring = self.ring.clone();
-        let sequence = self.sequence.clone();
-        let spawned_at = self.spawned_at;
-        let pending = self.pending.clone();
+#[cfg(unix)]
+fn process_group_alive(pgid: libc::pid_t) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        !process_group_members(pgid).is_empty()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        // SAFETY: signal 0 only checks for existence.
+        unsafe { libc::kill(-pgid, 0) == 0 }
+    }
+}
 
-        tokio::spawn(async move {
-            let status = {
-                let mut guard = child.lock().await;
-                guard.wait().await.ok()
+/// Live (non-zombie) members of a process group, as `{pid, command}`.
+#[cfg(unix)]
+fn process_group_members(pgid: libc::pid_t) -> Vec<Value> {
+    #[cfg(target_os = "linux")]
+    {
+        let Ok(entries) = std::fs::read_dir("/proc") else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|entry| {
+                let pid = entry.ok()?.file_name().to_str()?.parse::<i32>().ok()?;
+                let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+                // `pid (comm) state ppid pgrp ...`; comm may contain spaces.
+                let (head, tail) = stat.rsplit_once(')')?;
+                let command = head.split_once('(')?.1;
+                let mut fields = tail.split_whitespace();
+                let state = fields.next()?;
+                let group = fields.nth(1)?.parse::<i32>().ok()?;
+                (group == pgid && state != "Z").then(|| json!({ "pid": pid, "command": command }))
+            })
+            .collect()
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Vec::new()
+    }
+}
+
+/// Write one framed JSON-RPC message to the agent.
+async fn write_message(
+    stdin: &Mutex<ChildStdin>,
+    framing: &StdioFraming,
+    transcript: Option<&TranscriptWriter>,
+    payload: &Value,
+) -> Result<(), AdapterError> {
+    let bytes = framing.encode(payload).map_err(AdapterError::Serialize)?;
+    let mut stdin = stdin.lock().await;
+    // Record under the stdin lock so the transcript order matches the agent's.
+    if let Some(transcript) = transcript {
+        transcript.record(TranscriptDirection::ToAgent, payload);
+    }
+    stdin.write_all(&bytes).await.map_err(AdapterError::Write)?;
+    stdin.flush().await.map_err(AdapterError::Write)
+}
+
+fn spawn_process(
+    launch: &LaunchSpec,
+    limits: &LimitEnforcer,
+) -> Result<SpawnedProcess, AdapterError> {
+    let spawn_start = Instant::now();
+
+    let mut command = Command::new(&launch.program);
+    command
+        .args(&launch.args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+
+    for (key, value) in &launch.env {
+        command.env(key, value);
+    }
+    if let Some(cwd) = &launch.cwd {
+        command.current_dir(cwd);
+    }
+    // A group of its own lets shutdown reach the agent's descendants, such as
+    // the node processes behind npx launchers.
+    #[cfg(unix)]
+    command.process_group(0);
+    limits.apply(&mut command);
+
+    tracing::info!(
+        program = ?launch.program,
+        args = ?launch.args,
+        cwd = ?launch.cwd,
+        "spawning agent process"
+    );
+
+    let mut child = command.spawn().map_err(|err| {
+        tracing::error!(
+            program = ?launch.program,
+            error = %err,
+            "failed to spawn agent process"
+        );
+        AdapterError::Spawn(err)
+    })?;
+
+    let pid = child.id().unwrap_or(0);
+    let spawn_elapsed = spawn_start.elapsed();
+    tracing::info!(
+        pid = pid,
+        elapsed_ms = spawn_elapsed.as_millis() as u64,
+        "agent process spawned"
+    );
+
+    let stdin = child.stdin.take().ok_or(AdapterError::MissingStdin)?;
+    let stdout = child.stdout.take().ok_or(AdapterError::MissingStdout)?;
+    let stderr = child.stderr.take().ok_or(AdapterError::MissingStderr)?;
+
+    Ok(SpawnedProcess {
+        child,
+        stdin,
+        stdout,
+        stderr,
+    })
+}
+
+fn spawn_stdout_loop(sink: OutputSink, stdout: ChildStdout) {
+    let pending = sink.pending.clone();
+    let spawned_at = sink.spawned_at;
+    let first_stdout = sink.first_stdout.clone();
+
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(stdout);
+        let mut line_count: u64 = 0;
+
+        loop {
+            let line = match sink.framing.read_message(&mut reader).await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                // A malformed frame header; the agent is still running, so
+                // report it and carry on with whatever follows.
+                Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                    tracing::warn!(error = %err, "agent stdout: invalid frame");
+                    sink.push(json!({
+                        "jsonrpc": "2.0",
+                        "method": "_adapter/invalid_stdout",
+                        "params": { "error": err.to_string() }
+                    }))
+                    .await;
+                    continue;
+                }
+                Err(err) => {
+                    tracing::warn!(error = %err, "agent stdout: read failed");
+                    break;
+                }
             };
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
 
-            let age_ms = spawned_at.elapsed().as_millis() as u64;
-            let pending_count = pending.lock().await.len();
+            line_count += 1;
 
-            if let Some(status) = status {
-                tracing::warn!(
-                    success = status.success(),
-                    code = status.code(),
-                    age_ms = age_ms,
-                    pending_requests = pending_count,
-                    "agent process exited"
+            if first_stdout.set(spawned_at.elapsed()).is_ok() {
+                tracing::info!(
+                    first_stdout_ms = spawned_at.elapsed().as_millis() as u64,
+                    line_bytes = trimmed.len(),
+                    "agent process: first stdout line received"
                 );
+            }
 
-                let payload = json!({
-                    "jsonrpc": "2.0",
-                    "method": "_adapter/agent_exited",
-                    "params": {
-                        "success": status.success(),
-                        "code": status.code(),
+            let payload = match serde_json::from_str::<Value>(trimmed) {
+                Ok(payload) => {
+                    if let Some(transcript) = &sink.transcript {
+                        transcript.record(TranscriptDirection::FromAgent, &payload);
                     }
-                });
-
-                let seq = sequence.fetch_add(1, Ordering::SeqCst) + 1;
-                let message = StreamMessage {
-                    sequence: seq,
-                    payload,
-                };
+                    payload
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        error = %err,
+                        line_number = line_count,
+                        raw = %if trimmed.len() > 200 {
+                            format!("{}...", &trimmed[..200])
+                        } else {
+                            trimmed.to_string()
+                        },
+                        "agent stdout: invalid JSON"
+                    );
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": "_adapter/invalid_stdout",
+                        "params": {
+                            "error": err.to_string(),
+                            "raw": trimmed,
+                        }
+                    })
+                }
+            };
 
-                {
-                    let mut guard = ring.lock().await;
-                    guard.push_back(message.clone());
-                    while guard.len() > RING_BUFFER_SIZE {
-                        guard.pop_front();
-                    }
+            let is_response = payload.get("id").is_some() && payload.get("method").is_none();
+            if is_response {
+                let key = id_key(payload.get("id").expect("checked"));
+                let has_error = payload.get("error").is_some();
+                if let Some(tx) = pending.lock().await.remove(&key) {
+                    tracing::debug!(
+                        id = %key,
+                        has_error = has_error,
+                        age_ms = spawned_at.elapsed().as_millis() as u64,
+                        "agent stdout: response matched to pending request"
+                    );
+                    let _ = tx.send(payload.clone());
+                    // Also broadcast the response so SSE/notification subscribers
+                    // see it in order after preceding notifications. This lets the
+                    // SSE translation task detect turn completion after all
+                    // session/update events have been processed.
+                    sink.push(payload).await;
+                    continue;
+                } else {
+                    tracing::warn!(
+                        id = %key,
+                        has_error = has_error,
+                        "agent stdout: response has no matching pending request (orphan)"
+                    );
                 }
+            }
 
-                let _ = sender.send(message);
-            } else {
-                tracing::error!(
-                    age_ms = age_ms,
-                    pending_requests = pending_count,
-                    "agent process: failed to get exit status"
-                );
+            if sink.handle_client_request(&payload) {
+                continue;
             }
+            if let (Some(method), Some(id)) = (
+                payload.get("method").and_then(Value::as_str),
+                payload.get("id"),
+            ) {
+                sink.client_requests.track(id_key(id), method);
+            }
+
+            let method = payload
+                .get("method")
+                .and_then(|v| v.as_str())
+                .unwrap_or("<none>");
+            tracing::debug!(
+                method = method,
+                line_number = line_count,
+                "agent stdout: notification/event → SSE broadcast"
+            );
+
+            sink.push(payload).await;
+        }
+
+        tracing::info!(
+            total_lines = line_count,
+            age_ms = spawned_at.elapsed().as_millis() as u64,
+            "agent stdout: stream ended"
+        );
+    });
+}
+
+fn spawn_stderr_loop(log: Arc<StderrLog>, stderr: ChildStderr) {
+    let spawned_at = Instant::now();
+    log.reader_opened();
+
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut line_count: u64 = 0;
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            line_count += 1;
+            tracing::debug!(
+                line_number = line_count,
+                age_ms = spawned_at.elapsed().as_millis() as u64,
+                "agent stderr: {}",
+                line
+            );
+            log.push(line);
+        }
+
+        tracing::debug!(
+            total_lines = line_count,
+            age_ms = spawned_at.elapsed().as_millis() as u64,
+            "agent stderr: stream ended"
+        );
+        log.reader_closed();
+    });
+}
+
+/// A request written to the agent whose response has not arrived yet.
+struct InFlightRequest {
+    rx: oneshot::Receiver<Value>,
+    cancel: CancelOnDrop,
+    write_start: Instant,
+    write_ms: u64,
+}
+
+/// One element of a batch once it has been written to the agent.
+enum BatchSlot {
+    /// Handled without waiting on the agent, with the reply if any.
+    Done(Option<Value>),
+    /// A request awaiting the agent's response.
+    Awaiting(Value, Box<InFlightRequest>),
+}
+
+/// Cancels a request the agent is still working on when the caller goes
+/// away, i.e. the `post` future is dropped before the response arrives.
+struct CancelOnDrop {
+    /// `None` once the request has completed.
+    sink: Option<OutputSink>,
+    key: String,
+    id: Value,
+    method: String,
+    session_id: Option<Value>,
+}
+
+impl CancelOnDrop {
+    fn disarm(&mut self) {
+        self.sink = None;
+    }
+}
+
+/// Why a request still pending at the agent was cancelled.
+#[derive(Debug, Clone, Copy)]
+enum CancelReason {
+    ClientDisconnected,
+    Timeout,
+}
+
+impl CancelReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::ClientDisconnected => "client_disconnected",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let Some(sink) = self.sink.take() else {
+            return;
+        };
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let key = std::mem::take(&mut self.key);
+        let id = self.id.take();
+        let method = std::mem::take(&mut self.method);
+        let session_id = self.session_id.take();
+        handle.spawn(async move {
+            sink.cancel_request(
+                &key,
+                id,
+                &method,
+                session_id,
+                CancelReason::ClientDisconnected,
+            )
+            .await
         });
     }
+}
 
-    async fn send_to_subprocess(&self, payload: &Value) -> Result<(), AdapterError> {
-        let method = payload
-            .get("method")
-            .and_then(|v| v.as_str())
-            .unwrap_or("<none>");
-        let id = payload.get("id").map(|v| v.to_string()).unwrap_or_default();
+impl Subscription {
+    async fn next(&mut self) -> Option<(Option<u64>, Value)> {
+        loop {
+            if let Some(item) = self.queue.pop_front() {
+                return Some(item);
+            }
+            match self.receiver.recv().await {
+                // Replay already covered everything up to `last_sequence`.
+                Ok(message) if message.sequence <= self.last_sequence => {}
+                Ok(message) => {
+                    self.last_sequence = message.sequence;
+                    return Some((Some(message.sequence), message.payload));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => self.resync(missed).await,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
 
-        tracing::debug!(
+    /// Queue the lag notice, then resubscribe after the last delivered
+    /// message and queue whatever can still be replayed.
+    async fn resync(&mut self, missed: u64) {
+        let Some(runtime) = self.runtime.upgrade() else {
+            return;
+        };
+        runtime.lag_events.fetch_add(1, Ordering::SeqCst);
+        runtime.missed_messages.fetch_add(missed, Ordering::SeqCst);
+
+        let from = self.last_sequence + 1;
+        let to = self.last_sequence + missed;
+        let (replay, receiver) = runtime.subscribe(Some(self.last_sequence)).await;
+        let resynced = replay.gap.is_none();
+        tracing::warn!(
+            subscriber = self.id,
+            from = from,
+            to = to,
+            resynced = resynced,
+            "event stream: subscriber lagged behind live output"
+        );
+        self.queue.push_back((
+            None,
+            json!({
+                "jsonrpc": "2.0",
+                "method": "_adapter/lagged",
+                "params": {
+                    "from": from,
+                    "to": to,
+                    "missed": missed,
+                    "resynced": resynced,
+                }
+            }),
+        ));
+        self.receiver = receiver;
+        self.last_sequence = replay.position;
+        self.enqueue(replay);
+    }
+
+    fn enqueue(&mut self, replay: Replay) {
+        self.queue.extend(replay.gap.map(|payload| (None, payload)));
+        self.queue.extend(
+            replay
+                .messages
+                .into_iter()
+                .map(|(sequence, payload)| (Some(sequence), payload)),
+        );
+    }
+}
+
+impl OutputSink {
+    /// Forget a pending request and tell the agent to stop working on it:
+    /// `session/cancel` for prompts, `$/cancel_request` otherwise.
+    async fn cancel_request(
+        &self,
+        key: &str,
+        id: Value,
+        method: &str,
+        session_id: Option<Value>,
+        reason: CancelReason,
+    ) {
+        if self.pending.lock().await.remove(key).is_none() {
+            // Answered (or failed) while the caller was going away.
+            return;
+        }
+
+        let cancel = match session_id {
+            Some(session_id) if method == "session/prompt" => json!({
+                "jsonrpc": "2.0",
+                "method": "session/cancel",
+                "params": { "sessionId": session_id }
+            }),
+            _ => json!({
+                "jsonrpc": "2.0",
+                "method": "$/cancel_request",
+                "params": { "requestId": id }
+            }),
+        };
+        tracing::info!(
             method = method,
-            id = %id,
-            bytes = serde_json::to_vec(payload).map(|b| b.len()).unwrap_or(0),
-            "stdin: writing message to agent"
+            id = %key,
+            cancel_method = %cancel["method"],
+            reason = reason.as_str(),
+            "post: cancelling agent request"
         );
+        if let Err(err) = write_message(
+            &self.stdin,
+            &self.framing,
+            self.transcript.as_deref(),
+            &cancel,
+        )
+        .await
+        {
+            tracing::warn!(id = %key, error = %err, "post: failed to send cancellation");
+        }
 
-        let mut stdin = self.stdin.lock().await;
-        let bytes = serde_json::to_vec(payload).map_err(AdapterError::Serialize)?;
-        stdin.write_all(&bytes).await.map_err(|err| {
-            tracing::error!(method = method, id = %id, error = %err, "stdin: write_all failed");
-            AdapterError::Write(err)
-        })?;
-        stdin.write_all(b"\n").await.map_err(|err| {
-            tracing::error!(method = method, id = %id, error = %err, "stdin: newline write failed");
-            AdapterError::Write(err)
-        })?;
-        stdin.flush().await.map_err(|err| {
-            tracing::error!(method = method, id = %id, error = %err, "stdin: flush failed");
-            AdapterError::Write(err)
-        })?;
+        self.push(json!({
+            "jsonrpc": "2.0",
+            "method": "_adapter/request_cancelled",
+            "params": {
+                "id": id,
+                "method": method,
+                "reason": reason.as_str(),
+                "cancelMethod": cancel["method"],
+            }
+        }))
+        .await;
+    }
 
-        tracing::debug!(method = method, id = %id, "stdin: write+flush complete");
-        Ok(())
+    async fn push(&self, payload: Value) -> u64 {
+        // Hold the ring lock while sequencing so concurrent publishers (stdout
+        // loop, supervisor, synthetic events) stay ordered in the store, the
+        // replay buffer and the live broadcast. The store write itself
+        // happens on the writer thread; until then the event is in the ring.
+        let mut guard = self.ring.lock().await;
+        let seq = self.sequence.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(writer) = &self.store_writer {
+            writer.append(seq, &payload);
+        }
+        let message = StreamMessage {
+            sequence: seq,
+            payload,
+        };
+        guard.push_back(message.clone());
+        while guard.len() > RING_BUFFER_SIZE {
+            guard.pop_front();
+        }
+        let _ = self.sender.send(message);
+        seq
+    }
+
+    /// Hand an agent-to-client request to a client handler, if one serves
+    /// it. The reply is written back to the agent from a separate task so
+    /// slow handlers do not hold up stdout.
+    fn handle_client_request(&self, payload: &Value) -> bool {
+        if self.client_handlers.is_empty() {
+            return false;
+        }
+        let (Some(method), Some(id)) = (
+            payload.get("method").and_then(Value::as_str),
+            payload.get("id"),
+        ) else {
+            return false;
+        };
+        let params = payload.get("params").cloned().unwrap_or(Value::Null);
+        let publisher = StreamPublisher { sink: self.clone() };
+        let Some(reply) = self
+            .client_handlers
+            .iter()
+            .find_map(|handler| handler.handle(method, &params, &publisher))
+        else {
+            return false;
+        };
+
+        tracing::debug!(
+            method = method,
+            id = %id,
+            "agent stdout: request answered by client handler"
+        );
+        let method = method.to_string();
+        let id = id.clone();
+        let stdin = self.stdin.clone();
+        let framing = self.framing.clone();
+        let transcript = self.transcript.clone();
+        tokio::spawn(async move {
+            let response = match reply.await {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(err) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": err.code, "message": err.message },
+                }),
+            };
+            if let Err(err) =
+                write_message(&stdin, &framing, transcript.as_deref(), &response).await
+            {
+                tracing::warn!(
+                    method = %method,
+                    error = %err,
+                    "client handler: failed to write response to agent"
+                );
+            }
+        });
+        true
     }
 }
 
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message }
+    })
+}
+
 fn id_key(value: &Value) -> String {
     serde_json::to_string(value).unwrap_or_else(|_| "null".to_string())
 }
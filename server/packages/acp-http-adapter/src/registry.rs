@@ -5,11 +5,17 @@ use serde::Deserialize;
 use serde_json::Value;
 use thiserror::Error;
 
+use crate::framing::Framing;
+
 #[derive(Debug, Clone)]
 pub struct LaunchSpec {
     pub program: PathBuf,
     pub args: Vec<String>,
     pub env: HashMap<String, String>,
+    /// Working directory for the process; inherits the adapter's when unset.
+    pub cwd: Option<PathBuf>,
+    /// How JSON-RPC messages are framed on the process's stdio.
+    pub framing: Framing,
 }
 
 #[derive(Debug, Error)]
@@ -43,23 +49,26 @@ impl LaunchSpec {
                 .into_iter()
                 .find(|a| a.id == wanted)
                 .ok_or_else(|| RegistryError::AgentNotFound(wanted.to_string()))?;
-            return Self::from_distribution(agent.distribution);
+            return Self::from_distribution(agent.distribution, agent.framing);
         }
 
         if value.get("distribution").is_some() {
             let entry: RegistryAgent = serde_json::from_value(value)?;
-            return Self::from_distribution(entry.distribution);
+            return Self::from_distribution(entry.distribution, entry.framing);
         }
 
         if value.get("npx").is_some() || value.get("binary").is_some() {
             let distribution: RegistryDistribution = serde_json::from_value(value)?;
-            return Self::from_distribution(distribution);
+            return Self::from_distribution(distribution, Framing::default());
         }
 
         Err(RegistryError::UnsupportedBlob)
     }
 
-    fn from_distribution(distribution: RegistryDistribution) -> Result<Self, RegistryError> {
+    fn from_distribution(
+        distribution: RegistryDistribution,
+        framing: Framing,
+    ) -> Result<Self, RegistryError> {
         if let Some(npx) = distribution.npx {
             let mut args = vec!["-y".to_string(), npx.package];
             args.extend(npx.args);
@@ -67,6 +76,8 @@ impl LaunchSpec {
                 program: PathBuf::from("npx"),
                 args,
                 env: npx.env,
+                cwd: None,
+                framing,
             });
         }
 
@@ -83,6 +94,8 @@ impl LaunchSpec {
                 program: PathBuf::from(&target.cmd),
                 args: target.args.clone(),
                 env: target.env.clone(),
+                cwd: None,
+                framing,
             });
         }
 
@@ -112,6 +125,9 @@ struct RegistryAgent {
     #[allow(dead_code)]
     id: String,
     distribution: RegistryDistribution,
+    /// Stdio framing the agent speaks; newline-delimited JSON by default.
+    #[serde(default)]
+    framing: Framing,
 }
 
 #[derive(Debug, Deserialize)]
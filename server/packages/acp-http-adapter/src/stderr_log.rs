@@ -0,0 +1,219 @@
+//! Agent stderr capture: a bounded in-memory ring with live subscribers and
+//! an optional size-rotated file.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::{broadcast, watch};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+
+/// Lines kept in memory per runtime.
+pub const STDERR_RING_LINES: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StderrLine {
+    /// When the line was read, in milliseconds since the Unix epoch.
+    pub timestamp_ms: i64,
+    pub line: String,
+}
+
+/// Where to mirror stderr on disk. When the file grows past `max_bytes` it is
+/// renamed to `<path>.1`, replacing the previous rotation.
+#[derive(Debug, Clone)]
+pub struct StderrFileConfig {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct StderrLog {
+    ring: Mutex<VecDeque<StderrLine>>,
+    sender: broadcast::Sender<StderrLine>,
+    file: Option<Mutex<RotatingFile>>,
+    /// Number of stderr pipes still being read.
+    open_readers: watch::Sender<usize>,
+}
+
+#[derive(Debug)]
+struct RotatingFile {
+    config: StderrFileConfig,
+    file: Option<File>,
+    written: u64,
+}
+
+impl StderrLog {
+    pub(crate) fn new(file: Option<StderrFileConfig>) -> Self {
+        let (sender, _rx) = broadcast::channel(256);
+        Self {
+            ring: Mutex::new(VecDeque::with_capacity(STDERR_RING_LINES)),
+            sender,
+            file: file.map(|config| Mutex::new(RotatingFile::new(config))),
+            open_readers: watch::channel(0).0,
+        }
+    }
+
+    pub(crate) fn push(&self, line: String) {
+        let line = StderrLine {
+            timestamp_ms: now_ms(),
+            line,
+        };
+        if let Some(file) = &self.file {
+            lock(file).write(&line);
+        }
+        // Broadcast under the ring lock so subscribers never see a line both
+        // in their snapshot and live.
+        let mut ring = lock(&self.ring);
+        if ring.len() == STDERR_RING_LINES {
+            ring.pop_front();
+        }
+        ring.push_back(line.clone());
+        let _ = self.sender.send(line);
+    }
+
+    /// Retained lines at or after `since_ms`, limited to the last `tail`.
+    pub(crate) fn lines(&self, since_ms: Option<i64>, tail: Option<usize>) -> Vec<StderrLine> {
+        select(&lock(&self.ring), since_ms, tail)
+    }
+
+    /// [`Self::lines`] followed by live output. Lines a slow follower
+    /// misses are replaced by one line saying how many were skipped.
+    pub(crate) fn follow(
+        &self,
+        since_ms: Option<i64>,
+        tail: Option<usize>,
+    ) -> impl Stream<Item = StderrLine> + Send + 'static {
+        let (backlog, receiver) = {
+            let ring = lock(&self.ring);
+            (select(&ring, since_ms, tail), self.sender.subscribe())
+        };
+        // A follower too slow for the channel gets a marker in place of the
+        // lines it missed, rather than a silent hole in the output.
+        let live = BroadcastStream::new(receiver).map(|item| match item {
+            Ok(line) => line,
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => StderrLine {
+                timestamp_ms: now_ms(),
+                line: format!("[{skipped} stderr lines skipped: reader fell behind]"),
+            },
+        });
+        futures::stream::iter(backlog).chain(live)
+    }
+
+    pub(crate) fn reader_opened(&self) {
+        self.open_readers.send_modify(|open| *open += 1);
+    }
+
+    pub(crate) fn reader_closed(&self) {
+        self.open_readers
+            .send_modify(|open| *open = open.saturating_sub(1));
+    }
+
+    /// Wait until every stderr pipe has hit EOF, so a dead process's last
+    /// words are in the ring. A restarted process keeps its pipe open, hence
+    /// the timeout.
+    pub(crate) async fn drain(&self, timeout: Duration) {
+        let mut open = self.open_readers.subscribe();
+        let _ = tokio::time::timeout(timeout, open.wait_for(|open| *open == 0)).await;
+    }
+}
+
+impl RotatingFile {
+    fn new(config: StderrFileConfig) -> Self {
+        Self {
+            config,
+            file: None,
+            written: 0,
+        }
+    }
+
+    fn write(&mut self, line: &StderrLine) {
+        if let Err(err) = self.try_write(line) {
+            tracing::warn!(
+                path = %self.config.path.display(),
+                error = %err,
+                "agent stderr: failed to write log file"
+            );
+            self.file = None;
+        }
+    }
+
+    fn try_write(&mut self, line: &StderrLine) -> std::io::Result<()> {
+        if self.file.is_some() && self.written >= self.config.max_bytes {
+            self.file = None;
+            let mut rotated = self.config.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.config.path, rotated)?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                if let Some(parent) = self.config.path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.config.path)?;
+                self.written = file.metadata()?.len();
+                self.file.insert(file)
+            }
+        };
+        let entry = format!("{} {}\n", line.timestamp_ms, line.line);
+        file.write_all(entry.as_bytes())?;
+        self.written += entry.len() as u64;
+        Ok(())
+    }
+}
+
+fn select(
+    ring: &VecDeque<StderrLine>,
+    since_ms: Option<i64>,
+    tail: Option<usize>,
+) -> Vec<StderrLine> {
+    let lines = ring
+        .iter()
+        .filter(|line| since_ms.is_none_or(|since| line.timestamp_ms >= since))
+        .cloned()
+        .collect::<Vec<_>>();
+    match tail {
+        Some(tail) if tail < lines.len() => lines[lines.len() - tail..].to_vec(),
+        _ => lines,
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lagged_follower_gets_a_skip_marker() {
+        let log = StderrLog::new(None);
+        let mut follow = Box::pin(log.follow(None, Some(0)));
+        for n in 0..300 {
+            log.push(format!("line {n}"));
+        }
+
+        let marker = follow.next().await.expect("marker line");
+        assert_eq!(marker.line, "[44 stderr lines skipped: reader fell behind]");
+        let next = follow.next().await.expect("live line");
+        assert_eq!(next.line, "line 44");
+    }
+}
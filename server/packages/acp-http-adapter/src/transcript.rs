@@ -0,0 +1,298 @@
+//! JSONL transcripts of the JSON-RPC traffic on an agent's stdio, and a
+//! replayer that plays one back as a fake agent.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptDirection {
+    /// Written to the agent's stdin.
+    ToAgent,
+    /// Read from the agent's stdout.
+    FromAgent,
+}
+
+/// One line of a transcript file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptEntry {
+    /// Position in the transcript, starting at 1.
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: i64,
+    pub direction: TranscriptDirection,
+    pub message: Value,
+}
+
+/// Appends every message of one runtime to a transcript file. Restarted
+/// processes keep writing to the same file.
+#[derive(Debug)]
+pub(crate) struct TranscriptWriter {
+    path: PathBuf,
+    state: Mutex<WriterState>,
+}
+
+#[derive(Debug)]
+struct WriterState {
+    file: Option<File>,
+    seq: u64,
+}
+
+impl TranscriptWriter {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            state: Mutex::new(WriterState { file: None, seq: 0 }),
+        }
+    }
+
+    pub(crate) fn record(&self, direction: TranscriptDirection, message: &Value) {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state.seq += 1;
+        let entry = TranscriptEntry {
+            seq: state.seq,
+            timestamp_ms: now_ms(),
+            direction,
+            message: message.clone(),
+        };
+        if let Err(err) = self.write(&mut state, &entry) {
+            tracing::warn!(
+                path = %self.path.display(),
+                error = %err,
+                "transcript: failed to write entry"
+            );
+            state.file = None;
+        }
+    }
+
+    fn write(&self, state: &mut WriterState, entry: &TranscriptEntry) -> io::Result<()> {
+        let file = match &mut state.file {
+            Some(file) => file,
+            None => {
+                if let Some(parent) = self.path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                state.file.insert(file)
+            }
+        };
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        file.write_all(&line)
+    }
+}
+
+pub fn read_transcript(path: &Path) -> io::Result<Vec<TranscriptEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {err}", path.display(), index + 1),
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Act as the recorded agent: each message read from `input` stands in for
+/// the next recorded `to_agent` message, and the `from_agent` messages that
+/// followed it are written to `output` as newline-delimited JSON. Request
+/// ids are mapped from the recording to the live ones, so clients need not
+/// reuse the recorded ids. Timing is not reproduced.
+///
+/// A message whose method differs from the recording, or that arrives after
+/// the recording ran out, is reported on stderr and, if it is a request,
+/// answered with an error; the replay position does not advance. Returns
+/// when `input` closes.
+pub fn replay_transcript(
+    entries: &[TranscriptEntry],
+    input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let mut position = 0;
+    let mut ids = HashMap::<String, Value>::new();
+    emit_until_client_turn(entries, &mut position, &ids, &mut output)?;
+
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Ok(live) = serde_json::from_str::<Value>(&line) else {
+            eprintln!("replay: ignoring invalid JSON: {line}");
+            continue;
+        };
+
+        let expected = entries.get(position).map(|entry| &entry.message);
+        let live_method = live.get("method").and_then(Value::as_str);
+        match expected {
+            Some(expected) if expected.get("method").and_then(Value::as_str) == live_method => {
+                if let (Some(recorded), Some(live_id)) = (expected.get("id"), live.get("id")) {
+                    if live_method.is_some() {
+                        ids.insert(id_key(recorded), live_id.clone());
+                    }
+                }
+                position += 1;
+                emit_until_client_turn(entries, &mut position, &ids, &mut output)?;
+            }
+            expected => {
+                let reason = match expected {
+                    Some(expected) => {
+                        format!("expected {}, got {}", describe(expected), describe(&live))
+                    }
+                    None => format!("transcript exhausted, got {}", describe(&live)),
+                };
+                eprintln!("replay: {reason}");
+                if let (Some(id), Some(_)) = (live.get("id"), live_method) {
+                    let error = json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32603, "message": format!("replay: {reason}") },
+                    });
+                    write_line(&mut output, &error)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write recorded agent output up to the next message the client sends.
+fn emit_until_client_turn(
+    entries: &[TranscriptEntry],
+    position: &mut usize,
+    ids: &HashMap<String, Value>,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    while let Some(entry) = entries.get(*position) {
+        if entry.direction == TranscriptDirection::ToAgent {
+            break;
+        }
+        let mut message = entry.message.clone();
+        let is_response = message.get("method").is_none();
+        if is_response {
+            if let Some(live_id) = message.get("id").and_then(|id| ids.get(&id_key(id))) {
+                message["id"] = live_id.clone();
+            }
+        }
+        write_line(output, &message)?;
+        *position += 1;
+    }
+    output.flush()
+}
+
+fn write_line(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    output.write_all(&line)
+}
+
+fn describe(message: &Value) -> String {
+    match message.get("method").and_then(Value::as_str) {
+        Some(method) => format!("'{method}'"),
+        None => "a response".to_string(),
+    }
+}
+
+fn id_key(id: &Value) -> String {
+    serde_json::to_string(id).unwrap_or_default()
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(seq: u64, direction: TranscriptDirection, message: Value) -> TranscriptEntry {
+        TranscriptEntry {
+            seq,
+            timestamp_ms: 0,
+            direction,
+            message,
+        }
+    }
+
+    #[test]
+    fn replays_agent_output_with_live_ids() {
+        use TranscriptDirection::{FromAgent, ToAgent};
+        let entries = vec![
+            entry(
+                1,
+                ToAgent,
+                json!({"jsonrpc": "2.0", "id": 1, "method": "initialize"}),
+            ),
+            entry(
+                2,
+                FromAgent,
+                json!({"jsonrpc": "2.0", "id": 1, "result": {"v": 1}}),
+            ),
+            entry(
+                3,
+                ToAgent,
+                json!({"jsonrpc": "2.0", "id": 2, "method": "session/prompt"}),
+            ),
+            entry(
+                4,
+                FromAgent,
+                json!({"jsonrpc": "2.0", "method": "session/update"}),
+            ),
+            entry(
+                5,
+                FromAgent,
+                json!({"jsonrpc": "2.0", "id": 2, "result": {}}),
+            ),
+        ];
+        let input = concat!(
+            "{\"jsonrpc\":\"2.0\",\"id\":\"a\",\"method\":\"initialize\"}\n",
+            "{\"jsonrpc\":\"2.0\",\"id\":9,\"method\":\"session/cancel\"}\n",
+            "{\"jsonrpc\":\"2.0\",\"id\":\"b\",\"method\":\"session/prompt\"}\n",
+        );
+        let mut output = Vec::new();
+        replay_transcript(&entries, input.as_bytes(), &mut output).expect("replay");
+
+        let lines = String::from_utf8(output)
+            .expect("utf8")
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).expect("json"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                json!({"jsonrpc": "2.0", "id": "a", "result": {"v": 1}}),
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": 9,
+                    "error": {
+                        "code": -32603,
+                        "message": "replay: expected 'session/prompt', got 'session/cancel'"
+                    }
+                }),
+                json!({"jsonrpc": "2.0", "method": "session/update"}),
+                json!({"jsonrpc": "2.0", "id": "b", "result": {}}),
+            ]
+        );
+    }
+}
@@ -1,21 +1,34 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fs;
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
 
 use flate2::read::GzDecoder;
+use fs4::fs_std::FileExt;
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use url::Url;
 
+use crate::bundle::{
+    self, BundleManifest, BundledAgent, BundledAgentProcess, BundledNative, OFFLINE_BUNDLE_ENV,
+};
+use crate::custom::{self, CustomAgentDefinition, CustomAgentId, CustomAgentInstall, CustomAgents};
+use crate::lock::{self, LockedAgent, LockedArtifact};
+use crate::verify::{ArtifactDigest, ArtifactPolicy};
+
 const DEFAULT_ACP_REGISTRY_URL: &str =
     "https://cdn.agentclientprotocol.com/registry/v1/latest/registry.json";
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+/// How old a leftover install staging dir must be before [`AgentManager::gc`]
+/// removes it.
+const STALE_STAGING_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AgentId {
     Claude,
     Codex,
@@ -24,10 +37,22 @@ pub enum AgentId {
     Pi,
     Cursor,
     Mock,
+    /// A user-defined agent; see [`crate::custom`].
+    Custom(CustomAgentId),
 }
 
+const BUILTIN_AGENTS: &[AgentId] = &[
+    AgentId::Claude,
+    AgentId::Codex,
+    AgentId::Opencode,
+    AgentId::Amp,
+    AgentId::Pi,
+    AgentId::Cursor,
+    AgentId::Mock,
+];
+
 impl AgentId {
-    pub fn as_str(self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             AgentId::Claude => "claude",
             AgentId::Codex => "codex",
@@ -36,10 +61,11 @@ impl AgentId {
             AgentId::Pi => "pi",
             AgentId::Cursor => "cursor",
             AgentId::Mock => "mock",
+            AgentId::Custom(id) => id.as_str(),
         }
     }
 
-    pub fn binary_name(self) -> &'static str {
+    pub fn binary_name(&self) -> &str {
         match self {
             AgentId::Claude => "claude",
             AgentId::Codex => "codex",
@@ -48,9 +74,12 @@ impl AgentId {
             AgentId::Pi => "pi",
             AgentId::Cursor => "cursor-agent",
             AgentId::Mock => "mock",
+            AgentId::Custom(id) => id.as_str(),
         }
     }
 
+    /// A built-in agent. Custom agents are resolved with
+    /// [`AgentManager::parse_agent`].
     pub fn parse(value: &str) -> Option<Self> {
         match value {
             "claude" => Some(AgentId::Claude),
@@ -64,31 +93,13 @@ impl AgentId {
         }
     }
 
+    /// Built-in agents. [`AgentManager::agents`] adds registered custom
+    /// agents.
     pub fn all() -> &'static [AgentId] {
-        &[
-            AgentId::Claude,
-            AgentId::Codex,
-            AgentId::Opencode,
-            AgentId::Amp,
-            AgentId::Pi,
-            AgentId::Cursor,
-            AgentId::Mock,
-        ]
-    }
-
-    fn agent_process_registry_id(self) -> Option<&'static str> {
-        match self {
-            AgentId::Claude => Some("claude-code-acp"),
-            AgentId::Codex => Some("codex-acp"),
-            AgentId::Opencode => Some("opencode"),
-            AgentId::Amp => Some("amp-acp"),
-            AgentId::Pi => Some("pi-acp"),
-            AgentId::Cursor => Some("cursor-agent-acp"),
-            AgentId::Mock => None,
-        }
+        BUILTIN_AGENTS
     }
 
-    fn agent_process_binary_hint(self) -> Option<&'static str> {
+    fn agent_process_binary_hint(&self) -> Option<&'static str> {
         match self {
             AgentId::Claude => Some("claude-code-acp"),
             AgentId::Codex => Some("codex-acp"),
@@ -96,20 +107,40 @@ impl AgentId {
             AgentId::Amp => Some("amp-acp"),
             AgentId::Pi => Some("pi-acp"),
             AgentId::Cursor => Some("cursor-agent-acp"),
-            AgentId::Mock => None,
+            AgentId::Mock | AgentId::Custom(_) => None,
         }
     }
 
-    fn native_required(self) -> bool {
+    fn native_required(&self) -> bool {
         matches!(self, AgentId::Claude | AgentId::Codex | AgentId::Opencode)
     }
 
-    fn unstable_enabled(self) -> bool {
+    fn unstable_enabled(&self) -> bool {
         // v1 profile includes unstable methods; support still depends on agent process capability.
         !matches!(self, AgentId::Amp)
     }
 }
 
+impl Serialize for AgentId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Accepts built-in ids and well-formed custom ids; whether a custom agent is
+/// registered is up to the [`AgentManager`] that resolves it.
+impl<'de> Deserialize<'de> for AgentId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        match AgentId::parse(&value) {
+            Some(agent) => Ok(agent),
+            None => CustomAgentId::new(&value)
+                .map(AgentId::Custom)
+                .map_err(|_| serde::de::Error::custom(format!("unknown agent '{value}'"))),
+        }
+    }
+}
+
 impl fmt::Display for AgentId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str(self.as_str())
@@ -178,6 +209,10 @@ pub enum InstallSource {
     Fallback,
     LocalPath,
     Builtin,
+    /// Installed from a user-defined agent's npx package.
+    Custom,
+    /// Copied out of an offline bundle.
+    Bundle,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -199,6 +234,10 @@ pub struct InstalledArtifact {
 pub struct InstallResult {
     pub artifacts: Vec<InstalledArtifact>,
     pub already_installed: bool,
+    /// URLs downloaded without a published or pinned sha256 to check
+    /// against.
+    #[serde(default)]
+    pub unverified_downloads: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -206,6 +245,12 @@ pub struct InstallOptions {
     pub reinstall: bool,
     pub version: Option<String>,
     pub agent_process_version: Option<String>,
+    /// Install only from this bundle archive or directory, never from the
+    /// network. Defaults to `SANDBOX_AGENT_OFFLINE_BUNDLE`.
+    pub offline_bundle: Option<PathBuf>,
+    /// Fail rather than install anything the lockfile does not pin, and
+    /// leave the lockfile unchanged.
+    pub frozen: bool,
 }
 
 impl Default for InstallOptions {
@@ -214,6 +259,8 @@ impl Default for InstallOptions {
             reinstall: false,
             version: None,
             agent_process_version: None,
+            offline_bundle: None,
+            frozen: false,
         }
     }
 }
@@ -237,6 +284,9 @@ pub struct AgentProcessLaunchSpec {
     pub env: HashMap<String, String>,
     pub source: InstallSource,
     pub version: Option<String>,
+    /// Stdio framing from the registry entry (for example `content-length`);
+    /// `None` means newline-delimited JSON.
+    pub framing: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -244,15 +294,21 @@ pub struct AgentManager {
     install_dir: PathBuf,
     platform: Platform,
     registry_url: Url,
+    custom_agents: CustomAgents,
 }
 
 impl AgentManager {
+    /// Creates a manager and registers the custom agents defined under the
+    /// install dir and in `SANDBOX_AGENT_CUSTOM_AGENTS`.
     pub fn new(install_dir: impl Into<PathBuf>) -> Result<Self, AgentError> {
-        Ok(Self {
+        let manager = Self {
             install_dir: install_dir.into(),
             platform: Platform::detect()?,
             registry_url: registry_url_from_env()?,
-        })
+            custom_agents: CustomAgents::default(),
+        };
+        manager.load_custom_agents();
+        Ok(manager)
     }
 
     pub fn with_platform(install_dir: impl Into<PathBuf>, platform: Platform) -> Self {
@@ -263,6 +319,7 @@ impl AgentManager {
             install_dir: install_dir.into(),
             platform,
             registry_url,
+            custom_agents: CustomAgents::default(),
         }
     }
 
@@ -270,11 +327,11 @@ impl AgentManager {
         &self.install_dir
     }
 
-    pub fn binary_path(&self, agent: AgentId) -> PathBuf {
+    pub fn binary_path(&self, agent: &AgentId) -> PathBuf {
         self.install_dir.join(agent.binary_name())
     }
 
-    pub fn agent_process_path(&self, agent: AgentId) -> PathBuf {
+    pub fn agent_process_path(&self, agent: &AgentId) -> PathBuf {
         let base = self.install_dir.join("agent_processes");
         if cfg!(windows) {
             base.join(format!("{}-acp.cmd", agent.as_str()))
@@ -283,25 +340,278 @@ impl AgentManager {
         }
     }
 
-    pub fn agent_process_storage_dir(&self, agent: AgentId) -> PathBuf {
+    /// Registry launch settings the launcher script cannot carry.
+    pub fn agent_process_metadata_path(&self, agent: &AgentId) -> PathBuf {
+        self.install_dir
+            .join("agent_processes")
+            .join(format!("{}-acp.json", agent.as_str()))
+    }
+
+    pub fn agent_process_storage_dir(&self, agent: &AgentId) -> PathBuf {
         self.install_dir
             .join("agent_processes")
             .join(agent.as_str())
     }
 
+    pub fn custom_agents_path(&self) -> PathBuf {
+        self.install_dir.join(custom::CUSTOM_AGENTS_FILE)
+    }
+
+    /// Registers the custom agents defined under the install dir and in
+    /// `SANDBOX_AGENT_CUSTOM_AGENTS`, skipping invalid ones.
+    fn load_custom_agents(&self) {
+        let mut paths = vec![self.custom_agents_path()];
+        if let Some(path) = std::env::var_os(custom::CUSTOM_AGENTS_ENV) {
+            paths.push(PathBuf::from(path));
+        }
+        for path in paths {
+            for definition in custom::load_file(&path) {
+                if let Err(err) = self.custom_agents.register(definition) {
+                    tracing::warn!(
+                        path = %path.display(),
+                        error = %err,
+                        "skipping custom agent"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Resolves a built-in or registered custom agent id.
+    pub fn parse_agent(&self, value: &str) -> Option<AgentId> {
+        AgentId::parse(value).or_else(|| self.custom_agents.lookup(value))
+    }
+
+    /// Built-in agents followed by registered custom agents.
+    pub fn agents(&self) -> Vec<AgentId> {
+        let mut agents = AgentId::all().to_vec();
+        agents.extend(self.custom_agents.agents());
+        agents
+    }
+
+    /// The definition behind a custom agent, or `None` for built-ins and
+    /// unregistered ids.
+    pub fn custom_definition(&self, agent: &AgentId) -> Option<Arc<CustomAgentDefinition>> {
+        match agent {
+            AgentId::Custom(id) => self.custom_agents.definition(id.as_str()),
+            _ => None,
+        }
+    }
+
+    fn agent_process_registry_id(&self, agent: &AgentId) -> Option<String> {
+        let id = match agent {
+            AgentId::Claude => "claude-code-acp",
+            AgentId::Codex => "codex-acp",
+            AgentId::Opencode => "opencode",
+            AgentId::Amp => "amp-acp",
+            AgentId::Pi => "pi-acp",
+            AgentId::Cursor => "cursor-agent-acp",
+            AgentId::Mock => return None,
+            AgentId::Custom(_) => {
+                return match &self.custom_definition(agent)?.install {
+                    CustomAgentInstall::Registry(id) => Some(id.clone()),
+                    _ => None,
+                };
+            }
+        };
+        Some(id.to_string())
+    }
+
+    /// Adds or replaces a custom agent and persists it under the install
+    /// dir. Replacing a definition discards the agent process installed for
+    /// the old one.
+    pub fn register_custom_agent(
+        &self,
+        definition: CustomAgentDefinition,
+    ) -> Result<AgentId, AgentError> {
+        let previous = self.custom_agents.definition(&definition.id);
+        let agent = self.custom_agents.register(definition.clone())?;
+        if previous.is_some_and(|previous| *previous != definition) {
+            self.remove_agent_process(&agent)?;
+        }
+
+        let path = self.custom_agents_path();
+        let mut stored = custom::read_file(&path)?;
+        stored.retain(|stored| stored.id != definition.id);
+        stored.push(definition);
+        custom::write_file(&path, stored)?;
+        Ok(agent)
+    }
+
+    /// Removes a custom agent from the registry and the install dir's
+    /// definitions. Installed files are left in place. Returns whether the
+    /// agent was registered.
+    pub fn unregister_custom_agent(&self, id: &str) -> Result<bool, AgentError> {
+        let path = self.custom_agents_path();
+        let mut stored = custom::read_file(&path)?;
+        let count = stored.len();
+        stored.retain(|stored| stored.id != id);
+        if stored.len() != count {
+            custom::write_file(&path, stored)?;
+        }
+        Ok(self.custom_agents.unregister(id))
+    }
+
+    fn remove_agent_process(&self, agent: &AgentId) -> Result<(), AgentError> {
+        for path in [
+            self.agent_process_path(agent),
+            self.agent_process_metadata_path(agent),
+        ] {
+            match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes an agent's native binary, launcher and agent process files
+    /// from the install dir. Binaries found on `PATH` and the lockfile are
+    /// left alone. Returns the removed paths.
+    pub fn uninstall(&self, agent: &AgentId) -> Result<Vec<PathBuf>, AgentError> {
+        let _lock = self.lock_agent(agent)?;
+        let mut removed = Vec::new();
+        for path in self.installed_paths(agent) {
+            if remove_path(&path)? {
+                removed.push(path);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Everything an install of `agent` writes under the install dir.
+    fn installed_paths(&self, agent: &AgentId) -> [PathBuf; 4] {
+        [
+            self.binary_path(agent),
+            self.agent_process_path(agent),
+            self.agent_process_metadata_path(agent),
+            self.agent_process_storage_dir(agent),
+        ]
+    }
+
+    /// Prunes agent process files nothing launches: staging dirs left by
+    /// interrupted installs, storage dirs an agent's launcher no longer
+    /// points into (such as archives from a previous install), and files of
+    /// agents that are no longer registered. Files of the agent ids in
+    /// `in_use`, and of agents being installed or removed, are kept. Returns
+    /// the removed paths.
+    pub fn gc(&self, in_use: &[&str]) -> Result<Vec<PathBuf>, AgentError> {
+        let entries = match fs::read_dir(self.install_dir.join("agent_processes")) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut removed = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_dir = entry.file_type()?.is_dir();
+            let unused = if name.starts_with(".tmp") {
+                // Staging dirs from `tempfile`; a recent one may belong to an
+                // install that is still running.
+                entry
+                    .metadata()?
+                    .modified()?
+                    .elapsed()
+                    .is_ok_and(|age| age >= STALE_STAGING_AGE)
+            } else {
+                let id = if is_dir {
+                    Some(name.as_str())
+                } else {
+                    let stem = name
+                        .strip_suffix(".json")
+                        .or_else(|| name.strip_suffix(".cmd"))
+                        .unwrap_or(&name);
+                    stem.strip_suffix("-acp")
+                };
+                match id {
+                    Some(id) if !in_use.contains(&id) => match self.parse_agent(id) {
+                        None => true,
+                        // An install that holds the lock may not have
+                        // written its launcher yet.
+                        Some(agent) if is_dir => match self.try_lock_agent(&agent)? {
+                            Some(_lock) => !self.launcher_uses(&agent, &path),
+                            None => false,
+                        },
+                        Some(_) => false,
+                    },
+                    _ => false,
+                }
+            };
+            if unused && remove_path(&path)? {
+                removed.push(path);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Whether a path in `agent`'s launcher command or environment lies
+    /// under `dir`.
+    fn launcher_uses(&self, agent: &AgentId, dir: &Path) -> bool {
+        let launcher = self.agent_process_path(agent);
+        let words = match read_launcher(&launcher) {
+            Some((command, env)) => command.into_iter().chain(env.into_values()).collect(),
+            None => fs::read_to_string(&launcher)
+                .map(|script| {
+                    script
+                        .split(|c: char| c.is_whitespace() || c == '"')
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default(),
+        };
+        words.iter().any(|word| {
+            // Also look past `--flag=` and `KEY=` prefixes.
+            std::iter::once(word.as_str())
+                .chain(word.split_once('=').map(|(_, value)| value))
+                .any(|candidate| Path::new(candidate).starts_with(dir))
+        })
+    }
+
+    /// Lock `agent`'s files in the install dir, across processes, until the
+    /// returned file is dropped. Installs, imports and uninstalls hold it.
+    fn lock_agent(&self, agent: &AgentId) -> Result<fs::File, AgentError> {
+        let file = self.agent_lock_file(agent)?;
+        file.lock_exclusive()?;
+        Ok(file)
+    }
+
+    /// [`lock_agent`](Self::lock_agent), or `None` while someone else holds
+    /// the lock.
+    fn try_lock_agent(&self, agent: &AgentId) -> Result<Option<fs::File>, AgentError> {
+        let file = self.agent_lock_file(agent)?;
+        if file.try_lock_exclusive()? {
+            Ok(Some(file))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn agent_lock_file(&self, agent: &AgentId) -> Result<fs::File, AgentError> {
+        let dir = self.install_dir.join(".locks");
+        fs::create_dir_all(&dir)?;
+        Ok(fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(format!("{agent}.lock")))?)
+    }
+
     pub fn list_status(&self) -> Vec<AgentInstallStatus> {
-        AgentId::all()
-            .iter()
-            .copied()
+        self.agents()
+            .into_iter()
             .map(|agent| {
                 let native_required = agent.native_required();
-                let native_installed = !native_required || self.native_installed(agent);
+                let native_installed = !native_required || self.native_installed(&agent);
                 let native_version = if native_installed && native_required {
-                    self.version(agent).ok().flatten()
+                    self.version(&agent).ok().flatten()
                 } else {
                     None
                 };
-                let agent_process = self.agent_process_status(agent);
+                let agent_process = self.agent_process_status(&agent);
+                let unstable_enabled = agent.unstable_enabled();
                 AgentInstallStatus {
                     agent,
                     native_required,
@@ -310,56 +620,190 @@ impl AgentManager {
                     agent_process_installed: agent_process.is_some(),
                     agent_process_source: agent_process.as_ref().map(|a| a.source),
                     agent_process_version: agent_process.and_then(|a| a.version),
-                    unstable_enabled: agent.unstable_enabled(),
+                    unstable_enabled,
                 }
             })
             .collect()
     }
 
+    /// Installs an agent, pinning versions and digests to the lockfile and
+    /// recording what was installed in it (see [`AgentManager::lockfile_path`]).
     pub fn install(
         &self,
-        agent: AgentId,
-        options: InstallOptions,
+        agent: &AgentId,
+        mut options: InstallOptions,
     ) -> Result<InstallResult, AgentError> {
         fs::create_dir_all(&self.install_dir)?;
         fs::create_dir_all(self.install_dir.join("agent_processes"))?;
+        let _lock = self.lock_agent(agent)?;
+        if *agent == AgentId::Mock {
+            return self
+                .install_artifacts(agent, &options, &ArtifactPolicy::default())
+                .map(|(result, _)| result);
+        }
+
+        let lock_path = self.lockfile_path();
+        let locked = lock::read(&lock_path)?.agents.remove(agent.as_str());
+        if options.frozen && locked.is_none() {
+            return Err(AgentError::LockMismatch(format!(
+                "{agent} is not in {}",
+                lock_path.display()
+            )));
+        }
+        let locked = locked.unwrap_or_default();
+        let native_lock = lock::apply(
+            &format!("{agent} native"),
+            &mut options.version,
+            locked.native,
+            options.frozen,
+        )?;
+        let process_lock = lock::apply(
+            &format!("{agent} agent process"),
+            &mut options.agent_process_version,
+            locked.agent_process,
+            options.frozen,
+        )?;
+
+        let mut policy = ArtifactPolicy::from_env()?;
+        for locked in native_lock.iter().chain(&process_lock) {
+            for (url, sha256) in &locked.digests {
+                policy.pin(url, sha256);
+            }
+        }
+        if options.frozen {
+            policy.require_pinned();
+        }
+
+        if !options.frozen {
+            let (result, resolved) = self.install_artifacts(agent, &options, &policy)?;
+            lock::update(&lock_path, agent.as_str(), resolved)?;
+            return Ok(result);
+        }
+
+        // What gets installed is only known once it has been, so a frozen
+        // install that turns out not to match the lockfile puts the
+        // previous files back.
+        let backup = InstallBackup::create(&self.install_dir, self.installed_paths(agent))?;
+        let installed =
+            self.install_artifacts(agent, &options, &policy)
+                .and_then(|(result, resolved)| {
+                    lock::check(
+                        &format!("{agent} native"),
+                        resolved.native.as_ref(),
+                        native_lock.as_ref(),
+                    )?;
+                    lock::check(
+                        &format!("{agent} agent process"),
+                        resolved.agent_process.as_ref(),
+                        process_lock.as_ref(),
+                    )?;
+                    Ok(result)
+                });
+        if installed.is_err() {
+            if let Err(err) = backup.restore() {
+                tracing::warn!(agent = %agent, error = %err, "failed to restore previous install");
+            }
+        }
+        installed
+    }
+
+    /// The lockfile: `SANDBOX_AGENT_LOCKFILE`, or `agents.lock.json` under
+    /// the install dir.
+    pub fn lockfile_path(&self) -> PathBuf {
+        std::env::var_os(lock::AGENT_LOCK_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| self.install_dir.join(lock::AGENT_LOCK_FILE))
+    }
+
+    /// Install whatever is missing (or everything, on reinstall), returning
+    /// lockfile entries for what was installed.
+    fn install_artifacts(
+        &self,
+        agent: &AgentId,
+        options: &InstallOptions,
+        policy: &ArtifactPolicy,
+    ) -> Result<(InstallResult, LockedAgent), AgentError> {
+        let offline_bundle = options
+            .offline_bundle
+            .clone()
+            .or_else(|| std::env::var_os(OFFLINE_BUNDLE_ENV).map(PathBuf::from))
+            .filter(|path| !path.as_os_str().is_empty());
+        if let (Some(source), false) = (offline_bundle, *agent == AgentId::Mock) {
+            let result = self.install_from_bundle(agent, &source, options)?;
+            let mut resolved = LockedAgent::default();
+            for artifact in &result.artifacts {
+                let (slot, version) = match artifact.kind {
+                    InstalledArtifactKind::NativeAgent => (&mut resolved.native, None),
+                    InstalledArtifactKind::AgentProcess => {
+                        (&mut resolved.agent_process, artifact.version.clone())
+                    }
+                };
+                *slot = Some(LockedArtifact {
+                    source: artifact.source,
+                    version,
+                    digests: BTreeMap::new(),
+                });
+            }
+            return Ok((result, resolved));
+        }
 
         let mut artifacts = Vec::new();
         let mut already_installed = true;
+        let mut resolved = LockedAgent::default();
 
         if agent.native_required() {
-            let native_artifact = self.install_native(agent, &options)?;
+            let native_artifact = self.install_native(agent, options, policy)?;
             if native_artifact.is_some() {
                 already_installed = false;
             }
-            if let Some(artifact) = native_artifact {
+            if let Some((artifact, version)) = native_artifact {
+                resolved.native = Some(LockedArtifact {
+                    source: artifact.source,
+                    version,
+                    digests: policy.take_verified(),
+                });
                 artifacts.push(artifact);
             }
         }
 
-        let agent_process_artifact = self.install_agent_process(agent, &options)?;
+        let agent_process_artifact = self.install_agent_process(agent, options, policy)?;
         if agent_process_artifact.is_some() {
             already_installed = false;
         }
         if let Some(artifact) = agent_process_artifact {
+            resolved.agent_process = Some(LockedArtifact {
+                source: artifact.source,
+                version: artifact.version.clone(),
+                digests: policy.take_verified(),
+            });
             artifacts.push(artifact);
         }
 
-        Ok(InstallResult {
-            artifacts,
-            already_installed,
-        })
+        Ok((
+            InstallResult {
+                artifacts,
+                already_installed,
+                unverified_downloads: policy.take_unverified(),
+            },
+            resolved,
+        ))
     }
 
-    pub fn is_installed(&self, agent: AgentId) -> bool {
+    pub fn is_installed(&self, agent: &AgentId) -> bool {
         let native_ok = !agent.native_required() || self.native_installed(agent);
         native_ok && self.agent_process_status(agent).is_some()
     }
 
-    pub fn version(&self, agent: AgentId) -> Result<Option<String>, AgentError> {
-        if agent == AgentId::Mock {
+    pub fn version(&self, agent: &AgentId) -> Result<Option<String>, AgentError> {
+        if *agent == AgentId::Mock {
             return Ok(Some("builtin".to_string()));
         }
+        if matches!(agent, AgentId::Custom(_)) {
+            return Ok(self
+                .agent_process_status(agent)
+                .and_then(|status| status.version));
+        }
         let path = self.resolve_binary(agent)?;
         for args in [["--version"], ["version"], ["-V"]] {
             let output = Command::new(&path).args(args).output();
@@ -374,10 +818,18 @@ impl AgentManager {
         Ok(None)
     }
 
-    pub fn resolve_binary(&self, agent: AgentId) -> Result<PathBuf, AgentError> {
-        if agent == AgentId::Mock {
+    pub fn resolve_binary(&self, agent: &AgentId) -> Result<PathBuf, AgentError> {
+        if *agent == AgentId::Mock {
             return Ok(self.binary_path(agent));
         }
+        if let Some(definition) = self.custom_definition(agent) {
+            let launcher = self.agent_process_path(agent);
+            return custom_command_path(&definition)
+                .or_else(|| launcher.exists().then_some(launcher))
+                .ok_or(AgentError::BinaryNotFound {
+                    agent: agent.clone(),
+                });
+        }
         let path = self.binary_path(agent);
         if path.exists() {
             return Ok(path);
@@ -385,23 +837,27 @@ impl AgentManager {
         if let Some(path) = find_in_path(agent.binary_name()) {
             return Ok(path);
         }
-        Err(AgentError::BinaryNotFound { agent })
+        Err(AgentError::BinaryNotFound {
+            agent: agent.clone(),
+        })
     }
 
     pub fn resolve_agent_process(
         &self,
-        agent: AgentId,
+        agent: &AgentId,
     ) -> Result<AgentProcessLaunchSpec, AgentError> {
-        if agent == AgentId::Mock {
+        if *agent == AgentId::Mock {
             return Ok(AgentProcessLaunchSpec {
                 program: self.agent_process_path(agent),
                 args: Vec::new(),
                 env: HashMap::new(),
                 source: InstallSource::Builtin,
                 version: Some("builtin".to_string()),
+                framing: None,
             });
         }
 
+        let definition = self.custom_definition(agent);
         let launcher = self.agent_process_path(agent);
         if launcher.exists() {
             return Ok(AgentProcessLaunchSpec {
@@ -410,11 +866,28 @@ impl AgentManager {
                 env: HashMap::new(),
                 source: InstallSource::LocalPath,
                 version: None,
+                framing: definition
+                    .as_ref()
+                    .and_then(|definition| definition.framing.clone())
+                    .or(self.agent_process_metadata(agent).framing),
             });
         }
 
+        if let Some(definition) = definition {
+            if let Some(program) = custom_command_path(&definition) {
+                return Ok(AgentProcessLaunchSpec {
+                    program,
+                    args: definition.args.clone(),
+                    env: definition.env.clone().into_iter().collect(),
+                    source: InstallSource::LocalPath,
+                    version: None,
+                    framing: definition.framing.clone(),
+                });
+            }
+        }
+
         if let Some(bin) = agent.agent_process_binary_hint().and_then(find_in_path) {
-            let args = if agent == AgentId::Opencode {
+            let args = if *agent == AgentId::Opencode {
                 vec!["acp".to_string()]
             } else {
                 Vec::new()
@@ -425,10 +898,11 @@ impl AgentManager {
                 env: HashMap::new(),
                 source: InstallSource::LocalPath,
                 version: None,
+                framing: None,
             });
         }
 
-        if agent == AgentId::Opencode {
+        if *agent == AgentId::Opencode {
             let native = self.resolve_binary(agent)?;
             return Ok(AgentProcessLaunchSpec {
                 program: native,
@@ -436,56 +910,97 @@ impl AgentManager {
                 env: HashMap::new(),
                 source: InstallSource::LocalPath,
                 version: None,
+                framing: None,
             });
         }
 
         Err(AgentError::AgentProcessNotFound {
-            agent,
+            agent: agent.clone(),
             hint: Some("run install to provision ACP agent process".to_string()),
         })
     }
 
-    fn native_installed(&self, agent: AgentId) -> bool {
+    fn agent_process_metadata(&self, agent: &AgentId) -> AgentProcessMetadata {
+        fs::read(self.agent_process_metadata_path(agent))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Record registry launch settings next to the launcher, or remove a
+    /// stale record when there are none.
+    fn write_agent_process_metadata(
+        &self,
+        agent: &AgentId,
+        metadata: &AgentProcessMetadata,
+    ) -> Result<(), AgentError> {
+        let path = self.agent_process_metadata_path(agent);
+        if metadata.framing.is_none() {
+            match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => return Ok(()),
+            }
+        }
+        let bytes = serde_json::to_vec_pretty(metadata)
+            .map_err(|err| AgentError::ExtractFailed(err.to_string()))?;
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    fn native_installed(&self, agent: &AgentId) -> bool {
         self.binary_path(agent).exists() || find_in_path(agent.binary_name()).is_some()
     }
 
+    /// Returns the installed artifact and the version the installer
+    /// resolved, which reinstalls can request to get the same binary.
     fn install_native(
         &self,
-        agent: AgentId,
+        agent: &AgentId,
         options: &InstallOptions,
-    ) -> Result<Option<InstalledArtifact>, AgentError> {
+        policy: &ArtifactPolicy,
+    ) -> Result<Option<(InstalledArtifact, Option<String>)>, AgentError> {
         if !options.reinstall && self.native_installed(agent) {
             return Ok(None);
         }
 
         let path = self.binary_path(agent);
-        match agent {
-            AgentId::Claude => install_claude(&path, self.platform, options.version.as_deref())?,
-            AgentId::Codex => install_codex(&path, self.platform, options.version.as_deref())?,
+        let version = options.version.as_deref();
+        let resolved_version = match agent {
+            AgentId::Claude => Some(install_claude(&path, self.platform, version, policy)?),
+            AgentId::Codex => {
+                install_codex(&path, self.platform, version, policy)?;
+                options.version.clone()
+            }
             AgentId::Opencode => {
-                install_opencode(&path, self.platform, options.version.as_deref())?
+                install_opencode(&path, self.platform, version, policy)?;
+                options.version.clone()
             }
-            AgentId::Amp => install_amp(&path, self.platform, options.version.as_deref())?,
-            AgentId::Pi | AgentId::Cursor => {
+            AgentId::Amp => Some(install_amp(&path, self.platform, version, policy)?),
+            AgentId::Pi | AgentId::Cursor | AgentId::Custom(_) => {
                 return Ok(None);
             }
             AgentId::Mock => {
                 write_text_file(&path, "#!/usr/bin/env sh\nexit 0\n")?;
+                None
             }
-        }
+        };
 
-        Ok(Some(InstalledArtifact {
-            kind: InstalledArtifactKind::NativeAgent,
-            path,
-            version: self.version(agent).ok().flatten(),
-            source: InstallSource::Fallback,
-        }))
+        Ok(Some((
+            InstalledArtifact {
+                kind: InstalledArtifactKind::NativeAgent,
+                path,
+                version: self.version(agent).ok().flatten(),
+                source: InstallSource::Fallback,
+            },
+            resolved_version,
+        )))
     }
 
     fn install_agent_process(
         &self,
-        agent: AgentId,
+        agent: &AgentId,
         options: &InstallOptions,
+        policy: &ArtifactPolicy,
     ) -> Result<Option<InstalledArtifact>, AgentError> {
         if !options.reinstall {
             if self.agent_process_status(agent).is_some() {
@@ -493,7 +1008,7 @@ impl AgentManager {
             }
         }
 
-        if agent == AgentId::Mock {
+        if *agent == AgentId::Mock {
             let path = self.agent_process_path(agent);
             write_mock_agent_process_launcher(&path)?;
             return Ok(Some(InstalledArtifact {
@@ -504,7 +1019,13 @@ impl AgentManager {
             }));
         }
 
-        if let Some(artifact) = self.install_agent_process_from_registry(agent, options)? {
+        if let Some(definition) = self.custom_definition(agent) {
+            return self
+                .install_custom_agent_process(agent, &definition, options, policy)
+                .map(Some);
+        }
+
+        if let Some(artifact) = self.install_agent_process_from_registry(agent, options, policy)? {
             return Ok(Some(artifact));
         }
 
@@ -512,8 +1033,8 @@ impl AgentManager {
         Ok(Some(artifact))
     }
 
-    fn agent_process_status(&self, agent: AgentId) -> Option<AgentProcessStatus> {
-        if agent == AgentId::Mock {
+    fn agent_process_status(&self, agent: &AgentId) -> Option<AgentProcessStatus> {
+        if *agent == AgentId::Mock {
             return Some(AgentProcessStatus {
                 source: InstallSource::Builtin,
                 version: Some("builtin".to_string()),
@@ -528,7 +1049,10 @@ impl AgentManager {
             });
         }
 
-        agent.agent_process_binary_hint().and_then(find_in_path)?;
+        match self.custom_definition(agent) {
+            Some(definition) => custom_command_path(&definition)?,
+            None => find_in_path(agent.agent_process_binary_hint()?)?,
+        };
         Some(AgentProcessStatus {
             source: InstallSource::LocalPath,
             version: None,
@@ -537,10 +1061,11 @@ impl AgentManager {
 
     fn install_agent_process_from_registry(
         &self,
-        agent: AgentId,
+        agent: &AgentId,
         options: &InstallOptions,
+        policy: &ArtifactPolicy,
     ) -> Result<Option<InstalledArtifact>, AgentError> {
-        let Some(registry_id) = agent.agent_process_registry_id() else {
+        let Some(registry_id) = self.agent_process_registry_id(agent) else {
             return Ok(None);
         };
 
@@ -548,13 +1073,22 @@ impl AgentManager {
         let Some(entry) = registry.agents.into_iter().find(|a| a.id == registry_id) else {
             return Ok(None);
         };
+        let definition = self.custom_definition(agent);
+        let metadata = AgentProcessMetadata {
+            framing: definition
+                .as_ref()
+                .and_then(|definition| definition.framing.clone())
+                .or(entry.framing.clone()),
+        };
 
         if let Some(npx) = entry.distribution.npx {
             let package =
                 apply_npx_version_override(&npx.package, options.agent_process_version.as_deref());
             let launcher = self.agent_process_path(agent);
-            write_npx_agent_process_launcher(&launcher, &package, &npx.args, &npx.env)?;
+            let (args, env) = with_custom_launch(definition.as_deref(), &npx.args, &npx.env);
+            write_npx_agent_process_launcher(&launcher, &package, &args, &env)?;
             verify_command(&launcher, &[])?;
+            self.write_agent_process_metadata(agent, &metadata)?;
             return Ok(Some(InstalledArtifact {
                 kind: InstalledArtifactKind::AgentProcess,
                 path: launcher,
@@ -571,18 +1105,26 @@ impl AgentManager {
             let key = self.platform.registry_key();
             if let Some(target) = binary.get(key) {
                 let archive_url = Url::parse(&target.archive)?;
-                let payload = download_bytes(&archive_url)?;
+                let payload = download_artifact(&archive_url, policy, Some(&target.digest))?;
+
+                // Unpack beside the current install and swap it in only once
+                // the archive is known to be good.
                 let root = self.agent_process_storage_dir(agent);
+                let staging = tempfile::tempdir_in(self.install_dir.join("agent_processes"))?;
+                unpack_archive(&payload, &archive_url, staging.path())?;
+                resolve_extracted_command(staging.path(), &target.cmd)?;
                 if root.exists() {
                     fs::remove_dir_all(&root)?;
                 }
-                fs::create_dir_all(&root)?;
-                unpack_archive(&payload, &archive_url, &root)?;
+                fs::rename(staging.keep(), &root)?;
 
                 let cmd_path = resolve_extracted_command(&root, &target.cmd)?;
                 let launcher = self.agent_process_path(agent);
-                write_exec_agent_process_launcher(&launcher, &cmd_path, &target.args, &target.env)?;
+                let (args, env) =
+                    with_custom_launch(definition.as_deref(), &target.args, &target.env);
+                write_exec_agent_process_launcher(&launcher, &cmd_path, &args, &env)?;
                 verify_command(&launcher, &[])?;
+                self.write_agent_process_metadata(agent, &metadata)?;
 
                 return Ok(Some(InstalledArtifact {
                     kind: InstalledArtifactKind::AgentProcess,
@@ -598,7 +1140,7 @@ impl AgentManager {
 
     fn install_agent_process_fallback(
         &self,
-        agent: AgentId,
+        agent: &AgentId,
         options: &InstallOptions,
     ) -> Result<InstalledArtifact, AgentError> {
         let launcher = self.agent_process_path(agent);
@@ -647,9 +1189,16 @@ impl AgentManager {
             AgentId::Mock => {
                 write_mock_agent_process_launcher(&launcher)?;
             }
+            AgentId::Custom(_) => {
+                return Err(AgentError::AgentProcessNotFound {
+                    agent: agent.clone(),
+                    hint: None,
+                });
+            }
         }
 
         verify_command(&launcher, &[])?;
+        self.write_agent_process_metadata(agent, &AgentProcessMetadata::default())?;
 
         Ok(InstalledArtifact {
             kind: InstalledArtifactKind::AgentProcess,
@@ -658,6 +1207,327 @@ impl AgentManager {
             source: InstallSource::Fallback,
         })
     }
+
+    fn install_custom_agent_process(
+        &self,
+        agent: &AgentId,
+        definition: &CustomAgentDefinition,
+        options: &InstallOptions,
+        policy: &ArtifactPolicy,
+    ) -> Result<InstalledArtifact, AgentError> {
+        let launcher = self.agent_process_path(agent);
+        let env: HashMap<String, String> = definition.env.clone().into_iter().collect();
+        let (version, source) = match &definition.install {
+            CustomAgentInstall::Registry(registry_id) => {
+                return self
+                    .install_agent_process_from_registry(agent, options, policy)?
+                    .ok_or_else(|| AgentError::AgentProcessNotFound {
+                        agent: agent.clone(),
+                        hint: Some(format!(
+                            "ACP registry has no '{registry_id}' entry for this platform"
+                        )),
+                    });
+            }
+            CustomAgentInstall::Npx(package) => {
+                let package =
+                    apply_npx_version_override(package, options.agent_process_version.as_deref());
+                write_npx_agent_process_launcher(&launcher, &package, &definition.args, &env)?;
+                verify_command(&launcher, &[])?;
+                (extract_npx_version(&package), InstallSource::Custom)
+            }
+            CustomAgentInstall::Command(command) => {
+                let program = custom_command_path(definition).ok_or_else(|| {
+                    AgentError::AgentProcessNotFound {
+                        agent: agent.clone(),
+                        hint: Some(format!("'{command}' is not an executable path or on PATH")),
+                    }
+                })?;
+                write_exec_agent_process_launcher(&launcher, &program, &definition.args, &env)?;
+                (None, InstallSource::LocalPath)
+            }
+        };
+
+        self.write_agent_process_metadata(
+            agent,
+            &AgentProcessMetadata {
+                framing: definition.framing.clone(),
+            },
+        )?;
+
+        Ok(InstalledArtifact {
+            kind: InstalledArtifactKind::AgentProcess,
+            path: launcher,
+            version,
+            source,
+        })
+    }
+
+    /// Packs the given agents, or every agent with an installed launcher when
+    /// `agents` is empty, into a bundle archive at `destination`. npx-based
+    /// agents are installed into the bundle with `npm`, so exporting needs
+    /// network access even though importing does not.
+    pub fn export_bundle(
+        &self,
+        agents: &[AgentId],
+        destination: &Path,
+    ) -> Result<BundleManifest, AgentError> {
+        let agents = if agents.is_empty() {
+            self.agents()
+                .into_iter()
+                .filter(|agent| *agent != AgentId::Mock && self.agent_process_path(agent).exists())
+                .collect()
+        } else {
+            agents.to_vec()
+        };
+
+        let staging = tempfile::tempdir()?;
+        let mut manifest = BundleManifest {
+            platform: self.platform.registry_key().to_string(),
+            agents: Vec::new(),
+            custom_agents: Vec::new(),
+        };
+        for agent in &agents {
+            manifest
+                .agents
+                .push(self.stage_bundled_agent(agent, staging.path())?);
+            if let Some(definition) = self.custom_definition(agent) {
+                manifest.custom_agents.push((*definition).clone());
+            }
+        }
+        bundle::pack(staging.path(), &manifest, destination)?;
+        Ok(manifest)
+    }
+
+    /// Installs every agent in a bundle archive or unpacked bundle directory
+    /// and registers the custom agents it defines. Nothing is downloaded.
+    pub fn import_bundle(
+        &self,
+        source: &Path,
+        options: &InstallOptions,
+    ) -> Result<Vec<(AgentId, InstallResult)>, AgentError> {
+        let bundle = bundle::open(source)?;
+        self.check_bundle_platform(&bundle.manifest)?;
+        for definition in &bundle.manifest.custom_agents {
+            self.register_custom_agent(definition.clone())?;
+        }
+        bundle
+            .manifest
+            .agents
+            .iter()
+            .map(|entry| {
+                let agent = self.parse_agent(&entry.agent).ok_or_else(|| {
+                    AgentError::InvalidBundle(format!("unknown agent {}", entry.agent))
+                })?;
+                let _lock = self.lock_agent(&agent)?;
+                let result = self.install_bundled_agent(&agent, &bundle.root, entry, options)?;
+                Ok((agent, result))
+            })
+            .collect()
+    }
+
+    fn install_from_bundle(
+        &self,
+        agent: &AgentId,
+        source: &Path,
+        options: &InstallOptions,
+    ) -> Result<InstallResult, AgentError> {
+        let bundle = bundle::open(source)?;
+        self.check_bundle_platform(&bundle.manifest)?;
+        let entry = bundle.manifest.agent(agent.as_str()).ok_or_else(|| {
+            AgentError::InvalidBundle(format!("{} does not contain {agent}", source.display()))
+        })?;
+        self.install_bundled_agent(agent, &bundle.root, entry, options)
+    }
+
+    fn check_bundle_platform(&self, manifest: &BundleManifest) -> Result<(), AgentError> {
+        let platform = self.platform.registry_key();
+        if manifest.platform != platform {
+            return Err(AgentError::InvalidBundle(format!(
+                "bundle was built for {}, not {platform}",
+                manifest.platform
+            )));
+        }
+        Ok(())
+    }
+
+    /// Copy one agent's native binary and agent process files into
+    /// `staging`, returning how to recreate its launcher.
+    fn stage_bundled_agent(
+        &self,
+        agent: &AgentId,
+        staging: &Path,
+    ) -> Result<BundledAgent, AgentError> {
+        let native = if agent.native_required() {
+            fs::copy(
+                self.resolve_binary(agent)?,
+                staging.join(agent.binary_name()),
+            )?;
+            Some(BundledNative {
+                path: agent.binary_name().to_string(),
+                version: self.version(agent).ok().flatten(),
+            })
+        } else {
+            None
+        };
+
+        let (mut command, env) =
+            read_launcher(&self.agent_process_path(agent)).ok_or_else(|| {
+                AgentError::AgentProcessNotFound {
+                    agent: agent.clone(),
+                    hint: Some("only agents installed with a launcher can be bundled".to_string()),
+                }
+            })?;
+        let storage = Path::new("agent_processes").join(agent.as_str());
+        let mut version = None;
+        if command[0] == "npx" {
+            // `npx -y <package> [args...]`: vendor the package and run its bin
+            // directly.
+            let package_idx = command
+                .iter()
+                .skip(1)
+                .position(|arg| !arg.starts_with('-'))
+                .map(|idx| idx + 1)
+                .ok_or_else(|| {
+                    AgentError::InvalidBundle(format!("{agent} launcher names no npx package"))
+                })?;
+            let package = command[package_idx].clone();
+            let name = split_package_version(&package)
+                .map(|(name, _)| name)
+                .unwrap_or(&package);
+            let (bin, package_version) =
+                bundle::vendor_npm_package(&package, name, &staging.join(&storage))?;
+            let bin = bin
+                .strip_prefix(staging)
+                .map_err(|err| AgentError::ExtractFailed(err.to_string()))?;
+            command.splice(..=package_idx, [bin.to_string_lossy().into_owned()]);
+            version = package_version;
+        } else {
+            let installed = self.install_dir.join(&storage);
+            if installed.is_dir() {
+                bundle::copy_dir_all(&installed, &staging.join(&storage))?;
+            }
+            if let Ok(relative) = Path::new(&command[0]).strip_prefix(&self.install_dir) {
+                command[0] = relative.to_string_lossy().into_owned();
+            }
+        }
+
+        Ok(BundledAgent {
+            agent: agent.as_str().to_string(),
+            native,
+            agent_process: Some(BundledAgentProcess {
+                command,
+                env: env.into_iter().collect(),
+                framing: self.agent_process_metadata(agent).framing,
+                version,
+            }),
+        })
+    }
+
+    fn install_bundled_agent(
+        &self,
+        agent: &AgentId,
+        root: &Path,
+        entry: &BundledAgent,
+        options: &InstallOptions,
+    ) -> Result<InstallResult, AgentError> {
+        fs::create_dir_all(self.install_dir.join("agent_processes"))?;
+        let mut artifacts = Vec::new();
+
+        if agent.native_required() && (options.reinstall || !self.native_installed(agent)) {
+            let native = entry.native.as_ref().ok_or_else(|| {
+                AgentError::InvalidBundle(format!("no native binary for {agent}"))
+            })?;
+            let path = self.binary_path(agent);
+            move_executable(&root.join(&native.path), &path)?;
+            artifacts.push(InstalledArtifact {
+                kind: InstalledArtifactKind::NativeAgent,
+                path,
+                version: native.version.clone(),
+                source: InstallSource::Bundle,
+            });
+        }
+
+        if options.reinstall || self.agent_process_status(agent).is_none() {
+            let process = entry.agent_process.as_ref().ok_or_else(|| {
+                AgentError::InvalidBundle(format!("no agent process for {agent}"))
+            })?;
+            let bundled_storage = root.join("agent_processes").join(agent.as_str());
+            if bundled_storage.is_dir() {
+                let storage = self.agent_process_storage_dir(agent);
+                if storage.exists() {
+                    fs::remove_dir_all(&storage)?;
+                }
+                bundle::copy_dir_all(&bundled_storage, &storage)?;
+            }
+
+            let mut command = process.command.clone();
+            if let Some(program) = command.first_mut() {
+                if Path::new(program.as_str()).is_relative() {
+                    *program = self
+                        .install_dir
+                        .join(program.as_str())
+                        .to_string_lossy()
+                        .into_owned();
+                }
+            }
+            let launcher = self.agent_process_path(agent);
+            let env = process.env.clone().into_iter().collect();
+            write_launcher(&launcher, &command, &env)?;
+            verify_command(&launcher, &[])?;
+            self.write_agent_process_metadata(
+                agent,
+                &AgentProcessMetadata {
+                    framing: process.framing.clone(),
+                },
+            )?;
+            artifacts.push(InstalledArtifact {
+                kind: InstalledArtifactKind::AgentProcess,
+                path: launcher,
+                version: process.version.clone(),
+                source: InstallSource::Bundle,
+            });
+        }
+
+        Ok(InstallResult {
+            already_installed: artifacts.is_empty(),
+            artifacts,
+            unverified_downloads: Vec::new(),
+        })
+    }
+}
+
+/// Where a custom agent's `command` install points, when it exists.
+fn custom_command_path(definition: &CustomAgentDefinition) -> Option<PathBuf> {
+    let CustomAgentInstall::Command(command) = &definition.install else {
+        return None;
+    };
+    let path = Path::new(command);
+    if path.components().count() > 1 {
+        return path.exists().then(|| path.to_path_buf());
+    }
+    find_in_path(command)
+}
+
+/// Registry launch args and env with a custom agent's own appended.
+fn with_custom_launch(
+    definition: Option<&CustomAgentDefinition>,
+    args: &[String],
+    env: &HashMap<String, String>,
+) -> (Vec<String>, HashMap<String, String>) {
+    let mut args = args.to_vec();
+    let mut env = env.clone();
+    if let Some(definition) = definition {
+        args.extend(definition.args.iter().cloned());
+        env.extend(definition.env.clone());
+    }
+    (args, env)
+}
+
+/// Contents of [`AgentManager::agent_process_metadata_path`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AgentProcessMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    framing: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -676,6 +1546,8 @@ struct RegistryAgent {
     id: String,
     version: Option<String>,
     distribution: RegistryDistribution,
+    #[serde(default)]
+    framing: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -703,6 +1575,9 @@ struct RegistryBinaryTarget {
     args: Vec<String>,
     #[serde(default)]
     env: HashMap<String, String>,
+    /// Published `sha256` and `signature` of the archive.
+    #[serde(flatten)]
+    digest: ArtifactDigest,
 }
 
 #[derive(Debug, Error)]
@@ -732,6 +1607,87 @@ pub enum AgentError {
     RegistryParse(String),
     #[error("command verification failed: {0}")]
     VerifyFailed(String),
+    #[error("invalid custom agent {id}: {reason}")]
+    InvalidCustomAgent { id: String, reason: String },
+    #[error("sha256 mismatch for {url}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("no sha256 known for {url}")]
+    MissingDigest { url: String },
+    #[error("signature verification failed for {url}: {reason}")]
+    SignatureInvalid { url: String, reason: String },
+    #[error("invalid agent bundle: {0}")]
+    InvalidBundle(String),
+    #[error("lockfile not satisfied: {0}")]
+    LockMismatch(String),
+}
+
+/// Remove a file or directory tree. Returns whether anything was there.
+/// Copies of an agent's installed files, taken in a staging dir under
+/// `agent_processes` so `gc` prunes them if the process dies mid-install.
+struct InstallBackup {
+    dir: tempfile::TempDir,
+    entries: Vec<(PathBuf, Option<PathBuf>)>,
+}
+
+impl InstallBackup {
+    fn create(
+        install_dir: &Path,
+        paths: impl IntoIterator<Item = PathBuf>,
+    ) -> Result<Self, AgentError> {
+        let dir = tempfile::tempdir_in(install_dir.join("agent_processes"))?;
+        let mut entries = Vec::new();
+        for (index, path) in paths.into_iter().enumerate() {
+            let copy = dir.path().join(index.to_string());
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    entries.push((path, None));
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if metadata.is_dir() {
+                bundle::copy_dir_all(&path, &copy)?;
+            } else if metadata.is_symlink() {
+                bundle::copy_symlink(&path, &copy)?;
+            } else {
+                fs::copy(&path, &copy)?;
+            }
+            entries.push((path, Some(copy)));
+        }
+        Ok(Self { dir, entries })
+    }
+
+    /// Replaces whatever is at each path with its copy, removing paths that
+    /// did not exist when the backup was taken.
+    fn restore(self) -> Result<(), AgentError> {
+        for (path, copy) in &self.entries {
+            remove_path(path)?;
+            if let Some(copy) = copy {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(copy, path)?;
+            }
+        }
+        drop(self.dir);
+        Ok(())
+    }
+}
+
+fn remove_path(path: &Path) -> Result<bool, AgentError> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+    result?;
+    Ok(true)
 }
 
 fn fallback_npx_package(base: &str, version: Option<&str>) -> String {
@@ -813,6 +1769,23 @@ fn write_mock_agent_process_launcher(path: &Path) -> Result<(), AgentError> {
     write_text_file(path, script)
 }
 
+/// Write an agent process launcher that replays a transcript recorded with
+/// `SANDBOX_AGENT_ACP_TRANSCRIPT_DIR`, by running `<sandbox_agent>
+/// replay-agent <transcript>`. Installing it as an agent's launcher lets a
+/// recorded session be reproduced without the real agent.
+pub fn write_replay_agent_process_launcher(
+    path: &Path,
+    sandbox_agent: &Path,
+    transcript: &Path,
+) -> Result<(), AgentError> {
+    let command = [
+        sandbox_agent.to_string_lossy().into_owned(),
+        "replay-agent".to_string(),
+        transcript.to_string_lossy().into_owned(),
+    ];
+    write_launcher(path, &command, &HashMap::new())
+}
+
 fn write_launcher(
     path: &Path,
     command: &[String],
@@ -863,6 +1836,62 @@ fn write_launcher(
     Ok(())
 }
 
+/// Recover the command and environment from a launcher written by
+/// [`write_launcher`]. Returns `None` for Windows launchers or anything else
+/// it did not write.
+fn read_launcher(path: &Path) -> Option<(Vec<String>, HashMap<String, String>)> {
+    if cfg!(windows) {
+        return None;
+    }
+    let script = fs::read_to_string(path).ok()?;
+    let body = script.strip_prefix("#!/usr/bin/env sh\nset -e\n")?;
+
+    // Split into shell words; the launcher only quotes with `'...'` and
+    // `'\''`, plus the trailing `"$@"`.
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    word.push(c);
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            '"' => {}
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    let mut env = HashMap::new();
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        match word.as_str() {
+            "export" => {
+                let assignment = words.next()?;
+                let (key, value) = assignment.split_once('=')?;
+                env.insert(key.to_string(), value.to_string());
+            }
+            "exec" => {
+                let mut command: Vec<String> = words.collect();
+                if command.pop().as_deref() != Some("$@") || command.is_empty() {
+                    return None;
+                }
+                return Some((command, env));
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
 fn shell_escape(value: &str) -> String {
     value.replace('\'', "'\\''")
 }
@@ -993,11 +2022,24 @@ fn download_bytes(url: &Url) -> Result<Vec<u8>, AgentError> {
     Ok(bytes)
 }
 
+/// Download an install artifact and check it against `policy` before any of
+/// it is written to disk.
+fn download_artifact(
+    url: &Url,
+    policy: &ArtifactPolicy,
+    published: Option<&ArtifactDigest>,
+) -> Result<Vec<u8>, AgentError> {
+    let bytes = download_bytes(url)?;
+    policy.verify(url, &bytes, published)?;
+    Ok(bytes)
+}
+
 fn install_claude(
     path: &Path,
     platform: Platform,
     version: Option<&str>,
-) -> Result<(), AgentError> {
+    policy: &ArtifactPolicy,
+) -> Result<String, AgentError> {
     let version = match version {
         Some(version) => version.to_string(),
         None => {
@@ -1020,15 +2062,36 @@ fn install_claude(
         Platform::WindowsArm64 => "win32-arm64",
     };
 
-    let url = Url::parse(&format!(
-        "https://storage.googleapis.com/claude-code-dist-86c565f3-f756-42ad-8dfa-d59b1c096819/claude-code-releases/{version}/{platform_segment}/claude"
-    ))?;
-    let bytes = download_bytes(&url)?;
+    let release = format!(
+        "https://storage.googleapis.com/claude-code-dist-86c565f3-f756-42ad-8dfa-d59b1c096819/claude-code-releases/{version}"
+    );
+    let url = Url::parse(&format!("{release}/{platform_segment}/claude"))?;
+    let published = claude_published_digest(&release, platform_segment);
+    let bytes = download_artifact(&url, policy, published.as_ref())?;
     write_executable(path, &bytes)?;
-    Ok(())
+    Ok(version)
+}
+
+/// The sha256 listed for `platform_segment` in a Claude release's
+/// `manifest.json`, when it can be fetched.
+fn claude_published_digest(release: &str, platform_segment: &str) -> Option<ArtifactDigest> {
+    let url = Url::parse(&format!("{release}/manifest.json")).ok()?;
+    let manifest: serde_json::Value = serde_json::from_slice(&download_bytes(&url).ok()?).ok()?;
+    let checksum = manifest
+        .pointer(&format!("/platforms/{platform_segment}/checksum"))?
+        .as_str()?;
+    Some(ArtifactDigest {
+        sha256: Some(checksum.to_string()),
+        signature: None,
+    })
 }
 
-fn install_amp(path: &Path, platform: Platform, version: Option<&str>) -> Result<(), AgentError> {
+fn install_amp(
+    path: &Path,
+    platform: Platform,
+    version: Option<&str>,
+    policy: &ArtifactPolicy,
+) -> Result<String, AgentError> {
     let version = match version {
         Some(version) => version.to_string(),
         None => {
@@ -1053,12 +2116,17 @@ fn install_amp(path: &Path, platform: Platform, version: Option<&str>) -> Result
     let url = Url::parse(&format!(
         "https://storage.googleapis.com/amp-public-assets-prod-0/cli/{version}/amp-{platform_segment}"
     ))?;
-    let bytes = download_bytes(&url)?;
+    let bytes = download_artifact(&url, policy, None)?;
     write_executable(path, &bytes)?;
-    Ok(())
+    Ok(version)
 }
 
-fn install_codex(path: &Path, platform: Platform, version: Option<&str>) -> Result<(), AgentError> {
+fn install_codex(
+    path: &Path,
+    platform: Platform,
+    version: Option<&str>,
+    policy: &ArtifactPolicy,
+) -> Result<(), AgentError> {
     let target = match platform {
         Platform::LinuxX64 | Platform::LinuxX64Musl => "x86_64-unknown-linux-musl",
         Platform::LinuxArm64 => "aarch64-unknown-linux-musl",
@@ -1077,7 +2145,7 @@ fn install_codex(path: &Path, platform: Platform, version: Option<&str>) -> Resu
         ))?,
     };
 
-    let bytes = download_bytes(&url)?;
+    let bytes = download_artifact(&url, policy, None)?;
     let temp_dir = tempfile::tempdir()?;
     let cursor = io::Cursor::new(bytes);
     let mut archive = tar::Archive::new(GzDecoder::new(cursor));
@@ -1099,6 +2167,7 @@ fn install_opencode(
     path: &Path,
     platform: Platform,
     version: Option<&str>,
+    policy: &ArtifactPolicy,
 ) -> Result<(), AgentError> {
     match platform {
         Platform::MacosArm64 => {
@@ -1110,7 +2179,7 @@ fn install_opencode(
                     "https://github.com/anomalyco/opencode/releases/latest/download/opencode-darwin-arm64.zip",
                 )?,
             };
-            install_zip_binary(path, &url, "opencode")
+            install_zip_binary(path, &url, "opencode", policy)
         }
         Platform::MacosX64 => {
             let url = match version {
@@ -1121,7 +2190,7 @@ fn install_opencode(
                     "https://github.com/anomalyco/opencode/releases/latest/download/opencode-darwin-x64.zip",
                 )?,
             };
-            install_zip_binary(path, &url, "opencode")
+            install_zip_binary(path, &url, "opencode", policy)
         }
         _ => {
             let platform_segment = match platform {
@@ -1141,7 +2210,7 @@ fn install_opencode(
                 ))?,
             };
 
-            let bytes = download_bytes(&url)?;
+            let bytes = download_artifact(&url, policy, None)?;
             let temp_dir = tempfile::tempdir()?;
             let cursor = io::Cursor::new(bytes);
             let mut archive = tar::Archive::new(GzDecoder::new(cursor));
@@ -1155,8 +2224,13 @@ fn install_opencode(
     }
 }
 
-fn install_zip_binary(path: &Path, url: &Url, binary_name: &str) -> Result<(), AgentError> {
-    let bytes = download_bytes(url)?;
+fn install_zip_binary(
+    path: &Path,
+    url: &Url,
+    binary_name: &str,
+    policy: &ArtifactPolicy,
+) -> Result<(), AgentError> {
+    let bytes = download_artifact(url, policy, None)?;
     let reader = io::Cursor::new(bytes);
     let mut archive =
         zip::ZipArchive::new(reader).map_err(|err| AgentError::ExtractFailed(err.to_string()))?;
@@ -1321,6 +2395,46 @@ mod tests {
         stream.flush().expect("flush response");
     }
 
+    fn serve_archive_once(bytes: Vec<u8>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind archive server");
+        let addr = listener.local_addr().expect("local addr");
+
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut buffer = [0_u8; 4096];
+                let _ = stream.read(&mut buffer);
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    bytes.len()
+                );
+                stream.write_all(header.as_bytes()).expect("write header");
+                stream.write_all(&bytes).expect("write archive");
+                stream.flush().expect("flush archive");
+            }
+        });
+
+        Url::parse(&format!("http://{addr}/agent.tar.gz")).expect("archive url")
+    }
+
+    fn agent_archive(script: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(script.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "agent/run", script.as_bytes())
+            .expect("append script");
+        builder
+            .into_inner()
+            .expect("finish tar")
+            .finish()
+            .expect("finish gzip")
+    }
+
     #[test]
     fn install_is_idempotent_when_native_and_agent_process_exists() {
         let temp_dir = tempfile::tempdir().expect("create tempdir");
@@ -1328,12 +2442,12 @@ mod tests {
 
         fs::create_dir_all(temp_dir.path().join("agent_processes"))
             .expect("create agent processes dir");
-        fs::write(manager.binary_path(AgentId::Codex), b"stub").expect("write native binary");
-        fs::write(manager.agent_process_path(AgentId::Codex), b"stub")
+        fs::write(manager.binary_path(&AgentId::Codex), b"stub").expect("write native binary");
+        fs::write(manager.agent_process_path(&AgentId::Codex), b"stub")
             .expect("write agent process launcher");
 
         let result = manager
-            .install(AgentId::Codex, InstallOptions::default())
+            .install(&AgentId::Codex, InstallOptions::default())
             .expect("install should succeed");
 
         assert!(result.already_installed);
@@ -1362,14 +2476,14 @@ mod tests {
             .expect("create agent processes dir");
 
         for agent in [AgentId::Claude, AgentId::Codex, AgentId::Opencode] {
-            fs::write(manager.binary_path(agent), b"stub").expect("write native binary");
-            fs::write(manager.agent_process_path(agent), b"stub")
+            fs::write(manager.binary_path(&agent), b"stub").expect("write native binary");
+            fs::write(manager.agent_process_path(&agent), b"stub")
                 .expect("write agent process launcher");
         }
 
         // Pi and Cursor only need agent process launchers (native_required = false).
         for agent in [AgentId::Pi, AgentId::Cursor] {
-            fs::write(manager.agent_process_path(agent), b"stub")
+            fs::write(manager.agent_process_path(&agent), b"stub")
                 .expect("write agent process launcher");
         }
 
@@ -1382,7 +2496,7 @@ mod tests {
             AgentId::Mock,
         ] {
             let result = manager
-                .install(agent, InstallOptions::default())
+                .install(&agent, InstallOptions::default())
                 .expect("install should succeed");
             assert!(
                 result.already_installed,
@@ -1401,7 +2515,7 @@ mod tests {
 
         // Keep native install path satisfied locally so install only provisions agent process.
         write_exec(
-            &manager.binary_path(AgentId::Codex),
+            &manager.binary_path(&AgentId::Codex),
             "#!/usr/bin/env sh\nexit 0\n",
         );
 
@@ -1420,6 +2534,7 @@ mod tests {
                 {
                     "id": "codex-acp",
                     "version": "1.2.3",
+                    "framing": "content-length",
                     "distribution": {
                         "npx": {
                             "package": "@example/codex-acp@1.2.3",
@@ -1434,11 +2549,13 @@ mod tests {
 
         let result = manager
             .install(
-                AgentId::Codex,
+                &AgentId::Codex,
                 InstallOptions {
                     reinstall: false,
                     version: None,
                     agent_process_version: Some("9.9.9".to_string()),
+                    offline_bundle: None,
+                    frozen: false,
                 },
             )
             .expect("install succeeds");
@@ -1453,11 +2570,480 @@ mod tests {
         assert_eq!(agent_process_artifact.version.as_deref(), Some("9.9.9"));
 
         let launcher =
-            fs::read_to_string(manager.agent_process_path(AgentId::Codex)).expect("launcher");
+            fs::read_to_string(manager.agent_process_path(&AgentId::Codex)).expect("launcher");
         assert!(
             launcher.contains("@example/codex-acp@9.9.9"),
             "launcher should include overridden package version"
         );
+
+        let launch = manager
+            .resolve_agent_process(&AgentId::Codex)
+            .expect("resolve agent process");
+        assert_eq!(launch.framing.as_deref(), Some("content-length"));
+    }
+
+    #[test]
+    fn registry_binary_install_verifies_sha256_and_keeps_previous_install() {
+        let _env_lock = env_lock().lock().expect("env lock");
+
+        let temp_dir = tempfile::tempdir().expect("create tempdir");
+        let mut manager = AgentManager::with_platform(temp_dir.path(), Platform::LinuxX64);
+        fs::create_dir_all(manager.agent_process_storage_dir(&AgentId::Pi))
+            .expect("create storage dir");
+        fs::write(
+            manager
+                .agent_process_storage_dir(&AgentId::Pi)
+                .join("previous"),
+            b"old",
+        )
+        .expect("write previous artifact");
+        write_exec(
+            &manager.agent_process_path(&AgentId::Pi),
+            "#!/usr/bin/env sh\n# previous\nexit 0\n",
+        );
+
+        let archive = agent_archive("#!/usr/bin/env sh\nexit 0\n");
+        let registry = |archive_url: Url, sha256: String| {
+            serve_registry_once(serde_json::json!({
+                "agents": [
+                    {
+                        "id": "pi-acp",
+                        "version": "2.0.0",
+                        "distribution": {
+                            "binary": {
+                                "linux-x86_64": {
+                                    "archive": archive_url.as_str(),
+                                    "cmd": "./agent/run",
+                                    "sha256": sha256
+                                }
+                            }
+                        }
+                    }
+                ]
+            }))
+        };
+        let reinstall = InstallOptions {
+            reinstall: true,
+            ..InstallOptions::default()
+        };
+
+        manager.registry_url = registry(serve_archive_once(archive.clone()), "00".repeat(32));
+        let err = manager
+            .install(&AgentId::Pi, reinstall.clone())
+            .expect_err("mismatched digest fails");
+        assert!(
+            matches!(err, AgentError::ChecksumMismatch { .. }),
+            "unexpected error: {err}"
+        );
+        let launcher =
+            fs::read_to_string(manager.agent_process_path(&AgentId::Pi)).expect("launcher");
+        assert!(launcher.contains("# previous"), "launcher was replaced");
+        assert!(manager
+            .agent_process_storage_dir(&AgentId::Pi)
+            .join("previous")
+            .exists());
+
+        manager.registry_url = registry(
+            serve_archive_once(archive.clone()),
+            crate::verify::sha256_hex(&archive),
+        );
+        manager
+            .install(&AgentId::Pi, reinstall)
+            .expect("matching digest installs");
+        let launcher =
+            fs::read_to_string(manager.agent_process_path(&AgentId::Pi)).expect("launcher");
+        assert!(
+            launcher.contains("agent/run"),
+            "launcher should exec archive command"
+        );
+        assert!(!manager
+            .agent_process_storage_dir(&AgentId::Pi)
+            .join("previous")
+            .exists());
+    }
+
+    #[test]
+    fn install_records_lockfile_and_frozen_install_enforces_it() {
+        let _env_lock = env_lock().lock().expect("env lock");
+
+        let temp_dir = tempfile::tempdir().expect("create tempdir");
+        let mut manager = AgentManager::with_platform(temp_dir.path(), Platform::LinuxX64);
+        let archive = agent_archive("#!/usr/bin/env sh\nexit 0\n");
+        let registry = |archive_url: &Url| {
+            serve_registry_once(serde_json::json!({
+                "agents": [
+                    {
+                        "id": "pi-acp",
+                        "version": "2.0.0",
+                        "distribution": {
+                            "binary": {
+                                "linux-x86_64": {
+                                    "archive": archive_url.as_str(),
+                                    "cmd": "./agent/run"
+                                }
+                            }
+                        }
+                    }
+                ]
+            }))
+        };
+        let reinstall = InstallOptions {
+            reinstall: true,
+            ..InstallOptions::default()
+        };
+
+        let first_url = serve_archive_once(archive.clone());
+        manager.registry_url = registry(&first_url);
+        manager
+            .install(&AgentId::Pi, reinstall.clone())
+            .expect("install");
+        let locked = lock::read(&manager.lockfile_path()).expect("read lockfile");
+        assert_eq!(
+            locked.agents["pi"].agent_process,
+            Some(LockedArtifact {
+                source: InstallSource::Registry,
+                version: Some("2.0.0".to_string()),
+                digests: BTreeMap::from([(
+                    first_url.to_string(),
+                    crate::verify::sha256_hex(&archive)
+                )]),
+            })
+        );
+
+        // The registry now points at an archive the lockfile does not pin.
+        manager.registry_url = registry(&serve_archive_once(archive.clone()));
+        let frozen = InstallOptions {
+            frozen: true,
+            ..reinstall.clone()
+        };
+        let err = manager
+            .install(&AgentId::Pi, frozen.clone())
+            .expect_err("unpinned archive fails");
+        assert!(
+            matches!(err, AgentError::LockMismatch(_)),
+            "unexpected error: {err}"
+        );
+        assert_eq!(
+            lock::read(&manager.lockfile_path()).expect("read lockfile"),
+            locked
+        );
+
+        let other_version = InstallOptions {
+            agent_process_version: Some("3.0.0".to_string()),
+            ..frozen.clone()
+        };
+        assert!(matches!(
+            manager.install(&AgentId::Pi, other_version),
+            Err(AgentError::LockMismatch(_))
+        ));
+        assert!(matches!(
+            manager.install(&AgentId::Cursor, frozen),
+            Err(AgentError::LockMismatch(_))
+        ));
+
+        // Without --frozen the install moves the lockfile forward.
+        let second_url = serve_archive_once(archive.clone());
+        manager.registry_url = registry(&second_url);
+        manager
+            .install(&AgentId::Pi, reinstall)
+            .expect("unfrozen reinstall");
+        let locked = lock::read(&manager.lockfile_path()).expect("read lockfile");
+        let digests = &locked.agents["pi"]
+            .agent_process
+            .as_ref()
+            .expect("locked agent process")
+            .digests;
+        assert_eq!(
+            digests.keys().collect::<Vec<_>>(),
+            vec![second_url.as_str()]
+        );
+    }
+
+    #[test]
+    fn failed_frozen_install_leaves_previous_install_unchanged() {
+        let _env_lock = env_lock().lock().expect("env lock");
+
+        let temp_dir = tempfile::tempdir().expect("create tempdir");
+        let mut manager = AgentManager::with_platform(temp_dir.path(), Platform::LinuxX64);
+        let archive_url = serve_archive_once(agent_archive("#!/usr/bin/env sh\nexit 0\n"));
+        manager.registry_url = serve_registry_once(serde_json::json!({
+            "agents": [
+                {
+                    "id": "pi-acp",
+                    "version": "2.0.0",
+                    "distribution": {
+                        "binary": {
+                            "linux-x86_64": {
+                                "archive": archive_url.as_str(),
+                                "cmd": "./agent/run"
+                            }
+                        }
+                    }
+                }
+            ]
+        }));
+        let reinstall = InstallOptions {
+            reinstall: true,
+            ..InstallOptions::default()
+        };
+        manager
+            .install(&AgentId::Pi, reinstall.clone())
+            .expect("install");
+        let before = snapshot_dir(temp_dir.path());
+
+        // The registry switches to an npx package at the locked version; it
+        // installs fine but carries none of the locked digests.
+        let bin_dir = tempfile::tempdir().expect("create bin dir");
+        write_exec(&bin_dir.path().join("npx"), "#!/usr/bin/env sh\nexit 0\n");
+        let original_path = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![bin_dir.path().to_path_buf()];
+        paths.extend(std::env::split_paths(&original_path));
+        let combined_path = std::env::join_paths(paths).expect("join PATH");
+        let _path_guard = EnvVarGuard::set("PATH", &combined_path);
+        manager.registry_url = serve_registry_once(serde_json::json!({
+            "agents": [
+                {
+                    "id": "pi-acp",
+                    "version": "2.0.0",
+                    "distribution": {
+                        "npx": {
+                            "package": "pi-acp@2.0.0"
+                        }
+                    }
+                }
+            ]
+        }));
+        let err = manager
+            .install(
+                &AgentId::Pi,
+                InstallOptions {
+                    frozen: true,
+                    ..reinstall
+                },
+            )
+            .expect_err("npx install does not match the lockfile");
+        assert!(
+            matches!(err, AgentError::LockMismatch(_)),
+            "unexpected error: {err}"
+        );
+        assert_eq!(snapshot_dir(temp_dir.path()), before);
+    }
+
+    fn snapshot_dir(root: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir).expect("read dir") {
+                let path = entry.expect("dir entry").path();
+                let metadata = fs::symlink_metadata(&path).expect("metadata");
+                if metadata.is_dir() {
+                    files.insert(path.clone(), Vec::new());
+                    pending.push(path);
+                } else if metadata.is_symlink() {
+                    let target = fs::read_link(&path).expect("read link");
+                    files.insert(path, target.into_os_string().into_encoded_bytes());
+                } else {
+                    files.insert(path.clone(), fs::read(&path).expect("read file"));
+                }
+            }
+        }
+        files
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bundle_export_vendors_npx_agents_and_installs_offline() {
+        let _env = env_lock().lock().expect("env lock");
+        let _offline = EnvVarGuard::set(OFFLINE_BUNDLE_ENV, std::ffi::OsStr::new(""));
+        let source_dir = tempfile::tempdir().expect("source dir");
+        let target_dir = tempfile::tempdir().expect("target dir");
+        let tools = tempfile::tempdir().expect("tools dir");
+        let bundle_path = tools.path().join("agents.tar.gz");
+
+        // Stands in for `npm install --prefix <dir> ... <package>`.
+        write_exec(
+            &tools.path().join("npm"),
+            r#"#!/usr/bin/env sh
+pkg="$3/node_modules/codex-acp"
+mkdir -p "$pkg"
+printf '{"name":"codex-acp","version":"1.2.3","bin":{"codex-acp":"cli.sh"}}' > "$pkg/package.json"
+printf '#!/usr/bin/env sh\necho vendored "$CODEX_MODE" "$@"\n' > "$pkg/cli.sh"
+chmod +x "$pkg/cli.sh"
+"#,
+        );
+
+        let source = AgentManager::with_platform(source_dir.path(), Platform::LinuxX64);
+        write_exec(
+            &source.binary_path(&AgentId::Codex),
+            "#!/usr/bin/env sh\necho 'codex 0.1.0'\n",
+        );
+        write_npx_agent_process_launcher(
+            &source.agent_process_path(&AgentId::Codex),
+            "codex-acp@1.2.3",
+            &["--acp".to_string()],
+            &HashMap::from([("CODEX_MODE".to_string(), "it's bundled".to_string())]),
+        )
+        .expect("write launcher");
+
+        let manifest = {
+            let mut path = std::ffi::OsString::from(tools.path());
+            path.push(":");
+            path.push(std::env::var_os("PATH").unwrap_or_default());
+            let _path = EnvVarGuard::set("PATH", &path);
+            source
+                .export_bundle(&[AgentId::Codex], &bundle_path)
+                .expect("export bundle")
+        };
+        let process = manifest.agents[0]
+            .agent_process
+            .as_ref()
+            .expect("bundled agent process");
+        assert_eq!(
+            process.command,
+            vec![
+                "agent_processes/codex/node_modules/codex-acp/cli.sh".to_string(),
+                "--acp".to_string()
+            ]
+        );
+        assert_eq!(process.version.as_deref(), Some("1.2.3"));
+        assert_eq!(
+            manifest.agents[0]
+                .native
+                .as_ref()
+                .and_then(|native| native.version.as_deref()),
+            Some("codex 0.1.0")
+        );
+
+        let target = AgentManager::with_platform(target_dir.path(), Platform::LinuxX64);
+        let offline = InstallOptions {
+            offline_bundle: Some(bundle_path.clone()),
+            ..InstallOptions::default()
+        };
+        let result = target
+            .install(&AgentId::Codex, offline.clone())
+            .expect("offline install");
+        assert_eq!(result.artifacts.len(), 2);
+        assert!(result
+            .artifacts
+            .iter()
+            .all(|artifact| artifact.source == InstallSource::Bundle));
+        assert!(target.binary_path(&AgentId::Codex).exists());
+
+        let output = Command::new(target.agent_process_path(&AgentId::Codex))
+            .arg("hello")
+            .output()
+            .expect("run launcher");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "vendored it's bundled --acp hello"
+        );
+        let launcher =
+            fs::read_to_string(target.agent_process_path(&AgentId::Codex)).expect("launcher");
+        assert!(launcher.contains(&target_dir.path().display().to_string()));
+
+        assert!(matches!(
+            target.install(&AgentId::Claude, offline),
+            Err(AgentError::InvalidBundle(_))
+        ));
+    }
+
+    #[test]
+    fn gc_prunes_unused_agent_process_files_and_uninstall_removes_agent() {
+        let temp_dir = tempfile::tempdir().expect("create tempdir");
+        let manager = AgentManager::with_platform(temp_dir.path(), Platform::LinuxX64);
+        let processes = temp_dir.path().join("agent_processes");
+        for dir in [
+            "pi/agent",
+            "cursor/old",
+            "gone",
+            "busy",
+            "amp",
+            "amp-next",
+            "codex/staged",
+            ".tmpfresh",
+            ".tmpstale",
+        ] {
+            fs::create_dir_all(processes.join(dir)).expect("create dir");
+        }
+        write_exec(
+            &processes.join("pi/agent/run"),
+            "#!/usr/bin/env sh\nexit 0\n",
+        );
+        write_exec_agent_process_launcher(
+            &manager.agent_process_path(&AgentId::Pi),
+            &processes.join("pi/agent/run"),
+            &[],
+            &HashMap::new(),
+        )
+        .expect("pi launcher");
+        write_npx_agent_process_launcher(
+            &manager.agent_process_path(&AgentId::Cursor),
+            "cursor-agent-acp",
+            &[],
+            &HashMap::new(),
+        )
+        .expect("cursor launcher");
+        // Only a path under `amp-next` shares a string prefix with `amp`.
+        write_exec_agent_process_launcher(
+            &manager.agent_process_path(&AgentId::Amp),
+            &processes.join("amp-next/run"),
+            &[],
+            &HashMap::new(),
+        )
+        .expect("amp launcher");
+        for file in ["gone-acp", "gone-acp.json", "README"] {
+            fs::write(processes.join(file), "").expect("write file");
+        }
+        // An install in progress that has not written codex's launcher yet.
+        let install_lock = manager.lock_agent(&AgentId::Codex).expect("lock codex");
+        fs::File::open(processes.join(".tmpstale"))
+            .and_then(|dir| dir.set_modified(std::time::SystemTime::now() - 2 * STALE_STAGING_AGE))
+            .expect("age staging dir");
+
+        let mut removed = manager.gc(&["busy"]).expect("gc");
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                processes.join(".tmpstale"),
+                processes.join("amp"),
+                processes.join("amp-next"),
+                processes.join("cursor"),
+                processes.join("gone"),
+                processes.join("gone-acp"),
+                processes.join("gone-acp.json"),
+            ]
+        );
+        for kept in [
+            "pi",
+            "pi-acp",
+            "cursor-acp",
+            "codex",
+            "busy",
+            ".tmpfresh",
+            "README",
+        ] {
+            assert!(processes.join(kept).exists(), "{kept} was removed");
+        }
+        drop(install_lock);
+        assert_eq!(
+            manager.gc(&["busy"]).expect("gc"),
+            vec![processes.join("codex")]
+        );
+
+        let removed = manager.uninstall(&AgentId::Pi).expect("uninstall");
+        assert_eq!(
+            removed,
+            vec![
+                manager.agent_process_path(&AgentId::Pi),
+                processes.join("pi")
+            ]
+        );
+        assert!(!manager.is_installed(&AgentId::Pi));
+        assert!(manager
+            .uninstall(&AgentId::Pi)
+            .expect("uninstall again")
+            .is_empty());
     }
 
     #[test]
@@ -1468,7 +3054,7 @@ mod tests {
         let mut manager = AgentManager::with_platform(temp_dir.path(), Platform::LinuxX64);
 
         write_exec(
-            &manager.binary_path(AgentId::Codex),
+            &manager.binary_path(&AgentId::Codex),
             "#!/usr/bin/env sh\nexit 0\n",
         );
 
@@ -1485,7 +3071,7 @@ mod tests {
         manager.registry_url = serve_registry_once(serde_json::json!({ "agents": [] }));
 
         let result = manager
-            .install(AgentId::Codex, InstallOptions::default())
+            .install(&AgentId::Codex, InstallOptions::default())
             .expect("install succeeds");
         assert!(!result.already_installed);
         let agent_process_artifact = result
@@ -1503,11 +3089,13 @@ mod tests {
 
         let result = manager
             .install(
-                AgentId::Mock,
+                &AgentId::Mock,
                 InstallOptions {
                     reinstall: true,
                     version: None,
                     agent_process_version: None,
+                    offline_bundle: None,
+                    frozen: false,
                 },
             )
             .expect("mock reinstall");
@@ -1542,7 +3130,7 @@ mod tests {
         manager.registry_url = serve_registry_once(serde_json::json!({ "agents": [] }));
 
         let result = manager
-            .install(AgentId::Pi, InstallOptions::default())
+            .install(&AgentId::Pi, InstallOptions::default())
             .expect("pi install succeeds");
 
         // No native artifact (native_required = false).
@@ -1562,7 +3150,7 @@ mod tests {
         assert_eq!(agent_process.source, InstallSource::Fallback);
 
         let launcher =
-            fs::read_to_string(manager.agent_process_path(AgentId::Pi)).expect("read pi launcher");
+            fs::read_to_string(manager.agent_process_path(&AgentId::Pi)).expect("read pi launcher");
         assert!(
             launcher.contains("pi-acp"),
             "pi launcher should reference pi-acp package"
@@ -1570,18 +3158,18 @@ mod tests {
 
         // resolve_agent_process should now find it.
         let spec = manager
-            .resolve_agent_process(AgentId::Pi)
+            .resolve_agent_process(&AgentId::Pi)
             .expect("resolve pi agent process");
         assert_eq!(spec.source, InstallSource::LocalPath);
 
         // is_installed should return true.
-        assert!(manager.is_installed(AgentId::Pi), "pi should be installed");
+        assert!(manager.is_installed(&AgentId::Pi), "pi should be installed");
 
         // Second install should be idempotent.
         // Need a new registry server since the first one was consumed.
         manager.registry_url = serve_registry_once(serde_json::json!({ "agents": [] }));
         let result2 = manager
-            .install(AgentId::Pi, InstallOptions::default())
+            .install(&AgentId::Pi, InstallOptions::default())
             .expect("pi re-install succeeds");
         assert!(
             result2.already_installed,
@@ -1609,7 +3197,7 @@ mod tests {
         manager.registry_url = serve_registry_once(serde_json::json!({ "agents": [] }));
 
         let result = manager
-            .install(AgentId::Cursor, InstallOptions::default())
+            .install(&AgentId::Cursor, InstallOptions::default())
             .expect("cursor install succeeds");
 
         assert!(
@@ -1627,7 +3215,7 @@ mod tests {
             .expect("cursor agent process artifact");
         assert_eq!(agent_process.source, InstallSource::Fallback);
 
-        let launcher = fs::read_to_string(manager.agent_process_path(AgentId::Cursor))
+        let launcher = fs::read_to_string(manager.agent_process_path(&AgentId::Cursor))
             .expect("read cursor launcher");
         assert!(
             launcher.contains("@blowmage/cursor-agent-acp"),
@@ -1635,18 +3223,18 @@ mod tests {
         );
 
         let spec = manager
-            .resolve_agent_process(AgentId::Cursor)
+            .resolve_agent_process(&AgentId::Cursor)
             .expect("resolve cursor agent process");
         assert_eq!(spec.source, InstallSource::LocalPath);
 
         assert!(
-            manager.is_installed(AgentId::Cursor),
+            manager.is_installed(&AgentId::Cursor),
             "cursor should be installed"
         );
 
         manager.registry_url = serve_registry_once(serde_json::json!({ "agents": [] }));
         let result2 = manager
-            .install(AgentId::Cursor, InstallOptions::default())
+            .install(&AgentId::Cursor, InstallOptions::default())
             .expect("cursor re-install succeeds");
         assert!(
             result2.already_installed,
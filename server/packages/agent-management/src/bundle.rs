@@ -0,0 +1,212 @@
+//! Offline agent bundles.
+//!
+//! A bundle is a gzipped tarball (or an unpacked directory) laid out like an
+//! install dir, with a [`BundleManifest`] in `bundle.json` describing how to
+//! recreate each agent's launcher. npx-based agents are bundled with their
+//! `node_modules` so importing needs neither the registry nor npm.
+//! [`AgentManager::export_bundle`](crate::agents::AgentManager::export_bundle)
+//! writes bundles and
+//! [`AgentManager::import_bundle`](crate::agents::AgentManager::import_bundle)
+//! installs from them.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use crate::agents::AgentError;
+use crate::custom::CustomAgentDefinition;
+
+/// Manifest file at the root of a bundle.
+pub const BUNDLE_MANIFEST_FILE: &str = "bundle.json";
+/// Names a bundle archive or directory that installs must use instead of
+/// the network.
+pub const OFFLINE_BUNDLE_ENV: &str = "SANDBOX_AGENT_OFFLINE_BUNDLE";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleManifest {
+    /// ACP registry platform key the binaries were built for, such as
+    /// `linux-x86_64`.
+    pub platform: String,
+    pub agents: Vec<BundledAgent>,
+    /// Definitions of the bundled custom agents, registered on import.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_agents: Vec<CustomAgentDefinition>,
+}
+
+impl BundleManifest {
+    pub fn agent(&self, agent: &str) -> Option<&BundledAgent> {
+        self.agents.iter().find(|entry| entry.agent == agent)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundledAgent {
+    pub agent: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub native: Option<BundledNative>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_process: Option<BundledAgentProcess>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundledNative {
+    /// Path of the binary relative to the bundle root.
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundledAgentProcess {
+    /// Launcher command line. A relative program is a file in the bundle and
+    /// is resolved against the install dir on import.
+    pub command: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framing: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// An opened bundle. Archives are unpacked into a temporary directory that
+/// lives as long as this value.
+pub(crate) struct OpenBundle {
+    pub(crate) root: PathBuf,
+    pub(crate) manifest: BundleManifest,
+    _unpacked: Option<TempDir>,
+}
+
+pub(crate) fn open(source: &Path) -> Result<OpenBundle, AgentError> {
+    let (root, unpacked) = if source.is_dir() {
+        (source.to_path_buf(), None)
+    } else {
+        let file = fs::File::open(source)?;
+        let unpacked = tempfile::tempdir()?;
+        tar::Archive::new(GzDecoder::new(file)).unpack(unpacked.path())?;
+        (unpacked.path().to_path_buf(), Some(unpacked))
+    };
+    let bytes = fs::read(root.join(BUNDLE_MANIFEST_FILE)).map_err(|err| {
+        AgentError::InvalidBundle(format!(
+            "{}: cannot read {BUNDLE_MANIFEST_FILE}: {err}",
+            source.display()
+        ))
+    })?;
+    let manifest = serde_json::from_slice(&bytes)
+        .map_err(|err| AgentError::InvalidBundle(format!("{}: {err}", source.display())))?;
+    Ok(OpenBundle {
+        root,
+        manifest,
+        _unpacked: unpacked,
+    })
+}
+
+/// Write `manifest` into `staging` and pack the directory into a gzipped
+/// tarball at `destination`. Symlinks are stored as symlinks.
+pub(crate) fn pack(
+    staging: &Path,
+    manifest: &BundleManifest,
+    destination: &Path,
+) -> Result<(), AgentError> {
+    let bytes = serde_json::to_vec_pretty(manifest)
+        .map_err(|err| AgentError::ExtractFailed(err.to_string()))?;
+    fs::write(staging.join(BUNDLE_MANIFEST_FILE), bytes)?;
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::File::create(destination)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    builder.follow_symlinks(false);
+    builder.append_dir_all(".", staging)?;
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Install `package` (optionally `name@version`) with its dependencies
+/// under `prefix/node_modules`. Returns the package's bin script and
+/// version.
+pub(crate) fn vendor_npm_package(
+    package: &str,
+    name: &str,
+    prefix: &Path,
+) -> Result<(PathBuf, Option<String>), AgentError> {
+    fs::create_dir_all(prefix)?;
+    let output = Command::new("npm")
+        .arg("install")
+        .arg("--prefix")
+        .arg(prefix)
+        .args(["--no-audit", "--no-fund", "--omit=dev", package])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| AgentError::VerifyFailed(format!("npm failed to execute: {err}")))?;
+    if !output.status.success() {
+        return Err(AgentError::VerifyFailed(format!(
+            "npm install {package} exited with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let package_dir = prefix.join("node_modules").join(name);
+    let package_json: serde_json::Value = fs::read(package_dir.join("package.json"))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| AgentError::ExtractFailed(format!("npm did not install {name}")))?;
+    let unscoped = name.rsplit('/').next().unwrap_or(name);
+    let bin = match &package_json["bin"] {
+        serde_json::Value::String(bin) => Some(bin.as_str()),
+        serde_json::Value::Object(bins) => bins
+            .get(unscoped)
+            .or_else(|| match bins.len() {
+                1 => bins.values().next(),
+                _ => None,
+            })
+            .and_then(|bin| bin.as_str()),
+        _ => None,
+    }
+    .ok_or_else(|| AgentError::ExtractFailed(format!("{name} does not declare a bin")))?;
+    let version = package_json["version"].as_str().map(str::to_string);
+    Ok((package_dir.join(bin), version))
+}
+
+/// Recursively copy `source` to `destination`, recreating symlinks rather
+/// than following them.
+pub(crate) fn copy_dir_all(source: &Path, destination: &Path) -> Result<(), AgentError> {
+    fs::create_dir_all(destination)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = destination.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn copy_symlink(source: &Path, destination: &Path) -> Result<(), AgentError> {
+    std::os::unix::fs::symlink(fs::read_link(source)?, destination)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn copy_symlink(source: &Path, destination: &Path) -> Result<(), AgentError> {
+    fs::copy(source, destination)?;
+    Ok(())
+}
@@ -0,0 +1,311 @@
+//! User-defined ACP agents that sit alongside the built-in [`AgentId`]s.
+//!
+//! Each [`AgentManager`](crate::agents::AgentManager) owns its definitions
+//! in a [`CustomAgents`] registry and resolves ids against it. They are
+//! loaded from `<install_dir>/custom-agents.json` (written by
+//! [`AgentManager::register_custom_agent`](crate::agents::AgentManager::register_custom_agent))
+//! and from the file named by `SANDBOX_AGENT_CUSTOM_AGENTS`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use serde::{Deserialize, Serialize};
+
+use crate::agents::{AgentError, AgentId};
+
+/// File under the install dir holding agents registered through the API.
+pub const CUSTOM_AGENTS_FILE: &str = "custom-agents.json";
+/// Names an additional, read-only custom agents file.
+pub const CUSTOM_AGENTS_ENV: &str = "SANDBOX_AGENT_CUSTOM_AGENTS";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomAgentDefinition {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    pub install: CustomAgentInstall,
+    /// Appended to the agent process command line.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Stdio framing, as in ACP registry entries (`content-length` or
+    /// `auto`); newline-delimited JSON when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framing: Option<String>,
+    /// Capability flags reported by `/v1/agents`, keyed by their camelCase
+    /// names (for example `permissions` or `planMode`). Unset flags are
+    /// false.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub capabilities: BTreeMap<String, bool>,
+}
+
+/// Where the agent process comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CustomAgentInstall {
+    /// An entry id in the ACP registry.
+    Registry(String),
+    /// An npm package run through `npx`, optionally pinned with `@version`.
+    Npx(String),
+    /// An executable already on disk: an absolute path or a name on `PATH`.
+    Command(String),
+}
+
+/// Contents of a custom agents file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CustomAgentsFile {
+    #[serde(default)]
+    pub agents: Vec<CustomAgentDefinition>,
+}
+
+/// A custom agent id. It names a definition in an [`AgentManager`]'s
+/// [`CustomAgents`] but does not keep it registered.
+///
+/// [`AgentManager`]: crate::agents::AgentManager
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CustomAgentId(Arc<str>);
+
+impl CustomAgentId {
+    /// Checks that `id` is a well-formed custom agent id that does not
+    /// shadow a built-in agent.
+    pub fn new(id: &str) -> Result<Self, AgentError> {
+        validate_id(id)?;
+        Ok(Self(Arc::from(id)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for CustomAgentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CustomAgentId")
+            .field(&self.as_str())
+            .finish()
+    }
+}
+
+/// Registered custom agent definitions by id. Clones share the same
+/// definitions.
+#[derive(Debug, Clone, Default)]
+pub struct CustomAgents {
+    definitions: Arc<RwLock<BTreeMap<String, Arc<CustomAgentDefinition>>>>,
+}
+
+impl CustomAgents {
+    fn read(&self) -> RwLockReadGuard<'_, BTreeMap<String, Arc<CustomAgentDefinition>>> {
+        self.definitions
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, BTreeMap<String, Arc<CustomAgentDefinition>>> {
+        self.definitions
+            .write()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Add or replace a custom agent.
+    pub fn register(&self, definition: CustomAgentDefinition) -> Result<AgentId, AgentError> {
+        validate(&definition)?;
+        let id = CustomAgentId::new(&definition.id)?;
+        self.write()
+            .insert(definition.id.clone(), Arc::new(definition));
+        Ok(AgentId::Custom(id))
+    }
+
+    /// Remove a custom agent. Returns whether it was registered.
+    pub fn unregister(&self, id: &str) -> bool {
+        self.write().remove(id).is_some()
+    }
+
+    /// The registered agent named `id`.
+    pub fn lookup(&self, id: &str) -> Option<AgentId> {
+        self.read()
+            .contains_key(id)
+            .then(|| AgentId::Custom(CustomAgentId(Arc::from(id))))
+    }
+
+    /// The current definition, or `None` once the agent is unregistered.
+    pub fn definition(&self, id: &str) -> Option<Arc<CustomAgentDefinition>> {
+        self.read().get(id).cloned()
+    }
+
+    /// Registered custom agents, ordered by id.
+    pub fn agents(&self) -> Vec<AgentId> {
+        self.read()
+            .keys()
+            .map(|id| AgentId::Custom(CustomAgentId(Arc::from(id.as_str()))))
+            .collect()
+    }
+}
+
+fn validate_id(id: &str) -> Result<(), AgentError> {
+    let invalid = |reason: &str| AgentError::InvalidCustomAgent {
+        id: id.to_string(),
+        reason: reason.to_string(),
+    };
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err(invalid(
+            "ids may only contain lowercase letters, digits, '-' and '_'",
+        ));
+    }
+    if AgentId::parse(id).is_some() {
+        return Err(invalid("id is taken by a built-in agent"));
+    }
+    Ok(())
+}
+
+fn validate(definition: &CustomAgentDefinition) -> Result<(), AgentError> {
+    let target = match &definition.install {
+        CustomAgentInstall::Registry(target)
+        | CustomAgentInstall::Npx(target)
+        | CustomAgentInstall::Command(target) => target,
+    };
+    if target.trim().is_empty() {
+        return Err(AgentError::InvalidCustomAgent {
+            id: definition.id.clone(),
+            reason: "install target must not be empty".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Read a custom agents file; a missing file has no agents.
+pub fn read_file(path: &Path) -> Result<Vec<CustomAgentDefinition>, AgentError> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let file: CustomAgentsFile =
+        serde_json::from_slice(&bytes).map_err(|err| AgentError::InvalidCustomAgent {
+            id: path.display().to_string(),
+            reason: err.to_string(),
+        })?;
+    Ok(file.agents)
+}
+
+/// Read a custom agents file at startup. Unlike [`read_file`], an unreadable
+/// file or malformed entry is logged and skipped.
+pub(crate) fn load_file(path: &Path) -> Vec<CustomAgentDefinition> {
+    #[derive(Deserialize)]
+    struct RawFile {
+        #[serde(default)]
+        agents: Vec<serde_json::Value>,
+    }
+
+    let file = fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| serde_json::from_slice::<RawFile>(&bytes).map_err(|err| err.to_string()));
+    let file = match file {
+        Ok(file) => file,
+        Err(_) if !path.exists() => return Vec::new(),
+        Err(err) => {
+            tracing::warn!(path = %path.display(), error = %err, "skipping custom agents file");
+            return Vec::new();
+        }
+    };
+    file.agents
+        .into_iter()
+        .filter_map(|value| match serde_json::from_value(value) {
+            Ok(definition) => Some(definition),
+            Err(err) => {
+                tracing::warn!(path = %path.display(), error = %err, "skipping custom agent");
+                None
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn write_file(
+    path: &Path,
+    agents: Vec<CustomAgentDefinition>,
+) -> Result<(), AgentError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let bytes = serde_json::to_vec_pretty(&CustomAgentsFile { agents })
+        .map_err(|err| AgentError::ExtractFailed(err.to_string()))?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(id: &str) -> CustomAgentDefinition {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "displayName": "Acme",
+            "install": { "npx": "@acme/acp@1.0.0" },
+            "capabilities": { "permissions": true }
+        }))
+        .expect("definition")
+    }
+
+    #[test]
+    fn registries_resolve_their_own_agents() {
+        let agents = CustomAgents::default();
+        let other = CustomAgents::default();
+        let agent = agents.register(definition("acme-unit")).expect("register");
+        assert_eq!(agents.lookup("acme-unit"), Some(agent.clone()));
+        assert_eq!(other.lookup("acme-unit"), None);
+        assert_eq!(agents.agents(), vec![agent.clone()]);
+        assert_eq!(agent.as_str(), "acme-unit");
+        assert_eq!(
+            serde_json::to_value(&agent).expect("serialize"),
+            serde_json::json!("acme-unit")
+        );
+        assert_eq!(AgentId::parse("acme-unit"), None);
+
+        assert!(matches!(
+            agents.register(definition("codex")),
+            Err(AgentError::InvalidCustomAgent { .. })
+        ));
+        assert!(matches!(
+            agents.register(definition("Bad Id")),
+            Err(AgentError::InvalidCustomAgent { .. })
+        ));
+
+        assert!(agents.unregister("acme-unit"));
+        assert_eq!(agents.lookup("acme-unit"), None);
+        assert!(serde_json::from_value::<AgentId>(serde_json::json!("Bad Id")).is_err());
+    }
+
+    #[test]
+    fn load_file_skips_invalid_entries() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join(CUSTOM_AGENTS_FILE);
+        fs::write(
+            &path,
+            serde_json::to_vec(&serde_json::json!({
+                "agents": [
+                    { "id": "acme", "install": { "npx": "@acme/acp" } },
+                    { "id": "broken" }
+                ]
+            }))
+            .expect("serialize"),
+        )
+        .expect("write");
+        let loaded = load_file(&path);
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, "acme");
+
+        fs::write(&path, b"not json").expect("write");
+        assert!(load_file(&path).is_empty());
+        assert!(load_file(&dir.path().join("missing.json")).is_empty());
+    }
+}
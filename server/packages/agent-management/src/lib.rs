@@ -1,3 +1,7 @@
 pub mod agents;
+pub mod bundle;
 pub mod credentials;
+pub mod custom;
+pub mod lock;
 pub mod testing;
+pub mod verify;
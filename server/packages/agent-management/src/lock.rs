@@ -0,0 +1,136 @@
+//! Lockfile recording what [`AgentManager::install`] resolved for each agent.
+//!
+//! Installs pin the versions and digests found in the lockfile and record
+//! whatever they install, so sandboxes sharing a lockfile install the same
+//! artifacts. With `frozen` set, an install fails instead of departing from
+//! the lockfile and leaves it unchanged.
+//!
+//! [`AgentManager::install`]: crate::agents::AgentManager::install
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::agents::{AgentError, InstallSource};
+
+/// Lockfile under the install dir.
+pub const AGENT_LOCK_FILE: &str = "agents.lock.json";
+/// Names a lockfile to use instead of the one under the install dir.
+pub const AGENT_LOCK_ENV: &str = "SANDBOX_AGENT_LOCKFILE";
+
+/// Serializes read-modify-write cycles on lockfiles within this process.
+static UPDATE: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentLock {
+    #[serde(default)]
+    pub agents: BTreeMap<String, LockedAgent>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedAgent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub native: Option<LockedArtifact>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_process: Option<LockedArtifact>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedArtifact {
+    pub source: InstallSource,
+    /// Version requested from the installer; locked installs request it
+    /// again. `None` installs the latest, pinned only by digest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// sha256 of each downloaded file, by URL.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub digests: BTreeMap<String, String>,
+}
+
+/// Read a lockfile; a missing file locks nothing.
+pub fn read(path: &Path) -> Result<AgentLock, AgentError> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(AgentLock::default()),
+        Err(err) => return Err(err.into()),
+    };
+    serde_json::from_slice(&bytes)
+        .map_err(|err| AgentError::LockMismatch(format!("{}: {err}", path.display())))
+}
+
+/// Replace the artifacts of `agent` that `resolved` has entries for.
+pub(crate) fn update(path: &Path, agent: &str, resolved: LockedAgent) -> Result<(), AgentError> {
+    if resolved == LockedAgent::default() {
+        return Ok(());
+    }
+    let _guard = UPDATE.lock().unwrap_or_else(|err| err.into_inner());
+    let mut lock = read(path)?;
+    let entry = lock.agents.entry(agent.to_string()).or_default();
+    let previous = entry.clone();
+    if resolved.native.is_some() {
+        entry.native = resolved.native;
+    }
+    if resolved.agent_process.is_some() {
+        entry.agent_process = resolved.agent_process;
+    }
+    if *entry == previous {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let bytes = serde_json::to_vec_pretty(&lock)
+        .map_err(|err| AgentError::ExtractFailed(err.to_string()))?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Reconcile a requested version with a locked artifact. An unset request
+/// takes the locked version. Returns the locked artifact when it still
+/// applies; a different request drops it, or fails when `frozen`.
+pub(crate) fn apply(
+    what: &str,
+    requested: &mut Option<String>,
+    locked: Option<LockedArtifact>,
+    frozen: bool,
+) -> Result<Option<LockedArtifact>, AgentError> {
+    let Some(locked) = locked else {
+        return Ok(None);
+    };
+    match requested.as_deref() {
+        None => {
+            requested.clone_from(&locked.version);
+            Ok(Some(locked))
+        }
+        Some(version) if locked.version.as_deref() == Some(version) => Ok(Some(locked)),
+        Some(version) if frozen => Err(AgentError::LockMismatch(format!(
+            "{what} version {version} differs from locked {}",
+            locked.version.as_deref().unwrap_or("latest")
+        ))),
+        Some(_) => Ok(None),
+    }
+}
+
+/// Check a frozen install against the lockfile.
+pub(crate) fn check(
+    what: &str,
+    resolved: Option<&LockedArtifact>,
+    locked: Option<&LockedArtifact>,
+) -> Result<(), AgentError> {
+    match (resolved, locked) {
+        (Some(resolved), Some(locked)) if resolved == locked => Ok(()),
+        (Some(resolved), _) => Err(AgentError::LockMismatch(format!(
+            "{what} installed from {:?} at version {} does not match the lockfile",
+            resolved.source,
+            resolved.version.as_deref().unwrap_or("latest")
+        ))),
+        (None, _) => Ok(()),
+    }
+}
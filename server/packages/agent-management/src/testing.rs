@@ -107,7 +107,7 @@ pub fn test_agents_from_env() -> Result<Vec<TestAgentConfig>, TestAgentConfigErr
             AgentId::Claude | AgentId::Amp => {
                 let anthropic_cred = anthropic_cred.clone().ok_or_else(|| {
                     TestAgentConfigError::MissingCredentials {
-                        agent,
+                        agent: agent.clone(),
                         missing: ANTHROPIC_ENV.to_string(),
                     }
                 })?;
@@ -117,7 +117,7 @@ pub fn test_agents_from_env() -> Result<Vec<TestAgentConfig>, TestAgentConfigErr
             AgentId::Codex => {
                 let openai_cred = openai_cred.clone().ok_or_else(|| {
                     TestAgentConfigError::MissingCredentials {
-                        agent,
+                        agent: agent.clone(),
                         missing: OPENAI_ENV.to_string(),
                     }
                 })?;
@@ -127,7 +127,7 @@ pub fn test_agents_from_env() -> Result<Vec<TestAgentConfig>, TestAgentConfigErr
             AgentId::Opencode => {
                 if anthropic_cred.is_none() && openai_cred.is_none() {
                     return Err(TestAgentConfigError::MissingCredentials {
-                        agent,
+                        agent: agent.clone(),
                         missing: format!("{ANTHROPIC_ENV} or {OPENAI_ENV}"),
                     });
                 }
@@ -139,7 +139,7 @@ pub fn test_agents_from_env() -> Result<Vec<TestAgentConfig>, TestAgentConfigErr
                 }
                 credentials_with(anthropic_cred.clone(), openai_cred.clone())
             }
-            AgentId::Pi | AgentId::Cursor => credentials_with(None, None),
+            AgentId::Pi | AgentId::Cursor | AgentId::Custom(_) => credentials_with(None, None),
             AgentId::Mock => credentials_with(None, None),
         };
         configs.push(TestAgentConfig { agent, credentials });
@@ -0,0 +1,368 @@
+//! Integrity checks for downloaded agent artifacts.
+//!
+//! Expected digests come from the ACP registry entry or from a pinned
+//! manifest named by `SANDBOX_AGENT_ARTIFACT_MANIFEST`. Signatures use the
+//! minisign format and are checked against the public key in
+//! `SANDBOX_AGENT_ARTIFACT_PUBLIC_KEY`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::Deserialize;
+use url::Url;
+
+use crate::agents::AgentError;
+
+/// Path of a JSON manifest pinning artifact digests by download URL.
+pub const ARTIFACT_MANIFEST_ENV: &str = "SANDBOX_AGENT_ARTIFACT_MANIFEST";
+/// A minisign public key, or the path of a `.pub` file holding one.
+pub const ARTIFACT_PUBLIC_KEY_ENV: &str = "SANDBOX_AGENT_ARTIFACT_PUBLIC_KEY";
+/// When truthy, downloads without a known sha256 are refused.
+pub const REQUIRE_DIGESTS_ENV: &str = "SANDBOX_AGENT_REQUIRE_ARTIFACT_DIGESTS";
+
+/// What a downloaded artifact is expected to match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtifactDigest {
+    /// Lowercase or uppercase hex SHA-256 of the downloaded bytes.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Contents of a minisign `.minisig` file for the downloaded bytes.
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+/// Contents of the file named by [`ARTIFACT_MANIFEST_ENV`].
+#[derive(Debug, Default, Deserialize)]
+struct ArtifactManifest {
+    #[serde(default)]
+    artifacts: HashMap<String, ArtifactDigest>,
+}
+
+/// Verification settings for one install, read from the environment.
+#[derive(Debug, Default)]
+pub struct ArtifactPolicy {
+    manifest: HashMap<String, ArtifactDigest>,
+    public_key: Option<MinisignPublicKey>,
+    require_digests: bool,
+    /// Refuse URLs without a pinned sha256 (`install --frozen`).
+    pinned_only: bool,
+    /// sha256 of every artifact verified so far, by URL.
+    verified: Mutex<BTreeMap<String, String>>,
+    /// URLs downloaded so far without an expected sha256.
+    unverified: Mutex<Vec<String>>,
+}
+
+impl ArtifactPolicy {
+    pub fn from_env() -> Result<Self, AgentError> {
+        let manifest = match std::env::var_os(ARTIFACT_MANIFEST_ENV).filter(|v| !v.is_empty()) {
+            Some(path) => {
+                let bytes = fs::read(&path)?;
+                let manifest: ArtifactManifest = serde_json::from_slice(&bytes).map_err(|err| {
+                    AgentError::RegistryParse(format!(
+                        "artifact manifest {}: {err}",
+                        Path::new(&path).display()
+                    ))
+                })?;
+                manifest.artifacts
+            }
+            None => HashMap::new(),
+        };
+        let public_key = match std::env::var(ARTIFACT_PUBLIC_KEY_ENV)
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+        {
+            Some(value) => {
+                let text = if Path::new(&value).is_file() {
+                    fs::read_to_string(&value)?
+                } else {
+                    value
+                };
+                Some(MinisignPublicKey::parse(&text)?)
+            }
+            None => None,
+        };
+        let require_digests = std::env::var(REQUIRE_DIGESTS_ENV)
+            .map(|value| matches!(value.trim(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        Ok(Self {
+            manifest,
+            public_key,
+            require_digests,
+            ..Self::default()
+        })
+    }
+
+    /// Pin `url` to `sha256` unless the manifest already pins it.
+    pub(crate) fn pin(&mut self, url: &str, sha256: &str) {
+        let digest = self.manifest.entry(url.to_string()).or_default();
+        digest.sha256.get_or_insert_with(|| sha256.to_string());
+    }
+
+    pub(crate) fn require_pinned(&mut self) {
+        self.pinned_only = true;
+    }
+
+    /// Drain the sha256s recorded by [`verify`](Self::verify).
+    pub(crate) fn take_verified(&self) -> BTreeMap<String, String> {
+        std::mem::take(&mut *self.verified.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Drain the URLs [`verify`](Self::verify) had no sha256 for.
+    pub(crate) fn take_unverified(&self) -> Vec<String> {
+        std::mem::take(
+            &mut *self
+                .unverified
+                .lock()
+                .unwrap_or_else(|err| err.into_inner()),
+        )
+    }
+
+    /// Check `bytes` downloaded from `url` against the manifest entry for
+    /// the URL, falling back to `published` (for example the registry's
+    /// digests).
+    pub fn verify(
+        &self,
+        url: &Url,
+        bytes: &[u8],
+        published: Option<&ArtifactDigest>,
+    ) -> Result<(), AgentError> {
+        let pinned = self.manifest.get(url.as_str());
+        if self.pinned_only && pinned.and_then(|digest| digest.sha256.as_ref()).is_none() {
+            return Err(AgentError::LockMismatch(format!(
+                "{url} is not pinned in the lockfile"
+            )));
+        }
+        let sha256 = pinned
+            .and_then(|digest| digest.sha256.as_deref())
+            .or(published.and_then(|digest| digest.sha256.as_deref()));
+        let signature = pinned
+            .and_then(|digest| digest.signature.as_deref())
+            .or(published.and_then(|digest| digest.signature.as_deref()));
+
+        let actual = sha256_hex(bytes);
+        match sha256 {
+            Some(expected) if !expected.trim().eq_ignore_ascii_case(&actual) => {
+                return Err(AgentError::ChecksumMismatch {
+                    url: url.to_string(),
+                    expected: expected.trim().to_ascii_lowercase(),
+                    actual,
+                });
+            }
+            Some(_) => {}
+            None if self.require_digests => {
+                return Err(AgentError::MissingDigest {
+                    url: url.to_string(),
+                });
+            }
+            None => {
+                tracing::warn!(
+                    url = %url,
+                    "no sha256 published or pinned for artifact; installing it unverified"
+                );
+                self.unverified
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .push(url.to_string());
+            }
+        }
+
+        if let Some(public_key) = &self.public_key {
+            let signature = signature.ok_or_else(|| AgentError::SignatureInvalid {
+                url: url.to_string(),
+                reason: "no signature published for artifact".to_string(),
+            })?;
+            public_key
+                .verify(bytes, signature)
+                .map_err(|reason| AgentError::SignatureInvalid {
+                    url: url.to_string(),
+                    reason,
+                })?;
+        }
+        self.verified
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(url.to_string(), actual);
+        Ok(())
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    digest(&SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// A minisign Ed25519 public key.
+#[derive(Debug, Clone)]
+struct MinisignPublicKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+impl MinisignPublicKey {
+    /// Accepts a `.pub` file or just its base64 line.
+    fn parse(text: &str) -> Result<Self, AgentError> {
+        let invalid = |reason: &str| {
+            AgentError::RegistryParse(format!("invalid artifact public key: {reason}"))
+        };
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .ok_or_else(|| invalid("empty"))?;
+        let bytes = BASE64.decode(line).map_err(|_| invalid("not base64"))?;
+        if bytes.len() != 42 || &bytes[..2] != b"Ed" {
+            return Err(invalid("not a minisign Ed25519 key"));
+        }
+        let mut key_id = [0; 8];
+        key_id.copy_from_slice(&bytes[2..10]);
+        let mut key = [0; 32];
+        key.copy_from_slice(&bytes[10..]);
+        Ok(Self { key_id, key })
+    }
+
+    /// Checks a `.minisig` file: the signature over `bytes` and the global
+    /// signature over the trusted comment.
+    fn verify(&self, bytes: &[u8], minisig: &str) -> Result<(), String> {
+        let mut lines = minisig
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("untrusted comment:"));
+        let signature = lines
+            .next()
+            .and_then(|line| BASE64.decode(line).ok())
+            .filter(|decoded| decoded.len() == 74)
+            .ok_or("malformed signature line")?;
+        let trusted_comment = lines
+            .next()
+            .and_then(|line| line.strip_prefix("trusted comment: "))
+            .ok_or("missing trusted comment")?;
+        let global_signature = lines
+            .next()
+            .and_then(|line| BASE64.decode(line).ok())
+            .filter(|decoded| decoded.len() == 64)
+            .ok_or("malformed global signature")?;
+
+        match &signature[..2] {
+            b"Ed" => {}
+            b"ED" => {
+                return Err(
+                    "prehashed signatures are not supported; sign with `minisign -S -l`"
+                        .to_string(),
+                )
+            }
+            _ => return Err("unknown signature algorithm".to_string()),
+        }
+        if signature[2..10] != self.key_id {
+            return Err("signed with a different key".to_string());
+        }
+
+        let key = UnparsedPublicKey::new(&ED25519, &self.key);
+        key.verify(bytes, &signature[10..])
+            .map_err(|_| "signature does not match artifact".to_string())?;
+        let mut signed_comment = signature[10..].to_vec();
+        signed_comment.extend_from_slice(trusted_comment.as_bytes());
+        key.verify(&signed_comment, &global_signature)
+            .map_err(|_| "trusted comment signature does not match".to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    fn minisign_pair(key_id: [u8; 8]) -> (Ed25519KeyPair, String) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("generate key");
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("parse key");
+        let mut public = b"Ed".to_vec();
+        public.extend_from_slice(&key_id);
+        public.extend_from_slice(pair.public_key().as_ref());
+        let public = format!(
+            "untrusted comment: minisign public key\n{}\n",
+            BASE64.encode(public)
+        );
+        (pair, public)
+    }
+
+    fn minisign_sign(pair: &Ed25519KeyPair, key_id: [u8; 8], bytes: &[u8]) -> String {
+        let signature = pair.sign(bytes);
+        let mut line = b"Ed".to_vec();
+        line.extend_from_slice(&key_id);
+        line.extend_from_slice(signature.as_ref());
+        let comment = "timestamp:0\tfile:agent.tar.gz";
+        let mut global = signature.as_ref().to_vec();
+        global.extend_from_slice(comment.as_bytes());
+        format!(
+            "untrusted comment: signature\n{}\ntrusted comment: {comment}\n{}\n",
+            BASE64.encode(line),
+            BASE64.encode(pair.sign(&global).as_ref())
+        )
+    }
+
+    #[test]
+    fn verifies_sha256_and_minisign_signatures() {
+        let url = Url::parse("https://example.com/agent.tar.gz").expect("url");
+        let bytes = b"agent archive";
+        let key_id = [7; 8];
+        let (pair, public) = minisign_pair(key_id);
+        let policy = ArtifactPolicy {
+            public_key: Some(MinisignPublicKey::parse(&public).expect("public key")),
+            ..ArtifactPolicy::default()
+        };
+
+        let published = ArtifactDigest {
+            sha256: Some(sha256_hex(bytes).to_ascii_uppercase()),
+            signature: Some(minisign_sign(&pair, key_id, bytes)),
+        };
+        policy
+            .verify(&url, bytes, Some(&published))
+            .expect("valid artifact");
+
+        assert!(matches!(
+            policy.verify(&url, b"tampered", Some(&published)),
+            Err(AgentError::ChecksumMismatch { .. })
+        ));
+
+        let resigned = ArtifactDigest {
+            sha256: None,
+            signature: Some(minisign_sign(&pair, [8; 8], bytes)),
+        };
+        assert!(matches!(
+            policy.verify(&url, bytes, Some(&resigned)),
+            Err(AgentError::SignatureInvalid { .. })
+        ));
+        assert!(matches!(
+            policy.verify(&url, bytes, None),
+            Err(AgentError::SignatureInvalid { .. })
+        ));
+
+        let lenient = ArtifactPolicy::default();
+        lenient
+            .verify(&url, bytes, None)
+            .expect("unverified artifact");
+        assert_eq!(lenient.take_unverified(), vec![url.to_string()]);
+
+        let strict = ArtifactPolicy {
+            require_digests: true,
+            ..ArtifactPolicy::default()
+        };
+        assert!(matches!(
+            strict.verify(&url, bytes, None),
+            Err(AgentError::MissingDigest { .. })
+        ));
+    }
+}
@@ -165,6 +165,13 @@ pub enum SandboxError {
     StreamError { message: String },
     #[error("timeout")]
     Timeout { message: Option<String> },
+    #[error("timed out after {elapsed_ms} ms waiting for {agent} to answer '{method}'")]
+    AgentRequestTimeout {
+        agent: String,
+        method: String,
+        elapsed_ms: u64,
+        cancelled: bool,
+    },
 }
 
 impl SandboxError {
@@ -184,7 +191,7 @@ impl SandboxError {
             Self::SessionAlreadyExists { .. } => ErrorType::SessionAlreadyExists,
             Self::ModeNotSupported { .. } => ErrorType::ModeNotSupported,
             Self::StreamError { .. } => ErrorType::StreamError,
-            Self::Timeout { .. } => ErrorType::Timeout,
+            Self::Timeout { .. } | Self::AgentRequestTimeout { .. } => ErrorType::Timeout,
         }
     }
 
@@ -284,6 +291,21 @@ impl SandboxError {
                 });
                 (None, None, details)
             }
+            Self::AgentRequestTimeout {
+                agent,
+                method,
+                elapsed_ms,
+                cancelled,
+            } => {
+                let mut map = Map::new();
+                map.insert("method".to_string(), Value::String(method.clone()));
+                map.insert(
+                    "elapsedMs".to_string(),
+                    Value::Number(serde_json::Number::from(*elapsed_ms)),
+                );
+                map.insert("cancelled".to_string(), Value::Bool(*cancelled));
+                (Some(agent.clone()), None, Some(Value::Object(map)))
+            }
         };
 
         AgentError {
@@ -166,7 +166,7 @@ impl OpenCodeServerManager {
 
         let (base_url, child) = tokio::task::spawn_blocking(move || {
             let path = agent_manager
-                .resolve_binary(AgentId::Opencode)
+                .resolve_binary(&AgentId::Opencode)
                 .map_err(|err| err.to_string())?;
             let port = find_available_port()?;
             let mut command = Command::new(path);
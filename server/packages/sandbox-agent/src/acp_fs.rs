@@ -0,0 +1,160 @@
+//! Server-side implementation of the ACP `fs/*` client methods.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use acp_http_adapter::process::{ClientRequestError, ClientRequestHandler, StreamPublisher};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::metrics::{FsSource, Metrics};
+use crate::router::resolve_fs_path;
+
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+pub(crate) const INTERNAL_ERROR: i64 = -32603;
+pub(crate) const RESOURCE_NOT_FOUND: i64 = -32002;
+
+/// Answers `fs/read_text_file` and `fs/write_text_file` against the local
+/// filesystem, resolving paths like the `/v1/fs` endpoints do.
+#[derive(Debug)]
+pub(crate) struct AcpFsHandler {
+    metrics: Arc<Metrics>,
+}
+
+impl AcpFsHandler {
+    pub(crate) fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReadTextFileParams {
+    path: String,
+    /// 1-based line to start reading from.
+    #[serde(default)]
+    line: Option<u32>,
+    #[serde(default)]
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WriteTextFileParams {
+    path: String,
+    content: String,
+}
+
+impl ClientRequestHandler for AcpFsHandler {
+    fn handle(
+        &self,
+        method: &str,
+        params: &Value,
+        _stream: &StreamPublisher,
+    ) -> Option<BoxFuture<'static, Result<Value, ClientRequestError>>> {
+        let operation: fn(&Metrics, Value) -> Result<Value, ClientRequestError> = match method {
+            "fs/read_text_file" => read_text_file,
+            "fs/write_text_file" => write_text_file,
+            _ => return None,
+        };
+        let params = params.clone();
+        let metrics = self.metrics.clone();
+        Some(Box::pin(async move {
+            tokio::task::spawn_blocking(move || operation(&metrics, params))
+                .await
+                .unwrap_or_else(|err| {
+                    Err(ClientRequestError {
+                        code: INTERNAL_ERROR,
+                        message: err.to_string(),
+                    })
+                })
+        }))
+    }
+}
+
+/// ACP advertises the handler through `clientCapabilities.fs` in the
+/// `initialize` request; mark both methods as supported.
+pub(crate) fn advertise_fs_capability(payload: &mut Value) {
+    let Some(capabilities) = client_capabilities(payload) else {
+        return;
+    };
+    let fs = capabilities.entry("fs").or_insert_with(|| json!({}));
+    if let Some(fs) = fs.as_object_mut() {
+        fs.insert("readTextFile".to_string(), Value::Bool(true));
+        fs.insert("writeTextFile".to_string(), Value::Bool(true));
+    }
+}
+
+/// `params.clientCapabilities` of an `initialize` request, created if
+/// missing.
+pub(crate) fn client_capabilities(payload: &mut Value) -> Option<&mut Map<String, Value>> {
+    payload
+        .get_mut("params")?
+        .as_object_mut()?
+        .entry("clientCapabilities")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+}
+
+fn read_text_file(metrics: &Metrics, params: Value) -> Result<Value, ClientRequestError> {
+    let params: ReadTextFileParams = parse_params(params)?;
+    let path = resolve_path(&params.path)?;
+    let content = std::fs::read_to_string(&path).map_err(|err| io_error(&path, err))?;
+    metrics.record_fs_read(FsSource::Acp, content.len() as u64);
+    Ok(json!({ "content": slice_lines(&content, params.line, params.limit) }))
+}
+
+fn write_text_file(metrics: &Metrics, params: Value) -> Result<Value, ClientRequestError> {
+    let params: WriteTextFileParams = parse_params(params)?;
+    let path = resolve_path(&params.path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|err| io_error(parent, err))?;
+    }
+    std::fs::write(&path, &params.content).map_err(|err| io_error(&path, err))?;
+    metrics.record_fs_write(FsSource::Acp, params.content.len() as u64);
+    Ok(json!({}))
+}
+
+fn slice_lines(content: &str, line: Option<u32>, limit: Option<u32>) -> String {
+    if line.is_none() && limit.is_none() {
+        return content.to_string();
+    }
+    let skip = line.unwrap_or(1).saturating_sub(1) as usize;
+    let take = limit.map_or(usize::MAX, |limit| limit as usize);
+    content
+        .split_inclusive('\n')
+        .skip(skip)
+        .take(take)
+        .collect()
+}
+
+pub(crate) fn parse_params<T: serde::de::DeserializeOwned>(
+    params: Value,
+) -> Result<T, ClientRequestError> {
+    serde_json::from_value(params).map_err(|err| ClientRequestError {
+        code: INVALID_PARAMS,
+        message: format!("invalid params: {err}"),
+    })
+}
+
+pub(crate) fn resolve_path(raw: &str) -> Result<PathBuf, ClientRequestError> {
+    resolve_fs_path(raw).map_err(|err| ClientRequestError {
+        code: INVALID_PARAMS,
+        message: err.to_string(),
+    })
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> ClientRequestError {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        ClientRequestError {
+            code: RESOURCE_NOT_FOUND,
+            message: format!("path not found: {}", path.display()),
+        }
+    } else {
+        ClientRequestError {
+            code: INTERNAL_ERROR,
+            message: format!("{}: {err}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Labels on ACP server instances and the selectors that filter them.
+
+use std::collections::BTreeMap;
+
+use sandbox_agent_error::SandboxError;
+
+/// A comma-separated list of requirements that must all hold: `key=value`
+/// (or `key==value`), `key!=value`, `key` (present) and `!key` (absent).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector {
+    requirements: Vec<LabelRequirement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LabelRequirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+impl LabelSelector {
+    pub fn parse(raw: &str) -> Result<Self, SandboxError> {
+        let requirements = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|requirement| !requirement.is_empty())
+            .map(LabelRequirement::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { requirements })
+    }
+
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement.matches(labels))
+    }
+}
+
+impl LabelRequirement {
+    fn parse(raw: &str) -> Result<Self, SandboxError> {
+        let requirement = if let Some((key, value)) = raw.split_once("!=") {
+            Self::NotEquals(key.trim().to_string(), value.trim().to_string())
+        } else if let Some((key, value)) = raw.split_once('=') {
+            let value = value.strip_prefix('=').unwrap_or(value);
+            Self::Equals(key.trim().to_string(), value.trim().to_string())
+        } else if let Some(key) = raw.strip_prefix('!') {
+            Self::NotExists(key.trim().to_string())
+        } else {
+            Self::Exists(raw.to_string())
+        };
+        validate_label_key(requirement.key()).map_err(|err| SandboxError::InvalidRequest {
+            message: format!("invalid label selector '{raw}': {err}"),
+        })?;
+        Ok(requirement)
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            Self::Equals(key, _)
+            | Self::NotEquals(key, _)
+            | Self::Exists(key)
+            | Self::NotExists(key) => key,
+        }
+    }
+
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Self::Equals(key, value) => labels.get(key) == Some(value),
+            Self::NotEquals(key, value) => labels.get(key) != Some(value),
+            Self::Exists(key) => labels.contains_key(key),
+            Self::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+/// Keys are non-empty and limited to ASCII letters, digits, `-`, `_`, `.`
+/// and `/`, so they can always be written in a selector. Values are
+/// arbitrary strings.
+pub(crate) fn validate_label_key(key: &str) -> Result<(), String> {
+    if key.is_empty() {
+        return Err("label key must not be empty".to_string());
+    }
+    if let Some(invalid) = key
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')))
+    {
+        return Err(format!("label key '{key}' contains '{invalid}'"));
+    }
+    Ok(())
+}
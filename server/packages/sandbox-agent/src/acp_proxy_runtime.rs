@@ -1,20 +1,43 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
+use std::path::PathBuf;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
-use acp_http_adapter::process::{AdapterError, AdapterRuntime, PostOutcome};
+use acp_http_adapter::event_store::{EventRetention, EventStore, SegmentEventStore};
+use acp_http_adapter::framing::Framing;
+use acp_http_adapter::limits::ResourceLimits;
+use acp_http_adapter::process::{
+    AdapterError, AdapterRuntime, ClientRequestHandler, LagStats, PostOptions, PostOutcome,
+    ProcessState, RestartMode, RestartPolicy, RuntimeOptions,
+};
 use acp_http_adapter::registry::LaunchSpec;
+use acp_http_adapter::stderr_log::{StderrFileConfig, StderrLine};
 use axum::response::sse::Event;
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use sandbox_agent_agent_management::agents::{AgentId, AgentManager, InstallOptions};
 use sandbox_agent_error::SandboxError;
 use sandbox_agent_opencode_adapter::{AcpDispatch, AcpDispatchResult, AcpPayloadStream};
-use serde_json::Value;
-use tokio::sync::{Mutex, RwLock};
+use serde_json::{json, Value};
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+
+use crate::acp_fs::{advertise_fs_capability, AcpFsHandler};
+use crate::acp_labels::{validate_label_key, LabelSelector};
+use crate::acp_permissions::{AcpPermissionPolicy, PermissionPolicy};
+use crate::acp_terminal::{advertise_terminal_capability, AcpTerminals};
+use crate::metrics::Metrics;
 
 const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 120_000;
+const DEFAULT_SHUTDOWN_GRACE_MS: u64 = 5_000;
+const REAPER_MIN_INTERVAL: Duration = Duration::from_millis(50);
+const REAPER_MAX_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_EVENT_LOG_MAX_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_STDERR_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+/// Stderr lines included in the error when a request fails because the agent
+/// died.
+const ERROR_STDERR_LINES: usize = 20;
 
 #[derive(Debug, Clone)]
 pub struct AcpProxyRuntime {
@@ -24,19 +47,90 @@ pub struct AcpProxyRuntime {
 #[derive(Debug)]
 struct AcpProxyRuntimeInner {
     agent_manager: Arc<AgentManager>,
+    metrics: Arc<Metrics>,
     require_preinstall: bool,
     request_timeout: Duration,
+    method_timeouts: HashMap<String, Duration>,
+    cancel_on_timeout: bool,
+    shutdown_grace: Duration,
+    restart: RestartPolicy,
+    event_log: Option<EventLogConfig>,
+    stderr_log: Option<StderrLogConfig>,
+    /// Directory for JSONL transcripts of agent stdio traffic.
+    transcript_dir: Option<PathBuf>,
+    launch_allowlist: LaunchAllowlist,
+    resource_limits: HashMap<AgentId, ResourceLimits>,
+    serve_fs: bool,
+    serve_terminals: bool,
+    permission_policy: Option<Arc<PermissionPolicy>>,
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    reaper_started: AtomicBool,
     instances: RwLock<HashMap<String, Arc<ProxyInstance>>>,
+    /// Serializes creation per server id; entries only live while a
+    /// creation is under way.
     instance_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
-    install_locks: Mutex<HashMap<AgentId, Arc<Mutex<()>>>>,
+    /// Held for writing while an agent is installed or removed, and for
+    /// reading from the install check until a new instance is registered.
+    agent_locks: Mutex<HashMap<AgentId, Arc<RwLock<()>>>>,
+}
+
+/// On-disk event logs, one directory per server id.
+#[derive(Debug, Clone)]
+struct EventLogConfig {
+    dir: PathBuf,
+    retention: EventRetention,
+}
+
+/// Which launch overrides callers may set. Env and arg patterns match
+/// exactly or, with a trailing `*`, by prefix. Without `cwd_roots` any
+/// existing directory is accepted as the working directory.
+#[derive(Debug, Clone, Default)]
+struct LaunchAllowlist {
+    cwd_roots: Option<Vec<PathBuf>>,
+    env: Vec<String>,
+    args: Vec<String>,
+}
+
+/// Where per-instance agent stderr files go; see
+/// [`StderrFileConfig`].
+#[derive(Debug, Clone)]
+struct StderrLogConfig {
+    dir: PathBuf,
+    max_bytes: u64,
 }
 
 #[derive(Debug)]
 struct ProxyInstance {
     server_id: String,
     agent: AgentId,
+    launch: AcpLaunchOverrides,
     runtime: Arc<AdapterRuntime>,
+    terminals: Option<AcpTerminals>,
     created_at_ms: i64,
+    activity: Arc<InstanceActivity>,
+    labels: RwLock<BTreeMap<String, String>>,
+}
+
+/// Tracks when an instance was last used. An instance is busy while it has
+/// in-flight POSTs or connected stream subscribers; idle time is measured
+/// from the moment the last of those finished.
+#[derive(Debug)]
+struct InstanceActivity {
+    last_activity_ms: AtomicI64,
+    active: AtomicUsize,
+}
+
+/// Marks an instance busy for as long as the guard is alive.
+struct ActivityGuard {
+    activity: Arc<InstanceActivity>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ReapReason {
+    IdleTimeout,
+    MaxLifetime,
+    AgentUninstalled,
 }
 
 #[derive(Debug)]
@@ -45,73 +139,183 @@ pub enum ProxyPostOutcome {
     Accepted,
 }
 
+/// Launch settings supplied with the first POST for a server id. They are
+/// layered on top of the agent's resolved launch spec.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcpLaunchOverrides {
+    pub cwd: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    pub args: Vec<String>,
+    /// Rules answering `session/request_permission` for this instance ahead
+    /// of the global policy.
+    pub permission_policy: Option<PermissionPolicy>,
+}
+
+/// Per-request settings for one POST.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcpRequestOptions {
+    /// When the caller stops waiting. Time spent starting the agent counts
+    /// against it.
+    pub deadline: Option<Instant>,
+    /// Overrides `SANDBOX_AGENT_ACP_CANCEL_ON_TIMEOUT`.
+    pub cancel_on_timeout: Option<bool>,
+    /// Names the caller in `_adapter/request_resolved` and in conflicts
+    /// when it answers an agent-to-client request.
+    pub client_id: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct AcpServerInstanceInfo {
     pub server_id: String,
     pub agent: AgentId,
+    pub launch: AcpLaunchOverrides,
+    pub labels: BTreeMap<String, String>,
     pub created_at_ms: i64,
+    pub restart_count: u32,
+    pub pid: Option<u32>,
+    pub state: ProcessState,
+    pub pending_requests: usize,
+    /// Now while the instance has in-flight requests or subscribers.
+    pub last_activity_ms: i64,
+    pub subscribers: usize,
+    pub spawn_duration: Duration,
+    pub first_stdout_latency: Option<Duration>,
+    pub lag: LagStats,
 }
 
 pub type PinBoxSseStream =
     std::pin::Pin<Box<dyn Stream<Item = Result<Event, std::convert::Infallible>> + Send>>;
 
+/// Stream payloads paired with their sequence number; see
+/// [`AdapterRuntime::event_stream`].
+pub type PinBoxEventStream = std::pin::Pin<Box<dyn Stream<Item = (Option<u64>, Value)> + Send>>;
+
 impl AcpProxyRuntime {
-    pub fn new(agent_manager: Arc<AgentManager>) -> Self {
-        let require_preinstall = std::env::var("SANDBOX_AGENT_REQUIRE_PREINSTALL")
-            .ok()
-            .is_some_and(|value| {
-                let trimmed = value.trim();
-                trimmed == "1"
-                    || trimmed.eq_ignore_ascii_case("true")
-                    || trimmed.eq_ignore_ascii_case("yes")
-            });
+    pub(crate) fn new(agent_manager: Arc<AgentManager>, metrics: Arc<Metrics>) -> Self {
+        let require_preinstall = bool_from_env("SANDBOX_AGENT_REQUIRE_PREINSTALL");
+        let serve_fs = bool_from_env("SANDBOX_AGENT_ACP_SERVE_FS");
+        let serve_terminals = bool_from_env("SANDBOX_AGENT_ACP_SERVE_TERMINALS");
 
         let request_timeout = duration_from_env_ms(
             "SANDBOX_AGENT_ACP_REQUEST_TIMEOUT_MS",
             Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS),
         );
+        let method_timeouts = method_timeouts_from_env();
+        let cancel_on_timeout = bool_from_env("SANDBOX_AGENT_ACP_CANCEL_ON_TIMEOUT");
+
+        let shutdown_grace = duration_from_env_ms(
+            "SANDBOX_AGENT_ACP_SHUTDOWN_GRACE_MS",
+            Duration::from_millis(DEFAULT_SHUTDOWN_GRACE_MS),
+        );
+
+        let restart = restart_policy_from_env();
+        let event_log = event_log_config_from_env();
+        let stderr_log = stderr_log_config_from_env();
+        let transcript_dir = std::env::var_os("SANDBOX_AGENT_ACP_TRANSCRIPT_DIR")
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from);
+        let launch_allowlist = launch_allowlist_from_env();
+        let resource_limits = resource_limits_from_env();
+        let permission_policy = permission_policy_from_env().map(Arc::new);
+        let idle_timeout = optional_duration_from_env_ms("SANDBOX_AGENT_ACP_IDLE_TIMEOUT_MS");
+        let max_lifetime = optional_duration_from_env_ms("SANDBOX_AGENT_ACP_MAX_LIFETIME_MS");
 
         Self {
             inner: Arc::new(AcpProxyRuntimeInner {
                 agent_manager,
+                metrics,
                 require_preinstall,
                 request_timeout,
+                method_timeouts,
+                cancel_on_timeout,
+                shutdown_grace,
+                restart,
+                event_log,
+                stderr_log,
+                transcript_dir,
+                launch_allowlist,
+                resource_limits,
+                serve_fs,
+                serve_terminals,
+                permission_policy,
+                idle_timeout,
+                max_lifetime,
+                reaper_started: AtomicBool::new(false),
                 instances: RwLock::new(HashMap::new()),
                 instance_locks: Mutex::new(HashMap::new()),
-                install_locks: Mutex::new(HashMap::new()),
+                agent_locks: Mutex::new(HashMap::new()),
             }),
         }
     }
 
-    pub async fn list_instances(&self) -> Vec<AcpServerInstanceInfo> {
-        let mut infos = self
+    /// Instances whose labels match `selector`, ordered by server id.
+    pub async fn list_instances(&self, selector: &LabelSelector) -> Vec<AcpServerInstanceInfo> {
+        let instances = self
             .inner
             .instances
             .read()
             .await
             .values()
-            .map(|instance| AcpServerInstanceInfo {
-                server_id: instance.server_id.clone(),
-                agent: instance.agent,
-                created_at_ms: instance.created_at_ms,
-            })
+            .cloned()
             .collect::<Vec<_>>();
+        let mut infos = Vec::with_capacity(instances.len());
+        for instance in instances {
+            let info = instance.info().await;
+            if selector.matches(&info.labels) {
+                infos.push(info);
+            }
+        }
         infos.sort_by(|left, right| left.server_id.cmp(&right.server_id));
         infos
     }
 
+    /// Set labels on an instance; a `None` value removes the label.
+    pub async fn update_labels(
+        &self,
+        server_id: &str,
+        changes: BTreeMap<String, Option<String>>,
+    ) -> Result<AcpServerInstanceInfo, SandboxError> {
+        for key in changes.keys() {
+            validate_label_key(key).map_err(|message| SandboxError::InvalidRequest { message })?;
+        }
+        let instance = self.get_instance(server_id).await?;
+        {
+            let mut labels = instance.labels.write().await;
+            for (key, value) in changes {
+                match value {
+                    Some(value) => labels.insert(key, value),
+                    None => labels.remove(&key),
+                };
+            }
+        }
+        Ok(instance.info().await)
+    }
+
     pub async fn post(
         &self,
         server_id: &str,
         bootstrap_agent: Option<AgentId>,
-        payload: Value,
+        launch: &AcpLaunchOverrides,
+        labels: &BTreeMap<String, String>,
+        request: AcpRequestOptions,
+        mut payload: Value,
     ) -> Result<ProxyPostOutcome, SandboxError> {
-        let method: String = payload
-            .get("method")
-            .and_then(|v| v.as_str())
-            .unwrap_or("<none>")
-            .to_string();
+        let method: String = if payload.is_array() {
+            "<batch>".to_string()
+        } else {
+            payload
+                .get("method")
+                .and_then(|v| v.as_str())
+                .unwrap_or("<none>")
+                .to_string()
+        };
         let id: String = payload.get("id").map(|v| v.to_string()).unwrap_or_default();
+        match &mut payload {
+            Value::Array(batch) => batch
+                .iter_mut()
+                .for_each(|message| self.prepare_client_message(message)),
+            message => self.prepare_client_message(message),
+        }
 
         tracing::info!(
             server_id = server_id,
@@ -123,7 +327,7 @@ impl AcpProxyRuntime {
 
         let start = std::time::Instant::now();
         let instance = self
-            .get_or_create_instance(server_id, bootstrap_agent)
+            .get_or_create_instance(server_id, bootstrap_agent, launch, labels)
             .await?;
         let instance_elapsed = start.elapsed();
 
@@ -134,7 +338,47 @@ impl AcpProxyRuntime {
             "acp_proxy: instance resolved"
         );
 
-        match instance.runtime.post(payload).await {
+        let options = PostOptions {
+            timeout: request
+                .deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now())),
+            cancel_on_timeout: request.cancel_on_timeout,
+            client_id: request.client_id,
+        };
+        // Only requests wait for a reply; notifications and client responses
+        // are delivered whatever the deadline.
+        let awaits_response = match &payload {
+            Value::Array(batch) => !batch.is_empty() && batch.iter().all(is_request),
+            message => is_request(message),
+        };
+        if awaits_response && options.timeout == Some(Duration::ZERO) {
+            return Err(SandboxError::AgentRequestTimeout {
+                agent: instance.agent.as_str().to_string(),
+                method,
+                elapsed_ms: start.elapsed().as_millis() as u64,
+                cancelled: false,
+            });
+        }
+
+        let _activity = ActivityGuard::new(instance.activity.clone());
+        let post_start = std::time::Instant::now();
+        let result = instance.runtime.post_with_options(payload, &options).await;
+        let outcome = match &result {
+            Ok(PostOutcome::Response(value)) if value.get("error").is_some() => Some("error"),
+            Ok(PostOutcome::Response(_)) => Some("ok"),
+            Ok(PostOutcome::Accepted) => None,
+            Err(AdapterError::Timeout { .. }) => Some("timeout"),
+            Err(_) => Some("error"),
+        };
+        if let Some(outcome) = outcome {
+            self.inner.metrics.record_acp_request(
+                instance.agent.as_str(),
+                &method,
+                outcome,
+                post_start.elapsed(),
+            );
+        }
+        match result {
             Ok(PostOutcome::Response(value)) => {
                 let total_ms = start.elapsed().as_millis() as u64;
                 tracing::info!(
@@ -144,7 +388,15 @@ impl AcpProxyRuntime {
                     total_ms = total_ms,
                     "acp_proxy: POST → response"
                 );
-                let value = annotate_agent_error(instance.agent, value);
+                let value = match value {
+                    Value::Array(responses) => Value::Array(
+                        responses
+                            .into_iter()
+                            .map(|response| annotate_agent_error(&instance.agent, response))
+                            .collect(),
+                    ),
+                    value => annotate_agent_error(&instance.agent, value),
+                };
                 Ok(ProxyPostOutcome::Response(value))
             }
             Ok(PostOutcome::Accepted) => {
@@ -165,29 +417,185 @@ impl AcpProxyRuntime {
                     error = %err,
                     "acp_proxy: POST → error"
                 );
-                Err(map_adapter_error(err))
+                Err(match err {
+                    AdapterError::AgentExited { code } => {
+                        let stderr = instance
+                            .runtime
+                            .stderr_lines(None, Some(ERROR_STDERR_LINES))
+                            .into_iter()
+                            .map(|line| line.line)
+                            .collect::<Vec<_>>();
+                        SandboxError::AgentProcessExited {
+                            agent: instance.agent.as_str().to_string(),
+                            exit_code: code,
+                            stderr: (!stderr.is_empty()).then(|| stderr.join("\n")),
+                        }
+                    }
+                    AdapterError::Timeout {
+                        method, cancelled, ..
+                    } => SandboxError::AgentRequestTimeout {
+                        agent: instance.agent.as_str().to_string(),
+                        method,
+                        elapsed_ms: start.elapsed().as_millis() as u64,
+                        cancelled,
+                    },
+                    err => map_adapter_error(err),
+                })
             }
         }
     }
 
+    /// Advertise the client methods the server answers itself in
+    /// `initialize`.
+    fn prepare_client_message(&self, message: &mut Value) {
+        if message.get("method").and_then(Value::as_str) != Some("initialize") {
+            return;
+        }
+        if self.inner.serve_fs {
+            advertise_fs_capability(message);
+        }
+        if self.inner.serve_terminals {
+            advertise_terminal_capability(message);
+        }
+    }
+
+    /// Captured agent stderr; see [`AdapterRuntime::stderr_lines`].
+    pub async fn logs(
+        &self,
+        server_id: &str,
+        since_ms: Option<i64>,
+        tail: Option<usize>,
+    ) -> Result<Vec<StderrLine>, SandboxError> {
+        let instance = self.get_instance(server_id).await?;
+        Ok(instance.runtime.stderr_lines(since_ms, tail))
+    }
+
+    /// Captured agent stderr followed by live output, as SSE. Following logs
+    /// does not count as activity for idle reaping.
+    pub async fn follow_logs(
+        &self,
+        server_id: &str,
+        since_ms: Option<i64>,
+        tail: Option<usize>,
+    ) -> Result<PinBoxSseStream, SandboxError> {
+        let instance = self.get_instance(server_id).await?;
+        let stream = instance.runtime.stderr_stream(since_ms, tail);
+        Ok(Box::pin(stream.map(|line| {
+            Ok(Event::default().data(serde_json::to_string(&line).unwrap_or_default()))
+        })))
+    }
+
     pub async fn sse(
         &self,
         server_id: &str,
         last_event_id: Option<u64>,
     ) -> Result<PinBoxSseStream, SandboxError> {
         let instance = self.get_instance(server_id).await?;
+        let activity = ActivityGuard::new(instance.activity.clone());
         let stream = instance.runtime.clone().sse_stream(last_event_id).await;
-        Ok(Box::pin(stream))
+        Ok(Box::pin(stream.map(move |item| {
+            let _ = &activity;
+            item
+        })))
+    }
+
+    /// Subscribe to a server's stream for a bidirectional transport. The
+    /// bootstrap parameters behave as on the first POST; without them the
+    /// server must already exist.
+    pub async fn connect(
+        &self,
+        server_id: &str,
+        bootstrap_agent: Option<AgentId>,
+        launch: &AcpLaunchOverrides,
+        labels: &BTreeMap<String, String>,
+        last_event_id: Option<u64>,
+    ) -> Result<PinBoxEventStream, SandboxError> {
+        let instance = if bootstrap_agent.is_none() && launch.is_empty() {
+            self.get_instance(server_id).await?
+        } else {
+            self.get_or_create_instance(server_id, bootstrap_agent, launch, labels)
+                .await?
+        };
+        let activity = ActivityGuard::new(instance.activity.clone());
+        let stream = instance.runtime.clone().event_stream(last_event_id).await;
+        Ok(Box::pin(stream.map(move |item| {
+            let _ = &activity;
+            item
+        })))
     }
 
     pub async fn delete(&self, server_id: &str) -> Result<(), SandboxError> {
         let removed = self.inner.instances.write().await.remove(server_id);
         if let Some(instance) = removed {
-            instance.runtime.shutdown().await;
+            instance.shutdown().await;
+        }
+        // Deleting a server discards its history; reaped or shut down
+        // servers keep their log so a later bootstrap can replay it.
+        if let Some(config) = &self.inner.event_log {
+            let dir = config.dir.join(event_log_dir_name(server_id));
+            if let Err(err) = std::fs::remove_dir_all(&dir) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!(
+                        server_id = server_id,
+                        path = %dir.display(),
+                        error = %err,
+                        "acp_proxy: failed to remove event log"
+                    );
+                }
+            }
         }
         Ok(())
     }
 
+    /// Remove an agent's installed files. Refused while instances of the
+    /// agent are running unless `force` is set, in which case they are shut
+    /// down first.
+    pub async fn uninstall_agent(
+        &self,
+        agent: &AgentId,
+        force: bool,
+    ) -> Result<Vec<PathBuf>, SandboxError> {
+        // New instances of the agent wait for the removal to finish.
+        let lock = self.agent_lock(agent).await;
+        let _guard = lock.write().await;
+
+        let live = {
+            let mut instances = self.inner.instances.write().await;
+            let server_ids = instances
+                .values()
+                .filter(|instance| instance.agent == *agent)
+                .map(|instance| instance.server_id.clone())
+                .collect::<Vec<_>>();
+            if !server_ids.is_empty() && !force {
+                return Err(SandboxError::Conflict {
+                    message: format!(
+                        "{agent} is running in ACP servers {}; pass force=true to remove it anyway",
+                        server_ids.join(", ")
+                    ),
+                });
+            }
+            server_ids
+                .iter()
+                .filter_map(|server_id| instances.remove(server_id))
+                .collect::<Vec<_>>()
+        };
+        for instance in live {
+            self.stop_instance(&instance, ReapReason::AgentUninstalled)
+                .await;
+        }
+
+        let manager = self.inner.agent_manager.clone();
+        let uninstall_failed = |message: String| SandboxError::InstallFailed {
+            agent: agent.as_str().to_string(),
+            stderr: Some(message),
+        };
+        let agent = agent.clone();
+        tokio::task::spawn_blocking(move || manager.uninstall(&agent))
+            .await
+            .map_err(|err| uninstall_failed(format!("uninstall task failed: {err}")))?
+            .map_err(|err| uninstall_failed(format!("failed to uninstall: {err}")))
+    }
+
     pub async fn shutdown_all(&self) {
         let instances = {
             let mut guard = self.inner.instances.write().await;
@@ -198,7 +606,7 @@ impl AcpProxyRuntime {
         };
 
         for instance in instances {
-            instance.runtime.shutdown().await;
+            instance.shutdown().await;
         }
     }
 
@@ -214,22 +622,16 @@ impl AcpProxyRuntime {
             })
     }
 
+    /// `labels` only apply when the instance is created here.
     async fn get_or_create_instance(
         &self,
         server_id: &str,
         bootstrap_agent: Option<AgentId>,
+        launch: &AcpLaunchOverrides,
+        labels: &BTreeMap<String, String>,
     ) -> Result<Arc<ProxyInstance>, SandboxError> {
         if let Some(existing) = self.inner.instances.read().await.get(server_id).cloned() {
-            if let Some(agent) = bootstrap_agent {
-                if agent != existing.agent {
-                    return Err(SandboxError::Conflict {
-                        message: format!(
-                            "server '{server_id}' already exists for agent '{}'; requested '{agent}'",
-                            existing.agent.as_str()
-                        ),
-                    });
-                }
-            }
+            ensure_compatible(&existing, bootstrap_agent, launch)?;
             return Ok(existing);
         }
 
@@ -240,19 +642,27 @@ impl AcpProxyRuntime {
                 .or_insert_with(|| Arc::new(Mutex::new(())))
                 .clone()
         };
-        let _guard = lock.lock().await;
+        let result = {
+            let _guard = lock.lock().await;
+            self.create_if_absent(server_id, bootstrap_agent, launch, labels)
+                .await
+        };
+        drop(lock);
+        self.release_instance_lock(server_id).await;
+        result
+    }
 
+    /// The rest of [`Self::get_or_create_instance`], run under the server's
+    /// creation lock.
+    async fn create_if_absent(
+        &self,
+        server_id: &str,
+        bootstrap_agent: Option<AgentId>,
+        launch: &AcpLaunchOverrides,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<Arc<ProxyInstance>, SandboxError> {
         if let Some(existing) = self.inner.instances.read().await.get(server_id).cloned() {
-            if let Some(agent) = bootstrap_agent {
-                if agent != existing.agent {
-                    return Err(SandboxError::Conflict {
-                        message: format!(
-                            "server '{server_id}' already exists for agent '{}'; requested '{agent}'",
-                            existing.agent.as_str()
-                        ),
-                    });
-                }
-            }
+            ensure_compatible(&existing, bootstrap_agent, launch)?;
             return Ok(existing);
         }
 
@@ -262,12 +672,27 @@ impl AcpProxyRuntime {
             ),
         })?;
 
-        let created = self.create_instance(server_id, agent).await?;
+        let launch = self.inner.launch_allowlist.validate(launch)?;
+        // Hold the agent lock until the instance is registered, so an
+        // uninstall either sees it or runs before it is installed.
+        let agent_lock = self.agent_lock(&agent).await;
+        let install_start = Instant::now();
+        let _installed = self.ensure_installed(&agent, &agent_lock).await?;
+        tracing::info!(
+            server_id = server_id,
+            agent = agent.as_str(),
+            install_ms = install_start.elapsed().as_millis() as u64,
+            "create_instance: agent installed/verified"
+        );
+        let created = self
+            .create_instance(server_id, &agent, launch, labels.clone())
+            .await?;
         self.inner
             .instances
             .write()
             .await
             .insert(server_id.to_string(), created.clone());
+        self.ensure_reaper();
 
         Ok(created)
     }
@@ -275,7 +700,9 @@ impl AcpProxyRuntime {
     async fn create_instance(
         &self,
         server_id: &str,
-        agent: AgentId,
+        agent: &AgentId,
+        overrides: AcpLaunchOverrides,
+        labels: BTreeMap<String, String>,
     ) -> Result<Arc<ProxyInstance>, SandboxError> {
         let start = std::time::Instant::now();
         tracing::info!(
@@ -284,41 +711,120 @@ impl AcpProxyRuntime {
             "create_instance: starting"
         );
 
-        self.ensure_installed(agent).await?;
-        let install_elapsed = start.elapsed();
-        tracing::info!(
-            server_id = server_id,
-            agent = agent.as_str(),
-            install_ms = install_elapsed.as_millis() as u64,
-            "create_instance: agent installed/verified"
-        );
-
         let manager = self.inner.agent_manager.clone();
-        let launch = tokio::task::spawn_blocking(move || manager.resolve_agent_process(agent))
-            .await
-            .map_err(|err| SandboxError::StreamError {
-                message: format!("failed to resolve ACP agent process launch spec: {err}"),
-            })?
-            .map_err(|err| SandboxError::StreamError {
-                message: err.to_string(),
-            })?;
+        let agent_id = agent.clone();
+        let mut launch =
+            tokio::task::spawn_blocking(move || manager.resolve_agent_process(&agent_id))
+                .await
+                .map_err(|err| SandboxError::StreamError {
+                    message: format!("failed to resolve ACP agent process launch spec: {err}"),
+                })?
+                .map_err(|err| SandboxError::StreamError {
+                    message: err.to_string(),
+                })?;
 
         tracing::info!(
             server_id = server_id,
             agent = agent.as_str(),
             program = ?launch.program,
             args = ?launch.args,
+            cwd = ?overrides.cwd,
             resolve_ms = start.elapsed().as_millis() as u64,
             "create_instance: launch spec resolved, spawning"
         );
 
-        let runtime = AdapterRuntime::start(
+        launch.args.extend(overrides.args.iter().cloned());
+        launch.env.extend(
+            overrides
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+
+        let event_store = match &self.inner.event_log {
+            Some(config) => {
+                let dir = config.dir.join(event_log_dir_name(server_id));
+                let store =
+                    SegmentEventStore::open(&dir, config.retention.clone()).map_err(|err| {
+                        SandboxError::StreamError {
+                            message: format!(
+                                "failed to open ACP event log at {}: {err}",
+                                dir.display()
+                            ),
+                        }
+                    })?;
+                Some(Arc::new(store) as Arc<dyn EventStore>)
+            }
+            None => None,
+        };
+
+        let terminals = self
+            .inner
+            .serve_terminals
+            .then(|| AcpTerminals::new(overrides.cwd.clone()));
+        let mut client_handlers: Vec<Arc<dyn ClientRequestHandler>> = Vec::new();
+        if self.inner.serve_fs {
+            client_handlers.push(Arc::new(AcpFsHandler::new(self.inner.metrics.clone())));
+        }
+        if let Some(terminals) = &terminals {
+            client_handlers.push(Arc::new(terminals.clone()));
+        }
+        if let Some(policy) = AcpPermissionPolicy::new(
+            server_id,
+            overrides.permission_policy.clone(),
+            self.inner.permission_policy.clone(),
+        ) {
+            client_handlers.push(Arc::new(policy));
+        }
+
+        let framing = match launch.framing.as_deref() {
+            Some(raw) => Framing::parse(raw).ok_or_else(|| SandboxError::StreamError {
+                message: format!(
+                    "agent '{}' declares unsupported stdio framing '{raw}'",
+                    agent.as_str()
+                ),
+            })?,
+            None => Framing::default(),
+        };
+
+        let runtime = AdapterRuntime::start_with_options(
             LaunchSpec {
                 program: launch.program,
                 args: launch.args,
                 env: launch.env,
+                cwd: overrides.cwd.clone(),
+                framing,
+            },
+            RuntimeOptions {
+                request_timeout: self.inner.request_timeout,
+                method_timeouts: self.inner.method_timeouts.clone(),
+                cancel_on_timeout: self.inner.cancel_on_timeout,
+                restart: self.inner.restart.clone(),
+                event_store,
+                client_handlers,
+                limits: self
+                    .inner
+                    .resource_limits
+                    .get(agent)
+                    .cloned()
+                    .unwrap_or_else(|| resource_limits_for(agent)),
+                shutdown_grace: self.inner.shutdown_grace,
+                stderr_file: self
+                    .inner
+                    .stderr_log
+                    .as_ref()
+                    .map(|config| StderrFileConfig {
+                        path: config
+                            .dir
+                            .join(agent.as_str())
+                            .join(format!("{}.log", event_log_dir_name(server_id))),
+                        max_bytes: config.max_bytes,
+                    }),
+                transcript: self.inner.transcript_dir.as_ref().map(|dir| {
+                    dir.join(agent.as_str())
+                        .join(format!("{}.jsonl", event_log_dir_name(server_id)))
+                }),
             },
-            self.inner.request_timeout,
         )
         .await
         .map_err(map_adapter_error)?;
@@ -333,62 +839,353 @@ impl AcpProxyRuntime {
 
         Ok(Arc::new(ProxyInstance {
             server_id: server_id.to_string(),
-            agent,
+            agent: agent.clone(),
+            launch: overrides,
             runtime: Arc::new(runtime),
+            terminals,
             created_at_ms: now_ms(),
+            activity: Arc::new(InstanceActivity::new()),
+            labels: RwLock::new(labels),
         }))
     }
 
-    async fn ensure_installed(&self, agent: AgentId) -> Result<(), SandboxError> {
-        if self.inner.require_preinstall {
-            if !self.is_ready(agent).await {
-                return Err(SandboxError::AgentNotInstalled {
-                    agent: agent.as_str().to_string(),
-                });
+    /// Start the background reaper on first use. It is spawned lazily because
+    /// the runtime may be constructed before a tokio runtime exists, and it only
+    /// holds a weak reference so it stops once the proxy runtime is dropped.
+    fn ensure_reaper(&self) {
+        let Some(interval) = self.inner.reaper_interval() else {
+            return;
+        };
+        if self.inner.reaper_started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let weak: Weak<AcpProxyRuntimeInner> = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let Some(inner) = weak.upgrade() else {
+                    break;
+                };
+                AcpProxyRuntime { inner }.reap_expired().await;
             }
-            return Ok(());
+        });
+    }
+
+    async fn reap_expired(&self) {
+        let now = now_ms();
+        let expired = {
+            let mut instances = self.inner.instances.write().await;
+            let expired_ids = instances
+                .values()
+                .filter_map(|instance| {
+                    self.inner
+                        .reap_reason(instance, now)
+                        .map(|reason| (instance.server_id.clone(), reason))
+                })
+                .collect::<Vec<_>>();
+            expired_ids
+                .into_iter()
+                .filter_map(|(server_id, reason)| {
+                    instances
+                        .remove(&server_id)
+                        .map(|instance| (instance, reason))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (instance, reason) in expired {
+            self.stop_instance(&instance, reason).await;
         }
+    }
+
+    /// Tell subscribers why an instance already removed from the map is
+    /// going away, then shut it down.
+    async fn stop_instance(&self, instance: &ProxyInstance, reason: ReapReason) {
+        let now = now_ms();
+        let idle_ms = now - instance.activity.last_activity_ms.load(Ordering::SeqCst);
+        let age_ms = now - instance.created_at_ms;
+        tracing::info!(
+            server_id = %instance.server_id,
+            agent = instance.agent.as_str(),
+            reason = reason.as_str(),
+            idle_ms = idle_ms,
+            age_ms = age_ms,
+            "acp_proxy: reaping instance"
+        );
+        instance
+            .runtime
+            .publish(json!({
+                "jsonrpc": "2.0",
+                "method": "_adapter/instance_reaped",
+                "params": {
+                    "serverId": instance.server_id,
+                    "agent": instance.agent.as_str(),
+                    "reason": reason.as_str(),
+                    "idleMs": idle_ms,
+                    "ageMs": age_ms,
+                }
+            }))
+            .await;
+        instance.shutdown().await;
+    }
 
+    /// Install `agent` if needed. The returned guard on `lock` (the agent's
+    /// lock) keeps the install from being removed until it is dropped.
+    async fn ensure_installed<'a>(
+        &self,
+        agent: &AgentId,
+        lock: &'a RwLock<()>,
+    ) -> Result<RwLockReadGuard<'a, ()>, SandboxError> {
+        let guard = lock.read().await;
         if self.is_ready(agent).await {
-            return Ok(());
+            return Ok(guard);
         }
+        if self.inner.require_preinstall {
+            return Err(SandboxError::AgentNotInstalled {
+                agent: agent.as_str().to_string(),
+            });
+        }
+        drop(guard);
 
-        let lock = {
-            let mut locks = self.inner.install_locks.lock().await;
-            locks
-                .entry(agent)
-                .or_insert_with(|| Arc::new(Mutex::new(())))
-                .clone()
-        };
-        let _guard = lock.lock().await;
-
+        let guard = lock.write().await;
         if self.is_ready(agent).await {
-            return Ok(());
+            return Ok(guard.downgrade());
         }
 
         let manager = self.inner.agent_manager.clone();
-        tokio::task::spawn_blocking(move || manager.install(agent, InstallOptions::default()))
-            .await
-            .map_err(|err| SandboxError::InstallFailed {
-                agent: agent.as_str().to_string(),
-                stderr: Some(format!("installer task failed: {err}")),
-            })?
-            .map_err(|err| SandboxError::InstallFailed {
+        let agent_id = agent.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            manager.install(&agent_id, InstallOptions::default())
+        })
+        .await
+        .map_err(|err| SandboxError::InstallFailed {
+            agent: agent.as_str().to_string(),
+            stderr: Some(format!("installer task failed: {err}")),
+        })
+        .and_then(|result| {
+            result.map_err(|err| SandboxError::InstallFailed {
                 agent: agent.as_str().to_string(),
                 stderr: Some(err.to_string()),
-            })?;
+            })
+        });
+        self.inner
+            .metrics
+            .record_install(agent.as_str(), result.is_ok());
+        self.inner
+            .metrics
+            .record_unverified_downloads(agent.as_str(), result?.unverified_downloads.len());
 
-        Ok(())
+        Ok(guard.downgrade())
     }
 
-    async fn is_ready(&self, agent: AgentId) -> bool {
-        if agent == AgentId::Mock {
+    /// Forget a server's creation lock once no other caller holds it, so
+    /// servers that were deleted, reaped, stopped or never created leave no
+    /// entry behind.
+    async fn release_instance_lock(&self, server_id: &str) {
+        let mut locks = self.inner.instance_locks.lock().await;
+        if locks
+            .get(server_id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(server_id);
+        }
+    }
+
+    async fn agent_lock(&self, agent: &AgentId) -> Arc<RwLock<()>> {
+        let mut locks = self.inner.agent_locks.lock().await;
+        locks
+            .entry(agent.clone())
+            .or_insert_with(|| Arc::new(RwLock::new(())))
+            .clone()
+    }
+
+    async fn is_ready(&self, agent: &AgentId) -> bool {
+        if *agent == AgentId::Mock {
             return self.inner.agent_manager.agent_process_path(agent).exists();
         }
         self.inner.agent_manager.is_installed(agent)
     }
 }
 
+impl AcpProxyRuntimeInner {
+    fn reaper_interval(&self) -> Option<Duration> {
+        let shortest = match (self.idle_timeout, self.max_lifetime) {
+            (Some(idle), Some(lifetime)) => idle.min(lifetime),
+            (Some(idle), None) => idle,
+            (None, Some(lifetime)) => lifetime,
+            (None, None) => return None,
+        };
+        Some((shortest / 2).clamp(REAPER_MIN_INTERVAL, REAPER_MAX_INTERVAL))
+    }
+
+    fn reap_reason(&self, instance: &ProxyInstance, now: i64) -> Option<ReapReason> {
+        if let Some(max_lifetime) = self.max_lifetime {
+            if now - instance.created_at_ms >= max_lifetime.as_millis() as i64 {
+                return Some(ReapReason::MaxLifetime);
+            }
+        }
+        if let Some(idle_timeout) = self.idle_timeout {
+            let activity = &instance.activity;
+            if activity.active.load(Ordering::SeqCst) == 0
+                && now - activity.last_activity_ms.load(Ordering::SeqCst)
+                    >= idle_timeout.as_millis() as i64
+            {
+                return Some(ReapReason::IdleTimeout);
+            }
+        }
+        None
+    }
+}
+
+impl ProxyInstance {
+    async fn info(&self) -> AcpServerInstanceInfo {
+        AcpServerInstanceInfo {
+            server_id: self.server_id.clone(),
+            agent: self.agent.clone(),
+            launch: self.launch.clone(),
+            labels: self.labels.read().await.clone(),
+            created_at_ms: self.created_at_ms,
+            restart_count: self.runtime.restart_count(),
+            pid: self.runtime.pid(),
+            state: self.runtime.process_state(),
+            pending_requests: self.runtime.pending_count().await,
+            last_activity_ms: self.activity.last_activity_ms(),
+            subscribers: self.runtime.subscriber_count(),
+            spawn_duration: self.runtime.spawn_duration(),
+            first_stdout_latency: self.runtime.first_stdout_latency(),
+            lag: self.runtime.lag_stats(),
+        }
+    }
+
+    /// Stop the agent process and everything it started through the proxy.
+    async fn shutdown(&self) {
+        self.runtime.shutdown().await;
+        if let Some(terminals) = &self.terminals {
+            terminals.release_all().await;
+        }
+    }
+}
+
+impl AcpLaunchOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.cwd.is_none()
+            && self.env.is_empty()
+            && self.args.is_empty()
+            && self.permission_policy.is_none()
+    }
+}
+
+impl LaunchAllowlist {
+    /// Check overrides against the allowlist, returning them with `cwd`
+    /// canonicalized.
+    fn validate(&self, overrides: &AcpLaunchOverrides) -> Result<AcpLaunchOverrides, SandboxError> {
+        let cwd = match &overrides.cwd {
+            Some(cwd) => {
+                let canonical = std::fs::canonicalize(cwd)
+                    .ok()
+                    .filter(|path| path.is_dir())
+                    .ok_or_else(|| SandboxError::InvalidRequest {
+                        message: format!("cwd '{}' is not an existing directory", cwd.display()),
+                    })?;
+                if let Some(roots) = &self.cwd_roots {
+                    let allowed = roots.iter().any(|root| {
+                        std::fs::canonicalize(root).is_ok_and(|root| canonical.starts_with(root))
+                    });
+                    if !allowed {
+                        return Err(SandboxError::PermissionDenied {
+                            message: Some(format!(
+                                "cwd '{}' is outside SANDBOX_AGENT_ACP_ALLOWED_CWD_ROOTS",
+                                canonical.display()
+                            )),
+                        });
+                    }
+                }
+                Some(canonical)
+            }
+            None => None,
+        };
+
+        if let Some(key) = overrides
+            .env
+            .keys()
+            .find(|key| !matches_any_pattern(&self.env, key))
+        {
+            return Err(SandboxError::PermissionDenied {
+                message: Some(format!(
+                    "env var '{key}' is not allowed by SANDBOX_AGENT_ACP_ALLOWED_ENV"
+                )),
+            });
+        }
+        if let Some(arg) = overrides
+            .args
+            .iter()
+            .find(|arg| !matches_any_pattern(&self.args, arg))
+        {
+            return Err(SandboxError::PermissionDenied {
+                message: Some(format!(
+                    "argument '{arg}' is not allowed by SANDBOX_AGENT_ACP_ALLOWED_ARGS"
+                )),
+            });
+        }
+
+        Ok(AcpLaunchOverrides {
+            cwd,
+            env: overrides.env.clone(),
+            args: overrides.args.clone(),
+            permission_policy: overrides.permission_policy.clone(),
+        })
+    }
+}
+
+impl InstanceActivity {
+    fn new() -> Self {
+        Self {
+            last_activity_ms: AtomicI64::new(now_ms()),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        self.last_activity_ms.store(now_ms(), Ordering::SeqCst);
+    }
+
+    fn last_activity_ms(&self) -> i64 {
+        if self.active.load(Ordering::SeqCst) > 0 {
+            now_ms()
+        } else {
+            self.last_activity_ms.load(Ordering::SeqCst)
+        }
+    }
+}
+
+impl ActivityGuard {
+    fn new(activity: Arc<InstanceActivity>) -> Self {
+        activity.active.fetch_add(1, Ordering::SeqCst);
+        activity.touch();
+        Self { activity }
+    }
+}
+
+impl Drop for ActivityGuard {
+    fn drop(&mut self) {
+        self.activity.touch();
+        self.activity.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ReapReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::IdleTimeout => "idle_timeout",
+            Self::MaxLifetime => "max_lifetime",
+            Self::AgentUninstalled => "agent_uninstalled",
+        }
+    }
+}
+
 impl AcpDispatch for AcpProxyRuntime {
     fn post(
         &self,
@@ -397,9 +1194,19 @@ impl AcpDispatch for AcpProxyRuntime {
         payload: Value,
     ) -> Pin<Box<dyn Future<Output = Result<AcpDispatchResult, String>> + Send + '_>> {
         let server_id = server_id.to_string();
-        let agent = bootstrap_agent.and_then(AgentId::parse);
+        let agent = bootstrap_agent.and_then(|agent| self.inner.agent_manager.parse_agent(agent));
         Box::pin(async move {
-            match self.post(&server_id, agent, payload).await {
+            match self
+                .post(
+                    &server_id,
+                    agent,
+                    &AcpLaunchOverrides::default(),
+                    &BTreeMap::new(),
+                    AcpRequestOptions::default(),
+                    payload,
+                )
+                .await
+            {
                 Ok(ProxyPostOutcome::Response(value)) => Ok(AcpDispatchResult::Response(value)),
                 Ok(ProxyPostOutcome::Accepted) => Ok(AcpDispatchResult::Accepted),
                 Err(err) => Err(err.to_string()),
@@ -418,7 +1225,12 @@ impl AcpDispatch for AcpProxyRuntime {
                 .get_instance(&server_id)
                 .await
                 .map_err(|e| e.to_string())?;
+            let activity = ActivityGuard::new(instance.activity.clone());
             let stream = instance.runtime.clone().value_stream(last_event_id).await;
+            let stream = stream.map(move |item| {
+                let _ = &activity;
+                item
+            });
             Ok(Box::pin(stream) as AcpPayloadStream)
         })
     }
@@ -432,13 +1244,57 @@ impl AcpDispatch for AcpProxyRuntime {
     }
 }
 
+/// Reject bootstrap parameters that disagree with an existing instance.
+/// Omitted parameters always match.
+fn ensure_compatible(
+    existing: &ProxyInstance,
+    bootstrap_agent: Option<AgentId>,
+    launch: &AcpLaunchOverrides,
+) -> Result<(), SandboxError> {
+    let server_id = &existing.server_id;
+    if let Some(agent) = bootstrap_agent {
+        if agent != existing.agent {
+            return Err(SandboxError::Conflict {
+                message: format!(
+                    "server '{server_id}' already exists for agent '{}'; requested '{agent}'",
+                    existing.agent.as_str()
+                ),
+            });
+        }
+    }
+
+    if launch.is_empty() {
+        return Ok(());
+    }
+    let cwd = launch
+        .cwd
+        .as_ref()
+        .map(|cwd| std::fs::canonicalize(cwd).unwrap_or_else(|_| cwd.clone()));
+    if cwd != existing.launch.cwd
+        || launch.env != existing.launch.env
+        || launch.args != existing.launch.args
+        || launch.permission_policy != existing.launch.permission_policy
+    {
+        return Err(SandboxError::Conflict {
+            message: format!(
+                "server '{server_id}' already exists with different launch settings; \
+                 cwd, env, args and permissionPolicy only apply to the first POST"
+            ),
+        });
+    }
+    Ok(())
+}
+
 fn map_adapter_error(err: AdapterError) -> SandboxError {
     match err {
         AdapterError::InvalidEnvelope => SandboxError::InvalidRequest {
             message: "request body must be a JSON-RPC object".to_string(),
         },
-        AdapterError::Timeout => SandboxError::Timeout {
-            message: Some("timed out waiting for agent response".to_string()),
+        err @ AdapterError::Timeout { .. } => SandboxError::Timeout {
+            message: Some(err.to_string()),
+        },
+        err @ AdapterError::AlreadyResolved { .. } => SandboxError::Conflict {
+            message: err.to_string(),
         },
         AdapterError::Serialize(error) => SandboxError::InvalidRequest {
             message: format!("failed to serialize JSON payload: {error}"),
@@ -446,6 +1302,9 @@ fn map_adapter_error(err: AdapterError) -> SandboxError {
         AdapterError::Write(error) => SandboxError::StreamError {
             message: format!("failed writing to agent stdin: {error}"),
         },
+        AdapterError::AgentExited { code } => SandboxError::StreamError {
+            message: format!("agent process exited before responding (code {code:?})"),
+        },
         AdapterError::Spawn(error) => SandboxError::StreamError {
             message: format!("failed to start agent process: {error}"),
         },
@@ -459,8 +1318,8 @@ fn map_adapter_error(err: AdapterError) -> SandboxError {
 
 /// Inspect JSON-RPC error responses from agent processes and add helpful hints
 /// when we can infer the root cause from a known error pattern.
-fn annotate_agent_error(agent: AgentId, mut value: Value) -> Value {
-    if agent != AgentId::Pi {
+fn annotate_agent_error(agent: &AgentId, mut value: Value) -> Value {
+    if *agent != AgentId::Pi {
         return value;
     }
 
@@ -488,17 +1347,240 @@ fn annotate_agent_error(agent: AgentId, mut value: Value) -> Value {
     value
 }
 
-fn duration_from_env_ms(key: &str, default: Duration) -> Duration {
-    match std::env::var(key) {
-        Ok(raw) => raw
-            .trim()
-            .parse::<u64>()
+/// Whether a JSON-RPC message is a request, i.e. expects a response.
+fn is_request(message: &Value) -> bool {
+    message.get("method").is_some() && message.get("id").is_some()
+}
+
+fn restart_policy_from_env() -> RestartPolicy {
+    let defaults = RestartPolicy::default();
+    let mode = match std::env::var("SANDBOX_AGENT_ACP_RESTART_POLICY") {
+        Ok(raw) => RestartMode::parse(&raw).unwrap_or_else(|| {
+            tracing::warn!(
+                value = %raw,
+                "ignoring invalid SANDBOX_AGENT_ACP_RESTART_POLICY (expected never, on-failure or always)"
+            );
+            RestartMode::Never
+        }),
+        Err(_) => RestartMode::Never,
+    };
+    let max_attempts = std::env::var("SANDBOX_AGENT_ACP_RESTART_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|raw| raw.trim().parse::<u32>().ok())
+        .unwrap_or(defaults.max_attempts);
+
+    RestartPolicy {
+        mode,
+        max_attempts,
+        initial_backoff: duration_from_env_ms(
+            "SANDBOX_AGENT_ACP_RESTART_BACKOFF_MS",
+            defaults.initial_backoff,
+        ),
+        max_backoff: duration_from_env_ms(
+            "SANDBOX_AGENT_ACP_RESTART_MAX_BACKOFF_MS",
+            defaults.max_backoff,
+        ),
+    }
+}
+
+/// `SANDBOX_AGENT_ACP_METHOD_TIMEOUTS_MS`, e.g.
+/// `session/set_mode=10000,session/prompt=600000`.
+fn method_timeouts_from_env() -> HashMap<String, Duration> {
+    let Ok(raw) = std::env::var("SANDBOX_AGENT_ACP_METHOD_TIMEOUTS_MS") else {
+        return HashMap::new();
+    };
+    let mut timeouts = HashMap::new();
+    for entry in raw
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let parsed = entry.split_once('=').and_then(|(method, ms)| {
+            let ms = ms.trim().parse::<u64>().ok().filter(|ms| *ms > 0)?;
+            Some((method.trim().to_string(), Duration::from_millis(ms)))
+        });
+        match parsed {
+            Some((method, timeout)) if !method.is_empty() => {
+                timeouts.insert(method, timeout);
+            }
+            _ => tracing::warn!(
+                entry = %entry,
+                "ignoring invalid SANDBOX_AGENT_ACP_METHOD_TIMEOUTS_MS entry (expected METHOD=MS)"
+            ),
+        }
+    }
+    timeouts
+}
+
+fn launch_allowlist_from_env() -> LaunchAllowlist {
+    let patterns = |key: &str| {
+        std::env::var(key)
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    LaunchAllowlist {
+        cwd_roots: std::env::var_os("SANDBOX_AGENT_ACP_ALLOWED_CWD_ROOTS")
+            .filter(|value| !value.is_empty())
+            .map(|value| std::env::split_paths(&value).collect()),
+        env: patterns("SANDBOX_AGENT_ACP_ALLOWED_ENV"),
+        args: patterns("SANDBOX_AGENT_ACP_ALLOWED_ARGS"),
+    }
+}
+
+/// Limits come from `SANDBOX_AGENT_ACP_<LIMIT>`, overridden per agent by
+/// `SANDBOX_AGENT_ACP_<AGENT>_<LIMIT>` (for example
+/// `SANDBOX_AGENT_ACP_CODEX_MEMORY_LIMIT_MB`).
+fn resource_limits_from_env() -> HashMap<AgentId, ResourceLimits> {
+    AgentId::all()
+        .iter()
+        .map(|agent| (agent.clone(), resource_limits_for(agent)))
+        .collect()
+}
+
+/// Limits for one agent, including custom agents registered after startup.
+fn resource_limits_for(agent: &AgentId) -> ResourceLimits {
+    let agent_key = agent.as_str().to_ascii_uppercase().replace('-', "_");
+    let value = |limit: &str| {
+        std::env::var(format!("SANDBOX_AGENT_ACP_{agent_key}_{limit}"))
+            .or_else(|_| std::env::var(format!("SANDBOX_AGENT_ACP_{limit}")))
+            .ok()
+            .map(|raw| raw.trim().to_string())
+            .filter(|raw| !raw.is_empty())
+    };
+    let u64_value = |limit: &str| {
+        value(limit)
+            .and_then(|raw| raw.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+    };
+
+    ResourceLimits {
+        memory_bytes: u64_value("MEMORY_LIMIT_MB")
+            .map(|megabytes| megabytes.saturating_mul(1024 * 1024)),
+        cpu_cores: value("CPU_LIMIT")
+            .and_then(|raw| raw.parse::<f64>().ok())
+            .filter(|cores| cores.is_finite() && *cores > 0.0),
+        max_pids: u64_value("PIDS_LIMIT"),
+        open_files: u64_value("NOFILE_LIMIT"),
+        cgroup_parent: std::env::var_os("SANDBOX_AGENT_ACP_CGROUP_PARENT")
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from),
+    }
+}
+
+/// `SANDBOX_AGENT_ACP_PERMISSION_POLICY` holds either inline JSON or the path
+/// of a JSON file.
+fn permission_policy_from_env() -> Option<PermissionPolicy> {
+    let raw = std::env::var("SANDBOX_AGENT_ACP_PERMISSION_POLICY")
+        .ok()
+        .filter(|value| !value.trim().is_empty())?;
+    let json = if raw.trim_start().starts_with('{') {
+        raw
+    } else {
+        match std::fs::read_to_string(raw.trim()) {
+            Ok(json) => json,
+            Err(err) => {
+                tracing::warn!(path = %raw.trim(), error = %err, "failed to read ACP permission policy");
+                return None;
+            }
+        }
+    };
+    match PermissionPolicy::parse(&json) {
+        Ok(policy) => Some(policy),
+        Err(err) => {
+            tracing::warn!(error = %err, "invalid ACP permission policy; ignoring it");
+            None
+        }
+    }
+}
+
+fn matches_any_pattern(patterns: &[String], value: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => value.starts_with(prefix),
+            None => pattern == value,
+        })
+}
+
+fn event_log_config_from_env() -> Option<EventLogConfig> {
+    let dir = std::env::var_os("SANDBOX_AGENT_ACP_EVENT_LOG_DIR")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)?;
+    let u64_from_env = |key: &str| {
+        std::env::var(key)
             .ok()
+            .and_then(|raw| raw.trim().parse::<u64>().ok())
             .filter(|value| *value > 0)
-            .map(Duration::from_millis)
-            .unwrap_or(default),
-        Err(_) => default,
+    };
+
+    Some(EventLogConfig {
+        dir,
+        retention: EventRetention {
+            max_events: u64_from_env("SANDBOX_AGENT_ACP_EVENT_LOG_MAX_EVENTS"),
+            max_bytes: Some(
+                u64_from_env("SANDBOX_AGENT_ACP_EVENT_LOG_MAX_BYTES")
+                    .unwrap_or(DEFAULT_EVENT_LOG_MAX_BYTES),
+            ),
+            max_age: optional_duration_from_env_ms("SANDBOX_AGENT_ACP_EVENT_LOG_MAX_AGE_MS"),
+        },
+    })
+}
+
+fn stderr_log_config_from_env() -> Option<StderrLogConfig> {
+    let dir = std::env::var_os("SANDBOX_AGENT_ACP_STDERR_LOG_DIR")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)?;
+    let max_bytes = std::env::var("SANDBOX_AGENT_ACP_STDERR_LOG_MAX_BYTES")
+        .ok()
+        .and_then(|raw| raw.trim().parse::<u64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(DEFAULT_STDERR_LOG_MAX_BYTES);
+    Some(StderrLogConfig { dir, max_bytes })
+}
+
+/// Server ids are client-defined, so anything outside `[A-Za-z0-9_-]` is
+/// percent-encoded before it becomes a directory name.
+fn event_log_dir_name(server_id: &str) -> String {
+    let mut name = String::with_capacity(server_id.len());
+    for byte in server_id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{byte:02X}"));
+        }
     }
+    name
+}
+
+fn bool_from_env(key: &str) -> bool {
+    std::env::var(key).ok().is_some_and(|value| {
+        let trimmed = value.trim();
+        trimmed == "1"
+            || trimmed.eq_ignore_ascii_case("true")
+            || trimmed.eq_ignore_ascii_case("yes")
+    })
+}
+
+fn duration_from_env_ms(key: &str, default: Duration) -> Duration {
+    optional_duration_from_env_ms(key).unwrap_or(default)
+}
+
+/// Read an optional millisecond duration; unset, unparsable, or `0` disables it.
+fn optional_duration_from_env_ms(key: &str) -> Option<Duration> {
+    std::env::var(key)
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|value| *value > 0)
+        .map(Duration::from_millis)
 }
 
 fn now_ms() -> i64 {
@@ -0,0 +1,369 @@
+//! Server-side implementation of the ACP `terminal/*` client methods.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use acp_http_adapter::process::{
+    terminate_process_group, ClientRequestError, ClientRequestHandler, StreamPublisher,
+};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::sync::{oneshot, watch, Mutex};
+
+use crate::acp_fs::{
+    client_capabilities, parse_params, resolve_path, INTERNAL_ERROR, RESOURCE_NOT_FOUND,
+};
+
+/// Output kept per terminal when the agent does not set `outputByteLimit`.
+const DEFAULT_OUTPUT_BYTE_LIMIT: usize = 1024 * 1024;
+/// How long to keep reading output after the process exits, in case a
+/// descendant still holds the pipes open.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long a killed terminal's process group gets to exit after SIGTERM
+/// before it is sent SIGKILL.
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+/// Terminals created by one agent process. Cloning shares the same set.
+#[derive(Debug, Clone)]
+pub(crate) struct AcpTerminals {
+    inner: Arc<TerminalsInner>,
+}
+
+#[derive(Debug)]
+struct TerminalsInner {
+    default_cwd: Option<PathBuf>,
+    next_id: AtomicU64,
+    terminals: Mutex<HashMap<String, Arc<Terminal>>>,
+}
+
+#[derive(Debug)]
+struct Terminal {
+    output: Arc<std::sync::Mutex<OutputBuffer>>,
+    exit: watch::Receiver<Option<TerminalExit>>,
+    /// Sending or dropping this kills the process group.
+    kill: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+#[derive(Debug)]
+struct OutputBuffer {
+    bytes: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TerminalExit {
+    exit_code: Option<i32>,
+    signal: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateTerminalParams {
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: Vec<EnvVariable>,
+    #[serde(default)]
+    cwd: Option<String>,
+    #[serde(default)]
+    output_byte_limit: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EnvVariable {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TerminalParams {
+    terminal_id: String,
+}
+
+impl AcpTerminals {
+    /// `default_cwd` applies to terminals created without a `cwd`.
+    pub(crate) fn new(default_cwd: Option<PathBuf>) -> Self {
+        Self {
+            inner: Arc::new(TerminalsInner {
+                default_cwd,
+                next_id: AtomicU64::new(1),
+                terminals: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Kill and forget every terminal, for example when the instance is
+    /// deleted.
+    pub(crate) async fn release_all(&self) {
+        let terminals = std::mem::take(&mut *self.inner.terminals.lock().await);
+        for terminal in terminals.into_values() {
+            terminal.kill().await;
+        }
+    }
+}
+
+impl ClientRequestHandler for AcpTerminals {
+    fn handle(
+        &self,
+        method: &str,
+        params: &Value,
+        _stream: &StreamPublisher,
+    ) -> Option<BoxFuture<'static, Result<Value, ClientRequestError>>> {
+        let inner = self.inner.clone();
+        let params = params.clone();
+        match method {
+            "terminal/create" => Some(Box::pin(async move { inner.create(params).await })),
+            "terminal/output" => Some(Box::pin(async move {
+                let terminal = inner.get(params).await?;
+                Ok(terminal.output_response())
+            })),
+            "terminal/wait_for_exit" => Some(Box::pin(async move {
+                let terminal = inner.get(params).await?;
+                let exit = terminal.wait_for_exit().await?;
+                Ok(exit.to_json())
+            })),
+            "terminal/kill" => Some(Box::pin(async move {
+                inner.get(params).await?.kill().await;
+                Ok(json!({}))
+            })),
+            "terminal/release" => Some(Box::pin(async move {
+                let TerminalParams { terminal_id } = parse_params(params)?;
+                let terminal = inner.terminals.lock().await.remove(&terminal_id);
+                terminal
+                    .ok_or_else(|| unknown_terminal(&terminal_id))?
+                    .kill()
+                    .await;
+                Ok(json!({}))
+            })),
+            _ => None,
+        }
+    }
+}
+
+impl TerminalsInner {
+    async fn create(&self, params: Value) -> Result<Value, ClientRequestError> {
+        let params: CreateTerminalParams = parse_params(params)?;
+        let cwd = match &params.cwd {
+            Some(cwd) => Some(resolve_path(cwd)?),
+            None => self.default_cwd.clone(),
+        };
+
+        let mut command = Command::new(&params.command);
+        command
+            .args(&params.args)
+            .envs(params.env.iter().map(|var| (&var.name, &var.value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(cwd) = &cwd {
+            command.current_dir(cwd);
+        }
+        // A group of its own keeps the terminal's descendants out of the
+        // server's group and lets a kill reach them.
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = command.spawn().map_err(|err| ClientRequestError {
+            code: INTERNAL_ERROR,
+            message: format!("failed to spawn '{}': {err}", params.command),
+        })?;
+
+        let limit = params
+            .output_byte_limit
+            .map_or(DEFAULT_OUTPUT_BYTE_LIMIT, |limit| limit as usize);
+        let output = Arc::new(std::sync::Mutex::new(OutputBuffer::new(limit)));
+        let readers = [
+            child
+                .stdout
+                .take()
+                .map(|stdout| spawn_reader(stdout, output.clone())),
+            child
+                .stderr
+                .take()
+                .map(|stderr| spawn_reader(stderr, output.clone())),
+        ];
+
+        let (kill_tx, kill_rx) = oneshot::channel();
+        let (exit_tx, exit_rx) = watch::channel(None);
+        tokio::spawn(async move {
+            let status = tokio::select! {
+                status = child.wait() => status.ok(),
+                _ = kill_rx => terminate_process_group(&mut child, KILL_GRACE).await.0,
+            };
+            let drain = futures::future::join_all(readers.into_iter().flatten());
+            let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, drain).await;
+            exit_tx.send_replace(Some(match status {
+                Some(status) => TerminalExit::from_status(status),
+                None => TerminalExit {
+                    exit_code: None,
+                    signal: None,
+                },
+            }));
+        });
+
+        let terminal_id = format!("term-{}", self.next_id.fetch_add(1, Ordering::SeqCst));
+        tracing::info!(
+            terminal_id = %terminal_id,
+            command = %params.command,
+            args = ?params.args,
+            cwd = ?cwd,
+            "acp terminal: created"
+        );
+        self.terminals.lock().await.insert(
+            terminal_id.clone(),
+            Arc::new(Terminal {
+                output,
+                exit: exit_rx,
+                kill: Mutex::new(Some(kill_tx)),
+            }),
+        );
+        Ok(json!({ "terminalId": terminal_id }))
+    }
+
+    async fn get(&self, params: Value) -> Result<Arc<Terminal>, ClientRequestError> {
+        let TerminalParams { terminal_id } = parse_params(params)?;
+        self.terminals
+            .lock()
+            .await
+            .get(&terminal_id)
+            .cloned()
+            .ok_or_else(|| unknown_terminal(&terminal_id))
+    }
+}
+
+impl Terminal {
+    fn output_response(&self) -> Value {
+        let (output, truncated) = {
+            let buffer = self.output.lock().unwrap_or_else(|err| err.into_inner());
+            (
+                String::from_utf8_lossy(&buffer.bytes).into_owned(),
+                buffer.truncated,
+            )
+        };
+        let mut response = json!({ "output": output, "truncated": truncated });
+        if let Some(exit) = self.exit.borrow().as_ref() {
+            response["exitStatus"] = exit.to_json();
+        }
+        response
+    }
+
+    async fn wait_for_exit(&self) -> Result<TerminalExit, ClientRequestError> {
+        let mut exit = self.exit.clone();
+        let exit = exit
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| ClientRequestError {
+                code: INTERNAL_ERROR,
+                message: "terminal exit status unavailable".to_string(),
+            })?;
+        Ok(exit.clone().expect("checked"))
+    }
+
+    async fn kill(&self) {
+        if let Some(kill) = self.kill.lock().await.take() {
+            let _ = kill.send(());
+        }
+    }
+}
+
+impl OutputBuffer {
+    fn new(limit: usize) -> Self {
+        Self {
+            bytes: Vec::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    /// Append output, dropping the oldest bytes past the limit. The kept
+    /// output always starts on a UTF-8 character boundary.
+    fn push(&mut self, chunk: &[u8]) {
+        self.bytes.extend_from_slice(chunk);
+        if self.bytes.len() <= self.limit {
+            return;
+        }
+        let mut start = self.bytes.len() - self.limit;
+        while start < self.bytes.len() && (self.bytes[start] & 0xC0) == 0x80 {
+            start += 1;
+        }
+        self.bytes.drain(..start);
+        self.truncated = true;
+    }
+}
+
+impl TerminalExit {
+    fn from_status(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        let signal = {
+            use std::os::unix::process::ExitStatusExt;
+            status.signal().map(signal_name)
+        };
+        #[cfg(not(unix))]
+        let signal = None;
+
+        Self {
+            exit_code: status.code(),
+            signal,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "exitCode": self.exit_code, "signal": self.signal })
+    }
+}
+
+/// Mark `terminal` as supported in an `initialize` request.
+pub(crate) fn advertise_terminal_capability(payload: &mut Value) {
+    if let Some(capabilities) = client_capabilities(payload) {
+        capabilities.insert("terminal".to_string(), Value::Bool(true));
+    }
+}
+
+fn spawn_reader(
+    mut reader: impl AsyncRead + Unpin + Send + 'static,
+    output: Arc<std::sync::Mutex<OutputBuffer>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut chunk = [0u8; 8192];
+        loop {
+            match reader.read(&mut chunk).await {
+                Ok(0) | Err(_) => break,
+                Ok(read) => output
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .push(&chunk[..read]),
+            }
+        }
+    })
+}
+
+#[cfg(unix)]
+fn signal_name(signal: i32) -> String {
+    match signal {
+        1 => "SIGHUP".to_string(),
+        2 => "SIGINT".to_string(),
+        3 => "SIGQUIT".to_string(),
+        6 => "SIGABRT".to_string(),
+        9 => "SIGKILL".to_string(),
+        13 => "SIGPIPE".to_string(),
+        15 => "SIGTERM".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn unknown_terminal(terminal_id: &str) -> ClientRequestError {
+    ClientRequestError {
+        code: RESOURCE_NOT_FOUND,
+        message: format!("unknown terminal: {terminal_id}"),
+    }
+}
@@ -5,6 +5,7 @@ use std::process::Command as ProcessCommand;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use acp_http_adapter::transcript::{read_transcript, replay_transcript};
 use clap::{Args, Parser, Subcommand};
 
 mod build_version {
@@ -12,7 +13,8 @@ mod build_version {
 }
 
 use crate::router::{
-    build_router_with_state, shutdown_servers, AppState, AuthConfig, BrandingMode,
+    build_router_with_state, shutdown_servers, AcpServerListResponse, AppState, AuthConfig,
+    BrandingMode,
 };
 use crate::server_logs::ServerLogs;
 use crate::telemetry;
@@ -23,7 +25,7 @@ use sandbox_agent_agent_credentials::{
     extract_all_credentials, AuthType, CredentialExtractionOptions, ExtractedCredentials,
     ProviderCredentials,
 };
-use sandbox_agent_agent_management::agents::{AgentId, AgentManager, InstallOptions};
+use sandbox_agent_agent_management::agents::{AgentManager, InstallOptions, InstallResult};
 use serde::Serialize;
 use serde_json::{json, Value};
 use thiserror::Error;
@@ -81,8 +83,12 @@ pub enum Command {
     Daemon(DaemonArgs),
     /// Install or reinstall an agent without running the server.
     InstallAgent(InstallAgentArgs),
+    /// Manage locally installed agents without running the server.
+    Agents(LocalAgentsArgs),
     /// Inspect locally discovered credentials.
     Credentials(CredentialsArgs),
+    /// Act as an ACP agent on stdio by replaying a recorded transcript.
+    ReplayAgent(ReplayAgentArgs),
 }
 
 #[derive(Args, Debug)]
@@ -224,6 +230,73 @@ pub enum AgentsCommand {
     Install(ApiInstallAgentArgs),
 }
 
+#[derive(Args, Debug)]
+pub struct LocalAgentsArgs {
+    #[command(subcommand)]
+    command: LocalAgentsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LocalAgentsCommand {
+    /// Pack installed agents into, or install them from, an offline bundle.
+    Bundle(BundleArgs),
+    /// Remove an installed agent.
+    Uninstall(UninstallAgentArgs),
+    /// Prune staging dirs and agent process files nothing launches.
+    Gc(AgentsGcArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct UninstallAgentArgs {
+    agent: String,
+    /// Shut down the server's ACP servers running the agent and remove it.
+    #[arg(long, short = 'f')]
+    force: bool,
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct AgentsGcArgs {
+    /// Also prune files of agents a server has ACP servers running.
+    #[arg(long, short = 'f')]
+    force: bool,
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct BundleArgs {
+    #[command(subcommand)]
+    command: BundleCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BundleCommand {
+    /// Write installed agents to a bundle archive.
+    Export(BundleExportArgs),
+    /// Install every agent in a bundle archive or directory.
+    Import(BundleImportArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct BundleExportArgs {
+    /// Destination `.tar.gz` archive.
+    output: PathBuf,
+    /// Agent to include; repeat for several. Defaults to every installed
+    /// agent.
+    #[arg(long = "agent", short = 'a')]
+    agents: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct BundleImportArgs {
+    /// Bundle archive or unpacked bundle directory.
+    bundle: PathBuf,
+    #[arg(long, short = 'r')]
+    reinstall: bool,
+}
+
 #[derive(Args, Debug)]
 pub struct AcpArgs {
     #[command(subcommand)]
@@ -291,6 +364,12 @@ pub struct AcpCloseArgs {
     client: ClientArgs,
 }
 
+#[derive(Args, Debug)]
+pub struct ReplayAgentArgs {
+    /// JSONL transcript written under `SANDBOX_AGENT_ACP_TRANSCRIPT_DIR`.
+    transcript: PathBuf,
+}
+
 #[derive(Args, Debug)]
 pub struct InstallAgentArgs {
     agent: String,
@@ -300,6 +379,12 @@ pub struct InstallAgentArgs {
     agent_version: Option<String>,
     #[arg(long = "agent-process-version")]
     agent_process_version: Option<String>,
+    /// Install only from this bundle archive or directory.
+    #[arg(long, value_name = "BUNDLE")]
+    offline: Option<PathBuf>,
+    /// Fail unless the install matches the agent lockfile.
+    #[arg(long)]
+    frozen: bool,
 }
 
 #[derive(Args, Debug)]
@@ -400,7 +485,9 @@ pub fn run_command(command: &Command, cli: &CliConfig) -> Result<(), CliError> {
         Command::Opencode(args) => run_opencode(cli, args),
         Command::Daemon(subcommand) => run_daemon(&subcommand.command, cli),
         Command::InstallAgent(args) => install_agent_local(args),
+        Command::Agents(subcommand) => run_local_agents(&subcommand.command, cli),
         Command::Credentials(subcommand) => run_credentials(&subcommand.command),
+        Command::ReplayAgent(args) => run_replay_agent(args),
     }
 }
 
@@ -725,25 +812,39 @@ fn load_json_payload(
     }
 }
 
-fn install_agent_local(args: &InstallAgentArgs) -> Result<(), CliError> {
-    let agent_id = AgentId::parse(&args.agent)
-        .ok_or_else(|| CliError::Server(format!("unsupported agent: {}", args.agent)))?;
+fn run_replay_agent(args: &ReplayAgentArgs) -> Result<(), CliError> {
+    let entries = read_transcript(&args.transcript)?;
+    replay_transcript(&entries, std::io::stdin().lock(), std::io::stdout().lock())?;
+    Ok(())
+}
 
+fn install_agent_local(args: &InstallAgentArgs) -> Result<(), CliError> {
+    // Created first so custom agents are registered before parsing.
     let manager = AgentManager::new(default_install_dir())
         .map_err(|err| CliError::Server(err.to_string()))?;
 
+    let agent_id = manager
+        .parse_agent(&args.agent)
+        .ok_or_else(|| CliError::Server(format!("unsupported agent: {}", args.agent)))?;
+
     let result = manager
         .install(
-            agent_id,
+            &agent_id,
             InstallOptions {
                 reinstall: args.reinstall,
                 version: args.agent_version.clone(),
                 agent_process_version: args.agent_process_version.clone(),
+                offline_bundle: args.offline.clone(),
+                frozen: args.frozen,
             },
         )
         .map_err(|err| CliError::Server(err.to_string()))?;
 
-    let output = json!({
+    write_stdout_line(&serde_json::to_string_pretty(&install_result_json(result))?)
+}
+
+fn install_result_json(result: InstallResult) -> Value {
+    json!({
         "alreadyInstalled": result.already_installed,
         "artifacts": result.artifacts.into_iter().map(|artifact| json!({
             "kind": format!("{:?}", artifact.kind),
@@ -751,9 +852,108 @@ fn install_agent_local(args: &InstallAgentArgs) -> Result<(), CliError> {
             "source": format!("{:?}", artifact.source),
             "version": artifact.version,
         })).collect::<Vec<_>>()
-    });
+    })
+}
 
-    write_stdout_line(&serde_json::to_string_pretty(&output)?)
+fn run_local_agents(command: &LocalAgentsCommand, cli: &CliConfig) -> Result<(), CliError> {
+    let manager = AgentManager::new(default_install_dir())
+        .map_err(|err| CliError::Server(err.to_string()))?;
+    match command {
+        LocalAgentsCommand::Bundle(subcommand) => match &subcommand.command {
+            BundleCommand::Export(args) => {
+                let agents = args
+                    .agents
+                    .iter()
+                    .map(|agent| {
+                        manager
+                            .parse_agent(agent)
+                            .ok_or_else(|| CliError::Server(format!("unsupported agent: {agent}")))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let manifest = manager
+                    .export_bundle(&agents, &args.output)
+                    .map_err(|err| CliError::Server(err.to_string()))?;
+                write_stdout_line(&serde_json::to_string_pretty(&manifest)?)
+            }
+            BundleCommand::Import(args) => {
+                let results = manager
+                    .import_bundle(
+                        &args.bundle,
+                        &InstallOptions {
+                            reinstall: args.reinstall,
+                            ..InstallOptions::default()
+                        },
+                    )
+                    .map_err(|err| CliError::Server(err.to_string()))?;
+                let output: serde_json::Map<String, Value> = results
+                    .into_iter()
+                    .map(|(agent, result)| {
+                        (agent.as_str().to_string(), install_result_json(result))
+                    })
+                    .collect();
+                write_stdout_line(&serde_json::to_string_pretty(&output)?)
+            }
+        },
+        LocalAgentsCommand::Uninstall(args) => {
+            let agent_id = manager
+                .parse_agent(&args.agent)
+                .ok_or_else(|| CliError::Server(format!("unsupported agent: {}", args.agent)))?;
+            // A running server shuts down its instances of the agent (when
+            // forced) and removes the files itself.
+            let ctx = ClientContext::new(cli, &args.client)?;
+            match ctx.delete(&format!("/v1/agents/{agent_id}?force={}", args.force)) {
+                Ok(response) => return print_json_or_empty(response),
+                Err(CliError::Http(err)) if err.is_connect() => {}
+                Err(err) => return Err(err),
+            }
+            let removed = manager
+                .uninstall(&agent_id)
+                .map_err(|err| CliError::Server(err.to_string()))?;
+            write_stdout_line(&serde_json::to_string_pretty(
+                &json!({ "removed": removed }),
+            )?)
+        }
+        LocalAgentsCommand::Gc(args) => {
+            let live = if args.force {
+                Vec::new()
+            } else {
+                live_acp_servers(cli, &args.client)?
+                    .into_iter()
+                    .map(|(_, agent)| agent)
+                    .collect()
+            };
+            let in_use = live.iter().map(String::as_str).collect::<Vec<_>>();
+            let removed = manager
+                .gc(&in_use)
+                .map_err(|err| CliError::Server(err.to_string()))?;
+            write_stdout_line(&serde_json::to_string_pretty(
+                &json!({ "removed": removed }),
+            )?)
+        }
+    }
+}
+
+/// `(server id, agent)` of the ACP servers running on the server at the
+/// client endpoint. No server listening means none are running.
+fn live_acp_servers(cli: &CliConfig, args: &ClientArgs) -> Result<Vec<(String, String)>, CliError> {
+    let ctx = ClientContext::new(cli, args)?;
+    let response = match ctx.get("/v1/acp") {
+        Ok(response) => response,
+        Err(CliError::Http(err)) if err.is_connect() => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let status = response.status();
+    let text = response.text()?;
+    if !status.is_success() {
+        print_error_body(&text)?;
+        return Err(CliError::HttpStatus(status));
+    }
+    let list: AcpServerListResponse = serde_json::from_str(&text)?;
+    Ok(list
+        .servers
+        .into_iter()
+        .map(|server| (server.server_id, server.agent))
+        .collect())
 }
 
 #[derive(Serialize)]
@@ -1,8 +1,13 @@
 //! Sandbox agent core utilities.
 
+mod acp_fs;
+mod acp_labels;
+mod acp_permissions;
 mod acp_proxy_runtime;
+mod acp_terminal;
 pub mod cli;
 pub mod daemon;
+mod metrics;
 pub mod router;
 pub mod server_logs;
 pub mod telemetry;
@@ -0,0 +1,381 @@
+//! Prometheus metrics served from `GET /metrics`.
+//!
+//! Counters and histograms are aggregated in memory as requests happen;
+//! per-instance ACP gauges are read from the proxy when scraped.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::acp_proxy_runtime::AcpServerInstanceInfo;
+
+/// Bucket upper bounds in seconds. The long tail covers `session/prompt`
+/// turns, which routinely run for minutes.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+/// Client-to-agent ACP methods recorded under their own `method` label. Any
+/// other method, including `_`-prefixed extensions, is recorded as `other` so
+/// clients cannot grow the label set without bound.
+const ACP_METHODS: &[&str] = &[
+    "initialize",
+    "authenticate",
+    "session/new",
+    "session/load",
+    "session/list",
+    "session/resume",
+    "session/prompt",
+    "session/set_mode",
+    "session/set_model",
+    "session/set_config_option",
+];
+
+/// Reads one per-instance value; `None` skips the instance.
+type InstanceValue = fn(&AcpServerInstanceInfo) -> Option<f64>;
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    http_requests: Mutex<BTreeMap<[String; 3], u64>>,
+    http_latency: Mutex<BTreeMap<[String; 2], Histogram>>,
+    acp_latency: Mutex<BTreeMap<[String; 3], Histogram>>,
+    installs: Mutex<BTreeMap<[String; 2], u64>>,
+    unverified_downloads: Mutex<BTreeMap<String, u64>>,
+    fs_read_bytes: Mutex<BTreeMap<String, u64>>,
+    fs_written_bytes: Mutex<BTreeMap<String, u64>>,
+}
+
+/// Where filesystem traffic came from: the `/v1/fs` API or an agent's
+/// `fs/*` requests.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FsSource {
+    Http,
+    Acp,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    pub(crate) fn record_http(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        *lock(&self.http_requests)
+            .entry([method.to_string(), route.to_string(), status.to_string()])
+            .or_default() += 1;
+        lock(&self.http_latency)
+            .entry([method.to_string(), route.to_string()])
+            .or_default()
+            .observe(elapsed);
+    }
+
+    /// `outcome` is `ok`, `error` (a JSON-RPC or proxy error) or `timeout`.
+    pub(crate) fn record_acp_request(
+        &self,
+        agent: &str,
+        method: &str,
+        outcome: &str,
+        elapsed: Duration,
+    ) {
+        let method = if ACP_METHODS.contains(&method) {
+            method
+        } else {
+            "other"
+        };
+        lock(&self.acp_latency)
+            .entry([agent.to_string(), method.to_string(), outcome.to_string()])
+            .or_default()
+            .observe(elapsed);
+    }
+
+    pub(crate) fn record_install(&self, agent: &str, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        *lock(&self.installs)
+            .entry([agent.to_string(), result.to_string()])
+            .or_default() += 1;
+    }
+
+    /// Artifacts an install downloaded without a sha256 to check them
+    /// against.
+    pub(crate) fn record_unverified_downloads(&self, agent: &str, count: usize) {
+        if count == 0 {
+            return;
+        }
+        *lock(&self.unverified_downloads)
+            .entry(agent.to_string())
+            .or_default() += count as u64;
+    }
+
+    pub(crate) fn record_fs_read(&self, source: FsSource, bytes: u64) {
+        *lock(&self.fs_read_bytes)
+            .entry(source.as_str().to_string())
+            .or_default() += bytes;
+    }
+
+    pub(crate) fn record_fs_write(&self, source: FsSource, bytes: u64) {
+        *lock(&self.fs_written_bytes)
+            .entry(source.as_str().to_string())
+            .or_default() += bytes;
+    }
+
+    /// Render everything in the Prometheus text exposition format.
+    pub(crate) fn render(&self, instances: &[AcpServerInstanceInfo]) -> String {
+        let mut out = String::new();
+
+        family(
+            &mut out,
+            "sandbox_agent_http_requests_total",
+            "counter",
+            "HTTP requests by method, route and status.",
+        );
+        for ([method, route, status], count) in lock(&self.http_requests).iter() {
+            sample(
+                &mut out,
+                "sandbox_agent_http_requests_total",
+                &[("method", method), ("route", route), ("status", status)],
+                *count as f64,
+            );
+        }
+
+        family(
+            &mut out,
+            "sandbox_agent_http_request_duration_seconds",
+            "histogram",
+            "Time until the HTTP response head was sent, by method and route.",
+        );
+        for ([method, route], histogram) in lock(&self.http_latency).iter() {
+            histogram.render(
+                &mut out,
+                "sandbox_agent_http_request_duration_seconds",
+                &[("method", method), ("route", route)],
+            );
+        }
+
+        family(
+            &mut out,
+            "sandbox_agent_acp_request_duration_seconds",
+            "histogram",
+            "JSON-RPC request latency through the ACP proxy, by agent, method and outcome.",
+        );
+        for ([agent, method, outcome], histogram) in lock(&self.acp_latency).iter() {
+            histogram.render(
+                &mut out,
+                "sandbox_agent_acp_request_duration_seconds",
+                &[("agent", agent), ("method", method), ("outcome", outcome)],
+            );
+        }
+
+        let mut by_state = BTreeMap::<(&str, &str), u64>::new();
+        for instance in instances {
+            *by_state
+                .entry((instance.agent.as_str(), instance.state.as_str()))
+                .or_default() += 1;
+        }
+        family(
+            &mut out,
+            "sandbox_agent_acp_instances",
+            "gauge",
+            "ACP server instances by agent and process state.",
+        );
+        for ((agent, state), count) in by_state {
+            sample(
+                &mut out,
+                "sandbox_agent_acp_instances",
+                &[("agent", agent), ("state", state)],
+                count as f64,
+            );
+        }
+
+        let per_instance: [(&str, &str, &str, InstanceValue); 7] = [
+            (
+                "sandbox_agent_acp_pending_requests",
+                "gauge",
+                "Requests sent to the agent that have not been answered yet.",
+                |instance| Some(instance.pending_requests as f64),
+            ),
+            (
+                "sandbox_agent_acp_subscribers",
+                "gauge",
+                "Connected SSE and WebSocket stream subscribers.",
+                |instance| Some(instance.subscribers as f64),
+            ),
+            (
+                "sandbox_agent_acp_restarts_total",
+                "counter",
+                "Times the agent process was respawned.",
+                |instance| Some(instance.restart_count as f64),
+            ),
+            (
+                "sandbox_agent_acp_spawn_seconds",
+                "gauge",
+                "Time taken to spawn the agent process.",
+                |instance| Some(instance.spawn_duration.as_secs_f64()),
+            ),
+            (
+                "sandbox_agent_acp_first_stdout_seconds",
+                "gauge",
+                "Time from spawn to the agent's first stdout message.",
+                |instance| instance.first_stdout_latency.map(|d| d.as_secs_f64()),
+            ),
+            (
+                "sandbox_agent_acp_subscriber_lag_events_total",
+                "counter",
+                "Times any stream subscriber of the server fell behind live output.",
+                |instance| Some(instance.lag.lag_events as f64),
+            ),
+            (
+                "sandbox_agent_acp_lagged_messages_total",
+                "counter",
+                "Messages lagged subscribers missed live.",
+                |instance| Some(instance.lag.missed_messages as f64),
+            ),
+        ];
+        for (name, kind, help, value) in per_instance {
+            family(&mut out, name, kind, help);
+            for instance in instances {
+                if let Some(value) = value(instance) {
+                    sample(
+                        &mut out,
+                        name,
+                        &[
+                            ("server_id", &instance.server_id),
+                            ("agent", instance.agent.as_str()),
+                        ],
+                        value,
+                    );
+                }
+            }
+        }
+
+        family(
+            &mut out,
+            "sandbox_agent_agent_installs_total",
+            "counter",
+            "Agent installs by agent and result.",
+        );
+        for ([agent, result], count) in lock(&self.installs).iter() {
+            sample(
+                &mut out,
+                "sandbox_agent_agent_installs_total",
+                &[("agent", agent), ("result", result)],
+                *count as f64,
+            );
+        }
+
+        family(
+            &mut out,
+            "sandbox_agent_unverified_artifact_downloads_total",
+            "counter",
+            "Agent artifacts installed without a published or pinned sha256, by agent.",
+        );
+        for (agent, count) in lock(&self.unverified_downloads).iter() {
+            sample(
+                &mut out,
+                "sandbox_agent_unverified_artifact_downloads_total",
+                &[("agent", agent)],
+                *count as f64,
+            );
+        }
+
+        for (name, help, counters) in [
+            (
+                "sandbox_agent_fs_read_bytes_total",
+                "Bytes read from the filesystem, by source.",
+                &self.fs_read_bytes,
+            ),
+            (
+                "sandbox_agent_fs_written_bytes_total",
+                "Bytes written to the filesystem, by source.",
+                &self.fs_written_bytes,
+            ),
+        ] {
+            family(&mut out, name, "counter", help);
+            for (source, bytes) in lock(counters).iter() {
+                sample(&mut out, name, &[("source", source)], *bytes as f64);
+            }
+        }
+
+        out
+    }
+}
+
+impl FsSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Acp => "acp",
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &[(&str, &str)]) {
+        let bucket_name = format!("{name}_bucket");
+        for (count, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let le = bound.to_string();
+            let mut labels = labels.to_vec();
+            labels.push(("le", &le));
+            sample(out, &bucket_name, &labels, *count as f64);
+        }
+        let mut labels_inf = labels.to_vec();
+        labels_inf.push(("le", "+Inf"));
+        sample(out, &bucket_name, &labels_inf, self.count as f64);
+        sample(out, &format!("{name}_sum"), labels, self.sum);
+        sample(out, &format!("{name}_count"), labels, self.count as f64);
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        out.push('{');
+        for (index, (key, value)) in labels.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{key}=\"{}\"", escape_label(value));
+        }
+        out.push('}');
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
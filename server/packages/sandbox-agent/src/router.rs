@@ -6,19 +6,23 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use axum::body::Bytes;
-use axum::extract::{Path, Query, State};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{MatchedPath, Path, Query, State};
 use axum::http::{header, HeaderMap, Request, StatusCode};
 use axum::middleware::Next;
 use axum::response::sse::KeepAlive;
 use axum::response::{IntoResponse, Response, Sse};
-use axum::routing::{delete, get, post};
+use axum::routing::{delete, get, post, put};
 use axum::{Json, Router};
+use futures::{SinkExt, StreamExt};
 use sandbox_agent_agent_management::agents::{
-    AgentId, AgentManager, InstallOptions, InstallResult, InstallSource, InstalledArtifactKind,
+    AgentError, AgentId, AgentManager, InstallOptions, InstallResult, InstallSource,
+    InstalledArtifactKind,
 };
 use sandbox_agent_agent_management::credentials::{
     extract_all_credentials, CredentialExtractionOptions,
 };
+use sandbox_agent_agent_management::custom::{CustomAgentDefinition, CustomAgentInstall};
 use sandbox_agent_error::{ErrorType, ProblemDetails, SandboxError};
 use sandbox_agent_opencode_adapter::{build_opencode_router, OpenCodeAdapterConfig};
 use sandbox_agent_opencode_server_manager::{OpenCodeServerManager, OpenCodeServerManagerConfig};
@@ -31,11 +35,18 @@ use tower_http::trace::TraceLayer;
 use tracing::Span;
 use utoipa::{Modify, OpenApi, ToSchema};
 
-use crate::acp_proxy_runtime::{AcpProxyRuntime, ProxyPostOutcome};
+use crate::acp_labels::{validate_label_key, LabelSelector};
+use crate::acp_permissions::PermissionPolicy;
+use crate::acp_proxy_runtime::{
+    AcpLaunchOverrides, AcpProxyRuntime, AcpRequestOptions, AcpServerInstanceInfo,
+    PinBoxEventStream, ProxyPostOutcome,
+};
+use crate::metrics::{FsSource, Metrics};
 use crate::ui;
 
 mod support;
 mod types;
+pub(crate) use self::support::resolve_fs_path;
 use self::support::*;
 pub use self::types::*;
 
@@ -76,6 +87,7 @@ pub struct AppState {
     auth: AuthConfig,
     agent_manager: Arc<AgentManager>,
     acp_proxy: Arc<AcpProxyRuntime>,
+    metrics: Arc<Metrics>,
     opencode_server_manager: Arc<OpenCodeServerManager>,
     pub(crate) branding: BrandingMode,
     version_cache: Mutex<HashMap<AgentId, CachedAgentVersion>>,
@@ -92,7 +104,8 @@ impl AppState {
         branding: BrandingMode,
     ) -> Self {
         let agent_manager = Arc::new(agent_manager);
-        let acp_proxy = Arc::new(AcpProxyRuntime::new(agent_manager.clone()));
+        let metrics = Arc::new(Metrics::default());
+        let acp_proxy = Arc::new(AcpProxyRuntime::new(agent_manager.clone(), metrics.clone()));
         let opencode_server_manager = Arc::new(OpenCodeServerManager::new(
             agent_manager.clone(),
             OpenCodeServerManagerConfig {
@@ -104,6 +117,7 @@ impl AppState {
             auth,
             agent_manager,
             acp_proxy,
+            metrics,
             opencode_server_manager,
             branding,
             version_cache: Mutex::new(HashMap::new()),
@@ -114,6 +128,10 @@ impl AppState {
         self.acp_proxy.clone()
     }
 
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
     pub(crate) fn agent_manager(&self) -> Arc<AgentManager> {
         self.agent_manager.clone()
     }
@@ -122,8 +140,8 @@ impl AppState {
         self.opencode_server_manager.clone()
     }
 
-    pub(crate) fn purge_version_cache(&self, agent: AgentId) {
-        self.version_cache.lock().unwrap().remove(&agent);
+    pub(crate) fn purge_version_cache(&self, agent: &AgentId) {
+        self.version_cache.lock().unwrap().remove(agent);
     }
 }
 
@@ -157,8 +175,12 @@ pub fn build_router_with_state(shared: Arc<AppState>) -> (Router, Arc<AppState>)
     let mut v1_router = Router::new()
         .route("/health", get(get_v1_health))
         .route("/agents", get(get_v1_agents))
-        .route("/agents/:agent", get(get_v1_agent))
+        .route("/agents/:agent", get(get_v1_agent).delete(delete_v1_agent))
         .route("/agents/:agent/install", post(post_v1_agent_install))
+        .route(
+            "/agents/:agent/definition",
+            put(put_v1_agent_definition).delete(delete_v1_agent_definition),
+        )
         .route("/fs/entries", get(get_v1_fs_entries))
         .route("/fs/file", get(get_v1_fs_file).put(put_v1_fs_file))
         .route("/fs/entry", delete(delete_v1_fs_entry))
@@ -181,8 +203,13 @@ pub fn build_router_with_state(shared: Arc<AppState>) -> (Router, Arc<AppState>)
         .route("/acp", get(get_v1_acp_servers))
         .route(
             "/acp/:server_id",
-            post(post_v1_acp).get(get_v1_acp).delete(delete_v1_acp),
+            post(post_v1_acp)
+                .get(get_v1_acp)
+                .patch(patch_v1_acp)
+                .delete(delete_v1_acp),
         )
+        .route("/acp/:server_id/ws", get(get_v1_acp_ws))
+        .route("/acp/:server_id/logs", get(get_v1_acp_logs))
         .with_state(shared.clone());
 
     if shared.auth.token.is_some() {
@@ -192,6 +219,16 @@ pub fn build_router_with_state(shared: Arc<AppState>) -> (Router, Arc<AppState>)
         ));
     }
 
+    let mut metrics_router = Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(shared.clone());
+    if shared.auth.token.is_some() {
+        metrics_router = metrics_router.layer(axum::middleware::from_fn_with_state(
+            shared.clone(),
+            require_token,
+        ));
+    }
+
     let opencode_router = build_opencode_router(OpenCodeAdapterConfig {
         auth_token: shared.auth.token.clone(),
         sqlite_path: std::env::var("OPENCODE_COMPAT_DB_PATH").ok(),
@@ -212,7 +249,11 @@ pub fn build_router_with_state(shared: Arc<AppState>) -> (Router, Arc<AppState>)
         .nest("/opencode", opencode_router)
         .fallback(not_found);
 
-    router = router.merge(ui::router());
+    router = router.merge(ui::router()).merge(metrics_router);
+    router = router.layer(axum::middleware::from_fn_with_state(
+        shared.clone(),
+        record_http_metrics,
+    ));
 
     let http_logging = match std::env::var("SANDBOX_AGENT_LOG_HTTP") {
         Ok(value) if value == "0" || value.eq_ignore_ascii_case("false") => false,
@@ -286,7 +327,10 @@ pub async fn shutdown_servers(state: &Arc<AppState>) {
         get_v1_health,
         get_v1_agents,
         get_v1_agent,
+        delete_v1_agent,
         post_v1_agent_install,
+        put_v1_agent_definition,
+        delete_v1_agent_definition,
         get_v1_fs_entries,
         get_v1_fs_file,
         put_v1_fs_file,
@@ -304,6 +348,9 @@ pub async fn shutdown_servers(state: &Arc<AppState>) {
         get_v1_acp_servers,
         post_v1_acp,
         get_v1_acp,
+        get_v1_acp_ws,
+        get_v1_acp_logs,
+        patch_v1_acp,
         delete_v1_acp
     ),
     components(
@@ -317,6 +364,10 @@ pub async fn shutdown_servers(state: &Arc<AppState>) {
             AgentInstallRequest,
             AgentInstallArtifact,
             AgentInstallResponse,
+            AgentUninstallQuery,
+            AgentUninstallResponse,
+            CustomAgentRequest,
+            CustomAgentInstallSpec,
             FsPathQuery,
             FsEntriesQuery,
             FsDeleteQuery,
@@ -329,9 +380,13 @@ pub async fn shutdown_servers(state: &Arc<AppState>) {
             FsMoveResponse,
             FsActionResponse,
             FsUploadBatchResponse,
-            AcpPostQuery,
             AcpServerInfo,
+            AcpServerListQuery,
             AcpServerListResponse,
+            AcpServerPatchRequest,
+            AcpLogsQuery,
+            AcpLogLine,
+            AcpLogsResponse,
             McpConfigQuery,
             SkillsConfigQuery,
             McpServerConfig,
@@ -339,7 +394,8 @@ pub async fn shutdown_servers(state: &Arc<AppState>) {
             SkillSource,
             ProblemDetails,
             ErrorType,
-            AcpEnvelope
+            AcpEnvelope,
+            AcpPayload
         )
     ),
     tags(
@@ -386,6 +442,18 @@ async fn get_root() -> Json<Value> {
     }))
 }
 
+async fn get_metrics(State(state): State<Arc<AppState>>) -> Response {
+    let instances = state
+        .acp_proxy()
+        .list_instances(&LabelSelector::default())
+        .await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics().render(&instances),
+    )
+        .into_response()
+}
+
 #[utoipa::path(
     get,
     path = "/v1/health",
@@ -428,7 +496,10 @@ async fn get_v1_agents(
     let has_anthropic = credentials.anthropic.is_some();
     let has_openai = credentials.openai.is_some();
 
-    let instances = state.acp_proxy().list_instances().await;
+    let instances = state
+        .acp_proxy()
+        .list_instances(&LabelSelector::default())
+        .await;
     let mut active_by_agent = HashMap::<AgentId, Vec<i64>>::new();
     for instance in instances {
         active_by_agent
@@ -441,10 +512,11 @@ async fn get_v1_agents(
     let no_cache = query.no_cache.unwrap_or(false);
 
     let mut agents = Vec::new();
-    for agent_id in AgentId::all().iter().copied() {
-        let capabilities = agent_capabilities_for(agent_id);
-        let installed = state.agent_manager().is_installed(agent_id);
-        let credentials_available = credentials_available_for(agent_id, has_anthropic, has_openai);
+    let manager = state.agent_manager();
+    for agent_id in manager.agents() {
+        let capabilities = agent_capabilities_for(&manager, &agent_id);
+        let installed = manager.is_installed(&agent_id);
+        let credentials_available = credentials_available_for(&agent_id, has_anthropic, has_openai);
 
         let server_status = active_by_agent.get(&agent_id).map(|created_times| {
             let uptime_ms = created_times
@@ -463,6 +535,7 @@ async fn get_v1_agents(
 
         agents.push(AgentInfo {
             id: agent_id.as_str().to_string(),
+            display_name: agent_display_name(&manager, &agent_id),
             installed,
             credentials_available,
             version: None,
@@ -481,7 +554,7 @@ async fn get_v1_agents(
             .iter()
             .enumerate()
             .filter_map(|(idx, agent)| {
-                let agent_id = AgentId::parse(&agent.id)?;
+                let agent_id = manager.parse_agent(&agent.id)?;
                 if !no_cache {
                     if state.version_cache.lock().unwrap().contains_key(&agent_id) {
                         return None;
@@ -493,16 +566,16 @@ async fn get_v1_agents(
 
         if !need_lookup.is_empty() {
             let mgr = state.agent_manager();
-            let ids: Vec<AgentId> = need_lookup.iter().map(|(_, id)| *id).collect();
+            let ids: Vec<AgentId> = need_lookup.into_iter().map(|(_, id)| id).collect();
             let results = tokio::task::spawn_blocking(move || {
                 ids.iter()
                     .map(|agent_id| {
-                        let version = mgr.version(*agent_id).ok().flatten();
+                        let version = mgr.version(agent_id).ok().flatten();
                         let path = mgr
-                            .resolve_binary(*agent_id)
+                            .resolve_binary(agent_id)
                             .ok()
                             .map(|p| p.to_string_lossy().to_string());
-                        (*agent_id, CachedAgentVersion { version, path })
+                        (agent_id.clone(), CachedAgentVersion { version, path })
                     })
                     .collect::<Vec<_>>()
             })
@@ -518,14 +591,14 @@ async fn get_v1_agents(
         // Apply cached version/path + hardcoded config options
         let cache = state.version_cache.lock().unwrap();
         for agent in &mut agents {
-            let Some(agent_id) = AgentId::parse(&agent.id) else {
+            let Some(agent_id) = manager.parse_agent(&agent.id) else {
                 continue;
             };
             if let Some(cached) = cache.get(&agent_id) {
                 agent.version = cached.version.clone();
                 agent.path = cached.path.clone();
             }
-            let fallback = fallback_config_options(agent_id);
+            let fallback = fallback_config_options(&agent_id);
             if !fallback.is_empty() {
                 agent.config_options = Some(fallback);
             }
@@ -555,8 +628,10 @@ async fn get_v1_agent(
     Path(agent): Path<String>,
     Query(query): Query<AgentsQuery>,
 ) -> Result<Json<AgentInfo>, ApiError> {
-    let agent_id = AgentId::parse(&agent).ok_or_else(|| SandboxError::UnsupportedAgent {
-        agent: agent.clone(),
+    let agent_id = state.agent_manager().parse_agent(&agent).ok_or_else(|| {
+        SandboxError::UnsupportedAgent {
+            agent: agent.clone(),
+        }
     })?;
 
     let credentials = tokio::task::spawn_blocking(move || {
@@ -570,16 +645,20 @@ async fn get_v1_agent(
     let has_anthropic = credentials.anthropic.is_some();
     let has_openai = credentials.openai.is_some();
 
-    let instances = state.acp_proxy().list_instances().await;
+    let instances = state
+        .acp_proxy()
+        .list_instances(&LabelSelector::default())
+        .await;
     let created_times: Vec<i64> = instances
         .iter()
         .filter(|i| i.agent == agent_id)
         .map(|i| i.created_at_ms)
         .collect();
 
-    let capabilities = agent_capabilities_for(agent_id);
-    let installed = state.agent_manager().is_installed(agent_id);
-    let credentials_available = credentials_available_for(agent_id, has_anthropic, has_openai);
+    let manager = state.agent_manager();
+    let capabilities = agent_capabilities_for(&manager, &agent_id);
+    let installed = manager.is_installed(&agent_id);
+    let credentials_available = credentials_available_for(&agent_id, has_anthropic, has_openai);
 
     let server_status = if created_times.is_empty() {
         None
@@ -596,6 +675,7 @@ async fn get_v1_agent(
 
     let mut info = AgentInfo {
         id: agent_id.as_str().to_string(),
+        display_name: agent_display_name(&manager, &agent_id),
         installed,
         credentials_available,
         version: None,
@@ -620,11 +700,11 @@ async fn get_v1_agent(
             info.path = cached.path;
         } else {
             let mgr = state.agent_manager();
-            let aid = agent_id;
+            let aid = agent_id.clone();
             let result = tokio::task::spawn_blocking(move || {
-                let version = mgr.version(aid).ok().flatten();
+                let version = mgr.version(&aid).ok().flatten();
                 let path = mgr
-                    .resolve_binary(aid)
+                    .resolve_binary(&aid)
                     .ok()
                     .map(|p| p.to_string_lossy().to_string());
                 CachedAgentVersion { version, path }
@@ -636,11 +716,15 @@ async fn get_v1_agent(
             });
             info.version = result.version.clone();
             info.path = result.path.clone();
-            state.version_cache.lock().unwrap().insert(agent_id, result);
+            state
+                .version_cache
+                .lock()
+                .unwrap()
+                .insert(agent_id.clone(), result);
         }
 
         // Hardcoded config options
-        let fallback = fallback_config_options(agent_id);
+        let fallback = fallback_config_options(&agent_id);
         if !fallback.is_empty() {
             info.config_options = Some(fallback);
         }
@@ -715,6 +799,43 @@ async fn get_v1_agent(
 //     }
 // }
 
+#[utoipa::path(
+    delete,
+    path = "/v1/agents/{agent}",
+    tag = "v1",
+    params(
+        ("agent" = String, Path, description = "Agent id"),
+        ("force" = Option<bool>, Query, description = "Shut down ACP servers running the agent and remove it")
+    ),
+    responses(
+        (status = 200, description = "Removed agent files", body = AgentUninstallResponse),
+        (status = 400, description = "Unknown agent", body = ProblemDetails),
+        (status = 409, description = "ACP servers are running the agent", body = ProblemDetails)
+    )
+)]
+async fn delete_v1_agent(
+    State(state): State<Arc<AppState>>,
+    Path(agent): Path<String>,
+    Query(query): Query<AgentUninstallQuery>,
+) -> Result<Json<AgentUninstallResponse>, ApiError> {
+    let agent_id = state.agent_manager().parse_agent(&agent).ok_or_else(|| {
+        SandboxError::UnsupportedAgent {
+            agent: agent.clone(),
+        }
+    })?;
+    let removed = state
+        .acp_proxy()
+        .uninstall_agent(&agent_id, query.force.unwrap_or(false))
+        .await?;
+    state.purge_version_cache(&agent_id);
+    Ok(Json(AgentUninstallResponse {
+        removed: removed
+            .into_iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect(),
+    }))
+}
+
 #[utoipa::path(
     post,
     path = "/v1/agents/{agent}/install",
@@ -734,19 +855,25 @@ async fn post_v1_agent_install(
     Path(agent): Path<String>,
     Json(request): Json<AgentInstallRequest>,
 ) -> Result<Json<AgentInstallResponse>, ApiError> {
-    let agent_id = AgentId::parse(&agent).ok_or_else(|| SandboxError::UnsupportedAgent {
-        agent: agent.clone(),
+    let agent_id = state.agent_manager().parse_agent(&agent).ok_or_else(|| {
+        SandboxError::UnsupportedAgent {
+            agent: agent.clone(),
+        }
     })?;
 
     let manager = state.agent_manager();
     let reinstall = request.reinstall.unwrap_or(false);
+    let frozen = request.frozen.unwrap_or(false);
+    let install_agent = agent_id.clone();
     let install_result = tokio::task::spawn_blocking(move || {
         manager.install(
-            agent_id,
+            &install_agent,
             InstallOptions {
                 reinstall,
                 version: request.agent_version,
                 agent_process_version: request.agent_process_version,
+                offline_bundle: None,
+                frozen,
             },
         )
     })
@@ -754,18 +881,90 @@ async fn post_v1_agent_install(
     .map_err(|err| SandboxError::InstallFailed {
         agent,
         stderr: Some(format!("installer task failed: {err}")),
-    })?
-    .map_err(|err| SandboxError::InstallFailed {
-        agent: agent_id.as_str().to_string(),
-        stderr: Some(err.to_string()),
-    })?;
+    })
+    .and_then(|result| {
+        result.map_err(|err| SandboxError::InstallFailed {
+            agent: agent_id.as_str().to_string(),
+            stderr: Some(err.to_string()),
+        })
+    });
+    state
+        .metrics()
+        .record_install(agent_id.as_str(), install_result.is_ok());
+    let install_result = install_result?;
+    state
+        .metrics()
+        .record_unverified_downloads(agent_id.as_str(), install_result.unverified_downloads.len());
 
     // Purge version cache so next ?config=true picks up the new version
-    state.purge_version_cache(agent_id);
+    state.purge_version_cache(&agent_id);
 
     Ok(Json(map_install_result(install_result)))
 }
 
+#[utoipa::path(
+    put,
+    path = "/v1/agents/{agent}/definition",
+    tag = "v1",
+    params(
+        ("agent" = String, Path, description = "Custom agent id")
+    ),
+    request_body = CustomAgentRequest,
+    responses(
+        (status = 204, description = "Custom agent registered"),
+        (status = 400, description = "Invalid definition or built-in agent id", body = ProblemDetails)
+    )
+)]
+async fn put_v1_agent_definition(
+    State(state): State<Arc<AppState>>,
+    Path(agent): Path<String>,
+    Json(request): Json<CustomAgentRequest>,
+) -> Result<StatusCode, ApiError> {
+    let definition = map_custom_agent_request(agent, request);
+    let manager = state.agent_manager();
+    let agent_id = tokio::task::spawn_blocking(move || manager.register_custom_agent(definition))
+        .await
+        .map_err(|err| SandboxError::StreamError {
+            message: format!("failed to register custom agent: {err}"),
+        })?
+        .map_err(map_custom_agent_error)?;
+
+    state.purge_version_cache(&agent_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/agents/{agent}/definition",
+    tag = "v1",
+    params(
+        ("agent" = String, Path, description = "Custom agent id")
+    ),
+    responses(
+        (status = 204, description = "Custom agent unregistered"),
+        (status = 400, description = "Unknown or built-in agent", body = ProblemDetails)
+    )
+)]
+async fn delete_v1_agent_definition(
+    State(state): State<Arc<AppState>>,
+    Path(agent): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let Some(agent_id @ AgentId::Custom(_)) = state.agent_manager().parse_agent(&agent) else {
+        return Err(SandboxError::UnsupportedAgent { agent }.into());
+    };
+
+    let manager = state.agent_manager();
+    tokio::task::spawn_blocking(move || manager.unregister_custom_agent(&agent))
+        .await
+        .map_err(|err| SandboxError::StreamError {
+            message: format!("failed to unregister custom agent: {err}"),
+        })?
+        .map_err(map_custom_agent_error)?;
+
+    state.purge_version_cache(&agent_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[utoipa::path(
     get,
     path = "/v1/fs/entries",
@@ -830,7 +1029,10 @@ async fn get_v1_fs_entries(
         (status = 200, description = "File content")
     )
 )]
-async fn get_v1_fs_file(Query(query): Query<FsPathQuery>) -> Result<Response, ApiError> {
+async fn get_v1_fs_file(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FsPathQuery>,
+) -> Result<Response, ApiError> {
     let target = resolve_fs_path(&query.path)?;
     let metadata = fs::metadata(&target).map_err(|err| map_fs_error(&target, err))?;
     if !metadata.is_file() {
@@ -840,6 +1042,9 @@ async fn get_v1_fs_file(Query(query): Query<FsPathQuery>) -> Result<Response, Ap
         .into());
     }
     let bytes = fs::read(&target).map_err(|err| map_fs_error(&target, err))?;
+    state
+        .metrics()
+        .record_fs_read(FsSource::Http, bytes.len() as u64);
     Ok((
         [(header::CONTENT_TYPE, "application/octet-stream")],
         Bytes::from(bytes),
@@ -860,6 +1065,7 @@ async fn get_v1_fs_file(Query(query): Query<FsPathQuery>) -> Result<Response, Ap
     )
 )]
 async fn put_v1_fs_file(
+    State(state): State<Arc<AppState>>,
     Query(query): Query<FsPathQuery>,
     body: Bytes,
 ) -> Result<Json<FsWriteResponse>, ApiError> {
@@ -868,6 +1074,9 @@ async fn put_v1_fs_file(
         fs::create_dir_all(parent).map_err(|err| map_fs_error(parent, err))?;
     }
     fs::write(&target, &body).map_err(|err| map_fs_error(&target, err))?;
+    state
+        .metrics()
+        .record_fs_write(FsSource::Http, body.len() as u64);
     Ok(Json(FsWriteResponse {
         path: target.to_string_lossy().to_string(),
         bytes_written: body.len() as u64,
@@ -1011,6 +1220,7 @@ async fn get_v1_fs_stat(Query(query): Query<FsPathQuery>) -> Result<Json<FsStat>
     )
 )]
 async fn post_v1_fs_upload_batch(
+    State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     Query(query): Query<FsUploadBatchQuery>,
     body: Bytes,
@@ -1062,6 +1272,9 @@ async fn post_v1_fs_upload_batch(
             .map_err(|err| SandboxError::StreamError {
                 message: err.to_string(),
             })?;
+        state
+            .metrics()
+            .record_fs_write(FsSource::Http, entry.size());
         if extracted.len() < 1024 {
             extracted.push(dest.to_string_lossy().to_string());
         } else {
@@ -1243,52 +1456,91 @@ async fn delete_v1_config_skills(
     get,
     path = "/v1/acp",
     tag = "v1",
+    params(
+        ("labelSelector" = Option<String>, Query, description = "Comma-separated label requirements: key=value, key!=value, key or !key")
+    ),
     responses(
-        (status = 200, description = "Active ACP server instances", body = AcpServerListResponse)
+        (status = 200, description = "Active ACP server instances", body = AcpServerListResponse),
+        (status = 400, description = "Invalid label selector", body = ProblemDetails)
     )
 )]
 async fn get_v1_acp_servers(
     State(state): State<Arc<AppState>>,
+    Query(query): Query<AcpServerListQuery>,
 ) -> Result<Json<AcpServerListResponse>, ApiError> {
+    let selector = match query.label_selector.as_deref() {
+        Some(raw) => LabelSelector::parse(raw)?,
+        None => LabelSelector::default(),
+    };
     let servers = state
         .acp_proxy()
-        .list_instances()
+        .list_instances(&selector)
         .await
         .into_iter()
-        .map(|instance| AcpServerInfo {
-            server_id: instance.server_id,
-            agent: instance.agent.as_str().to_string(),
-            created_at_ms: instance.created_at_ms,
-        })
+        .map(acp_server_info)
         .collect::<Vec<_>>();
 
     Ok(Json(AcpServerListResponse { servers }))
 }
 
+fn acp_server_info(instance: AcpServerInstanceInfo) -> AcpServerInfo {
+    AcpServerInfo {
+        server_id: instance.server_id,
+        agent: instance.agent.as_str().to_string(),
+        cwd: instance
+            .launch
+            .cwd
+            .map(|cwd| cwd.to_string_lossy().into_owned()),
+        env: instance.launch.env,
+        args: instance.launch.args,
+        labels: instance.labels,
+        created_at_ms: instance.created_at_ms,
+        uptime_ms: (now_ms() - instance.created_at_ms).max(0),
+        restart_count: instance.restart_count,
+        pid: instance.pid,
+        state: instance.state.as_str().to_string(),
+        pending_requests: instance.pending_requests as u64,
+        last_activity_ms: instance.last_activity_ms,
+    }
+}
+
 #[utoipa::path(
     post,
     path = "/v1/acp/{server_id}",
     tag = "v1",
     params(
         ("server_id" = String, Path, description = "Client-defined ACP server id"),
-        ("agent" = Option<String>, Query, description = "Agent id required for first POST")
+        ("agent" = Option<String>, Query, description = "Agent id required for first POST"),
+        ("cwd" = Option<String>, Query, description = "Working directory for the agent process; first POST only"),
+        ("env" = Option<Vec<String>>, Query, description = "Extra agent environment as KEY=VALUE, repeatable; first POST only"),
+        ("arg" = Option<Vec<String>>, Query, description = "Extra agent argument, repeatable; first POST only"),
+        ("permissionPolicy" = Option<String>, Query, description = "JSON permission policy for this server; first POST only"),
+        ("label" = Option<Vec<String>>, Query, description = "Label as KEY=VALUE, repeatable; first POST only"),
+        ("timeoutMs" = Option<u64>, Query, description = "Milliseconds to wait for the agent's response; overrides the server defaults"),
+        ("deadline" = Option<u64>, Query, description = "Unix time in milliseconds after which the response is no longer awaited"),
+        ("cancelOnTimeout" = Option<bool>, Query, description = "Send the agent a cancellation when the request times out"),
+        ("X-Request-Timeout-Ms" = Option<u64>, Header, description = "Same as timeoutMs"),
+        ("X-Request-Deadline" = Option<u64>, Header, description = "Same as deadline"),
+        ("clientId" = Option<String>, Query, description = "Names the caller when it answers an agent request"),
+        ("X-Client-Id" = Option<String>, Header, description = "Same as clientId")
     ),
-    request_body = AcpEnvelope,
+    request_body = AcpPayload,
     responses(
-        (status = 200, description = "JSON-RPC response envelope", body = AcpEnvelope),
+        (status = 200, description = "JSON-RPC response envelope, or an array of responses for a batch", body = AcpPayload),
         (status = 202, description = "JSON-RPC notification accepted"),
         (status = 406, description = "Client does not accept JSON responses", body = ProblemDetails),
         (status = 415, description = "Unsupported media type", body = ProblemDetails),
-        (status = 400, description = "Invalid ACP envelope", body = ProblemDetails),
+        (status = 400, description = "Invalid ACP envelope or launch override", body = ProblemDetails),
+        (status = 403, description = "Launch override not allowed", body = ProblemDetails),
         (status = 404, description = "Unknown ACP server", body = ProblemDetails),
-        (status = 409, description = "ACP server bound to different agent", body = ProblemDetails),
+        (status = 409, description = "ACP server bound to different agent or launch settings, or agent request already answered", body = ProblemDetails),
         (status = 504, description = "ACP agent process response timeout", body = ProblemDetails)
     )
 )]
 async fn post_v1_acp(
     State(state): State<Arc<AppState>>,
     Path(server_id): Path<String>,
-    Query(query): Query<AcpPostQuery>,
+    Query(query): Query<Vec<(String, String)>>,
     headers: HeaderMap,
     body: Bytes,
 ) -> Result<Response, ApiError> {
@@ -1310,20 +1562,19 @@ async fn post_v1_acp(
             message: format!("invalid JSON body: {err}"),
         })?;
 
-    let bootstrap_agent = match query.agent {
-        Some(agent) => {
-            Some(
-                AgentId::parse(&agent).ok_or_else(|| SandboxError::UnsupportedAgent {
-                    agent: agent.clone(),
-                })?,
-            )
-        }
-        None => None,
-    };
+    let request = parse_acp_request_options(&headers, &query)?;
+    let bootstrap = parse_acp_post_query(&state.agent_manager(), query)?;
 
     match state
         .acp_proxy()
-        .post(&server_id, bootstrap_agent, payload)
+        .post(
+            &server_id,
+            bootstrap.agent,
+            &bootstrap.launch,
+            &bootstrap.labels,
+            request,
+            payload,
+        )
         .await?
     {
         ProxyPostOutcome::Response(value) => Ok((StatusCode::OK, Json(value)).into_response()),
@@ -1367,6 +1618,334 @@ async fn get_v1_acp(
     ))
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/acp/{server_id}/ws",
+    tag = "v1",
+    params(
+        ("server_id" = String, Path, description = "Client-defined ACP server id"),
+        ("agent" = Option<String>, Query, description = "Agent id; creates the server if it does not exist"),
+        ("cwd" = Option<String>, Query, description = "Working directory for the agent process; first connection only"),
+        ("env" = Option<Vec<String>>, Query, description = "Extra agent environment as KEY=VALUE, repeatable; first connection only"),
+        ("arg" = Option<Vec<String>>, Query, description = "Extra agent argument, repeatable; first connection only"),
+        ("permissionPolicy" = Option<String>, Query, description = "JSON permission policy for this server; first connection only"),
+        ("label" = Option<Vec<String>>, Query, description = "Label as KEY=VALUE, repeatable; first connection only"),
+        ("lastEventId" = Option<u64>, Query, description = "Replay stream messages after this event id"),
+        ("clientId" = Option<String>, Query, description = "Names this connection when it answers agent requests")
+    ),
+    responses(
+        (status = 101, description = "WebSocket carrying JSON-RPC envelopes in both directions"),
+        (status = 400, description = "Invalid request or launch override", body = ProblemDetails),
+        (status = 403, description = "Launch override not allowed", body = ProblemDetails),
+        (status = 404, description = "Unknown ACP server", body = ProblemDetails),
+        (status = 409, description = "ACP server bound to different agent or launch settings", body = ProblemDetails)
+    )
+)]
+async fn get_v1_acp_ws(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    Query(query): Query<Vec<(String, String)>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let last_event_id = match parse_last_event_id_query(&query)? {
+        Some(id) => Some(id),
+        None => parse_last_event_id(&headers)?,
+    };
+    // Validated now; timeouts are applied per frame, as for each POST.
+    parse_acp_request_options(&headers, &query)?;
+    let request_query = query.clone();
+    let bootstrap = parse_acp_post_query(&state.agent_manager(), query)?;
+
+    let proxy = state.acp_proxy();
+    let events = proxy
+        .connect(
+            &server_id,
+            bootstrap.agent,
+            &bootstrap.launch,
+            &bootstrap.labels,
+            last_event_id,
+        )
+        .await?;
+
+    Ok(ws.on_upgrade(move |socket| {
+        run_acp_socket(proxy, server_id, headers, request_query, socket, events)
+    }))
+}
+
+/// What became of one inbound WebSocket frame.
+enum SocketReply {
+    /// The agent's response to a request. It replaces the copy published on
+    /// the stream, taking that copy's place and `eventId`.
+    Agent { key: String, response: Value },
+    /// Responses to a batch. Copies of its elements on the stream are dropped.
+    Batch { keys: Vec<String>, responses: Value },
+    /// A reply that never went through the stream, if any.
+    Direct {
+        keys: Vec<String>,
+        reply: Option<Value>,
+    },
+}
+
+/// Where a request sent on this socket stands against its response's copy
+/// on the stream.
+enum OwnResponse {
+    Pending,
+    Streamed(Option<u64>),
+    Replied(Option<Value>),
+}
+
+/// Pump one ACP WebSocket. Stream messages go out as they are published,
+/// tagged with their `eventId` for resume. Each inbound frame is handled
+/// like a POST body with the connection's query and headers, concurrently
+/// so an agent request can be answered while a prompt is still running.
+/// Replies are those a POST would return; an agent response is sent in
+/// place of its copy on the stream, so it arrives once and in order after
+/// the notifications preceding it. Closing the socket abandons requests
+/// still in flight.
+async fn run_acp_socket(
+    proxy: Arc<AcpProxyRuntime>,
+    server_id: String,
+    headers: HeaderMap,
+    query: Vec<(String, String)>,
+    socket: WebSocket,
+    mut events: PinBoxEventStream,
+) {
+    let (mut outgoing, mut incoming) = socket.split();
+    let mut in_flight = tokio::task::JoinSet::<SocketReply>::new();
+    let mut own = HashMap::<String, OwnResponse>::new();
+    let mut heartbeat = tokio::time::interval(Duration::from_secs(15));
+    heartbeat.tick().await;
+
+    loop {
+        let frame = tokio::select! {
+            event = events.next() => match event {
+                Some((sequence, mut payload)) => {
+                    if let Some(key) = response_key(&payload) {
+                        match own.remove(&key) {
+                            Some(OwnResponse::Pending) => {
+                                own.insert(key, OwnResponse::Streamed(sequence));
+                                continue;
+                            }
+                            Some(OwnResponse::Replied(Some(response))) => payload = response,
+                            Some(OwnResponse::Replied(None)) => continue,
+                            Some(streamed @ OwnResponse::Streamed(_)) => {
+                                own.insert(key, streamed);
+                            }
+                            None => {}
+                        }
+                    }
+                    if let (Some(sequence), Some(object)) = (sequence, payload.as_object_mut()) {
+                        object.insert("eventId".to_string(), json!(sequence));
+                    }
+                    Message::Text(payload.to_string())
+                }
+                None => break,
+            },
+            Some(joined) = in_flight.join_next(), if !in_flight.is_empty() => {
+                let reply = match joined {
+                    Ok(SocketReply::Agent { key, mut response }) => match own.remove(&key) {
+                        Some(OwnResponse::Pending) => {
+                            own.insert(key, OwnResponse::Replied(Some(response)));
+                            continue;
+                        }
+                        Some(OwnResponse::Streamed(Some(sequence))) => {
+                            if let Some(object) = response.as_object_mut() {
+                                object.insert("eventId".to_string(), json!(sequence));
+                            }
+                            response
+                        }
+                        _ => response,
+                    },
+                    Ok(SocketReply::Batch { keys, responses }) => {
+                        for key in keys {
+                            if let Some(OwnResponse::Pending) = own.remove(&key) {
+                                own.insert(key, OwnResponse::Replied(None));
+                            }
+                        }
+                        responses
+                    }
+                    Ok(SocketReply::Direct { keys, reply }) => {
+                        for key in keys {
+                            own.remove(&key);
+                        }
+                        match reply {
+                            Some(reply) => reply,
+                            None => continue,
+                        }
+                    }
+                    Err(_) => continue,
+                };
+                Message::Text(reply.to_string())
+            }
+            message = incoming.next() => {
+                let payload = match message {
+                    Some(Ok(Message::Text(text))) => serde_json::from_str::<Value>(&text),
+                    Some(Ok(Message::Binary(bytes))) => serde_json::from_slice::<Value>(&bytes),
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                };
+                match payload {
+                    Ok(payload) => {
+                        let keys = request_keys(&payload);
+                        for key in &keys {
+                            own.insert(key.clone(), OwnResponse::Pending);
+                        }
+                        let proxy = proxy.clone();
+                        let server_id = server_id.clone();
+                        let request = parse_acp_request_options(&headers, &query)
+                            .unwrap_or_default();
+                        in_flight.spawn(async move {
+                            let id = payload.get("id").cloned().unwrap_or(Value::Null);
+                            let batch = payload.is_array();
+                            let result = proxy
+                                .post(
+                                    &server_id,
+                                    None,
+                                    &AcpLaunchOverrides::default(),
+                                    &BTreeMap::new(),
+                                    request,
+                                    payload,
+                                )
+                                .await;
+                            match result {
+                                Ok(ProxyPostOutcome::Response(responses)) if batch => {
+                                    SocketReply::Batch { keys, responses }
+                                }
+                                Ok(ProxyPostOutcome::Response(response)) if !keys.is_empty() => {
+                                    let key = keys.into_iter().next().expect("checked");
+                                    SocketReply::Agent { key, response }
+                                }
+                                Ok(ProxyPostOutcome::Response(reply)) => SocketReply::Direct {
+                                    keys,
+                                    reply: Some(reply),
+                                },
+                                Ok(ProxyPostOutcome::Accepted) => {
+                                    SocketReply::Direct { keys, reply: None }
+                                }
+                                Err(err) => SocketReply::Direct {
+                                    keys,
+                                    reply: Some(jsonrpc_error_reply(id, -32603, &err)),
+                                },
+                            }
+                        });
+                        continue;
+                    }
+                    Err(err) => {
+                        let err = SandboxError::InvalidRequest {
+                            message: format!("invalid JSON frame: {err}"),
+                        };
+                        Message::Text(jsonrpc_error_reply(Value::Null, -32700, &err).to_string())
+                    }
+                }
+            }
+            _ = heartbeat.tick() => Message::Ping(Vec::new()),
+        };
+
+        if outgoing.send(frame).await.is_err() {
+            break;
+        }
+    }
+
+    in_flight.abort_all();
+    let _ = outgoing.close().await;
+}
+
+/// Ids of the requests in a frame, as keys matching [`response_key`].
+fn request_keys(payload: &Value) -> Vec<String> {
+    let request_key = |message: &Value| {
+        message
+            .get("id")
+            .filter(|_| message.get("method").is_some())
+            .map(Value::to_string)
+    };
+    match payload {
+        Value::Array(batch) => batch.iter().filter_map(request_key).collect(),
+        message => request_key(message).into_iter().collect(),
+    }
+}
+
+/// The id a stream message answers, when it is a response.
+fn response_key(payload: &Value) -> Option<String> {
+    payload
+        .get("id")
+        .filter(|_| payload.get("method").is_none())
+        .map(Value::to_string)
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/acp/{server_id}/logs",
+    tag = "v1",
+    params(
+        ("server_id" = String, Path, description = "Client-defined ACP server id"),
+        ("tail" = Option<usize>, Query, description = "Only return the last N lines"),
+        ("since" = Option<i64>, Query, description = "Only return lines captured at or after this Unix time in milliseconds"),
+        ("follow" = Option<bool>, Query, description = "Stream new lines as SSE after the matching backlog")
+    ),
+    responses(
+        (status = 200, description = "Captured agent stderr, or an SSE stream of lines when following", body = AcpLogsResponse),
+        (status = 404, description = "Unknown ACP server", body = ProblemDetails)
+    )
+)]
+async fn get_v1_acp_logs(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    Query(query): Query<AcpLogsQuery>,
+) -> Result<Response, ApiError> {
+    if query.follow.unwrap_or(false) {
+        let stream = state
+            .acp_proxy()
+            .follow_logs(&server_id, query.since, query.tail)
+            .await?;
+        return Ok(Sse::new(stream)
+            .keep_alive(
+                KeepAlive::new()
+                    .interval(Duration::from_secs(15))
+                    .text("heartbeat"),
+            )
+            .into_response());
+    }
+
+    let lines = state
+        .acp_proxy()
+        .logs(&server_id, query.since, query.tail)
+        .await?
+        .into_iter()
+        .map(|line| AcpLogLine {
+            timestamp_ms: line.timestamp_ms,
+            line: line.line,
+        })
+        .collect();
+    Ok(Json(AcpLogsResponse { lines }).into_response())
+}
+
+#[utoipa::path(
+    patch,
+    path = "/v1/acp/{server_id}",
+    tag = "v1",
+    params(
+        ("server_id" = String, Path, description = "Client-defined ACP server id")
+    ),
+    request_body = AcpServerPatchRequest,
+    responses(
+        (status = 200, description = "Updated ACP server", body = AcpServerInfo),
+        (status = 400, description = "Invalid label key", body = ProblemDetails),
+        (status = 404, description = "Unknown ACP server", body = ProblemDetails)
+    )
+)]
+async fn patch_v1_acp(
+    State(state): State<Arc<AppState>>,
+    Path(server_id): Path<String>,
+    Json(request): Json<AcpServerPatchRequest>,
+) -> Result<Json<AcpServerInfo>, ApiError> {
+    let instance = state
+        .acp_proxy()
+        .update_labels(&server_id, request.labels)
+        .await?;
+    Ok(Json(acp_server_info(instance)))
+}
+
 #[utoipa::path(
     delete,
     path = "/v1/acp/{server_id}",
@@ -42,10 +42,34 @@ pub(super) async fn require_token(
     }))
 }
 
+/// Count and time every request by its route template, so ids in paths do
+/// not become separate series.
+pub(super) async fn record_http_metrics(
+    State(state): State<Arc<AppState>>,
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let start = std::time::Instant::now();
+    let response = next.run(request).await;
+    state.metrics().record_http(
+        method.as_str(),
+        &route,
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+    response
+}
+
 pub(super) type PinBoxSseStream = crate::acp_proxy_runtime::PinBoxSseStream;
 
 pub(super) fn credentials_available_for(
-    agent: AgentId,
+    agent: &AgentId,
     has_anthropic: bool,
     has_openai: bool,
 ) -> bool {
@@ -55,6 +79,8 @@ pub(super) fn credentials_available_for(
         AgentId::Opencode => has_anthropic || has_openai,
         AgentId::Pi | AgentId::Cursor => true,
         AgentId::Mock => true,
+        // Custom agents bring their own credentials through their env.
+        AgentId::Custom(_) => true,
     }
 }
 
@@ -63,7 +89,7 @@ pub(super) fn credentials_available_for(
 /// in `scripts/agent-configs/resources/` (generated by `scripts/agent-configs/dump.ts`).
 ///
 /// To refresh: `cd scripts/agent-configs && npx tsx dump.ts`
-pub(super) fn fallback_config_options(agent: AgentId) -> Vec<Value> {
+pub(super) fn fallback_config_options(agent: &AgentId) -> Vec<Value> {
     static CLAUDE: std::sync::LazyLock<Vec<Value>> = std::sync::LazyLock::new(|| {
         parse_agent_config(include_str!(
             "../../../../../scripts/agent-configs/resources/claude.json"
@@ -115,7 +141,7 @@ pub(super) fn fallback_config_options(agent: AgentId) -> Vec<Value> {
                 ]
             }),
         ],
-        AgentId::Pi => vec![json!({
+        AgentId::Pi | AgentId::Custom(_) => vec![json!({
             "id": "model",
             "name": "Model",
             "category": "model",
@@ -196,7 +222,7 @@ fn parse_agent_config(json_str: &str) -> Vec<Value> {
     options
 }
 
-pub(super) fn agent_capabilities_for(agent: AgentId) -> AgentCapabilities {
+pub(super) fn agent_capabilities_for(manager: &AgentManager, agent: &AgentId) -> AgentCapabilities {
     match agent {
         AgentId::Claude => AgentCapabilities {
             plan_mode: false,
@@ -338,6 +364,33 @@ pub(super) fn agent_capabilities_for(agent: AgentId) -> AgentCapabilities {
             item_started: true,
             shared_process: false,
         },
+        AgentId::Custom(_) => {
+            let declared = manager
+                .custom_definition(agent)
+                .map(|definition| definition.capabilities.clone())
+                .unwrap_or_default();
+            let flag = |name: &str| declared.get(name).copied().unwrap_or(false);
+            AgentCapabilities {
+                plan_mode: flag("planMode"),
+                permissions: flag("permissions"),
+                questions: flag("questions"),
+                tool_calls: flag("toolCalls"),
+                tool_results: flag("toolResults"),
+                text_messages: flag("textMessages"),
+                images: flag("images"),
+                file_attachments: flag("fileAttachments"),
+                session_lifecycle: flag("sessionLifecycle"),
+                error_events: flag("errorEvents"),
+                reasoning: flag("reasoning"),
+                status: flag("status"),
+                command_execution: flag("commandExecution"),
+                file_changes: flag("fileChanges"),
+                mcp_tools: flag("mcpTools"),
+                streaming_deltas: flag("streamingDeltas"),
+                item_started: flag("itemStarted"),
+                shared_process: flag("sharedProcess"),
+            }
+        }
     }
 }
 
@@ -357,12 +410,44 @@ pub(super) fn map_install_result(result: InstallResult) -> AgentInstallResponse
     }
 }
 
+pub(super) fn map_custom_agent_request(
+    id: String,
+    request: CustomAgentRequest,
+) -> CustomAgentDefinition {
+    CustomAgentDefinition {
+        id,
+        display_name: request.display_name,
+        install: match request.install {
+            CustomAgentInstallSpec::Registry(id) => CustomAgentInstall::Registry(id),
+            CustomAgentInstallSpec::Npx(package) => CustomAgentInstall::Npx(package),
+            CustomAgentInstallSpec::Command(command) => CustomAgentInstall::Command(command),
+        },
+        args: request.args,
+        env: request.env,
+        framing: request.framing,
+        capabilities: request.capabilities,
+    }
+}
+
+pub(super) fn map_custom_agent_error(err: AgentError) -> SandboxError {
+    match err {
+        AgentError::InvalidCustomAgent { .. } => SandboxError::InvalidRequest {
+            message: err.to_string(),
+        },
+        err => SandboxError::StreamError {
+            message: format!("failed to store custom agents: {err}"),
+        },
+    }
+}
+
 pub(super) fn map_install_source(source: InstallSource) -> String {
     match source {
         InstallSource::Registry => "registry",
         InstallSource::Fallback => "fallback",
         InstallSource::LocalPath => "local_path",
         InstallSource::Builtin => "builtin",
+        InstallSource::Custom => "custom",
+        InstallSource::Bundle => "bundle",
     }
     .to_string()
 }
@@ -375,7 +460,7 @@ pub(super) fn map_artifact_kind(kind: InstalledArtifactKind) -> String {
     .to_string()
 }
 
-pub(super) fn resolve_fs_path(raw_path: &str) -> Result<PathBuf, SandboxError> {
+pub(crate) fn resolve_fs_path(raw_path: &str) -> Result<PathBuf, SandboxError> {
     let path = PathBuf::from(raw_path);
     if path.is_absolute() {
         return Ok(path);
@@ -421,6 +506,72 @@ pub(super) fn map_fs_error(path: &StdPath, err: std::io::Error) -> SandboxError
     }
 }
 
+/// What the first POST or WebSocket connection may set on an ACP server.
+pub(super) struct AcpBootstrap {
+    pub(super) agent: Option<AgentId>,
+    pub(super) launch: AcpLaunchOverrides,
+    pub(super) labels: BTreeMap<String, String>,
+}
+
+/// Parse the bootstrap query of `POST /v1/acp/{server_id}`. `env`, `arg`
+/// and `label` may repeat, so the query is read as raw pairs; the route's
+/// `utoipa::path` documents each parameter.
+pub(super) fn parse_acp_post_query(
+    manager: &AgentManager,
+    pairs: Vec<(String, String)>,
+) -> Result<AcpBootstrap, SandboxError> {
+    let mut agent = None;
+    let mut launch = AcpLaunchOverrides::default();
+    let mut labels = BTreeMap::new();
+    for (key, value) in pairs {
+        match key.as_str() {
+            "agent" => {
+                agent = Some(
+                    manager
+                        .parse_agent(&value)
+                        .ok_or(SandboxError::UnsupportedAgent { agent: value })?,
+                );
+            }
+            "cwd" => launch.cwd = Some(resolve_fs_path(&value)?),
+            "env" => {
+                let Some((name, env_value)) =
+                    value.split_once('=').filter(|(name, _)| !name.is_empty())
+                else {
+                    return Err(SandboxError::InvalidRequest {
+                        message: format!("env must be KEY=VALUE, got '{value}'"),
+                    });
+                };
+                launch.env.insert(name.to_string(), env_value.to_string());
+            }
+            "arg" => launch.args.push(value),
+            "label" => {
+                let Some((name, label_value)) = value.split_once('=') else {
+                    return Err(SandboxError::InvalidRequest {
+                        message: format!("label must be KEY=VALUE, got '{value}'"),
+                    });
+                };
+                validate_label_key(name)
+                    .map_err(|message| SandboxError::InvalidRequest { message })?;
+                labels.insert(name.to_string(), label_value.to_string());
+            }
+            "permissionPolicy" => {
+                launch.permission_policy =
+                    Some(PermissionPolicy::parse(&value).map_err(|err| {
+                        SandboxError::InvalidRequest {
+                            message: format!("invalid permissionPolicy: {err}"),
+                        }
+                    })?);
+            }
+            _ => {}
+        }
+    }
+    Ok(AcpBootstrap {
+        agent,
+        launch,
+        labels,
+    })
+}
+
 pub(super) fn content_type_is(headers: &HeaderMap, expected: &str) -> bool {
     let Some(value) = headers
         .get(header::CONTENT_TYPE)
@@ -478,7 +629,103 @@ pub(super) fn parse_last_event_id(headers: &HeaderMap) -> Result<Option<u64>, Sa
     let value = headers
         .get("last-event-id")
         .and_then(|value| value.to_str().ok());
+    parse_event_id(value, "Last-Event-ID")
+}
+
+/// `lastEventId` query parameter, for transports such as browser
+/// WebSockets that cannot set a `Last-Event-ID` header.
+pub(super) fn parse_last_event_id_query(
+    pairs: &[(String, String)],
+) -> Result<Option<u64>, SandboxError> {
+    let value = pairs
+        .iter()
+        .rev()
+        .find(|(key, _)| key == "lastEventId")
+        .map(|(_, value)| value.as_str());
+    parse_event_id(value, "lastEventId")
+}
 
+/// Per-request deadline and cancellation settings of `POST
+/// /v1/acp/{server_id}`: a relative `timeoutMs` (or `X-Request-Timeout-Ms`)
+/// and an absolute `deadline` in Unix milliseconds (or `X-Request-Deadline`).
+/// When several are given the earliest wins. `clientId` (or `X-Client-Id`)
+/// names the caller; see [`parse_client_id`].
+pub(super) fn parse_acp_request_options(
+    headers: &HeaderMap,
+    pairs: &[(String, String)],
+) -> Result<AcpRequestOptions, SandboxError> {
+    let query = |name: &str| {
+        pairs
+            .iter()
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let now = std::time::Instant::now();
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0);
+    let timeouts = [
+        parse_u64_param(query("timeoutMs"), "timeoutMs")?,
+        parse_u64_param(header("x-request-timeout-ms"), "X-Request-Timeout-Ms")?,
+    ];
+    let deadlines = [
+        parse_u64_param(query("deadline"), "deadline")?,
+        parse_u64_param(header("x-request-deadline"), "X-Request-Deadline")?,
+    ];
+    let deadline = timeouts
+        .into_iter()
+        .flatten()
+        .chain(
+            deadlines
+                .into_iter()
+                .flatten()
+                .map(|deadline| deadline.saturating_sub(now_ms)),
+        )
+        .min()
+        .map(|remaining_ms| now + Duration::from_millis(remaining_ms));
+
+    let cancel_on_timeout = match query("cancelOnTimeout") {
+        None => None,
+        Some("true" | "1") => Some(true),
+        Some("false" | "0") => Some(false),
+        Some(value) => {
+            return Err(SandboxError::InvalidRequest {
+                message: format!("cancelOnTimeout must be true or false, got '{value}'"),
+            })
+        }
+    };
+
+    Ok(AcpRequestOptions {
+        deadline,
+        cancel_on_timeout,
+        client_id: parse_client_id(headers, pairs),
+    })
+}
+
+/// Who is answering agent-to-client requests: the `clientId` query
+/// parameter or `X-Client-Id` header. Reported to other subscribers when a
+/// request is resolved and to clients whose answer came too late.
+fn parse_client_id(headers: &HeaderMap, pairs: &[(String, String)]) -> Option<String> {
+    pairs
+        .iter()
+        .rev()
+        .find(|(key, _)| key == "clientId")
+        .map(|(_, value)| value.as_str())
+        .or_else(|| {
+            headers
+                .get("x-client-id")
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn parse_event_id(value: Option<&str>, name: &str) -> Result<Option<u64>, SandboxError> {
     match value {
         Some(value) if !value.trim().is_empty() => {
             value
@@ -486,7 +733,25 @@ pub(super) fn parse_last_event_id(headers: &HeaderMap) -> Result<Option<u64>, Sa
                 .parse::<u64>()
                 .map(Some)
                 .map_err(|_| SandboxError::InvalidRequest {
-                    message: "Last-Event-ID must be a positive integer".to_string(),
+                    message: format!("{name} must be a positive integer"),
+                })
+        }
+        _ => Ok(None),
+    }
+}
+
+/// A millisecond count or Unix time in milliseconds from a query parameter
+/// or header.
+fn parse_u64_param(value: Option<&str>, name: &str) -> Result<Option<u64>, SandboxError> {
+    match value.map(str::trim) {
+        Some(value) if !value.is_empty() => {
+            value
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|_| SandboxError::InvalidRequest {
+                    message: format!(
+                        "{name} must be a whole number of milliseconds, got '{value}'"
+                    ),
                 })
         }
         _ => Ok(None),
@@ -500,7 +765,7 @@ pub(super) fn problem_from_sandbox_error(error: &SandboxError) -> ProblemDetails
         SandboxError::InvalidRequest { .. } => {
             problem.status = 400;
         }
-        SandboxError::Timeout { .. } => {
+        SandboxError::Timeout { .. } | SandboxError::AgentRequestTimeout { .. } => {
             problem.status = 504;
         }
         _ => {}
@@ -509,12 +774,28 @@ pub(super) fn problem_from_sandbox_error(error: &SandboxError) -> ProblemDetails
     problem
 }
 
+/// JSON-RPC error response for a message that failed before reaching the
+/// agent, carrying the problem details as `data`.
+pub(super) fn jsonrpc_error_reply(id: Value, code: i64, error: &SandboxError) -> Value {
+    let problem = problem_from_sandbox_error(error);
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": problem.detail.clone().unwrap_or_else(|| problem.title.clone()),
+            "data": problem,
+        }
+    })
+}
+
 /// Build the OpenCode-compatible provider payload from installed agent config
 /// options. This replaces the hardcoded mock/amp/claude/codex list in the
 /// opencode-adapter with real model information derived from
 /// `fallback_config_options()`.
-pub(super) fn build_provider_payload_for_opencode(_state: &Arc<AppState>) -> Value {
-    let agents: &[AgentId] = &[
+pub(super) fn build_provider_payload_for_opencode(state: &Arc<AppState>) -> Value {
+    let manager = state.agent_manager();
+    let mut agents = vec![
         AgentId::Mock,
         AgentId::Claude,
         AgentId::Codex,
@@ -523,6 +804,12 @@ pub(super) fn build_provider_payload_for_opencode(_state: &Arc<AppState>) -> Val
         AgentId::Pi,
         AgentId::Cursor,
     ];
+    agents.extend(
+        manager
+            .agents()
+            .into_iter()
+            .filter(|agent| matches!(agent, AgentId::Custom(_))),
+    );
 
     let has_anthropic = std::env::var("ANTHROPIC_API_KEY").is_ok();
     let has_openai = std::env::var("OPENAI_API_KEY").is_ok();
@@ -531,10 +818,10 @@ pub(super) fn build_provider_payload_for_opencode(_state: &Arc<AppState>) -> Val
     let mut defaults = serde_json::Map::new();
     let mut connected = Vec::new();
 
-    for &agent in agents {
+    for agent in agents {
         let agent_str = agent.as_str();
 
-        let options = fallback_config_options(agent);
+        let options = fallback_config_options(&agent);
         let model_option = options
             .iter()
             .find(|opt| opt.get("category").and_then(Value::as_str) == Some("model"));
@@ -579,13 +866,13 @@ pub(super) fn build_provider_payload_for_opencode(_state: &Arc<AppState>) -> Val
 
         defaults.insert(agent_str.to_string(), json!(current_value));
 
-        if agent == AgentId::Mock || credentials_available_for(agent, has_anthropic, has_openai) {
+        if agent == AgentId::Mock || credentials_available_for(&agent, has_anthropic, has_openai) {
             connected.push(json!(agent_str));
         }
 
         all_providers.push(json!({
             "id": agent_str,
-            "name": agent_display_name(agent),
+            "name": agent_display_name(&manager, &agent),
             "env": [],
             "models": Value::Object(models),
         }));
@@ -598,7 +885,7 @@ pub(super) fn build_provider_payload_for_opencode(_state: &Arc<AppState>) -> Val
     })
 }
 
-fn agent_display_name(agent: AgentId) -> &'static str {
+pub(super) fn agent_display_name(manager: &AgentManager, agent: &AgentId) -> String {
     match agent {
         AgentId::Mock => "Mock",
         AgentId::Claude => "Claude Code",
@@ -607,7 +894,14 @@ fn agent_display_name(agent: AgentId) -> &'static str {
         AgentId::Opencode => "OpenCode",
         AgentId::Pi => "Pi",
         AgentId::Cursor => "Cursor Agent",
+        AgentId::Custom(id) => {
+            return manager
+                .custom_definition(agent)
+                .and_then(|definition| definition.display_name.clone())
+                .unwrap_or_else(|| id.as_str().to_string());
+        }
     }
+    .to_string()
 }
 
 fn capitalize_first(s: &str) -> String {
@@ -49,6 +49,7 @@ pub struct AgentCapabilities {
 #[serde(rename_all = "camelCase")]
 pub struct AgentInfo {
     pub id: String,
+    pub display_name: String,
     pub installed: bool,
     pub credentials_available: bool,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -84,6 +85,55 @@ pub struct AgentInstallRequest {
     pub reinstall: Option<bool>,
     pub agent_version: Option<String>,
     pub agent_process_version: Option<String>,
+    /// Fail unless the install matches the agent lockfile.
+    pub frozen: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentUninstallQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentUninstallResponse {
+    /// Files and directories removed from the install dir.
+    pub removed: Vec<String>,
+}
+
+/// A user-defined agent registered under the id in the path.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomAgentRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    pub install: CustomAgentInstallSpec,
+    /// Appended to the agent process command line.
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Stdio framing (`content-length` or `auto`); newline-delimited JSON
+    /// when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub framing: Option<String>,
+    /// Capability flags keyed like `AgentCapabilities`; unset flags are false.
+    #[serde(default)]
+    pub capabilities: BTreeMap<String, bool>,
+}
+
+/// Where a custom agent process comes from.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum CustomAgentInstallSpec {
+    /// An ACP registry entry id.
+    Registry(String),
+    /// An npm package run through `npx`.
+    Npx(String),
+    /// An absolute executable path or a name on `PATH`.
+    Command(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
@@ -194,17 +244,39 @@ pub struct FsUploadBatchResponse {
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct AcpPostQuery {
+pub struct AcpServerInfo {
+    pub server_id: String,
+    pub agent: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub agent: Option<String>,
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    pub created_at_ms: i64,
+    pub uptime_ms: i64,
+    pub restart_count: u32,
+    /// Agent process id; absent while the process is restarting or after it
+    /// exited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// `running`, `restarting` or `exited`.
+    pub state: String,
+    /// Requests sent to the agent that have not been answered yet.
+    pub pending_requests: u64,
+    /// Last request or stream activity, in milliseconds since the Unix epoch.
+    pub last_activity_ms: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct AcpServerInfo {
-    pub server_id: String,
-    pub agent: String,
-    pub created_at_ms: i64,
+pub struct AcpServerListQuery {
+    /// Comma-separated label requirements: `key=value`, `key!=value`, `key`
+    /// or `!key`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label_selector: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
@@ -213,6 +285,42 @@ pub struct AcpServerListResponse {
     pub servers: Vec<AcpServerInfo>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AcpServerPatchRequest {
+    /// Labels to set; a `null` value removes the label.
+    #[serde(default)]
+    pub labels: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AcpLogsQuery {
+    /// Only return the last N lines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tail: Option<usize>,
+    /// Only return lines captured at or after this time, in milliseconds
+    /// since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<i64>,
+    /// Stream new lines as SSE after the matching backlog.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub follow: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AcpLogLine {
+    pub timestamp_ms: i64,
+    pub line: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AcpLogsResponse {
+    pub lines: Vec<AcpLogLine>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct McpConfigQuery {
@@ -362,3 +470,11 @@ pub struct AcpEnvelope {
     #[serde(default)]
     pub error: Option<Value>,
 }
+
+/// A JSON-RPC envelope, or a batch of them.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(untagged)]
+pub enum AcpPayload {
+    Envelope(AcpEnvelope),
+    Batch(Vec<AcpEnvelope>),
+}
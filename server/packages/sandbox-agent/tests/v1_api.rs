@@ -268,6 +268,57 @@ async fn read_first_sse_data_with_last_id(
     .expect("timed out reading sse")
 }
 
+/// Read SSE events (id, payload) until `done` returns true for the events
+/// collected so far.
+async fn collect_sse_events<F>(
+    app: &Router,
+    server_id: &str,
+    last_event_id: Option<u64>,
+    mut done: F,
+) -> Vec<(Option<u64>, Value)>
+where
+    F: FnMut(&[(Option<u64>, Value)]) -> bool,
+{
+    let mut builder = Request::builder()
+        .method(Method::GET)
+        .uri(format!("/v1/acp/{server_id}"));
+    if let Some(last_event_id) = last_event_id {
+        builder = builder.header("last-event-id", last_event_id.to_string());
+    }
+    let request = builder.body(Body::empty()).expect("build request");
+
+    let response = app.clone().oneshot(request).await.expect("sse response");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut stream = response.into_body().into_data_stream();
+    tokio::time::timeout(Duration::from_secs(5), async move {
+        let mut buffered = String::new();
+        let mut events = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let bytes = chunk.expect("stream chunk");
+            buffered.push_str(&String::from_utf8_lossy(&bytes));
+            while let Some(end) = buffered.find("\n\n") {
+                let event = buffered[..end].to_string();
+                buffered.drain(..end + 2);
+                if !event.contains("data:") {
+                    continue;
+                }
+                let id = event
+                    .lines()
+                    .find_map(|line| line.strip_prefix("id: "))
+                    .and_then(|value| value.trim().parse::<u64>().ok());
+                events.push((id, parse_sse_data(&event)));
+                if done(&events) {
+                    return events;
+                }
+            }
+        }
+        panic!("SSE stream ended early")
+    })
+    .await
+    .expect("timed out reading sse")
+}
+
 fn parse_sse_data(chunk: &str) -> Value {
     let data = chunk
         .lines()
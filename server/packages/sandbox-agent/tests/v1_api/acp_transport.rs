@@ -17,12 +17,71 @@ while IFS= read -r line; do
   method=$(printf '%s\n' "$line" | sed -n 's/.*"method"[[:space:]]*:[[:space:]]*"\([^"]*\)".*/\1/p')
   id=$(printf '%s\n' "$line" | sed -n 's/.*"id"[[:space:]]*:[[:space:]]*\([^,}}]*\).*/\1/p')
 
+  if [ "$method" = "_test/exit" ]; then
+    echo "fatal: stub agent exiting" >&2
+    exit 3
+  fi
+
+  if [ "$method" = "_test/stderr" ] && [ -n "$id" ]; then
+    echo "stub stderr $id" >&2
+    printf '{{"jsonrpc":"2.0","id":%s,"result":{{}}}}\n' "$id"
+    continue
+  fi
+
+  case "$line" in
+    *'"hang":true'*)
+      printf '{{"jsonrpc":"2.0","method":"server/echo","params":{{"method":"%s"}}}}\n' "$method"
+      continue
+      ;;
+  esac
+
+  if [ "$method" = "_test/launch" ] && [ -n "$id" ]; then
+    printf '{{"jsonrpc":"2.0","id":%s,"result":{{"cwd":"%s","env":"%s","args":"%s"}}}}\n' "$id" "$(pwd)" "${{ACP_TEST_VALUE:-}}" "$*"
+    continue
+  fi
+
+  if [ "$method" = "_test/spawn_stubborn" ] && [ -n "$id" ]; then
+    (trap '' TERM; exec sleep 30) </dev/null >/dev/null 2>&1 &
+    printf '{{"jsonrpc":"2.0","id":%s,"result":{{"pid":%s}}}}\n' "$id" "$!"
+    continue
+  fi
+
+  if [ "$method" = "_test/flood" ] && [ -n "$id" ]; then
+    n=0
+    while [ "$n" -lt 600 ]; do
+      printf '{{"jsonrpc":"2.0","method":"server/flood","params":{{"n":%s}}}}\n' "$n"
+      n=$((n + 1))
+    done
+    printf '{{"jsonrpc":"2.0","id":%s,"result":{{}}}}\n' "$id"
+    continue
+  fi
+
+  if [ "$method" = "_test/limits" ] && [ -n "$id" ]; then
+    printf '{{"jsonrpc":"2.0","id":%s,"result":{{"openFiles":"%s"}}}}\n' "$id" "$(ulimit -n)"
+    continue
+  fi
+
+  case "$method" in
+    _test/client/*)
+      params=$(printf '%s\n' "$line" | sed -n 's/.*"params"[[:space:]]*:[[:space:]]*\(.*\)}}$/\1/p')
+      printf '{{"jsonrpc":"2.0","id":"client-%s","method":"%s","params":%s}}\n' "$id" "${{method#_test/client/}}" "$params"
+      IFS= read -r reply
+      printf '{{"jsonrpc":"2.0","id":%s,"result":{{"reply":%s}}}}\n' "$id" "$reply"
+      continue
+      ;;
+  esac
+
+  extra=""
+  if [ "$method" = "initialize" ]; then
+    extra=",\"request\":$line"
+  fi
+
   if [ -n "$method" ]; then
     printf '{{"jsonrpc":"2.0","method":"server/echo","params":{{"method":"%s"}}}}\n' "$method"
   fi
 
   if [ -n "$method" ] && [ -n "$id" ]; then
-    printf '{{"jsonrpc":"2.0","id":%s,"result":{{"ok":true,"echoedMethod":"%s"}}}}\n' "$id" "$method"
+    printf '{{"jsonrpc":"2.0","id":%s,"result":{{"ok":true,"echoedMethod":"%s"%s}}}}\n' "$id" "$method" "$extra"
   elif [ -z "$method" ] && [ -n "$id" ]; then
     printf '{{"jsonrpc":"2.0","method":"server/client_response","params":{{"id":%s}}}}\n' "$id"
   fi
@@ -33,6 +92,66 @@ done
     write_executable(path, &script);
 }
 
+/// A stub agent that speaks `Content-Length` framed JSON-RPC on stdio and
+/// echoes requests like [`write_stub_agent_process`].
+fn write_stub_framed_agent_process(path: &Path) {
+    let script = r#"#!/usr/bin/env sh
+if [ "${1:-}" = "--help" ]; then
+  echo "framed-agent-process 0.0.1"
+  exit 0
+fi
+
+send() {
+  printf 'Content-Length: %s\r\n\r\n%s' "${#1}" "$1"
+}
+
+while IFS= read -r header; do
+  case "$header" in
+    Content-Length:*) ;;
+    *) continue ;;
+  esac
+  length=$(printf '%s' "$header" | tr -cd '0-9')
+  IFS= read -r _blank
+  body=$(dd bs=1 count="$length" 2>/dev/null)
+  method=$(printf '%s\n' "$body" | sed -n 's/.*"method"[[:space:]]*:[[:space:]]*"\([^"]*\)".*/\1/p')
+  id=$(printf '%s\n' "$body" | sed -n 's/.*"id"[[:space:]]*:[[:space:]]*\([^,}]*\).*/\1/p')
+
+  if [ "$method" = "_test/bad_header" ]; then
+    printf 'Content-Length: not-a-number\r\n\r\n'
+  fi
+  send "{\"jsonrpc\":\"2.0\",\"method\":\"server/echo\",\"params\":{\"method\":\"$method\"}}"
+  if [ -n "$id" ]; then
+    send "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"ok\":true,\"echoedMethod\":\"$method\"}}"
+  fi
+done
+"#;
+    write_executable(path, script);
+}
+
+/// Wait until the agent killed by dropping a `TestApp` has had its exit
+/// recorded in the on-disk event log.
+async fn wait_for_logged_exit(log_dir: &Path, server_id: &str) {
+    let dir = log_dir.join(server_id);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let logged = fs::read_dir(&dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .any(|entry| {
+                    fs::read_to_string(entry.path())
+                        .is_ok_and(|text| text.contains("_adapter/agent_exited"))
+                });
+            if logged {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("agent exit was not logged");
+}
+
 fn setup_stub_artifacts(install_dir: &Path, agent: &str) {
     let native = install_dir.join(agent);
     write_stub_native(&native, agent);
@@ -144,164 +263,2263 @@ async fn acp_agent_mismatch_returns_conflict() {
     assert_eq!(parse_json(&body)["status"], 409);
 }
 
+#[cfg(unix)]
 #[tokio::test]
-async fn acp_get_unknown_returns_not_found() {
-    let test_app = TestApp::new(AuthConfig::disabled());
-
-    let (status, _, body) =
-        send_request(&test_app.app, Method::GET, "/v1/acp/missing", None, &[]).await;
-    assert_eq!(status, StatusCode::NOT_FOUND);
-    assert_eq!(parse_json(&body)["status"], 404);
-}
-
-#[tokio::test]
-async fn acp_delete_is_idempotent() {
-    let test_app = TestApp::new(AuthConfig::disabled());
+#[serial]
+async fn acp_first_post_applies_launch_overrides() {
+    let work_dir = tempfile::tempdir().expect("create work dir");
+    let work_dir_path = work_dir
+        .path()
+        .canonicalize()
+        .expect("canonicalize work dir");
+    let test_app = {
+        let _env = EnvVarGuard::set("SANDBOX_AGENT_ACP_ALLOWED_ENV", "ACP_TEST_*");
+        let _args = EnvVarGuard::set("SANDBOX_AGENT_ACP_ALLOWED_ARGS", "--flag*");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
 
-    let (status, _, _) = send_request(
+    let launch = json!({"jsonrpc": "2.0", "id": 1, "method": "_test/launch"});
+    let (status, _, body) = send_request(
         &test_app.app,
-        Method::DELETE,
-        "/v1/acp/server-delete",
-        None,
+        Method::POST,
+        &format!(
+            "/v1/acp/server-launch?agent=codex&cwd={}&env=ACP_TEST_VALUE%3Dhello&arg=--flag-one",
+            work_dir_path.display()
+        ),
+        Some(launch.clone()),
         &[],
     )
     .await;
-    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(status, StatusCode::OK);
+    let result = &parse_json(&body)["result"];
+    assert_eq!(result["cwd"], work_dir_path.to_string_lossy().as_ref());
+    assert_eq!(result["env"], "hello");
+    assert_eq!(result["args"], "--flag-one");
+
+    let (status, _, body) = send_request(&test_app.app, Method::GET, "/v1/acp", None, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let server = &parse_json(&body)["servers"][0];
+    assert_eq!(server["cwd"], work_dir_path.to_string_lossy().as_ref());
+    assert_eq!(server["env"]["ACP_TEST_VALUE"], "hello");
+    assert_eq!(server["args"], json!(["--flag-one"]));
 
     let (status, _, _) = send_request(
         &test_app.app,
-        Method::DELETE,
-        "/v1/acp/server-delete",
-        None,
+        Method::POST,
+        "/v1/acp/server-launch?arg=--flag-two",
+        Some(launch),
         &[],
     )
     .await;
-    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+#[serial]
+async fn acp_launch_overrides_are_validated() {
+    let test_app = {
+        let _env = EnvVarGuard::set("SANDBOX_AGENT_ACP_ALLOWED_ENV", "ACP_TEST_*");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize"});
+
+    for (uri, expected) in [
+        (
+            "/v1/acp/server-denied?agent=codex&env=PATH%3D%2Ftmp",
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "/v1/acp/server-denied?agent=codex&arg=--anything",
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "/v1/acp/server-denied?agent=codex&env=MISSING_EQUALS",
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/v1/acp/server-denied?agent=codex&cwd=%2Fdefinitely%2Fmissing%2Fdir",
+            StatusCode::BAD_REQUEST,
+        ),
+    ] {
+        let (status, _, _) =
+            send_request(&test_app.app, Method::POST, uri, Some(request.clone()), &[]).await;
+        assert_eq!(status, expected, "{uri}");
+    }
+
+    let (_, _, body) = send_request(&test_app.app, Method::GET, "/v1/acp", None, &[]).await;
+    assert_eq!(parse_json(&body)["servers"], json!([]));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_serves_fs_client_methods() {
+    let work_dir = tempfile::tempdir().expect("create work dir");
+    let notes = work_dir.path().join("notes.txt");
+    fs::write(&notes, "one\ntwo\nthree\n").expect("write notes");
+    let test_app = {
+        let _serve_fs = EnvVarGuard::set("SANDBOX_AGENT_ACP_SERVE_FS", "1");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
 
-    let request = json!({
-        "jsonrpc": "2.0",
-        "id": 1,
-        "method": "initialize",
-        "params": {
-            "protocolVersion": "1.0",
-            "clientCapabilities": {}
-        }
-    });
     let (status, _, body) = send_request(
         &test_app.app,
         Method::POST,
-        "/v1/acp/server-delete",
-        Some(request),
+        "/v1/acp/server-fs?agent=codex",
+        Some(initialize_payload()),
         &[],
     )
     .await;
-    assert_eq!(status, StatusCode::BAD_REQUEST);
-    assert_eq!(parse_json(&body)["status"], 400);
-}
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        parse_json(&body)["result"]["request"]["params"]["clientCapabilities"]["fs"],
+        json!({"readTextFile": true, "writeTextFile": true})
+    );
 
-#[cfg(unix)]
-#[tokio::test]
-async fn acp_list_servers_returns_active_instances() {
-    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
-        setup_stub_artifacts(install_dir, "codex");
-    });
+    let client_request = |id: u64, method: &str, params: Value| {
+        let app = test_app.app.clone();
+        let payload = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        async move {
+            let (status, _, body) =
+                send_request(&app, Method::POST, "/v1/acp/server-fs", Some(payload), &[]).await;
+            assert_eq!(status, StatusCode::OK);
+            let reply = parse_json(&body)["result"]["reply"].clone();
+            assert_eq!(reply["id"], format!("client-{id}"));
+            reply
+        }
+    };
 
-    bootstrap_server(&test_app.app, "server-1", "codex").await;
-    bootstrap_server(&test_app.app, "server-2", "codex").await;
+    let reply = client_request(
+        2,
+        "_test/client/fs/read_text_file",
+        json!({"sessionId": "s", "path": notes, "line": 2, "limit": 1}),
+    )
+    .await;
+    assert_eq!(reply["result"]["content"], "two\n");
 
-    let (status, _, body) = send_request(&test_app.app, Method::GET, "/v1/acp", None, &[]).await;
-    assert_eq!(status, StatusCode::OK);
+    let output = work_dir.path().join("nested").join("out.txt");
+    let reply = client_request(
+        3,
+        "_test/client/fs/write_text_file",
+        json!({"sessionId": "s", "path": output, "content": "hello"}),
+    )
+    .await;
+    assert_eq!(reply["result"], json!({}));
+    assert_eq!(fs::read_to_string(&output).expect("read output"), "hello");
 
-    let parsed = parse_json(&body);
-    let servers = parsed["servers"].as_array().expect("servers array");
-    assert!(servers
-        .iter()
-        .any(|server| server["serverId"] == "server-1"));
-    assert!(servers
-        .iter()
-        .any(|server| server["serverId"] == "server-2"));
+    let reply = client_request(
+        4,
+        "_test/client/fs/read_text_file",
+        json!({"sessionId": "s", "path": work_dir.path().join("missing.txt")}),
+    )
+    .await;
+    assert_eq!(reply["error"]["code"], -32002);
 }
 
 #[cfg(unix)]
 #[tokio::test]
-async fn sandboxagent_methods_are_not_handled_specially() {
-    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
-        setup_stub_artifacts(install_dir, "codex");
-    });
-
-    bootstrap_server(&test_app.app, "server-ext", "codex").await;
+#[serial]
+async fn acp_serves_terminal_client_methods() {
+    let work_dir = tempfile::tempdir().expect("create work dir");
+    let pid_file = work_dir.path().join("sleep.pid");
+    let test_app = {
+        let _serve_terminals = EnvVarGuard::set("SANDBOX_AGENT_ACP_SERVE_TERMINALS", "1");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
 
-    let request = json!({
-        "jsonrpc": "2.0",
-        "id": 22,
-        "method": "_sandboxagent/session/list",
-        "params": {}
-    });
     let (status, _, body) = send_request(
         &test_app.app,
         Method::POST,
-        "/v1/acp/server-ext",
-        Some(request),
+        "/v1/acp/server-term?agent=codex",
+        Some(initialize_payload()),
         &[],
     )
     .await;
-
     assert_eq!(status, StatusCode::OK);
     assert_eq!(
-        parse_json(&body)["result"]["echoedMethod"],
-        "_sandboxagent/session/list"
+        parse_json(&body)["result"]["request"]["params"]["clientCapabilities"]["terminal"],
+        true
     );
+
+    let mut next_id = 1;
+    let mut client_request = |method: &str, params: Value| {
+        next_id += 1;
+        let id = next_id;
+        let app = test_app.app.clone();
+        let payload = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": format!("_test/client/{method}"),
+            "params": params,
+        });
+        async move {
+            let (status, _, body) = send_request(
+                &app,
+                Method::POST,
+                "/v1/acp/server-term",
+                Some(payload),
+                &[],
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            parse_json(&body)["result"]["reply"].clone()
+        }
+    };
+
+    let reply = client_request(
+        "terminal/create",
+        json!({
+            "sessionId": "s",
+            "command": "sh",
+            "args": ["-c", "printf \"$GREETING\\nworld\\n\"; exit 4"],
+            "env": [{"name": "GREETING", "value": "hello"}],
+            "outputByteLimit": 6,
+        }),
+    )
+    .await;
+    let terminal_id = reply["result"]["terminalId"].clone();
+    let reply = client_request(
+        "terminal/wait_for_exit",
+        json!({"sessionId": "s", "terminalId": terminal_id}),
+    )
+    .await;
+    assert_eq!(reply["result"], json!({"exitCode": 4, "signal": null}));
+    let reply = client_request(
+        "terminal/output",
+        json!({"sessionId": "s", "terminalId": terminal_id}),
+    )
+    .await;
+    assert_eq!(reply["result"]["output"], "world\n");
+    assert_eq!(reply["result"]["truncated"], true);
+    assert_eq!(reply["result"]["exitStatus"]["exitCode"], 4);
+    client_request(
+        "terminal/release",
+        json!({"sessionId": "s", "terminalId": terminal_id}),
+    )
+    .await;
+    let reply = client_request(
+        "terminal/output",
+        json!({"sessionId": "s", "terminalId": terminal_id}),
+    )
+    .await;
+    assert_eq!(reply["error"]["code"], -32002);
+
+    let reply = client_request(
+        "terminal/create",
+        json!({"sessionId": "s", "command": "sleep", "args": ["30"]}),
+    )
+    .await;
+    let terminal_id = reply["result"]["terminalId"].clone();
+    client_request(
+        "terminal/kill",
+        json!({"sessionId": "s", "terminalId": terminal_id}),
+    )
+    .await;
+    let reply = client_request(
+        "terminal/wait_for_exit",
+        json!({"sessionId": "s", "terminalId": terminal_id}),
+    )
+    .await;
+    assert_eq!(reply["result"]["signal"], "SIGTERM");
+
+    // A background child shares the terminal's process group, so deleting
+    // the server stops it too.
+    client_request(
+        "terminal/create",
+        json!({
+            "sessionId": "s",
+            "command": "sh",
+            "args": ["-c", format!("sleep 30 & echo $! > {}; wait", pid_file.display())],
+        }),
+    )
+    .await;
+    let pid = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(pid) = fs::read_to_string(&pid_file)
+                .ok()
+                .filter(|pid| pid.ends_with('\n'))
+            {
+                return pid.trim().to_string();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("terminal wrote its pid");
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::DELETE,
+        "/v1/acp/server-term",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while std::process::Command::new("kill")
+            .args(["-0", &pid])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .expect("run kill")
+            .success()
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("terminal process killed on delete");
 }
 
+#[cfg(unix)]
 #[tokio::test]
-async fn post_requires_json_content_type() {
-    let test_app = TestApp::new(AuthConfig::disabled());
-    let payload = br#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"1.0","clientCapabilities":{}}}"#
-        .to_vec();
-    let (status, _, body) = send_request_raw(
+#[serial]
+async fn acp_agent_processes_get_resource_limits() {
+    let test_app = {
+        let _global = EnvVarGuard::set("SANDBOX_AGENT_ACP_NOFILE_LIMIT", "128");
+        let _codex = EnvVarGuard::set("SANDBOX_AGENT_ACP_CODEX_NOFILE_LIMIT", "64");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+            setup_stub_artifacts(install_dir, "claude");
+        })
+    };
+
+    for (agent, expected) in [("codex", "64"), ("claude", "128")] {
+        let (status, _, body) = send_request(
+            &test_app.app,
+            Method::POST,
+            &format!("/v1/acp/server-limits-{agent}?agent={agent}"),
+            Some(json!({"jsonrpc": "2.0", "id": 1, "method": "_test/limits"})),
+            &[],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            parse_json(&body)["result"]["openFiles"],
+            expected,
+            "{agent}"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_delete_kills_agent_process_group() {
+    let test_app = {
+        let _grace = EnvVarGuard::set("SANDBOX_AGENT_ACP_SHUTDOWN_GRACE_MS", "200");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+
+    let (status, _, body) = send_request(
         &test_app.app,
         Method::POST,
-        "/v1/acp/server-content?agent=mock",
-        Some(payload),
+        "/v1/acp/server-group?agent=codex",
+        Some(json!({"jsonrpc": "2.0", "id": 1, "method": "_test/spawn_stubborn"})),
         &[],
-        Some("text/plain"),
     )
     .await;
-    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
-    assert_eq!(parse_json(&body)["status"], 415);
+    assert_eq!(status, StatusCode::OK);
+    let pid = parse_json(&body)["result"]["pid"]
+        .as_u64()
+        .expect("grandchild pid");
+    assert!(process_running(pid));
+
+    let app = test_app.app.clone();
+    let events = tokio::spawn(async move {
+        collect_sse_events(&app, "server-group", Some(0), |events| {
+            events
+                .iter()
+                .any(|(_, event)| event["method"] == "_adapter/agent_exited")
+        })
+        .await
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::DELETE,
+        "/v1/acp/server-group",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(!process_running(pid), "grandchild survived delete");
+
+    let events = events.await.expect("collect events");
+    let exited = events
+        .iter()
+        .map(|(_, event)| event)
+        .find(|event| event["method"] == "_adapter/agent_exited")
+        .expect("agent_exited event");
+    if cfg!(target_os = "linux") {
+        let leftovers = exited["params"]["leftoverProcesses"]
+            .as_array()
+            .expect("leftover processes");
+        assert!(leftovers.iter().any(|process| process["pid"] == pid));
+    }
 }
 
+/// Zombies count as stopped: orphans may never be reaped in a container.
+fn process_running(pid: u64) -> bool {
+    match fs::read_to_string(format!("/proc/{pid}/stat")) {
+        Ok(stat) => !stat
+            .rsplit_once(')')
+            .is_some_and(|(_, tail)| tail.trim_start().starts_with('Z')),
+        Err(_) if Path::new("/proc/self/stat").exists() => false,
+        Err(_) => std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .expect("run kill")
+            .success(),
+    }
+}
+
+#[cfg(unix)]
 #[tokio::test]
-async fn sse_rejects_non_sse_accept() {
-    let test_app = TestApp::new(AuthConfig::disabled());
+#[serial]
+async fn acp_agent_stderr_is_captured_per_instance() {
+    let log_dir = tempfile::tempdir().expect("create stderr log dir");
+    let test_app = {
+        let _log_dir = EnvVarGuard::set_os(
+            "SANDBOX_AGENT_ACP_STDERR_LOG_DIR",
+            log_dir.path().as_os_str(),
+        );
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
 
-    let (status, _, body) = send_request(
+    for id in 1..=2 {
+        let (status, _, _) = send_request(
+            &test_app.app,
+            Method::POST,
+            "/v1/acp/server-logs?agent=codex",
+            Some(json!({"jsonrpc": "2.0", "id": id, "method": "_test/stderr"})),
+            &[],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let lines = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let (status, _, body) = send_request(
+                &test_app.app,
+                Method::GET,
+                "/v1/acp/server-logs/logs",
+                None,
+                &[],
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let lines = parse_json(&body)["lines"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            if lines.len() == 2 {
+                return lines;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("stderr captured");
+    assert_eq!(lines[0]["line"], "stub stderr 1");
+    assert!(lines[0]["timestampMs"].as_i64().is_some());
+
+    let (_, _, body) = send_request(
         &test_app.app,
         Method::GET,
-        "/v1/acp/server-a",
+        "/v1/acp/server-logs/logs?tail=1",
         None,
-        &[("accept", "application/json")],
+        &[],
     )
     .await;
-    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
-    assert_eq!(parse_json(&body)["status"], 406);
-}
+    assert_eq!(parse_json(&body)["lines"], json!([lines[1]]));
 
-#[tokio::test]
-async fn invalid_last_event_id_returns_bad_request() {
-    let test_app = TestApp::new(AuthConfig::disabled());
-    let (status, _, body) = send_request(
+    let since = lines[1]["timestampMs"].as_i64().expect("timestamp") + 60_000;
+    let (_, _, body) = send_request(
         &test_app.app,
         Method::GET,
-        "/v1/acp/server-a",
+        &format!("/v1/acp/server-logs/logs?since={since}"),
         None,
-        &[("last-event-id", "not-a-number")],
+        &[],
     )
     .await;
+    assert_eq!(parse_json(&body)["lines"], json!([]));
 
-    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri("/v1/acp/server-logs/logs?follow=true&tail=1")
+        .body(Body::empty())
+        .expect("build request");
+    let response = test_app
+        .app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("follow response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut stream = response.into_body().into_data_stream();
+    let chunk = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("follow chunk")
+        .expect("follow stream open")
+        .expect("follow chunk bytes");
+    assert!(String::from_utf8_lossy(&chunk).contains("stub stderr 2"));
+
+    let log_file = log_dir.path().join("codex").join("server-logs.log");
+    let contents = fs::read_to_string(&log_file).expect("read stderr log file");
+    assert!(contents.contains("stub stderr 1"));
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-logs",
+        Some(json!({"jsonrpc": "2.0", "id": 3, "method": "_test/exit"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    let problem = parse_json(&body);
+    assert_eq!(problem["details"]["exitCode"], 3);
+    assert!(problem["details"]["stderr"]
+        .as_str()
+        .is_some_and(|stderr| stderr.ends_with("fatal: stub agent exiting")));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_caller_disconnect_cancels_agent_request() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-cancel?agent=codex",
+        Some(initialize_payload()),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    for request in [
+        json!({"jsonrpc": "2.0", "id": 2, "method": "_test/slow", "params": {"hang": true}}),
+        json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "session/prompt",
+            "params": {"sessionId": "s1", "hang": true}
+        }),
+    ] {
+        // Give up on the response, like a client closing its connection.
+        let abandoned = tokio::time::timeout(
+            Duration::from_millis(300),
+            send_request(
+                &test_app.app,
+                Method::POST,
+                "/v1/acp/server-cancel",
+                Some(request),
+                &[],
+            ),
+        )
+        .await;
+        assert!(abandoned.is_err(), "hanging request should not complete");
+    }
+
+    let events = collect_sse_events(&test_app.app, "server-cancel", Some(0), |events| {
+        events
+            .iter()
+            .filter(|(_, event)| event["method"] == "_adapter/request_cancelled")
+            .count()
+            == 2
+            && events
+                .iter()
+                .any(|(_, event)| event["params"]["method"] == "session/cancel")
+    })
+    .await;
+
+    let cancelled = events
+        .iter()
+        .filter(|(_, event)| event["method"] == "_adapter/request_cancelled")
+        .map(|(_, event)| event["params"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        cancelled,
+        vec![
+            json!({
+                "id": 2,
+                "method": "_test/slow",
+                "reason": "client_disconnected",
+                "cancelMethod": "$/cancel_request"
+            }),
+            json!({
+                "id": 3,
+                "method": "session/prompt",
+                "reason": "client_disconnected",
+                "cancelMethod": "session/cancel"
+            }),
+        ]
+    );
+    // The agent saw both cancellations.
+    for method in ["$/cancel_request", "session/cancel"] {
+        assert!(
+            events
+                .iter()
+                .any(|(_, event)| event["method"] == "server/echo"
+                    && event["params"]["method"] == method),
+            "agent did not receive {method}"
+        );
+    }
+
+    // The next request is unaffected.
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-cancel",
+        Some(json!({"jsonrpc": "2.0", "id": 4, "method": "session/new"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["result"]["echoedMethod"], "session/new");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_lagged_subscriber_is_resynced() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+    bootstrap_server(&test_app.app, "server-lag", "codex").await;
+
+    // Subscribe, then stop reading while the agent outruns the broadcast
+    // buffer.
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri("/v1/acp/server-lag")
+        .body(Body::empty())
+        .expect("build request");
+    let response = test_app
+        .app
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("sse response");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-lag",
+        Some(json!({"jsonrpc": "2.0", "id": 2, "method": "_test/flood"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut stream = response.into_body().into_data_stream();
+    let events = tokio::time::timeout(Duration::from_secs(5), async move {
+        let mut buffered = String::new();
+        let mut events = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            buffered.push_str(&String::from_utf8_lossy(&chunk.expect("stream chunk")));
+            while let Some(end) = buffered.find("\n\n") {
+                let event = buffered[..end].to_string();
+                buffered.drain(..end + 2);
+                if !event.contains("data:") {
+                    continue;
+                }
+                let id = event
+                    .lines()
+                    .find_map(|line| line.strip_prefix("id: "))
+                    .and_then(|value| value.trim().parse::<u64>().ok());
+                let payload = parse_sse_data(&event);
+                let last = payload["params"]["n"] == 599;
+                events.push((id, payload));
+                if last {
+                    return events;
+                }
+            }
+        }
+        panic!("SSE stream ended early")
+    })
+    .await
+    .expect("timed out reading sse");
+
+    let lagged = events
+        .iter()
+        .find(|(_, event)| event["method"] == "_adapter/lagged")
+        .map(|(id, event)| (*id, event["params"].clone()))
+        .expect("lag notice");
+    assert_eq!(lagged.0, None);
+    assert_eq!(lagged.1["resynced"], true);
+    assert!(lagged.1["missed"].as_u64().expect("missed") > 0);
+    assert!(!events
+        .iter()
+        .any(|(_, event)| event["method"] == "_adapter/gap"));
+
+    // Nothing was lost: every sequence arrives exactly once, in order.
+    let sequences = events.iter().filter_map(|(id, _)| *id).collect::<Vec<_>>();
+    assert!(sequences.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    let flood = events
+        .iter()
+        .filter(|(_, event)| event["method"] == "server/flood")
+        .count();
+    assert_eq!(flood, 600);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_post_accepts_jsonrpc_batches() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+    bootstrap_server(&test_app.app, "server-batch", "codex").await;
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-batch",
+        Some(json!([
+            {"jsonrpc": "2.0", "id": 2, "method": "session/new"},
+            {"jsonrpc": "2.0", "method": "session/notify"},
+            {"jsonrpc": "2.0", "id": "three", "method": "session/list"},
+            5,
+            {"jsonrpc": "2.0", "id": 2, "method": "session/load"}
+        ])),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let responses = parse_json(&body);
+    let responses = responses.as_array().expect("batch response array");
+    assert_eq!(responses.len(), 4);
+    assert_eq!(responses[0]["id"], 2);
+    assert_eq!(responses[0]["result"]["echoedMethod"], "session/new");
+    assert_eq!(responses[1]["id"], "three");
+    assert_eq!(responses[1]["result"]["echoedMethod"], "session/list");
+    assert_eq!(responses[2]["id"], Value::Null);
+    assert_eq!(responses[2]["error"]["code"], -32600);
+    assert_eq!(responses[3]["id"], 2);
+    assert_eq!(responses[3]["error"]["code"], -32600);
+
+    // Every element reached the agent, in batch order.
+    let events = collect_sse_events(&test_app.app, "server-batch", Some(0), |events| {
+        events.iter().any(|(_, event)| {
+            event["method"] == "server/echo" && event["params"]["method"] == "session/list"
+        })
+    })
+    .await;
+    let echoed = events
+        .iter()
+        .filter(|(_, event)| event["method"] == "server/echo")
+        .filter_map(|(_, event)| event["params"]["method"].as_str())
+        .filter(|method| *method != "initialize")
+        .collect::<Vec<_>>();
+    assert_eq!(echoed, ["session/new", "session/notify", "session/list"]);
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-batch",
+        Some(json!([{"jsonrpc": "2.0", "method": "session/notify"}])),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(body.is_empty());
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-batch",
+        Some(json!([])),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let response = parse_json(&body);
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32600);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_content_length_framed_agent_round_trip() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+        let agent_processes = install_dir.join("agent_processes");
+        write_stub_framed_agent_process(&agent_processes.join("codex-acp"));
+        fs::write(
+            agent_processes.join("codex-acp.json"),
+            r#"{"framing":"content-length"}"#,
+        )
+        .expect("write agent process metadata");
+    });
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-framed?agent=codex",
+        Some(initialize_payload()),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["result"]["echoedMethod"], "initialize");
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-framed",
+        Some(json!({"jsonrpc": "2.0", "method": "session/notify"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let events = collect_sse_events(&test_app.app, "server-framed", Some(0), |events| {
+        events
+            .iter()
+            .any(|(_, event)| event["params"]["method"] == "session/notify")
+    })
+    .await;
+    assert!(!events
+        .iter()
+        .any(|(_, event)| event["method"] == "_adapter/invalid_stdout"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_malformed_frame_header_is_reported_and_skipped() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+        let agent_processes = install_dir.join("agent_processes");
+        write_stub_framed_agent_process(&agent_processes.join("codex-acp"));
+        fs::write(
+            agent_processes.join("codex-acp.json"),
+            r#"{"framing":"content-length"}"#,
+        )
+        .expect("write agent process metadata");
+    });
+    bootstrap_server(&test_app.app, "server-bad-header", "codex").await;
+
+    // The agent writes a bad Content-Length header before this response.
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-bad-header",
+        Some(json!({"jsonrpc": "2.0", "id": 2, "method": "_test/bad_header"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        parse_json(&body)["result"]["echoedMethod"],
+        "_test/bad_header"
+    );
+
+    let events = collect_sse_events(&test_app.app, "server-bad-header", Some(0), |events| {
+        events
+            .iter()
+            .any(|(_, event)| event["method"] == "_adapter/invalid_stdout")
+    })
+    .await;
+    let invalid = events
+        .iter()
+        .map(|(_, event)| event)
+        .find(|event| event["method"] == "_adapter/invalid_stdout")
+        .expect("invalid stdout event");
+    assert!(invalid["params"]["error"]
+        .as_str()
+        .expect("error")
+        .contains("Content-Length"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_permission_policy_answers_matching_requests() {
+    let test_app = {
+        let _policy = EnvVarGuard::set(
+            "SANDBOX_AGENT_ACP_PERMISSION_POLICY",
+            r#"{"rules": [{"kind": ["execute"], "command": ["git status*"], "decision": "allow_always"}]}"#,
+        );
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+    let instance_policy = r#"{"rules": [{"title": ["*rm -rf*"], "decision": "reject"}]}"#;
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        &format!(
+            "/v1/acp/server-perm?agent=codex&permissionPolicy={}",
+            query_escape(instance_policy)
+        ),
+        Some(initialize_payload()),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let permission_request = |id: u64, kind: &str, title: &str, command: &str| {
+        let app = test_app.app.clone();
+        let payload = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "_test/client/session/request_permission",
+            "params": {
+                "sessionId": "s",
+                "toolCall": {
+                    "toolCallId": format!("call-{id}"),
+                    "kind": kind,
+                    "title": title,
+                    "rawInput": {"command": command}
+                },
+                "options": [
+                    {"optionId": "once", "name": "Allow", "kind": "allow_once"},
+                    {"optionId": "always", "name": "Always allow", "kind": "allow_always"},
+                    {"optionId": "deny", "name": "Reject", "kind": "reject_once"}
+                ]
+            }
+        });
+        async move {
+            let (status, _, body) = send_request(
+                &app,
+                Method::POST,
+                "/v1/acp/server-perm",
+                Some(payload),
+                &[],
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            parse_json(&body)["result"]["reply"].clone()
+        }
+    };
+
+    let reply = permission_request(2, "execute", "Run git status", "git status --short").await;
+    assert_eq!(reply["result"]["outcome"]["optionId"], "always");
+
+    let reply =
+        permission_request(3, "execute", "Run rm -rf build", "git status; rm -rf build").await;
+    assert_eq!(reply["result"]["outcome"]["optionId"], "deny");
+
+    // No rule matches, so the request reaches SSE clients for a decision.
+    let pending = tokio::spawn(permission_request(4, "edit", "Edit main.rs", ""));
+    let events = collect_sse_events(&test_app.app, "server-perm", Some(0), |events| {
+        events
+            .iter()
+            .any(|(_, event)| event["method"] == "session/request_permission")
+    })
+    .await;
+    let forwarded = events
+        .iter()
+        .map(|(_, event)| event)
+        .find(|event| event["method"] == "session/request_permission")
+        .expect("forwarded permission request");
+    assert_eq!(forwarded["params"]["toolCall"]["toolCallId"], "call-4");
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-perm",
+        Some(json!({
+            "jsonrpc": "2.0",
+            "id": forwarded["id"],
+            "result": {"outcome": {"outcome": "selected", "optionId": "once"}}
+        })),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let reply = pending.await.expect("pending permission request");
+    assert_eq!(reply["result"]["outcome"]["optionId"], "once");
+
+    let decisions = events
+        .iter()
+        .map(|(_, event)| event)
+        .filter(|event| event["method"] == "_adapter/permission_decision")
+        .map(|event| {
+            (
+                event["params"]["toolCallId"].clone(),
+                event["params"]["source"].clone(),
+                event["params"]["rule"].clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        decisions,
+        vec![
+            (json!("call-2"), json!("global"), json!(0)),
+            (json!("call-3"), json!("instance"), json!(0)),
+        ]
+    );
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-perm?permissionPolicy=not-json",
+        Some(initialize_payload()),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+fn query_escape(raw: &str) -> String {
+    raw.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() {
+                (byte as char).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
+}
+
+type AcpSocket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn serve(app: &Router) -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("listener addr");
+    let app = app.clone();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve");
+    });
+    addr
+}
+
+async fn next_ws_json(socket: &mut AcpSocket, done: impl Fn(&Value) -> bool) -> Vec<Value> {
+    use tokio_tungstenite::tungstenite::Message;
+
+    let mut frames = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(message) = socket.next().await {
+            if let Message::Text(text) = message.expect("websocket frame") {
+                let value: Value = serde_json::from_str(&text).expect("json frame");
+                let finished = done(&value);
+                frames.push(value);
+                if finished {
+                    return;
+                }
+            }
+        }
+        panic!("websocket closed early");
+    })
+    .await
+    .expect("timed out waiting for websocket frame");
+    frames
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn acp_websocket_round_trip_and_resume() {
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+    let addr = serve(&test_app.app).await;
+
+    let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://{addr}/v1/acp/server-ws/ws?agent=codex"))
+            .await
+            .expect("connect websocket");
+    socket
+        .send(Message::Text(initialize_payload().to_string()))
+        .await
+        .expect("send initialize");
+    let frames = next_ws_json(&mut socket, |frame| frame["id"] == 1).await;
+
+    let response = frames.last().expect("initialize response");
+    assert_eq!(response["result"]["echoedMethod"], "initialize");
+    let echo = frames
+        .iter()
+        .find(|frame| frame["method"] == "server/echo")
+        .expect("echo notification");
+    let echo_id = echo["eventId"].as_u64().expect("echo event id");
+    assert_eq!(response["eventId"], echo_id + 1);
+
+    socket
+        .send(Message::Text(
+            json!({"jsonrpc": "2.0", "id": 2, "method": "session/new", "params": {}}).to_string(),
+        ))
+        .await
+        .expect("send session/new");
+    let frames = next_ws_json(&mut socket, |frame| frame["id"] == 2).await;
+    assert!(
+        frames.iter().all(|frame| frame["id"] != 1),
+        "responses are delivered once: {frames:?}"
+    );
+
+    socket
+        .send(Message::Text("not json".to_string()))
+        .await
+        .expect("send invalid frame");
+    let frames = next_ws_json(&mut socket, |frame| frame.get("error").is_some()).await;
+    assert_eq!(frames.last().expect("parse error")["error"]["code"], -32700);
+    socket.close(None).await.expect("close websocket");
+
+    let (mut resumed, _) = tokio_tungstenite::connect_async(format!(
+        "ws://{addr}/v1/acp/server-ws/ws?lastEventId={}",
+        echo_id - 1
+    ))
+    .await
+    .expect("reconnect websocket");
+    let frames = next_ws_json(&mut resumed, |_| true).await;
+    assert_eq!(frames[0]["eventId"], echo_id);
+    assert_eq!(frames[0]["params"]["method"], "initialize");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn acp_websocket_replies_like_post() {
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+    let addr = serve(&test_app.app).await;
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!(
+        "ws://{addr}/v1/acp/server-ws-timeout/ws?agent=codex&timeoutMs=200"
+    ))
+    .await
+    .expect("connect websocket");
+    socket
+        .send(Message::Text(initialize_payload().to_string()))
+        .await
+        .expect("send initialize");
+    next_ws_json(&mut socket, |frame| frame["id"] == 1).await;
+
+    // The timeout is per frame, so it applies afresh to a later request.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    socket
+        .send(Message::Text(
+            json!({"jsonrpc": "2.0", "id": 2, "method": "session/new", "params": {}}).to_string(),
+        ))
+        .await
+        .expect("send session/new");
+    let frames = next_ws_json(&mut socket, |frame| frame["id"] == 2).await;
+    assert!(frames.last().expect("session/new response")["result"].is_object());
+
+    socket
+        .send(Message::Text(
+            json!({"jsonrpc": "2.0", "id": 3, "method": "_test/slow", "params": {"hang": true}})
+                .to_string(),
+        ))
+        .await
+        .expect("send slow request");
+    let frames = next_ws_json(&mut socket, |frame| frame["id"] == 3).await;
+    let error = &frames.last().expect("timeout reply")["error"];
+    assert_eq!(error["data"]["type"], "urn:sandbox-agent:error:timeout");
+    assert_eq!(error["data"]["details"]["method"], "_test/slow");
+    socket.close(None).await.expect("close websocket");
+}
+
+#[tokio::test]
+async fn acp_websocket_unknown_server_is_rejected() {
+    let test_app = TestApp::new(AuthConfig::disabled());
+    let addr = serve(&test_app.app).await;
+
+    let err = tokio_tungstenite::connect_async(format!("ws://{addr}/v1/acp/missing/ws"))
+        .await
+        .expect_err("upgrade should fail");
+    match err {
+        tokio_tungstenite::tungstenite::Error::Http(response) => {
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+        other => panic!("unexpected websocket error: {other}"),
+    }
+}
+
+#[tokio::test]
+async fn acp_get_unknown_returns_not_found() {
+    let test_app = TestApp::new(AuthConfig::disabled());
+
+    let (status, _, body) =
+        send_request(&test_app.app, Method::GET, "/v1/acp/missing", None, &[]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(parse_json(&body)["status"], 404);
+}
+
+#[tokio::test]
+async fn acp_delete_is_idempotent() {
+    let test_app = TestApp::new(AuthConfig::disabled());
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::DELETE,
+        "/v1/acp/server-delete",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::DELETE,
+        "/v1/acp/server-delete",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "1.0",
+            "clientCapabilities": {}
+        }
+    });
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-delete",
+        Some(request),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(parse_json(&body)["status"], 400);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn acp_list_servers_returns_active_instances() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+
+    bootstrap_server(&test_app.app, "server-1", "codex").await;
+    bootstrap_server(&test_app.app, "server-2", "codex").await;
+
+    let (status, _, body) = send_request(&test_app.app, Method::GET, "/v1/acp", None, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let parsed = parse_json(&body);
+    let servers = parsed["servers"].as_array().expect("servers array");
+    assert!(servers
+        .iter()
+        .any(|server| server["serverId"] == "server-1"));
+    assert!(servers
+        .iter()
+        .any(|server| server["serverId"] == "server-2"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn acp_server_labels_filter_listing() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+
+    for (server_id, team) in [("server-a", "red"), ("server-b", "blue")] {
+        let uri = format!("/v1/acp/{server_id}?agent=codex&label=team={team}&label=tier=dev");
+        let (status, _, _) = send_request(
+            &test_app.app,
+            Method::POST,
+            &uri,
+            Some(initialize_payload()),
+            &[],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    bootstrap_server(&test_app.app, "server-c", "codex").await;
+
+    let list = |selector: &str| {
+        let uri = format!("/v1/acp?labelSelector={}", query_escape(selector));
+        let app = test_app.app.clone();
+        async move {
+            let (status, _, body) = send_request(&app, Method::GET, &uri, None, &[]).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            parse_json(&body)["servers"]
+                .as_array()
+                .expect("servers array")
+                .iter()
+                .map(|server| server["serverId"].as_str().expect("server id").to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+    assert_eq!(list("team=red").await, vec!["server-a"]);
+    assert_eq!(list("tier==dev,team!=red").await, vec!["server-b"]);
+    assert_eq!(list("!team").await, vec!["server-c"]);
+    assert_eq!(list("tier").await, vec!["server-a", "server-b"]);
+
+    let (status, _, body) = send_request(&test_app.app, Method::GET, "/v1/acp", None, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let server = &parse_json(&body)["servers"][0];
+    assert_eq!(server["labels"], json!({"team": "red", "tier": "dev"}));
+    assert_eq!(server["state"], "running");
+    assert!(server["pid"].as_u64().is_some_and(|pid| pid > 0));
+    assert_eq!(server["pendingRequests"], 0);
+    assert!(server["uptimeMs"]
+        .as_i64()
+        .is_some_and(|uptime| uptime >= 0));
+    assert!(server["lastActivityMs"].as_i64().is_some());
+
+    // Labels on later POSTs are ignored.
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-c?label=team=green",
+        Some(json!({"jsonrpc": "2.0", "id": 2, "method": "session/new", "params": {}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(list("team=green").await, Vec::<String>::new());
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::PATCH,
+        "/v1/acp/server-c",
+        Some(json!({"labels": {"team": "green"}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["labels"], json!({"team": "green"}));
+    assert_eq!(list("team=green").await, vec!["server-c"]);
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::PATCH,
+        "/v1/acp/server-a",
+        Some(json!({"labels": {"team": null, "owner": "ci"}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        parse_json(&body)["labels"],
+        json!({"owner": "ci", "tier": "dev"})
+    );
+
+    for (method, uri, body) in [
+        (Method::GET, "/v1/acp?labelSelector=bad%20key", None),
+        (
+            Method::PATCH,
+            "/v1/acp/server-a",
+            Some(json!({"labels": {"bad key": "x"}})),
+        ),
+        (
+            Method::POST,
+            "/v1/acp/server-d?agent=codex&label=novalue",
+            Some(initialize_payload()),
+        ),
+    ] {
+        let (status, _, _) = send_request(&test_app.app, method, uri, body, &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+    }
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::PATCH,
+        "/v1/acp/missing",
+        Some(json!({"labels": {}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn metrics_endpoint_reports_http_acp_and_fs() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+
+    let (status, _, _) = send_request_raw(
+        &test_app.app,
+        Method::PUT,
+        "/v1/fs/file?path=metrics/file.txt",
+        Some(b"hello".to_vec()),
+        &[],
+        Some("application/octet-stream"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    bootstrap_server(&test_app.app, "server-metrics", "codex").await;
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-metrics",
+        Some(json!({"jsonrpc": "2.0", "id": 2, "method": "_client/random-7f3a", "params": {}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, headers, body) =
+        send_request(&test_app.app, Method::GET, "/metrics", None, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/plain")));
+    let text = String::from_utf8(body).expect("utf8 metrics");
+    for expected in [
+        "# TYPE sandbox_agent_http_requests_total counter",
+        r#"sandbox_agent_http_requests_total{method="PUT",route="/v1/fs/file",status="200"} 1"#,
+        r#"sandbox_agent_http_request_duration_seconds_count{method="POST",route="/v1/acp/:server_id"} 2"#,
+        r#"sandbox_agent_acp_request_duration_seconds_count{agent="codex",method="initialize",outcome="ok"} 1"#,
+        r#"sandbox_agent_acp_instances{agent="codex",state="running"} 1"#,
+        r#"sandbox_agent_acp_pending_requests{server_id="server-metrics",agent="codex"} 0"#,
+        r#"sandbox_agent_acp_subscribers{server_id="server-metrics",agent="codex"} 0"#,
+        r#"sandbox_agent_acp_spawn_seconds{server_id="server-metrics",agent="codex"}"#,
+        r#"sandbox_agent_acp_first_stdout_seconds{server_id="server-metrics",agent="codex"}"#,
+        r#"sandbox_agent_fs_written_bytes_total{source="http"} 5"#,
+    ] {
+        assert!(text.contains(expected), "missing {expected} in:\n{text}");
+    }
+    assert!(text.contains(r#"agent="codex",method="other""#), "{text}");
+    assert!(!text.contains("random-7f3a"), "{text}");
+
+    let test_app = TestApp::new(AuthConfig::with_token("secret-token".to_string()));
+    let (status, _, _) = send_request(&test_app.app, Method::GET, "/metrics", None, &[]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::GET,
+        "/metrics",
+        None,
+        &[("authorization", "Bearer secret-token")],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_transcript_records_and_replays_session() {
+    let transcript_dir = tempfile::tempdir().expect("create transcript dir");
+    let recording = {
+        let _dir = EnvVarGuard::set_os(
+            "SANDBOX_AGENT_ACP_TRANSCRIPT_DIR",
+            transcript_dir.path().as_os_str(),
+        );
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+
+    let session_new = |id: Value| json!({"jsonrpc": "2.0", "id": id, "method": "session/new", "params": {"cwd": "/"}});
+    let mut recorded = Vec::new();
+    for (uri, payload) in [
+        ("/v1/acp/server-rec?agent=codex", initialize_payload()),
+        ("/v1/acp/server-rec", session_new(json!(2))),
+    ] {
+        let (status, _, body) =
+            send_request(&recording.app, Method::POST, uri, Some(payload), &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        recorded.push(parse_json(&body));
+    }
+
+    let transcript = transcript_dir.path().join("codex").join("server-rec.jsonl");
+    let entries = fs::read_to_string(&transcript)
+        .expect("read transcript")
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).expect("transcript line"))
+        .collect::<Vec<_>>();
+    let summary = entries
+        .iter()
+        .map(|entry| {
+            let message = &entry["message"];
+            (
+                entry["seq"].as_u64().expect("seq"),
+                entry["direction"].as_str().expect("direction").to_string(),
+                message["method"]
+                    .as_str()
+                    .unwrap_or("<response>")
+                    .to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let expected = [
+        ("to_agent", "initialize"),
+        ("from_agent", "server/echo"),
+        ("from_agent", "<response>"),
+        ("to_agent", "session/new"),
+        ("from_agent", "server/echo"),
+        ("from_agent", "<response>"),
+    ]
+    .iter()
+    .enumerate()
+    .map(|(index, (direction, method))| {
+        (index as u64 + 1, direction.to_string(), method.to_string())
+    })
+    .collect::<Vec<_>>();
+    assert_eq!(summary, expected);
+    assert!(entries
+        .iter()
+        .all(|entry| entry["timestampMs"].as_i64().is_some()));
+
+    let replaying = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+        sandbox_agent_agent_management::agents::write_replay_agent_process_launcher(
+            &install_dir.join("agent_processes").join("codex-acp"),
+            Path::new(env!("CARGO_BIN_EXE_sandbox-agent")),
+            &transcript,
+        )
+        .expect("write replay launcher");
+    });
+
+    let mut initialize = initialize_payload();
+    initialize["id"] = json!("init-live");
+    let (status, _, body) = send_request(
+        &replaying.app,
+        Method::POST,
+        "/v1/acp/server-replay?agent=codex",
+        Some(initialize),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let replayed = parse_json(&body);
+    assert_eq!(replayed["id"], "init-live");
+    assert_eq!(replayed["result"], recorded[0]["result"]);
+
+    let (status, _, body) = send_request(
+        &replaying.app,
+        Method::POST,
+        "/v1/acp/server-replay",
+        Some(session_new(json!(42))),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let replayed = parse_json(&body);
+    assert_eq!(replayed["id"], 42);
+    assert_eq!(replayed["result"], recorded[1]["result"]);
+
+    // Traffic the recording does not cover is answered with an error.
+    let (status, _, body) = send_request(
+        &replaying.app,
+        Method::POST,
+        "/v1/acp/server-replay",
+        Some(json!({"jsonrpc": "2.0", "id": 43, "method": "session/prompt", "params": {}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let message = parse_json(&body)["error"]["message"]
+        .as_str()
+        .expect("error message")
+        .to_string();
+    assert!(message.contains("transcript exhausted"), "{message}");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_request_timeouts_per_method_and_per_request() {
+    let test_app = {
+        let _timeouts = EnvVarGuard::set("SANDBOX_AGENT_ACP_METHOD_TIMEOUTS_MS", "_test/slow=200");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-deadline?agent=codex",
+        Some(initialize_payload()),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The per-method default applies without anything on the request.
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-deadline",
+        Some(json!({"jsonrpc": "2.0", "id": 2, "method": "_test/slow", "params": {"hang": true}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    let problem = parse_json(&body);
+    assert_eq!(problem["type"], "urn:sandbox-agent:error:timeout");
+    assert_eq!(problem["agent"], "codex");
+    assert_eq!(problem["details"]["method"], "_test/slow");
+    assert_eq!(problem["details"]["cancelled"], false);
+    assert!(problem["details"]["elapsedMs"]
+        .as_u64()
+        .is_some_and(|elapsed| elapsed >= 200));
+
+    // A header sets the timeout for one request, which is then cancelled.
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-deadline?cancelOnTimeout=true",
+        Some(json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "session/prompt",
+            "params": {"sessionId": "s1", "hang": true}
+        })),
+        &[("x-request-timeout-ms", "150")],
+    )
+    .await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    let problem = parse_json(&body);
+    assert_eq!(problem["details"]["method"], "session/prompt");
+    assert_eq!(problem["details"]["cancelled"], true);
+
+    let events = collect_sse_events(&test_app.app, "server-deadline", Some(0), |events| {
+        events
+            .iter()
+            .any(|(_, event)| event["method"] == "_adapter/request_cancelled")
+    })
+    .await;
+    let cancelled = events
+        .iter()
+        .filter(|(_, event)| event["method"] == "_adapter/request_cancelled")
+        .map(|(_, event)| event["params"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        cancelled,
+        vec![json!({
+            "id": 3,
+            "method": "session/prompt",
+            "reason": "timeout",
+            "cancelMethod": "session/cancel"
+        })]
+    );
+
+    // A deadline that has already passed fails without reaching the agent.
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-deadline?deadline=1",
+        Some(json!({"jsonrpc": "2.0", "id": 4, "method": "session/new"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(parse_json(&body)["details"]["method"], "session/new");
+
+    // Notifications await nothing, so no deadline stops them.
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-deadline?timeoutMs=0",
+        Some(json!({"jsonrpc": "2.0", "method": "session/cancel", "params": {"sessionId": "s1"}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-deadline?timeoutMs=soon",
+        Some(json!({"jsonrpc": "2.0", "id": 5, "method": "session/new"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-deadline?timeoutMs=5000",
+        Some(json!({"jsonrpc": "2.0", "id": 6, "method": "session/new"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["result"]["echoedMethod"], "session/new");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_first_client_response_wins() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+    bootstrap_server(&test_app.app, "server-claim", "codex").await;
+
+    let app = test_app.app.clone();
+    let prompt = tokio::spawn(async move {
+        send_request(
+            &app,
+            Method::POST,
+            "/v1/acp/server-claim",
+            Some(json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "_test/client/session/request_permission",
+                "params": {"sessionId": "s1"}
+            })),
+            &[],
+        )
+        .await
+    });
+    collect_sse_events(&test_app.app, "server-claim", Some(0), |events| {
+        events
+            .iter()
+            .any(|(_, event)| event["method"] == "session/request_permission")
+    })
+    .await;
+
+    let answer =
+        |outcome: &str| json!({"jsonrpc": "2.0", "id": "client-2", "result": {"outcome": outcome}});
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-claim",
+        Some(answer("allow")),
+        &[("x-client-id", "ui-a")],
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-claim?clientId=ui-b",
+        Some(answer("reject")),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let detail = parse_json(&body)["details"]["message"]
+        .as_str()
+        .expect("conflict message")
+        .to_string();
+    assert!(detail.contains("answered by 'ui-a'"), "{detail}");
+
+    let (status, _, body) = prompt.await.expect("join prompt");
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        parse_json(&body)["result"]["reply"]["result"],
+        json!({"outcome": "allow"})
+    );
+
+    let events = collect_sse_events(&test_app.app, "server-claim", Some(0), |events| {
+        events
+            .iter()
+            .any(|(_, event)| event["method"] == "_adapter/request_resolved")
+    })
+    .await;
+    let resolved = events
+        .iter()
+        .filter(|(_, event)| event["method"] == "_adapter/request_resolved")
+        .map(|(_, event)| event["params"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        resolved,
+        vec![json!({
+            "id": "client-2",
+            "method": "session/request_permission",
+            "resolvedBy": "ui-a",
+            "outcome": "result"
+        })]
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn custom_agents_register_list_and_launch() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        fs::create_dir_all(install_dir.join("bin")).expect("create bin dir");
+        write_stub_agent_process(&install_dir.join("bin").join("acme-acp"), "acme");
+    });
+    let command = test_app.install_path().join("bin").join("acme-acp");
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::PUT,
+        "/v1/agents/acme-test/definition",
+        Some(json!({
+            "displayName": "Acme Agent",
+            "install": { "command": command.to_string_lossy() },
+            "args": ["--custom"],
+            "env": { "ACP_TEST_VALUE": "from-definition" },
+            "capabilities": { "permissions": true }
+        })),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(test_app.install_path().join("custom-agents.json").exists());
+
+    let (status, _, body) = send_request(&test_app.app, Method::GET, "/v1/agents", None, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let agents = parse_json(&body)["agents"]
+        .as_array()
+        .cloned()
+        .expect("agents array");
+    let acme = agents
+        .iter()
+        .find(|agent| agent["id"] == "acme-test")
+        .expect("custom agent listed");
+    assert_eq!(acme["displayName"], "Acme Agent");
+    assert_eq!(acme["installed"], true);
+    assert_eq!(acme["capabilities"]["permissions"], true);
+    assert_eq!(acme["capabilities"]["planMode"], false);
+    let codex = agents
+        .iter()
+        .find(|agent| agent["id"] == "codex")
+        .expect("built-in agent listed");
+    assert_eq!(codex["displayName"], "Codex CLI");
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/custom-server?agent=acme-test",
+        Some(initialize_payload()),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["result"]["echoedMethod"], "initialize");
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/custom-server",
+        Some(json!({"jsonrpc": "2.0", "id": 2, "method": "_test/launch", "params": {}})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let launch = parse_json(&body);
+    assert_eq!(launch["result"]["env"], "from-definition");
+    assert_eq!(launch["result"]["args"], "--custom");
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::PUT,
+        "/v1/agents/codex/definition",
+        Some(json!({ "install": { "npx": "@example/codex" } })),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(parse_json(&body)["status"], 400);
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::DELETE,
+        "/v1/acp/custom-server",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::DELETE,
+        "/v1/agents/acme-test/definition",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::GET,
+        "/v1/agents/acme-test",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn uninstall_agent_refuses_live_servers_unless_forced() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+    let launcher = if cfg!(windows) {
+        "codex-acp.cmd"
+    } else {
+        "codex-acp"
+    };
+    let launcher = test_app
+        .install_path()
+        .join("agent_processes")
+        .join(launcher);
+
+    bootstrap_server(&test_app.app, "server-uninstall", "codex").await;
+
+    let (status, _, body) =
+        send_request(&test_app.app, Method::DELETE, "/v1/agents/codex", None, &[]).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(parse_json(&body)["detail"]
+        .as_str()
+        .expect("detail")
+        .contains("server-uninstall"));
+    assert!(launcher.exists());
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::DELETE,
+        "/v1/agents/codex?force=true",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let removed = parse_json(&body)["removed"]
+        .as_array()
+        .cloned()
+        .expect("removed array");
+    assert!(removed
+        .iter()
+        .any(|path| path.as_str() == Some(&*launcher.to_string_lossy())));
+    assert!(!launcher.exists());
+
+    let (status, _, body) =
+        send_request(&test_app.app, Method::GET, "/v1/agents/codex", None, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["installed"], false);
+
+    let (status, _, body) = send_request(&test_app.app, Method::GET, "/v1/acp", None, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["servers"], json!([]));
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::DELETE,
+        "/v1/agents/not-an-agent",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sandboxagent_methods_are_not_handled_specially() {
+    let test_app = TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+        setup_stub_artifacts(install_dir, "codex");
+    });
+
+    bootstrap_server(&test_app.app, "server-ext", "codex").await;
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 22,
+        "method": "_sandboxagent/session/list",
+        "params": {}
+    });
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-ext",
+        Some(request),
+        &[],
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        parse_json(&body)["result"]["echoedMethod"],
+        "_sandboxagent/session/list"
+    );
+}
+
+#[tokio::test]
+async fn post_requires_json_content_type() {
+    let test_app = TestApp::new(AuthConfig::disabled());
+    let payload = br#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"1.0","clientCapabilities":{}}}"#
+        .to_vec();
+    let (status, _, body) = send_request_raw(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-content?agent=mock",
+        Some(payload),
+        &[],
+        Some("text/plain"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(parse_json(&body)["status"], 415);
+}
+
+#[tokio::test]
+async fn sse_rejects_non_sse_accept() {
+    let test_app = TestApp::new(AuthConfig::disabled());
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::GET,
+        "/v1/acp/server-a",
+        None,
+        &[("accept", "application/json")],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(parse_json(&body)["status"], 406);
+}
+
+#[tokio::test]
+async fn invalid_last_event_id_returns_bad_request() {
+    let test_app = TestApp::new(AuthConfig::disabled());
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::GET,
+        "/v1/acp/server-a",
+        None,
+        &[("last-event-id", "not-a-number")],
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
     assert_eq!(
         parse_json(&body)["detail"],
         "invalid request: Last-Event-ID must be a positive integer"
     );
 }
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_idle_instances_are_reaped() {
+    let test_app = {
+        let _idle = EnvVarGuard::set("SANDBOX_AGENT_ACP_IDLE_TIMEOUT_MS", "200");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+
+    // Reap twice to make sure the reaper keeps running after its first pass.
+    for server_id in ["server-idle-a", "server-idle-b"] {
+        bootstrap_server(&test_app.app, server_id, "codex").await;
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let (status, _, body) =
+                    send_request(&test_app.app, Method::GET, "/v1/acp", None, &[]).await;
+                assert_eq!(status, StatusCode::OK);
+                if parse_json(&body)["servers"]
+                    .as_array()
+                    .is_some_and(|servers| servers.is_empty())
+                {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("idle instance was not reaped");
+
+        let (status, _, _) = send_request(
+            &test_app.app,
+            Method::GET,
+            &format!("/v1/acp/{server_id}"),
+            None,
+            &[],
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_max_lifetime_reaps_with_connected_subscriber() {
+    let test_app = {
+        let _lifetime = EnvVarGuard::set("SANDBOX_AGENT_ACP_MAX_LIFETIME_MS", "300");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+
+    bootstrap_server(&test_app.app, "server-ttl", "codex").await;
+
+    let events = collect_sse_events(&test_app.app, "server-ttl", None, |events| {
+        events
+            .last()
+            .is_some_and(|(_, payload)| payload["method"] == "_adapter/instance_reaped")
+    })
+    .await;
+    let (_, reaped) = events.last().expect("reap notification");
+
+    assert_eq!(reaped["params"]["serverId"], "server-ttl");
+    assert_eq!(reaped["params"]["reason"], "max_lifetime");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_restarts_crashed_agent_process() {
+    let test_app = {
+        let _policy = EnvVarGuard::set("SANDBOX_AGENT_ACP_RESTART_POLICY", "on-failure");
+        let _backoff = EnvVarGuard::set("SANDBOX_AGENT_ACP_RESTART_BACKOFF_MS", "20");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+
+    bootstrap_server(&test_app.app, "server-restart", "codex").await;
+
+    let (status, _, _) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-restart",
+        Some(json!({"jsonrpc": "2.0", "method": "_test/exit"})),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let events = collect_sse_events(&test_app.app, "server-restart", None, |events| {
+        events
+            .last()
+            .is_some_and(|(_, payload)| payload["method"] == "_adapter/agent_restarted")
+    })
+    .await;
+
+    assert!(events.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    let exited = events
+        .iter()
+        .find(|(_, payload)| payload["method"] == "_adapter/agent_exited")
+        .expect("agent_exited event");
+    assert_eq!(exited.1["params"]["code"], 3);
+    assert_eq!(exited.1["params"]["restarting"], true);
+
+    let (status, _, body) = send_request(&test_app.app, Method::GET, "/v1/acp", None, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["servers"][0]["restartCount"], 1);
+
+    let (status, _, body) = send_request(
+        &test_app.app,
+        Method::POST,
+        "/v1/acp/server-restart",
+        Some(initialize_payload()),
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(parse_json(&body)["result"]["echoedMethod"], "initialize");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_event_log_replays_history_after_restart() {
+    let log_dir = tempfile::tempdir().expect("create event log dir");
+    let new_app = || {
+        let _log_dir = EnvVarGuard::set_os(
+            "SANDBOX_AGENT_ACP_EVENT_LOG_DIR",
+            log_dir.path().as_os_str(),
+        );
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+
+    let first_app = new_app();
+    bootstrap_server(&first_app.app, "server-durable", "codex").await;
+    drop(first_app);
+    wait_for_logged_exit(log_dir.path(), "server-durable").await;
+
+    let second_app = new_app();
+    bootstrap_server(&second_app.app, "server-durable", "codex").await;
+
+    // initialize (echo + response), the first agent's exit, then the second
+    // initialize.
+    let events = collect_sse_events(&second_app.app, "server-durable", Some(0), |events| {
+        events.len() >= 5
+    })
+    .await;
+    let ids = events.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![Some(1), Some(2), Some(3), Some(4), Some(5)]);
+    assert_eq!(events[0].1["method"], "server/echo");
+    assert_eq!(events[2].1["method"], "_adapter/agent_exited");
+
+    let (status, _, _) = send_request(
+        &second_app.app,
+        Method::DELETE,
+        "/v1/acp/server-durable",
+        None,
+        &[],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(!log_dir.path().join("server-durable").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn acp_replay_reports_gap_for_expired_history() {
+    let log_dir = tempfile::tempdir().expect("create event log dir");
+    let new_app = || {
+        let _log_dir = EnvVarGuard::set_os(
+            "SANDBOX_AGENT_ACP_EVENT_LOG_DIR",
+            log_dir.path().as_os_str(),
+        );
+        let _max_events = EnvVarGuard::set("SANDBOX_AGENT_ACP_EVENT_LOG_MAX_EVENTS", "2");
+        TestApp::with_setup(AuthConfig::disabled(), |install_dir| {
+            setup_stub_artifacts(install_dir, "codex");
+        })
+    };
+
+    let first_app = new_app();
+    bootstrap_server(&first_app.app, "server-gap", "codex").await;
+    bootstrap_server(&first_app.app, "server-gap", "codex").await;
+    drop(first_app);
+    wait_for_logged_exit(log_dir.path(), "server-gap").await;
+
+    let second_app = new_app();
+    bootstrap_server(&second_app.app, "server-gap", "codex").await;
+
+    let events = collect_sse_events(&second_app.app, "server-gap", Some(0), |events| {
+        events.len() >= 3
+    })
+    .await;
+    let (gap_id, gap) = &events[0];
+    assert_eq!(*gap_id, None);
+    assert_eq!(gap["method"], "_adapter/gap");
+    assert_eq!(gap["params"]["from"], 1);
+    assert_eq!(gap["params"]["to"], 5);
+    assert_eq!(events[1].0, Some(6));
+    assert_eq!(events[2].0, Some(7));
+}